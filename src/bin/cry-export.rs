@@ -0,0 +1,64 @@
+use pokemon::cry::{Cry, SAMPLE_RATE};
+use pokemon::pokedex::SPECIES_COUNT;
+use std::path::Path;
+use std::{env, fs};
+
+// Plain 8 bit mono PCM, which is exactly what the synthesiser makes, so the
+// samples go in as they are.
+fn wav(samples: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(44 + samples.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes()); // bytes per second
+    out.extend_from_slice(&1u16.to_le_bytes()); // bytes per frame
+    out.extend_from_slice(&8u16.to_le_bytes()); // bits per sample
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    out.extend_from_slice(samples);
+    return out;
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        println!("usage: {} <output-dir> [species-id...]", args[0]);
+        println!("writes every species when no ids are given");
+        return;
+    }
+
+    let ids: Vec<u8> = if args.len() > 2 {
+        args[2..]
+            .iter()
+            .map(|id| id.parse().expect("species ids are numbers"))
+            .collect()
+    } else {
+        (1..=SPECIES_COUNT).collect()
+    };
+
+    let output_dir = Path::new(&args[1]);
+    fs::create_dir_all(output_dir).unwrap();
+    for id in ids {
+        let cry = Cry::new(id);
+        let samples: Vec<u8> = cry.samples().collect();
+        // Same cry, same sum. Handy for spotting when a change to the
+        // synthesiser changes how things sound.
+        let sum = samples
+            .iter()
+            .fold(0u32, |sum, s| sum.wrapping_mul(31).wrapping_add(*s as u32));
+        fs::write(output_dir.join(format!("{:03}.wav", id)), wav(&samples)).unwrap();
+        println!(
+            "{:03}: {} notes, {}ms, sum {:08x}",
+            id,
+            cry.notes().len(),
+            samples.len() as u32 * 1000 / SAMPLE_RATE,
+            sum
+        );
+    }
+}
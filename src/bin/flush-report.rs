@@ -0,0 +1,131 @@
+use pokemon::app::{App, Context};
+use pokemon::cry::Speaker;
+use pokemon::encounter::wild_pokemon;
+use pokemon::framebuffer::{FlushStats, FrameBuffer, HEIGHT, WIDTH};
+use pokemon::host::{MemFlash, Panel};
+use pokemon::input::{ButtonStates, Buttons};
+use pokemon::led::Leds;
+use pokemon::link::NoLink;
+use pokemon::save::Save;
+use pokemon::screens::BattleState;
+use pokemon::time::{TimeOfDay, Timestamp};
+use pokemon::trainer::FakeNetworks;
+use std::{env, fs, process};
+
+// The firmware runs its bit-banged I2C off a 200kHz timer, which toggles the
+// clock at half that.
+const I2C_KHZ: u32 = 100;
+
+fn matches(panel: &Panel, display: &FrameBuffer) -> bool {
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            if panel.pixel(x, y) != display.pixel(x, y) {
+                return false;
+            }
+        }
+    }
+    return true;
+}
+
+// Each step presses a button (or doesn't) and then lets the app run for a
+// while, so animations get counted too.
+const STEPS: [(&str, Option<Buttons>, u32); 12] = [
+    ("boot", None, 1),
+    ("menu down", Some(Buttons::DOWN), 1),
+    ("menu down", Some(Buttons::DOWN), 1),
+    ("menu up", Some(Buttons::UP), 1),
+    ("open pokedex", Some(Buttons::A), 1),
+    ("pokedex down", Some(Buttons::DOWN), 1),
+    ("back to menu", Some(Buttons::B), 1),
+    ("menu up", Some(Buttons::UP), 1),
+    ("scan", Some(Buttons::A), 40),
+    ("battle text", Some(Buttons::A), 40),
+    ("fight", Some(Buttons::A), 20),
+    ("first move", Some(Buttons::A), 80),
+];
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 2 {
+        println!("usage: {} <rom-file>", args[0]);
+        return;
+    }
+
+    let mut flash = MemFlash::with_rom(&fs::read(&args[1]).unwrap());
+
+    // Skip naming the player and setting the clock, and give them something
+    // to battle with.
+    let mut save = Save::load(&mut flash);
+    let mut name = [0u8; 12];
+    name[..3].copy_from_slice(b"RED");
+    save.data.player_name = Some(name);
+    save.data.set_clock(Timestamp::new(0, 12, 0));
+    save.data.party[0] = Some(wild_pokemon(7, 8, TimeOfDay::Day, &mut flash).unwrap());
+
+    let mut app = App::boot(&save);
+    let mut buttons = ButtonStates::new();
+    let mut leds = Leds::new();
+    let mut speaker = Speaker::new();
+    let mut battle = BattleState::new();
+    let mut display = FrameBuffer::new();
+    let mut panel = Panel::new();
+    let mut total = FlushStats::default();
+    let mut total_full = FlushStats::default();
+
+    println!(
+        "{:<14} {:>6} {:>7} {:>7} {:>9} {:>9}",
+        "step", "frames", "pages", "bytes", "dirty ms", "full ms"
+    );
+    for (label, button, ticks) in STEPS.iter() {
+        if let Some(button) = button {
+            buttons.update(*button, true);
+        }
+        let mut frames = 0;
+        let mut step = FlushStats::default();
+        let mut full = FlushStats::default();
+        for _ in 0..*ticks {
+            let mut ctx = Context {
+                storage: &mut flash,
+                save: &mut save,
+                leds: &mut leds,
+                link: &mut NoLink,
+                scanner: &mut FakeNetworks,
+                speaker: &mut speaker,
+                battle: &mut battle,
+            };
+            app.tick(&mut buttons, &mut ctx);
+            if let Some(button) = button {
+                buttons.update(*button, false);
+            }
+            if !app.render(&mut display, &mut ctx) {
+                continue;
+            }
+            step.add(display.flush(&mut panel).unwrap());
+            full.add(FlushStats::full());
+            frames += 1;
+            if !matches(&panel, &display) {
+                println!("{}: panel doesn't match the frame after a flush", label);
+                process::exit(1);
+            }
+        }
+        println!(
+            "{:<14} {:>6} {:>7} {:>7} {:>9.1} {:>9.1}",
+            label,
+            frames,
+            step.pages,
+            step.bytes(),
+            step.micros(I2C_KHZ) as f32 / 1000.0,
+            full.micros(I2C_KHZ) as f32 / 1000.0,
+        );
+        total.add(step);
+        total_full.add(full);
+    }
+    println!(
+        "total: {} bytes in {:.1}ms, {} bytes in {:.1}ms flushing everything",
+        total.bytes(),
+        total.micros(I2C_KHZ) as f32 / 1000.0,
+        total_full.bytes(),
+        total_full.micros(I2C_KHZ) as f32 / 1000.0,
+    );
+}
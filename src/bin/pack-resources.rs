@@ -1,39 +1,173 @@
-use minicbor::bytes::ByteArray;
+use embedded_graphics::{image::IntoPixelIter, pixelcolor::BinaryColor, prelude::*};
+use minicbor::bytes::ByteSlice;
 use minicbor_io::Writer;
+use pokemon::font::{GLYPHS_PER_ROW, SHEET_ROWS};
 use pokemon::pokedex::{
-    self, LearnCondition, LearnableMove, Move, MoveListChunk, PokemonSpecies, SpeciesStatData, Type,
+    self, AilmentParameter, AilmentType, DamageClass, Evolution, EvolutionTrigger, GrowthRate,
+    Item, ItemEffect, LearnCondition, LearnableMove, Move, MoveListChunk, ParameterRange,
+    Parameters, PokemonSpecies, SpeciesStatData, StatChange, Stats, Target, Type, MAX_EVOLUTIONS,
 };
 use serde_json::Value;
-use std::ascii::AsciiExt;
-use std::collections::HashMap;
-use std::fs::read_dir;
-use std::io::{prelude::*, BufReader, BufWriter, Cursor};
-use std::path::PathBuf;
-use std::{env, fs::File};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, read_dir, File};
+use std::io::{self, prelude::*, BufReader, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+use std::{env, fmt, process};
+use tinybmp::Bmp;
 
+// Something wrong with the data, and where: which file, and where in it.
+// The path is empty when the problem is the whole file.
 #[derive(Debug)]
-struct ParseError(String);
+struct ParseError {
+    file: PathBuf,
+    path: String,
+    reason: String,
+}
+
+impl ParseError {
+    // The file gets filled in by the report, since the parsers don't know it.
+    fn new(path: &str, reason: impl Into<String>) -> ParseError {
+        return ParseError {
+            file: PathBuf::new(),
+            path: path.to_string(),
+            reason: reason.into(),
+        };
+    }
+
+    // For when the part that failed was inside something else.
+    fn under(mut self, parent: &str) -> ParseError {
+        self.path = match self.path.as_str() {
+            "" => parent.to_string(),
+            path if path.starts_with('[') => format!("{}{}", parent, path),
+            path => format!("{}.{}", parent, path),
+        };
+        return self;
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if !self.path.is_empty() {
+            write!(f, ": {}", self.path)?;
+        }
+        return write!(f, ": {}", self.reason);
+    }
+}
+
+// Everything that went wrong, so one bad file doesn't hide the rest.
+struct Report {
+    file: PathBuf,
+    errors: Vec<ParseError>,
+}
+
+impl Report {
+    fn new() -> Report {
+        return Report {
+            file: PathBuf::new(),
+            errors: Vec::new(),
+        };
+    }
+
+    // Errors get put down to whichever file was read last.
+    fn reading(&mut self, file: &Path) {
+        self.file = file.to_path_buf();
+    }
+
+    fn add(&mut self, mut error: ParseError) {
+        error.file = self.file.clone();
+        self.errors.push(error);
+    }
+
+    fn check<T>(&mut self, result: Result<T, ParseError>) -> Option<T> {
+        return match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.add(e);
+                None
+            }
+        };
+    }
+
+    // Lists everything and quits if there was anything to list.
+    fn finish(&self, keep_going: bool) {
+        if self.errors.is_empty() {
+            return;
+        }
+        let files: HashSet<&PathBuf> = self.errors.iter().map(|e| &e.file).collect();
+        println!();
+        println!("{} problems in {} files:", self.errors.len(), files.len());
+        for error in &self.errors {
+            println!("  {}", error);
+        }
+        if keep_going {
+            println!("packed everything else, but the output is missing those");
+        } else {
+            println!("nothing was written, use --keep-going to pack the rest anyway");
+        }
+        process::exit(1);
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value, ParseError> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(ParseError::new("", format!("can't open: {}", e))),
+    };
+    return match serde_json::from_reader(BufReader::new(file)) {
+        Ok(json) => Ok(json),
+        Err(e) => Err(ParseError::new("", format!("bad json: {}", e))),
+    };
+}
+
+fn read_sprite_file(path: &Path) -> Result<[u8; 578], ParseError> {
+    let mut buffer = [0u8; 578];
+    let result = File::open(path).and_then(|mut f| f.read_exact(&mut buffer));
+    return match result {
+        Ok(()) => Ok(buffer),
+        Err(e) => Err(ParseError::new("", format!("can't read sprite: {}", e))),
+    };
+}
+
+// Lists a directory, or gives up, since there's nothing to report on
+// without it.
+fn list_dir(dir: &Path) -> Vec<PathBuf> {
+    return match read_dir(dir) {
+        Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+        Err(e) => {
+            println!("can't read {}: {}", dir.display(), e);
+            process::exit(1);
+        }
+    };
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // Packs whatever did parse, leaving out the rest, rather than writing
+    // nothing at all.
+    let keep_going = args.iter().any(|a| a == "--keep-going");
+    args.retain(|a| a != "--keep-going");
 
+    if args.len() == 4 && args[1] == "fonts" {
+        pack_fonts(&args[2], &args[3], keep_going);
+        return;
+    }
     if args.len() != 3 {
-        println!("usage: {} <path-to-pokemon-dir> <output-file>", args[0]);
+        println!(
+            "usage: {} [--keep-going] <path-to-pokemon-dir> <output-file>",
+            args[0]
+        );
+        println!(
+            "       {} [--keep-going] fonts <path-to-font-dir> <output-dir>",
+            args[0]
+        );
         return;
     }
 
-    let mut files: Vec<PathBuf> = read_dir(&args[1])
-        .unwrap()
-        .filter(|f| {
-            f.as_ref()
-                .unwrap()
-                .path()
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or("")
-                == "json"
-        })
-        .map(|d| d.unwrap().path())
+    let mut report = Report::new();
+    let mut files: Vec<PathBuf> = list_dir(Path::new(&args[1]))
+        .into_iter()
+        .filter(|f| f.extension().and_then(|ext| ext.to_str()).unwrap_or("") == "json")
         .collect();
 
     files.sort_by_key(|a| {
@@ -42,40 +176,69 @@ fn main() {
             .and_then(|i| i.parse::<u32>().ok())
     });
 
+    // Items and evolutions live next to the pokemon directory. Evolutions
+    // refer to stones by name, so items have to be read first.
+    let items_filename = PathBuf::from(&args[1]).join("..").join("items.json");
+    println!("processing {:?}", items_filename);
+    report.reading(&items_filename);
+    let items_json = report
+        .check(read_json(&items_filename))
+        .unwrap_or(Value::Null);
+    let items = parse_items(&items_json, &mut report);
+
+    let evolutions_filename = PathBuf::from(&args[1]).join("..").join("evolutions.json");
+    println!("processing {:?}", evolutions_filename);
+    report.reading(&evolutions_filename);
+    let evolutions_json = report
+        .check(read_json(&evolutions_filename))
+        .unwrap_or(Value::Null);
+    let evolutions = parse_evolutions(&evolutions_json, &items_json, &mut report);
+    let mut chains: HashMap<u8, String> = HashMap::new();
+    // Species that were left out, going by their filenames.
+    let mut skipped: HashSet<u8> = HashSet::new();
+
     let mut table: Vec<(u32, u32)> = Vec::new();
     table.push((0xdeadbeef, 0xcafebabe));
+    let mut sprites: Vec<Option<Vec<u8>>> = Vec::new();
+    let mut bmp_bytes = 0;
     let data_buffer: Vec<u8> = Vec::new();
     let mut cursor = Cursor::new(data_buffer);
 
-    for path in files {
-        let json_filename = match path.extension().and_then(|ext| ext.to_str()) {
-            Some("json") => path,
-            _ => continue,
-        };
+    for json_filename in files {
         let bmp_filename = json_filename.with_extension("bmp");
 
         print!(
             "\rprocessing {:?} and {:?}     ",
             json_filename, bmp_filename
         );
-        let mut bmp_file = File::open(bmp_filename).unwrap();
-        let mut buffer = [0u8; 578];
-        bmp_file.read_exact(&mut buffer).unwrap();
-
-        let json_file = File::open(json_filename).unwrap();
-        let json: Value = serde_json::from_reader(BufReader::new(json_file)).unwrap();
-
-        let pokemon = parse_pokemon(&json, &buffer).unwrap();
-        let move_list = parse_movelist(&json["moves"]).unwrap();
-
+        // Species are found by their row, so one that didn't parse still
+        // needs a row, just with nothing in it.
         let initial_position = cursor.position();
-        minicbor::encode(pokemon, &mut cursor).unwrap();
-        let pokemon_size = cursor.position() - initial_position;
-        {
+        let mut pokemon_size = 0;
+        let pokemon = pack_pokemon(
+            &json_filename,
+            &bmp_filename,
+            &evolutions,
+            &mut sprites,
+            &mut report,
+        );
+        if let Some((pokemon, move_list, chain, bmp_size)) = pokemon {
+            bmp_bytes += bmp_size;
+            if let Some(url) = chain {
+                chains.insert(pokemon.id, url);
+            }
+            minicbor::encode(pokemon, &mut cursor).unwrap();
+            pokemon_size = cursor.position() - initial_position;
             let mut length_delimited_encoder = Writer::new(&mut cursor);
             for chunk in move_list {
                 length_delimited_encoder.write(chunk).unwrap();
             }
+        } else if let Some(id) = json_filename
+            .file_stem()
+            .and_then(|i| i.to_str())
+            .and_then(|i| i.parse().ok())
+        {
+            skipped.insert(id);
         }
         table.push((
             initial_position.try_into().unwrap(),
@@ -84,69 +247,221 @@ fn main() {
     }
     println!();
 
+    // The chain urls are all we have from the species data, but they're
+    // enough to catch an evolution between two unrelated pokemon. Anything
+    // involving a species that's already been reported gets let off.
+    report.reading(&evolutions_filename);
+    for (from, evolution) in &evolutions {
+        if skipped.contains(from) || skipped.contains(&evolution.species_id) {
+            continue;
+        }
+        if chains.get(from) != chains.get(&evolution.species_id) {
+            report.add(ParseError::new(
+                "",
+                format!(
+                    "{} and {} are not in the same evolution chain",
+                    from, evolution.species_id
+                ),
+            ));
+        }
+    }
+
+    // Moves are named by id with no extension, and go in by id so the
+    // firmware can find them without searching.
+    let moves_dir = PathBuf::from(&args[1]).join("..").join("moves");
+    println!("processing {:?}", moves_dir);
+    let mut moves: Vec<Option<Move>> = Vec::new();
+    for path in list_dir(&moves_dir) {
+        report.reading(&path);
+        let m = match report.check(read_json(&path).and_then(parse_move)) {
+            Some(m) => m,
+            None => continue,
+        };
+        let id = m.id as usize;
+        if id > u8::MAX as usize {
+            report.add(ParseError::new(
+                "id",
+                format!("move id {} is too big for the moves section", id),
+            ));
+            continue;
+        }
+        if moves.len() <= id {
+            moves.resize_with(id + 1, || None);
+        }
+        moves[id] = Some(m);
+    }
+
+    // Nothing gets written unless everything's there, or that's been asked
+    // for.
+    if !keep_going {
+        report.finish(false);
+    }
+
+    // Items, moves and extra sprites go in their own sections straight after
+    // the species.
+    let packed_bytes: usize = sprites.iter().flatten().map(|s| s.len()).sum();
+    println!(
+        "{} sprites packed from {} to {} bytes, saving {}",
+        sprites.len(),
+        bmp_bytes,
+        packed_bytes,
+        // Packing can lose to the bitmaps on tiny sprites.
+        bmp_bytes as i64 - packed_bytes as i64
+    );
+    let sprites: Vec<Option<&ByteSlice>> = sprites
+        .iter()
+        .map(|s| s.as_deref().map(Into::into))
+        .collect();
+    for section in [
+        encode_section(&items),
+        encode_section(&moves),
+        encode_section(&sprites),
+    ] {
+        let section_position = cursor.position();
+        cursor.write_all(&section).unwrap();
+        table.push((
+            section_position.try_into().unwrap(),
+            section.len().try_into().unwrap(),
+        ));
+    }
+
     let table_size: u32 = (table.len() * 8).try_into().unwrap();
     println!("All data parsed. Table size {} bytes", table_size);
 
-    let mut output_file = BufWriter::new(File::create(&args[2]).unwrap());
+    if let Err(e) = write_rom(&args[2], &table, table_size, &cursor.into_inner()) {
+        println!("failed to write {}: {}", args[2], e);
+        process::exit(1);
+    }
+
+    report.finish(true);
+    println!("Finished!");
+}
+
+fn write_rom(filename: &str, table: &[(u32, u32)], table_size: u32, data: &[u8]) -> io::Result<()> {
+    let mut output_file = BufWriter::new(File::create(filename)?);
     for (i, row) in table.iter().enumerate() {
         let (mut offset, size) = row;
         if i > 0 {
             offset += table_size;
         }
-        output_file.write(&offset.to_be_bytes()).unwrap();
-        output_file.write(&size.to_be_bytes()).unwrap();
+        output_file.write_all(&offset.to_be_bytes())?;
+        output_file.write_all(&size.to_be_bytes())?;
     }
-    output_file.write_all(&cursor.into_inner()).unwrap();
+    output_file.write_all(data)?;
+    return output_file.flush();
+}
 
-    println!("Finished!");
+// Reads one species and its sprites. Gives back the species, what it can
+// learn, its evolution chain and how many bytes of bmp went into it, or
+// nothing if any of that went wrong.
+fn pack_pokemon(
+    json_filename: &Path,
+    bmp_filename: &Path,
+    evolutions: &[(u8, Evolution)],
+    sprites: &mut Vec<Option<Vec<u8>>>,
+    report: &mut Report,
+) -> Option<(PokemonSpecies, Vec<MoveListChunk>, Option<String>, usize)> {
+    report.reading(json_filename);
+    let json = report.check(read_json(json_filename))?;
+    // Both halves get checked, so a file with a problem in each says so.
+    let pokemon = report.check(parse_pokemon(&json));
+    let move_list = report.check(parse_movelist(&json["moves"]).map_err(|e| e.under("moves")));
+    let (mut pokemon, move_list) = (pokemon?, move_list?);
+
+    report.reading(bmp_filename);
+    let front = report.check(read_sprite_file(bmp_filename))?;
+    let mut bmp_bytes = front.len();
+    pokemon.sprite = report.check(add_sprite(sprites, &front))?;
+
+    // Back sprites are optional, and sit next to the front one as
+    // <id>-back.bmp.
+    let back_filename = bmp_filename.with_file_name(format!("{}-back.bmp", pokemon.id));
+    if back_filename.exists() {
+        report.reading(&back_filename);
+        let back = report.check(read_sprite_file(&back_filename))?;
+        pokemon.back_sprite = Some(report.check(add_sprite(sprites, &back))?);
+        bmp_bytes += back.len();
+    }
+    for (i, evolution) in evolutions
+        .iter()
+        .filter(|(from, _)| *from == pokemon.id)
+        .enumerate()
+    {
+        pokemon.evolutions[i] = Some(evolution.1);
+    }
+    let chain = json["species"]["evolution_chain"]["url"]
+        .as_str()
+        .map(|url| url.to_string());
+    return Some((pokemon, move_list, chain, bmp_bytes));
 }
 
 fn parse_movelist(json: &Value) -> Result<Vec<MoveListChunk>, ParseError> {
+    let mut all_moves = Vec::new();
+    for (i, m) in json.as_array().unwrap_or(&Vec::new()).iter().enumerate() {
+        match parse_learnable_move(m) {
+            Ok(p) => all_moves.extend(p),
+            Err(e) => return Err(e.under(&format!("[{}]", i))),
+        };
+    }
+
+    // Level up moves go first, lowest level first, so generation can stop
+    // reading as soon as it passes the level it cares about.
+    all_moves.sort_by_key(|m| match m.condition {
+        LearnCondition::LevelUp(level) => (0, level),
+        LearnCondition::Machine => (1, 0),
+    });
+
     let mut all_chunks = Vec::new();
     let mut current_chunk: [Option<LearnableMove>; 16] = [None; 16];
     let mut current_chunk_i = 0;
-    for m in json.as_array().unwrap_or(&Vec::new()) {
-        let parsed = match parse_learnable_move(m) {
-            Ok(p) => p,
-            Err(e) => return Err(ParseError(format!("failed to parse move: {:?}", e))),
-        };
-        for i in parsed {
-            if current_chunk_i == 16 {
-                all_chunks.push(MoveListChunk {
-                    is_final_chunk: false,
-                    moves: current_chunk,
-                });
-                current_chunk = [None; 16];
-                current_chunk_i = 0;
-            }
-
-            current_chunk[current_chunk_i] = Some(i);
-            current_chunk_i += 1;
+    for i in all_moves {
+        if current_chunk_i == 16 {
+            all_chunks.push(MoveListChunk {
+                is_final_chunk: false,
+                moves: current_chunk,
+            });
+            current_chunk = [None; 16];
+            current_chunk_i = 0;
         }
+
+        current_chunk[current_chunk_i] = Some(i);
+        current_chunk_i += 1;
     }
 
     // Double check that we actually got results
     if current_chunk_i == 0 && all_chunks.len() == 0 {
-        return Err(ParseError("no moves in movelist".to_string()));
+        return Err(ParseError::new("", "no moves in movelist"));
     }
 
+    // Whatever's left over goes in one last chunk, so the reader knows when
+    // to stop.
+    all_chunks.push(MoveListChunk {
+        is_final_chunk: true,
+        moves: current_chunk,
+    });
+
     return Ok(all_chunks);
 }
 
 fn parse_learnable_move(json: &Value) -> Result<Vec<LearnableMove>, ParseError> {
     let parts: Vec<&str> = match json["move"]["url"].as_str() {
         Some(i) => i.split("/").collect(),
-        None => return Err(ParseError("missing move id".to_string())),
+        None => return Err(ParseError::new("move.url", "missing move id")),
     };
-    let id: u16 = match parts[parts.len() - 2].parse() {
-        Ok(i) => i,
-        Err(e) => return Err(ParseError(format!("failed to parse id: {}", e))),
+    // The id is the last part, before the trailing slash.
+    let id: u16 = match parts.iter().rev().nth(1).map(|p| p.parse()) {
+        Some(Ok(i)) => i,
+        Some(Err(e)) => return Err(ParseError::new("move.url", format!("bad id: {}", e))),
+        None => return Err(ParseError::new("move.url", "no id in the url")),
     };
     let mut moves = Vec::new();
-    for method in json["version_group_details"]
+    for (i, method) in json["version_group_details"]
         .as_array()
         .unwrap_or(&Vec::new())
+        .iter()
+        .enumerate()
     {
+        let path = format!("version_group_details[{}]", i);
         moves.push(LearnableMove {
             id,
             condition: match method["move_learn_method"].as_str() {
@@ -158,32 +473,506 @@ fn parse_learnable_move(json: &Value) -> Result<Vec<LearnableMove>, ParseError>
                     {
                         Some(l) => l,
                         None => {
-                            return Err(ParseError(
-                                "missing or malformed level up method".to_string(),
+                            return Err(ParseError::new(
+                                &path,
+                                "missing or malformed level up method",
                             ))
                         }
                     },
                 ),
                 Some("machine") => LearnCondition::Machine,
-                Some(m) => return Err(ParseError(format!("unknown learn method {}", m))),
-                None => return Err(ParseError("missing learn method".to_string())),
+                Some(m) => {
+                    return Err(ParseError::new(
+                        &path,
+                        format!("unknown learn method {}", m),
+                    ))
+                }
+                None => return Err(ParseError::new(&path, "missing learn method")),
             },
         })
     }
-    // TODO: sort moves by level before returning.
     return match moves.len() {
-        0 => Err(ParseError("move had no learn methods".to_string())),
+        0 => Err(ParseError::new(
+            "version_group_details",
+            "move had no learn methods",
+        )),
         _ => Ok(moves),
     };
 }
 
-fn parse_move(json: Value) -> Result<Move, &'static str> {
-    return Err("uh oh");
+// Packs a sprite and adds it to the sprites section, returning where it went.
+fn add_sprite(sprites: &mut Vec<Option<Vec<u8>>>, bmp: &[u8]) -> Result<u8, ParseError> {
+    let index = match u8::try_from(sprites.len()) {
+        Ok(i) => i,
+        Err(_) => {
+            return Err(ParseError::new(
+                "",
+                "too many sprites for the sprites section",
+            ))
+        }
+    };
+    sprites.push(Some(pack_sprite(bmp)?));
+    return Ok(index);
+}
+
+// Turns a BMP into what the firmware draws from: the width and height, then
+// one bit per pixel, rows top to bottom, run through PackBits.
+fn pack_sprite(bmp: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let image = match Bmp::from_slice(bmp) {
+        Ok(i) => i,
+        Err(e) => return Err(ParseError::new("", format!("bad sprite: {:?}", e))),
+    };
+    let (width, height) = match (u8::try_from(image.width()), u8::try_from(image.height())) {
+        (Ok(w), Ok(h)) => (w, h),
+        _ => return Err(ParseError::new("", "sprite too big")),
+    };
+    let row_bytes = (width as usize).div_ceil(8);
+    let mut bits = vec![0u8; row_bytes * height as usize];
+    for Pixel(point, color) in IntoPixelIter::<BinaryColor>::pixel_iter(&image) {
+        if color == BinaryColor::On {
+            let (x, y) = (point.x as usize, point.y as usize);
+            bits[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    let mut packed = vec![width, height];
+    packed.extend(packbits(&bits));
+    return Ok(packed);
+}
+
+// Runs of three or more of the same byte become a repeat, everything else
+// is copied as literals, up to 128 bytes at a time either way.
+fn packbits(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && data[i + run] == data[i] && run < 128 {
+            run += 1;
+        }
+        if run >= 3 {
+            flush_literals(&mut out, &mut literals);
+            out.push((257 - run) as u8);
+            out.push(data[i]);
+            i += run;
+        } else {
+            literals.push(data[i]);
+            if literals.len() == 128 {
+                flush_literals(&mut out, &mut literals);
+            }
+            i += 1;
+        }
+    }
+    flush_literals(&mut out, &mut literals);
+    return out;
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &mut Vec<u8>) {
+    if literals.is_empty() {
+        return;
+    }
+    out.push((literals.len() - 1) as u8);
+    out.extend(literals.drain(..));
 }
 
-fn parse_pokemon(json: &Value, bitmap: &[u8; 578]) -> Result<PokemonSpecies, &'static str> {
+// Fonts get built into the firmware rather than the ROM, so each sheet is
+// written out as its own raw 1bpp image for the font module to include.
+fn pack_fonts(font_dir: &str, output_dir: &str, keep_going: bool) {
+    let mut report = Report::new();
+    let mut fonts = Vec::new();
+    for path in list_dir(Path::new(font_dir)) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("bmp") {
+            continue;
+        }
+        println!("processing {:?}", path);
+        report.reading(&path);
+        let bmp = match fs::read(&path) {
+            Ok(b) => b,
+            Err(e) => {
+                report.add(ParseError::new("", format!("can't read: {}", e)));
+                continue;
+            }
+        };
+        if let Some(raw) = report.check(pack_font(&bmp)) {
+            fonts.push((path, raw));
+        }
+    }
+    if !keep_going {
+        report.finish(false);
+    }
+    for (path, raw) in fonts {
+        let output = PathBuf::from(output_dir)
+            .join(path.file_name().unwrap())
+            .with_extension("raw");
+        if let Err(e) = fs::write(&output, &raw) {
+            println!("failed to write {}: {}", output.display(), e);
+            process::exit(1);
+        }
+    }
+    report.finish(true);
+    println!("Finished!");
+}
+
+// Glyph size comes from the sheet size, since the layout is fixed.
+fn pack_font(bmp: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let image = match Bmp::from_slice(bmp) {
+        Ok(i) => i,
+        Err(e) => return Err(ParseError::new("", format!("bad font sheet: {:?}", e))),
+    };
+    let (width, height) = (image.width(), image.height());
+    if width % GLYPHS_PER_ROW != 0 || height % SHEET_ROWS != 0 {
+        return Err(ParseError::new(
+            "",
+            format!(
+                "sheet is {}x{}, which isn't {} by {} glyphs",
+                width, height, GLYPHS_PER_ROW, SHEET_ROWS
+            ),
+        ));
+    }
+    // embedded-graphics reads font images a whole byte at a time.
+    if width % 8 != 0 {
+        return Err(ParseError::new(
+            "",
+            format!("sheet width {} isn't a whole byte", width),
+        ));
+    }
+    println!("{}x{} glyphs", width / GLYPHS_PER_ROW, height / SHEET_ROWS);
+    let row_bytes = width as usize / 8;
+    let mut bits = vec![0u8; row_bytes * height as usize];
+    for Pixel(point, color) in IntoPixelIter::<BinaryColor>::pixel_iter(&image) {
+        if color == BinaryColor::On {
+            let (x, y) = (point.x as usize, point.y as usize);
+            bits[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    return Ok(bits);
+}
+
+// Sections start with their own lookup table, with offsets relative to the
+// start of the section. Missing entries get a row with no data.
+fn encode_section<T: minicbor::Encode>(entries: &[Option<T>]) -> Vec<u8> {
+    let mut rows = Vec::new();
+    let mut data = Vec::new();
+    let table_size = entries.len() * 8;
+    for entry in entries {
+        let offset = table_size + data.len();
+        if let Some(entry) = entry {
+            minicbor::encode(entry, &mut data).unwrap();
+        }
+        let size = table_size + data.len() - offset;
+        rows.extend_from_slice(&(offset as u32).to_be_bytes());
+        rows.extend_from_slice(&(size as u32).to_be_bytes());
+    }
+    rows.extend(data);
+    return rows;
+}
+
+// Bad evolutions get reported and left out.
+fn parse_evolutions(json: &Value, items_json: &Value, report: &mut Report) -> Vec<(u8, Evolution)> {
+    let mut evolutions: Vec<(u8, Evolution)> = Vec::new();
+    for (i, evolution) in json.as_array().unwrap_or(&Vec::new()).iter().enumerate() {
+        let parsed = parse_evolution(evolution, items_json).and_then(|(from, evolution)| {
+            if evolutions.iter().filter(|(f, _)| *f == from).count() == MAX_EVOLUTIONS {
+                return Err(ParseError::new(
+                    "",
+                    format!("too many evolutions for {}", from),
+                ));
+            }
+            return Ok((from, evolution));
+        });
+        if let Some(evolution) = report.check(parsed.map_err(|e| e.under(&format!("[{}]", i)))) {
+            evolutions.push(evolution);
+        }
+    }
+    return evolutions;
+}
+
+fn parse_evolution(evolution: &Value, items_json: &Value) -> Result<(u8, Evolution), ParseError> {
+    let from = match parse_as_u8(&evolution["from"]) {
+        Some(i) => i,
+        None => return Err(ParseError::new("from", "evolution missing from")),
+    };
+    let species_id = match parse_as_u8(&evolution["to"]) {
+        Some(i) => i,
+        None => {
+            return Err(ParseError::new(
+                "to",
+                format!("evolution of {} missing to", from),
+            ))
+        }
+    };
+    let trigger = match evolution["trigger"].as_str() {
+        Some("level-up") => match parse_as_u8(&evolution["level"]) {
+            Some(l) => EvolutionTrigger::Level(l),
+            None => {
+                return Err(ParseError::new(
+                    "level",
+                    format!("evolution of {} missing level", from),
+                ))
+            }
+        },
+        Some("use-item") => {
+            let name = evolution["item"].as_str().unwrap_or("");
+            let item = items_json
+                .as_array()
+                .unwrap_or(&Vec::new())
+                .iter()
+                .find(|i| i["name"].as_str() == Some(name))
+                .and_then(|i| parse_as_u8(&i["id"]));
+            match item {
+                Some(id) => EvolutionTrigger::Item(id),
+                None => {
+                    return Err(ParseError::new(
+                        "item",
+                        format!("unknown evolution item {}", name),
+                    ))
+                }
+            }
+        }
+        Some("trade") => EvolutionTrigger::Trade,
+        Some(t) => {
+            return Err(ParseError::new(
+                "trigger",
+                format!("unknown evolution trigger {}", t),
+            ))
+        }
+        None => {
+            return Err(ParseError::new(
+                "trigger",
+                format!("evolution of {} missing trigger", from),
+            ))
+        }
+    };
+    return Ok((
+        from,
+        Evolution {
+            species_id,
+            trigger,
+        },
+    ));
+}
+
+// Items are looked up by id, so a bad one leaves a gap rather than moving
+// everything after it along.
+fn parse_items(json: &Value, report: &mut Report) -> Vec<Option<Item>> {
+    let mut items = Vec::new();
+    for (i, item) in json.as_array().unwrap_or(&Vec::new()).iter().enumerate() {
+        let parsed = match parse_item(item) {
+            Ok(p) if p.id as usize == i => Ok(p),
+            Ok(p) => Err(ParseError::new("id", format!("item {} out of order", p.id))),
+            Err(e) => Err(e),
+        };
+        items.push(report.check(parsed.map_err(|e| e.under(&format!("[{}]", i)))));
+    }
+    return items;
+}
+
+fn parse_item(json: &Value) -> Result<Item, ParseError> {
     let name = match json["name"].as_str() {
-        Some(n) => {
+        Some(n) if n.len() <= 12 => {
+            let mut name = [0; 12];
+            for (i, c) in n.as_bytes().iter().enumerate() {
+                name[i] = match c {
+                    b'-' => b' ',
+                    c => c.to_ascii_uppercase(),
+                };
+            }
+            name
+        }
+        Some(_) => return Err(ParseError::new("name", "name too long")),
+        None => return Err(ParseError::new("name", "name was missing")),
+    };
+    let amount = json["amount"].as_u64();
+
+    let effect = match json["effect"].as_str() {
+        Some("heal") => match amount.map(|i| u16::try_from(i).ok()).flatten() {
+            Some(i) => ItemEffect::Heal(i),
+            None => return Err(ParseError::new("amount", "missing heal amount")),
+        },
+        Some("cure") => ItemEffect::CureStatus(match json["ailment"].as_str() {
+            Some("burn") => Some(AilmentType::Burn),
+            Some("freeze") => Some(AilmentType::Freeze),
+            Some("paralysis") => Some(AilmentType::Paralysis),
+            Some("poison") => Some(AilmentType::Poison),
+            Some("sleep") => Some(AilmentType::Sleep),
+            Some(_) => return Err(ParseError::new("ailment", "unknown ailment")),
+            None => None,
+        }),
+        Some("ball") => match parse_as_u8(&json["amount"]) {
+            Some(i) => ItemEffect::Ball(i),
+            None => return Err(ParseError::new("amount", "missing ball multiplier")),
+        },
+        Some("revive") => match parse_as_u8(&json["amount"]) {
+            Some(i) if i <= 100 => ItemEffect::Revive(i),
+            _ => {
+                return Err(ParseError::new(
+                    "amount",
+                    "missing or malformed revive percentage",
+                ))
+            }
+        },
+        Some("stone") => ItemEffect::EvolutionStone,
+        Some("tm") => match json["move"]
+            .as_u64()
+            .map(|i| u16::try_from(i).ok())
+            .flatten()
+        {
+            Some(i) => ItemEffect::TechnicalMachine(i),
+            None => return Err(ParseError::new("move", "missing tm move")),
+        },
+        Some("rare-candy") => ItemEffect::RareCandy,
+        Some(_) => return Err(ParseError::new("effect", "unknown item effect")),
+        None => return Err(ParseError::new("effect", "missing item effect")),
+    };
+
+    return Ok(Item {
+        id: match parse_as_u8(&json["id"]) {
+            Some(i) => i,
+            None => return Err(ParseError::new("id", "missing id")),
+        },
+        name,
+        effect,
+    });
+}
+
+fn parse_move(json: Value) -> Result<Move, ParseError> {
+    let meta = &json["meta"];
+    let mut stat_changes = [None, None];
+    for (i, change) in json["stat_changes"]
+        .as_array()
+        .unwrap_or(&Vec::new())
+        .iter()
+        .enumerate()
+    {
+        let path = format!("stat_changes[{}]", i);
+        if i >= stat_changes.len() {
+            return Err(ParseError::new(&path, "too many stat changes"));
+        }
+        stat_changes[i] = Some(StatChange {
+            amount: match parse_as_i8(&change["change"]) {
+                Some(i) => i,
+                None => return Err(ParseError::new(&path, "missing stat change amount")),
+            },
+            stat: match change["stat"]["name"].as_str() {
+                Some("hp") => Stats::Hp,
+                Some("attack") => Stats::Attack,
+                Some("defense") => Stats::Defense,
+                Some("special-attack") => Stats::SpecialAttack,
+                Some("special-defense") => Stats::SpecialDefense,
+                Some("speed") => Stats::Speed,
+                Some("accuracy") => Stats::Accuracy,
+                Some("evasion") => Stats::Evasion,
+                Some(_) => return Err(ParseError::new(&path, "unknown stat")),
+                None => return Err(ParseError::new(&path, "missing stat")),
+            },
+        });
+    }
+
+    let ailment = match meta["ailment"].as_str() {
+        Some("none") | Some("unknown") | None => None,
+        Some(a) => Some(AilmentParameter {
+            ailment: match a {
+                "burn" => AilmentType::Burn,
+                "confusion" => AilmentType::Confusion,
+                "disable" => AilmentType::Disable,
+                "freeze" => AilmentType::Freeze,
+                "leech-seed" => AilmentType::LeechSeed,
+                "paralysis" => AilmentType::Paralysis,
+                "poison" => AilmentType::Poison,
+                "sleep" => AilmentType::Sleep,
+                "trap" => AilmentType::Trap,
+                _ => return Err(ParseError::new("meta.ailment", "unknown ailment")),
+            },
+            chance: parse_as_u8(&meta["ailment_chance"]).unwrap_or(0),
+        }),
+    };
+
+    return Ok(Move {
+        id: match json["id"].as_u64().map(|i| u16::try_from(i).ok()).flatten() {
+            Some(i) => i,
+            None => return Err(ParseError::new("id", "missing id")),
+        },
+        name: match json["name"].as_str() {
+            Some(n) => {
+                // A few move names don't fit, so they get cut short.
+                let mut name = [0; 12];
+                for (i, c) in n.as_bytes().iter().take(12).enumerate() {
+                    name[i] = match c {
+                        b'-' => b' ',
+                        c => c.to_ascii_uppercase(),
+                    };
+                }
+                name
+            }
+            None => return Err(ParseError::new("name", "name was missing")),
+        },
+        type_: match json["type"].as_str().map(type_from_string) {
+            Some(Ok(t)) => t,
+            _ => return Err(ParseError::new("type", "missing or unknown type")),
+        },
+        damage_class: match json["damage_class"].as_str() {
+            Some("physical") => DamageClass::Physical,
+            Some("special") => DamageClass::Special,
+            Some("status") => DamageClass::Status,
+            _ => {
+                return Err(ParseError::new(
+                    "damage_class",
+                    "missing or unknown damage class",
+                ))
+            }
+        },
+        target: match json["target"].as_str() {
+            Some("all-opponents") => Target::AllOpponents,
+            Some("all-other-pokemon") => Target::AllOtherPokemon,
+            Some("entire-field") => Target::EntireField,
+            Some("random-opponent") => Target::RandomOpponent,
+            Some("selected-pokemon") => Target::SelectedPokemon,
+            Some("specific-move") => Target::SpecificMove,
+            Some("user") => Target::User,
+            Some("users-field") => Target::UserField,
+            _ => return Err(ParseError::new("target", "missing or unknown target")),
+        },
+        // Moves that never miss and moves that don't do damage have these as
+        // null, which we store as zero.
+        accuracy: parse_as_u8(&json["accuracy"]).unwrap_or(0),
+        power: parse_as_u8(&json["power"]).unwrap_or(0),
+        pp: match parse_as_u8(&json["pp"]) {
+            Some(i) => i,
+            None => return Err(ParseError::new("pp", "missing pp")),
+        },
+        priority: match parse_as_i8(&json["priority"]) {
+            Some(i) => i,
+            None => return Err(ParseError::new("priority", "missing priority")),
+        },
+        parameters: Parameters {
+            ailment,
+            crit_rate: parse_as_u8(&meta["crit_rate"]).unwrap_or(0),
+            drain: parse_as_i8(&meta["drain"]).unwrap_or(0),
+            flinch_chance: parse_as_u8(&meta["flinch_chance"]).unwrap_or(0),
+            healing: parse_as_u8(&meta["healing"]).unwrap_or(0),
+            stat_chance: parse_as_u8(&meta["stat_chance"]).unwrap_or(0),
+            turn_range: parse_range(&meta["min_turns"], &meta["max_turns"]),
+            hit_range: parse_range(&meta["min_hits"], &meta["max_hits"]),
+        },
+        stat_changes,
+    });
+}
+
+fn parse_range(min: &Value, max: &Value) -> Option<ParameterRange> {
+    return match (parse_as_u8(min), parse_as_u8(max)) {
+        (Some(min), Some(max)) => Some(ParameterRange { min, max }),
+        _ => None,
+    };
+}
+
+fn parse_as_i8(json: &Value) -> Option<i8> {
+    return json.as_i64().and_then(|i| i8::try_from(i).ok());
+}
+
+// The sprite gets filled in afterwards, once it's been packed.
+fn parse_pokemon(json: &Value) -> Result<PokemonSpecies, ParseError> {
+    let name = match json["name"].as_str() {
+        Some(n) if n.len() <= 12 => {
             let mut name = [08; 12];
             let tmp = n.as_bytes();
             for (i, c) in tmp.iter().enumerate() {
@@ -191,65 +980,110 @@ fn parse_pokemon(json: &Value, bitmap: &[u8; 578]) -> Result<PokemonSpecies, &'s
             }
             name
         }
-        None => return Err("name was missing"),
+        Some(_) => return Err(ParseError::new("name", "name too long")),
+        None => return Err(ParseError::new("name", "name was missing")),
     };
 
     let mut types = [Option::None, Option::None];
     match json["types"].as_array() {
         Some(raw_types) => {
-            for t in raw_types {
+            for (i, t) in raw_types.iter().enumerate() {
+                let path = format!("types[{}]", i);
                 let slot = match t["slot"].as_i64() {
                     Some(1) => 0,
                     Some(2) => 1,
-                    None => return Err("missing type slot"),
-                    _ => return Err("invalid type slot"),
+                    None => return Err(ParseError::new(&path, "missing type slot")),
+                    _ => return Err(ParseError::new(&path, "invalid type slot")),
                 };
                 types[slot] = match t["type"].as_str() {
                     Some(val) => match type_from_string(val) {
                         Ok(t) => Some(t),
-                        Err(_) => return Err("failed to parse type"),
+                        Err(_) => return Err(ParseError::new(&path, "failed to parse type")),
                     },
                     None => None,
                 }
             }
         }
-        None => return Err("no types found"),
+        None => return Err(ParseError::new("types", "no types found")),
     }
 
-    let stats: HashMap<_, _> = match json["stats"].as_array() {
-        Some(all_stats) => all_stats
-            .iter()
-            .flat_map(|j| parse_as_stat_data(j))
-            .collect(),
-        None => return Err("missing stats"),
+    let mut stats: HashMap<_, _> = HashMap::new();
+    match json["stats"].as_array() {
+        Some(all_stats) => {
+            for (i, j) in all_stats.iter().enumerate() {
+                match parse_as_stat_data(j) {
+                    Some((name, data)) => stats.insert(name, data),
+                    None => {
+                        return Err(ParseError::new(&format!("stats[{}]", i), "malformed stat"))
+                    }
+                };
+            }
+        }
+        None => return Err(ParseError::new("stats", "missing stats")),
+    };
+    let stat = |name: &str| match stats.get(name) {
+        Some(s) => Ok(*s),
+        None => Err(ParseError::new("stats", format!("missing {} stat", name))),
     };
 
     Ok(pokedex::PokemonSpecies {
         id: match parse_as_u8(&json["id"]) {
             Some(i) => i,
-            None => return Err("missing id"),
+            None => return Err(ParseError::new("id", "missing id")),
         },
         name,
         type_primary: match types[0] {
             Some(t) => t,
-            None => return Err("no primary type given"),
+            None => return Err(ParseError::new("types", "no primary type given")),
         },
         type_secondary: types[1],
+        growth_rate: match json["species"]["growth_rate"]["name"].as_str() {
+            Some("erratic") => GrowthRate::ERRATIC,
+            Some("fast") => GrowthRate::FAST,
+            Some("medium") => GrowthRate::MEDIUM_FAST,
+            Some("medium-slow") => GrowthRate::MEDIUM_SLOW,
+            Some("slow") => GrowthRate::SLOW,
+            Some("fluctuating") => GrowthRate::FLUCTUATING,
+            Some(_) => {
+                return Err(ParseError::new(
+                    "species.growth_rate.name",
+                    "unknown growth rate",
+                ))
+            }
+            None => {
+                return Err(ParseError::new(
+                    "species.growth_rate.name",
+                    "missing growth rate",
+                ))
+            }
+        },
         capture_rate: match parse_as_u8(&json["species"]["capture_rate"]) {
             Some(i) => i,
-            None => return Err("missing capture rate"),
+            None => {
+                return Err(ParseError::new(
+                    "species.capture_rate",
+                    "missing capture rate",
+                ))
+            }
         },
-        base_experience: match parse_as_u8(&json["id"]) {
+        base_experience: match parse_as_u16(&json["base_experience"]) {
             Some(i) => i,
-            None => return Err("missing base experience"),
+            None => {
+                return Err(ParseError::new(
+                    "base_experience",
+                    "missing base experience",
+                ))
+            }
         },
-        hp: stats["hp"],
-        attack: stats["attack"],
-        defense: stats["defense"],
-        special_attack: stats["special-attack"],
-        special_defense: stats["special-defense"],
-        speed: stats["speed"],
-        sprite: ByteArray::from(*bitmap),
+        hp: stat("hp")?,
+        attack: stat("attack")?,
+        defense: stat("defense")?,
+        special_attack: stat("special-attack")?,
+        special_defense: stat("special-defense")?,
+        speed: stat("speed")?,
+        sprite: 0,
+        evolutions: [None; MAX_EVOLUTIONS],
+        back_sprite: None,
     })
 }
 
@@ -263,6 +1097,10 @@ fn parse_as_u8(json: &Value) -> Option<u8> {
     };
 }
 
+fn parse_as_u16(json: &Value) -> Option<u16> {
+    return json.as_u64().and_then(|i| u16::try_from(i).ok());
+}
+
 fn parse_as_stat_data(json: &Value) -> Option<(&str, SpeciesStatData)> {
     Some((
         match json["stat"].as_str() {
@@ -311,3 +1149,75 @@ fn type_from_string(s: &str) -> Result<Type, &str> {
         _ => Err("unknown type"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pokemon::sprite::PackedSprite;
+
+    const WIDTH: u8 = 128;
+    const ROW_BYTES: usize = WIDTH as usize / 8;
+
+    // Goes back through the firmware's decoder, a pixel at a time.
+    fn unpack(bits: &[u8]) -> Vec<u8> {
+        let height = (bits.len() / ROW_BYTES) as u8;
+        let mut packed = vec![WIDTH, height];
+        packed.extend(packbits(bits));
+        let sprite = PackedSprite::from_slice(&packed).unwrap();
+        let mut unpacked = vec![0u8; bits.len()];
+        let mut pixels = 0;
+        for Pixel(point, color) in sprite.pixel_iter() {
+            let (x, y) = (point.x as usize, point.y as usize);
+            if color == BinaryColor::On {
+                unpacked[y * ROW_BYTES + x / 8] |= 0x80 >> (x % 8);
+            }
+            pixels += 1;
+        }
+        assert_eq!(pixels, bits.len() * 8);
+        return unpacked;
+    }
+
+    fn runs(runs: &[(u8, usize)]) -> Vec<u8> {
+        return runs
+            .iter()
+            .flat_map(|&(byte, count)| std::iter::repeat_n(byte, count))
+            .collect();
+    }
+
+    // Never the same byte twice in a row.
+    fn noise(len: usize) -> Vec<u8> {
+        return (0..len).map(|i| (i * 7 + i / 3) as u8).collect();
+    }
+
+    #[test]
+    fn runs_hit_the_128_byte_limit() {
+        assert_eq!(packbits(&runs(&[(0xaa, 128)])), vec![129, 0xaa]);
+        // One over starts a second run, which is too short to be a repeat.
+        assert_eq!(packbits(&runs(&[(0xaa, 129)])), vec![129, 0xaa, 0, 0xaa]);
+        assert_eq!(packbits(&runs(&[(0xaa, 131)])), vec![129, 0xaa, 254, 0xaa]);
+        for count in [2, 3, 127, 128, 129, 130, 131, 256, 320] {
+            let mut bits = runs(&[(0xff, count), (0x00, 3), (0x81, 2)]);
+            bits.resize(bits.len().div_ceil(ROW_BYTES) * ROW_BYTES, 0x3c);
+            assert_eq!(unpack(&bits), bits, "run of {}", count);
+        }
+    }
+
+    #[test]
+    fn literals_hit_the_128_byte_limit() {
+        assert_eq!(packbits(&noise(128)).len(), 129);
+        assert_eq!(packbits(&noise(129)).len(), 131);
+        for len in [16, 128, 144, 256, 272] {
+            let bits = noise(len);
+            assert_eq!(unpack(&bits), bits, "{} literals", len);
+        }
+    }
+
+    #[test]
+    fn mixed_runs_and_literals_come_back() {
+        let mut bits = noise(130);
+        bits.extend(runs(&[(0x00, 140), (0x01, 1), (0x02, 2), (0x03, 3)]));
+        bits.extend(noise(50));
+        bits.resize(bits.len().div_ceil(ROW_BYTES) * ROW_BYTES, 0xff);
+        assert_eq!(unpack(&bits), bits);
+    }
+}
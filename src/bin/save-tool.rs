@@ -0,0 +1,631 @@
+use minicbor::Decode;
+use pokemon::gym::BADGE_COUNT;
+use pokemon::link::{write_frame, FrameReader, Link, LinkError, READ_SAVE, SAVE_CHUNK, SAVE_DATA};
+use pokemon::pokedex::{name_str, Pokemon, StatData, SPECIES_COUNT};
+use pokemon::rom::{ReadError, Storage, WriteError};
+use pokemon::save::{
+    Badge, BagSlot, PendingTrade, PokemonBox, Save, SaveData, Settings, BAG_SIZE, BOX_SIZE,
+    PARTY_SIZE, SAVE_OFFSET, SAVE_SIZE,
+};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::{env, process, thread};
+
+#[derive(Debug)]
+struct ParseError(String);
+
+// Just the save region, which is what the simulator keeps in its save file
+// and what a flash dump of SAVE_OFFSET..SAVE_OFFSET + SAVE_SIZE holds.
+struct Image(Vec<u8>);
+
+impl Image {
+    fn range(&self, offset: u32, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = offset.checked_sub(SAVE_OFFSET)? as usize;
+        if start + len > self.0.len() {
+            return None;
+        }
+        return Some(start..start + len);
+    }
+}
+
+impl Storage for Image {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+        match self.range(offset, buf.len()) {
+            Some(range) => buf.copy_from_slice(&self.0[range]),
+            None => return Err(ReadError("read outside the save region")),
+        }
+        return Ok(());
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError> {
+        let start = offset / 0x1000 * 0x1000;
+        match self.range(start, 0x1000) {
+            Some(range) => self.0[range].fill(0xff),
+            None => return Err(WriteError("erase outside the save region")),
+        }
+        return Ok(());
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError> {
+        match self.range(offset, data.len()) {
+            Some(range) => self.0[range].copy_from_slice(data),
+            None => return Err(WriteError("write outside the save region")),
+        }
+        return Ok(());
+    }
+}
+
+// The board's end of the link cable, as a terminal device. It needs setting
+// up first, something like `stty -F /dev/ttyUSB0 115200 raw`.
+struct Port {
+    writer: fs::File,
+    incoming: Receiver<u8>,
+}
+
+impl Port {
+    fn open(path: &str) -> Result<Port, String> {
+        let opened = |writing: bool| {
+            let file = OpenOptions::new().read(!writing).write(writing).open(path);
+            return file.map_err(|e| format!("couldn't open {}: {}", path, e));
+        };
+        let mut reader = opened(false)?;
+        let writer = opened(true)?;
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 || buf[..n].iter().any(|b| sender.send(*b).is_err()) {
+                    return;
+                }
+            }
+        });
+        return Ok(Port { writer, incoming });
+    }
+
+    // Waits for the reply to a read, which might take a tick or two. None if
+    // it never comes.
+    fn reply(&mut self, offset: u32, reader: &mut FrameReader) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + Duration::from_millis(500);
+        loop {
+            let left = deadline.checked_duration_since(Instant::now())?;
+            let byte = match self.incoming.recv_timeout(left) {
+                Ok(byte) => byte,
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            };
+            if let Ok(Some(frame)) = reader.push(byte) {
+                let payload = frame.payload();
+                if frame.kind == SAVE_DATA
+                    && payload.len() >= 4
+                    && payload[..4] == offset.to_be_bytes()
+                {
+                    return Some(payload[4..].to_vec());
+                }
+            }
+        }
+    }
+}
+
+impl Link for Port {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, LinkError> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.incoming.try_recv() {
+                Ok(byte) => buf[n] = byte,
+                Err(_) => break,
+            }
+            n += 1;
+        }
+        return Ok(n);
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), LinkError> {
+        match self.writer.write_all(data) {
+            Ok(_) => return Ok(()),
+            Err(_) => return Err(LinkError("couldn't write to the port")),
+        }
+    }
+}
+
+// The board only answers from the main menu, so give it a few goes before
+// deciding nobody's there.
+fn pull(port_path: &str) -> Result<Vec<u8>, String> {
+    let mut port = Port::open(port_path)?;
+    let mut reader = FrameReader::new();
+    let mut image = Vec::with_capacity(SAVE_SIZE as usize);
+    while image.len() < SAVE_SIZE as usize {
+        let offset = image.len() as u32;
+        let len = SAVE_CHUNK.min(SAVE_SIZE as usize - image.len());
+        let mut request = [0u8; 5];
+        request[..4].copy_from_slice(&offset.to_be_bytes());
+        request[4] = len as u8;
+        let mut data = None;
+        for _ in 0..5 {
+            write_frame(&mut port, READ_SAVE, &request).map_err(|e| e.0.to_string())?;
+            data = port.reply(offset, &mut reader);
+            if data.is_some() {
+                break;
+            }
+        }
+        match data {
+            Some(data) if data.len() == len => image.extend_from_slice(&data),
+            Some(_) => return Err(format!("short read at {:#x}", offset)),
+            None => {
+                return Err(format!(
+                    "no answer at {:#x}, is it on the main menu?",
+                    offset
+                ))
+            }
+        }
+        print!("\rpulled {}/{} bytes", image.len(), SAVE_SIZE);
+        std::io::stdout().flush().ok();
+    }
+    println!();
+    return Ok(image);
+}
+
+// Enums go by their variant names in lower case, which for the types is
+// also how the pokedex json spells them. They're stored as a bare index,
+// so going back means trying each one until the name matches.
+fn enum_name<T: Debug>(value: &T) -> String {
+    return format!("{:?}", value).to_lowercase();
+}
+
+fn enum_from_name<T: Debug + for<'b> Decode<'b>>(json: &Value) -> Result<T, ParseError> {
+    let name = json
+        .as_str()
+        .ok_or_else(|| ParseError(format!("expected a name, got {}", json)))?;
+    for i in 0u8..24 {
+        match minicbor::decode::<T>(&[i]) {
+            Ok(value) if enum_name(&value) == name => return Ok(value),
+            _ => continue,
+        }
+    }
+    return Err(ParseError(format!("unknown name {}", name)));
+}
+
+fn name_json(name: &Option<[u8; 12]>) -> Value {
+    return name.map_or(Value::Null, |n| json!(name_str(&n)));
+}
+
+fn name_from_json(json: &Value) -> Result<Option<[u8; 12]>, ParseError> {
+    let text = match json {
+        Value::Null => return Ok(None),
+        Value::String(text) => text,
+        _ => return Err(ParseError(format!("expected a name, got {}", json))),
+    };
+    if text.len() > 12 || !text.bytes().all(|c| c.is_ascii_graphic() || c == b' ') {
+        return Err(ParseError(format!(
+            "{:?} won't fit in 12 plain letters",
+            text
+        )));
+    }
+    let mut name = [0u8; 12];
+    name[..text.len()].copy_from_slice(text.as_bytes());
+    return Ok(Some(name));
+}
+
+fn number<T: TryFrom<u64>>(json: &Value, field: &str) -> Result<T, ParseError> {
+    return json[field]
+        .as_u64()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| ParseError(format!("{} is missing or out of range", field)));
+}
+
+fn flag(json: &Value, field: &str) -> Result<bool, ParseError> {
+    return json[field]
+        .as_bool()
+        .ok_or_else(|| ParseError(format!("{} should be true or false", field)));
+}
+
+// Fixed size lists come out as arrays with a null for each empty slot, and
+// have to go back in the same size.
+fn slots<T, const N: usize>(
+    json: &Value,
+    field: &str,
+    parse: impl Fn(&Value) -> Result<T, ParseError>,
+) -> Result<[Option<T>; N], ParseError> {
+    let list = match json[field].as_array() {
+        Some(list) if list.len() == N => list,
+        _ => return Err(ParseError(format!("{} should be a list of {}", field, N))),
+    };
+    let mut out: [Option<T>; N] = [(); N].map(|_| None);
+    for (i, item) in list.iter().enumerate() {
+        if !item.is_null() {
+            let parsed =
+                parse(item).map_err(|e| ParseError(format!("{} {}: {}", field, i, e.0)))?;
+            out[i] = Some(parsed);
+        }
+    }
+    return Ok(out);
+}
+
+fn stat_json(stat: &StatData) -> Value {
+    return json!({
+        "value": stat.value,
+        "effort_value": stat.effort_value,
+        "individual_value": stat.individual_value,
+    });
+}
+
+fn stat_from_json(json: &Value, field: &str) -> Result<StatData, ParseError> {
+    let stat = &json[field];
+    let context = |e: ParseError| ParseError(format!("{} {}", field, e.0));
+    return Ok(StatData {
+        value: number(stat, "value").map_err(context)?,
+        effort_value: number(stat, "effort_value").map_err(context)?,
+        individual_value: number(stat, "individual_value").map_err(context)?,
+    });
+}
+
+fn pokemon_json(p: &Pokemon) -> Value {
+    return json!({
+        "species_id": p.species_id,
+        "nickname": name_json(&p.nickname),
+        "level": p.level,
+        "xp": p.xp,
+        "current_hp": p.current_hp,
+        "hp": stat_json(&p.hp),
+        "attack": stat_json(&p.attack),
+        "defense": stat_json(&p.defense),
+        "special_attack": stat_json(&p.special_attack),
+        "special_defense": stat_json(&p.special_defense),
+        "speed": stat_json(&p.speed),
+        "moves": p.moves,
+        "status": p.status.map_or(Value::Null, |s| json!(enum_name(&s))),
+        "shiny": p.shiny,
+    });
+}
+
+fn pokemon_from_json(json: &Value) -> Result<Pokemon, ParseError> {
+    let species_id = number(json, "species_id")?;
+    if !(1..=SPECIES_COUNT).contains(&species_id) {
+        return Err(ParseError(format!("no species {}", species_id)));
+    }
+    let moves = slots(json, "moves", |m| {
+        m.as_u64()
+            .and_then(|m| u16::try_from(m).ok())
+            .ok_or_else(|| ParseError(format!("bad move {}", m)))
+    })?;
+    let status = match &json["status"] {
+        Value::Null => None,
+        status => Some(enum_from_name(status)?),
+    };
+    let shiny = match &json["shiny"] {
+        Value::Null => None,
+        _ => Some(flag(json, "shiny")?),
+    };
+    return Ok(Pokemon {
+        species_id,
+        nickname: name_from_json(&json["nickname"])?,
+        level: number(json, "level")?,
+        xp: number(json, "xp")?,
+        current_hp: number(json, "current_hp")?,
+        hp: stat_from_json(json, "hp")?,
+        attack: stat_from_json(json, "attack")?,
+        defense: stat_from_json(json, "defense")?,
+        special_attack: stat_from_json(json, "special_attack")?,
+        special_defense: stat_from_json(json, "special_defense")?,
+        speed: stat_from_json(json, "speed")?,
+        moves,
+        status,
+        shiny,
+    });
+}
+
+fn settings_json(s: &Settings) -> Value {
+    return json!({
+        "text_speed": enum_name(&s.text_speed),
+        "contrast": s.contrast,
+        "inverted": s.inverted,
+        "led_brightness": s.led_brightness,
+        "battle_animations": s.battle_animations,
+        "auto_sleep_minutes": s.auto_sleep_minutes,
+    });
+}
+
+fn settings_from_json(json: &Value) -> Result<Settings, ParseError> {
+    return Ok(Settings {
+        text_speed: enum_from_name(&json["text_speed"])?,
+        contrast: number(json, "contrast")?,
+        inverted: flag(json, "inverted")?,
+        led_brightness: number(json, "led_brightness")?,
+        battle_animations: flag(json, "battle_animations")?,
+        auto_sleep_minutes: number(json, "auto_sleep_minutes")?,
+    });
+}
+
+fn to_json(save: &Save) -> Value {
+    let data = &save.data;
+    let species = 1..=SPECIES_COUNT;
+    let badges: Vec<Value> = data
+        .badges
+        .iter()
+        .map(|b| {
+            b.map_or(
+                Value::Null,
+                |b| json!({ "theme": enum_name(&b.theme), "gym_id": b.gym_id }),
+            )
+        })
+        .collect();
+    let bag: Vec<Value> = data
+        .bag
+        .iter()
+        .map(|s| {
+            s.map_or(
+                Value::Null,
+                |s| json!({ "item_id": s.item_id, "quantity": s.quantity }),
+            )
+        })
+        .collect();
+    let pending_trade = data.pending_trade.as_ref().map_or(Value::Null, |t| {
+        json!({
+            "id": t.id,
+            "slot": t.slot,
+            "incoming": pokemon_json(&t.incoming),
+            "partner": name_str(&t.partner),
+        })
+    });
+    return json!({
+        // Only there to look at. Encoding works these out again.
+        "slot": save.slot(),
+        "sequence": save.sequence(),
+        "settings": settings_json(&data.settings),
+        "player_name": name_json(&data.player_name),
+        "party": data.party.iter().map(|p| p.as_ref().map_or(Value::Null, pokemon_json)).collect::<Vec<_>>(),
+        "dex": {
+            "seen": species.clone().filter(|id| data.dex.has_seen(*id)).collect::<Vec<_>>(),
+            "caught": species.filter(|id| data.dex.has_caught(*id)).collect::<Vec<_>>(),
+        },
+        "defeated_trainers": data.defeated_trainers.iter().collect::<Vec<_>>(),
+        "badges": badges,
+        "bag": bag,
+        "clock": data.clock,
+        "pending_trade": pending_trade,
+        "finished_trades": data.finished_trades.iter().flat_map(|f| f.iter()).map(|t| {
+            json!({ "id": t.id, "partner": name_str(&t.partner) })
+        }).collect::<Vec<_>>(),
+        // Null on saves from before there was a box, which is kept that way
+        // so an image comes back out the same.
+        "box": data.pokemon_box.as_ref().map_or(Value::Null, |b| {
+            b.pokemon.iter().map(|p| p.as_ref().map_or(Value::Null, pokemon_json)).collect()
+        }),
+    });
+}
+
+fn from_json(json: &Value) -> Result<SaveData, ParseError> {
+    let mut data = SaveData::new();
+    data.settings = settings_from_json(&json["settings"])?;
+    data.player_name = name_from_json(&json["player_name"])?;
+    data.party = slots::<_, PARTY_SIZE>(json, "party", pokemon_from_json)?;
+    // Anything caught gets marked seen as well, same as in the game.
+    for (field, caught) in [("seen", false), ("caught", true)] {
+        for id in json["dex"][field].as_array().unwrap_or(&Vec::new()) {
+            let species = id
+                .as_u64()
+                .and_then(|id| u8::try_from(id).ok())
+                .filter(|id| (1..=SPECIES_COUNT).contains(id));
+            match species {
+                Some(id) if caught => data.dex.mark_caught(id),
+                Some(id) => data.dex.mark_seen(id),
+                None => return Err(ParseError(format!("dex {}: no species {}", field, id))),
+            }
+        }
+    }
+    for id in json["defeated_trainers"].as_array().unwrap_or(&Vec::new()) {
+        match id.as_u64().and_then(|id| u32::try_from(id).ok()) {
+            Some(id) => data.defeated_trainers.record(id),
+            None => return Err(ParseError(format!("bad trainer id {}", id))),
+        }
+    }
+    data.badges = slots::<_, BADGE_COUNT>(json, "badges", |b| {
+        Ok(Badge {
+            theme: enum_from_name(&b["theme"])?,
+            gym_id: number(b, "gym_id")?,
+        })
+    })?;
+    data.bag = slots::<_, BAG_SIZE>(json, "bag", |s| {
+        Ok(BagSlot {
+            item_id: number(s, "item_id")?,
+            quantity: number(s, "quantity")?,
+        })
+    })?;
+    data.clock = match &json["clock"] {
+        Value::Null => None,
+        _ => Some(number(json, "clock")?),
+    };
+    data.pending_trade = match &json["pending_trade"] {
+        Value::Null => None,
+        trade => Some(PendingTrade {
+            id: number(trade, "id")?,
+            slot: number(trade, "slot")?,
+            incoming: pokemon_from_json(&trade["incoming"])?,
+            partner: name_from_json(&trade["partner"])?.unwrap_or([0; 12]),
+        }),
+    };
+    for trade in json["finished_trades"].as_array().unwrap_or(&Vec::new()) {
+        let id = number(trade, "id")?;
+        let partner = name_from_json(&trade["partner"])?.unwrap_or([0; 12]);
+        if !data.finished_trades().record(id, &partner) {
+            return Err(ParseError("too many finished trades".to_string()));
+        }
+    }
+    data.pokemon_box = match &json["box"] {
+        Value::Null => None,
+        _ => Some(PokemonBox {
+            pokemon: slots::<_, BOX_SIZE>(json, "box", pokemon_from_json)?,
+        }),
+    };
+    return Ok(data);
+}
+
+fn fail(message: String) -> ! {
+    println!("{}", message);
+    process::exit(1);
+}
+
+fn read(path: &str) -> Vec<u8> {
+    return fs::read(path).unwrap_or_else(|e| fail(format!("couldn't read {}: {}", path, e)));
+}
+
+fn write(path: &str, data: impl AsRef<[u8]>) {
+    if let Err(e) = fs::write(path, data) {
+        fail(format!("couldn't write {}: {}", path, e));
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 4 {
+        println!("usage: {} pull <serial-port> <image-file>", args[0]);
+        println!("       {} decode <image-file> <json-file>", args[0]);
+        println!("       {} encode <json-file> <image-file>", args[0]);
+        println!("an image is the save region, the same as the simulator's save file");
+        return;
+    }
+
+    match args[1].as_str() {
+        "pull" => {
+            let image = pull(&args[2]).unwrap_or_else(|e| fail(e));
+            write(&args[3], image);
+        }
+        "decode" => {
+            let image = read(&args[2]);
+            if image.len() != SAVE_SIZE as usize {
+                fail(format!("expected {} bytes, got {}", SAVE_SIZE, image.len()));
+            }
+            let save = Save::load(&mut Image(image));
+            if save.sequence() == 0 {
+                fail("neither slot holds a valid save".to_string());
+            }
+            let text = serde_json::to_string_pretty(&to_json(&save)).unwrap();
+            write(&args[3], text + "\n");
+            println!("slot {}, sequence {}", save.slot(), save.sequence());
+        }
+        "encode" => {
+            let json: Value = serde_json::from_slice(&read(&args[2]))
+                .unwrap_or_else(|e| fail(format!("bad json: {}", e)));
+            let data = from_json(&json).unwrap_or_else(|e| fail(e.0));
+            // Editing an existing image keeps the save it came from in the
+            // other slot, same as the board would. Otherwise it's a fresh one.
+            let mut image = Image(
+                fs::read(&args[3])
+                    .ok()
+                    .filter(|i| i.len() == SAVE_SIZE as usize)
+                    .unwrap_or_else(|| vec![0xff; SAVE_SIZE as usize]),
+            );
+            let mut save = Save::load(&mut image);
+            save.data = data;
+            save.commit(&mut image)
+                .unwrap_or_else(|e| fail(e.0.to_string()));
+            write(&args[3], &image.0);
+            println!("slot {}, sequence {}", save.slot(), save.sequence());
+        }
+        other => fail(format!("unknown command {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pokemon::pokedex::{AilmentType, Type};
+    use pokemon::save::TextSpeed;
+
+    fn stat(value: u16, effort_value: u16, individual_value: u8) -> StatData {
+        return StatData {
+            value,
+            effort_value,
+            individual_value,
+        };
+    }
+
+    fn pokemon(species_id: u8, nickname: Option<&[u8]>) -> Pokemon {
+        return Pokemon {
+            species_id,
+            nickname: nickname.map(|n| {
+                let mut name = [0u8; 12];
+                name[..n.len()].copy_from_slice(n);
+                name
+            }),
+            level: 23,
+            xp: 12167,
+            current_hp: 41,
+            hp: stat(58, 300, 12),
+            attack: stat(40, 1200, 3),
+            defense: stat(35, 0, 15),
+            special_attack: stat(51, 65535, 0),
+            special_defense: stat(44, 7, 9),
+            speed: stat(60, 880, 11),
+            moves: [Some(33), Some(45), None, Some(164)],
+            status: Some(AilmentType::Burn),
+            shiny: Some(true),
+        };
+    }
+
+    // Something in every field, so nothing can get dropped on the way
+    // through without the images coming out different.
+    fn everything() -> SaveData {
+        let mut data = SaveData::new();
+        data.settings.text_speed = TextSpeed::Fast;
+        data.settings.contrast = 0x3f;
+        data.settings.inverted = true;
+        data.settings.auto_sleep_minutes = 5;
+        data.player_name = Some(*b"RED\0\0\0\0\0\0\0\0\0");
+        data.party[0] = Some(pokemon(25, Some(b"SPARKY")));
+        data.party[3] = Some(pokemon(7, None));
+        data.dex.mark_seen(16);
+        data.dex.mark_caught(25);
+        data.dex.mark_caught(7);
+        data.defeated_trainers.record(0xdeadbeef);
+        data.badges[1] = Some(Badge {
+            theme: Type::Water,
+            gym_id: 77,
+        });
+        data.bag[0] = Some(BagSlot {
+            item_id: 4,
+            quantity: 12,
+        });
+        data.clock = Some(123456);
+        data.pending_trade = Some(PendingTrade {
+            id: 99,
+            slot: 3,
+            incoming: pokemon(150, Some(b"MEWTWO")),
+            partner: *b"BLUE\0\0\0\0\0\0\0\0",
+        });
+        data.finished_trades().record(42, b"GARY\0\0\0\0\0\0\0\0");
+        data.pokemon_box().add(pokemon(16, Some(b"PIDGE"))).unwrap();
+        return data;
+    }
+
+    fn encode(data: SaveData) -> Image {
+        let mut image = Image(vec![0xff; SAVE_SIZE as usize]);
+        let mut save = Save::load(&mut image);
+        save.data = data;
+        save.commit(&mut image).unwrap();
+        return image;
+    }
+
+    #[test]
+    fn json_and_images_round_trip() {
+        let image = encode(everything());
+        let json = to_json(&Save::load(&mut Image(image.0.clone())));
+        let again = encode(from_json(&json).unwrap());
+        assert!(image.0 == again.0);
+        // And back out to the same json, which is what gets edited.
+        assert_eq!(to_json(&Save::load(&mut Image(again.0))), json);
+    }
+
+    #[test]
+    fn bad_json_gets_turned_away() {
+        let mut json = to_json(&Save::load(&mut encode(everything())));
+        json["party"][0]["species_id"] = json!(152);
+        assert!(from_json(&json).is_err());
+        json["party"] = json!([]);
+        assert!(from_json(&json).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use embedded_graphics_simulator::{
+    BinaryColorTheme, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
+};
+use pokemon::animation::Clock;
+use pokemon::app::{App, Context};
+use pokemon::cry::Speaker;
+use pokemon::framebuffer::{FrameBuffer, HEIGHT, WIDTH};
+use pokemon::host::{MemFlash, Panel};
+use pokemon::input::{ButtonStates, Buttons};
+use pokemon::led::{Leds, MockLed};
+use pokemon::link::{Link, LinkError, NoLink};
+use pokemon::rom::{ReadError, Storage, WriteError};
+use pokemon::save::{Save, SAVE_OFFSET, SAVE_SIZE};
+use pokemon::screens::BattleState;
+use pokemon::trainer::FakeNetworks;
+use sdl2::keyboard::Keycode;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+// The packed resources get loaded where the firmware expects them, and the
+// save region is written back to a file whenever it changes so progress
+// survives a restart.
+struct FileFlash {
+    flash: MemFlash,
+    save_path: String,
+}
+
+impl FileFlash {
+    fn open(rom_path: &str, save_path: &str) -> FileFlash {
+        let mut flash = MemFlash::with_rom(&fs::read(rom_path).unwrap());
+        if let Ok(save) = fs::read(save_path) {
+            let start = SAVE_OFFSET as usize;
+            let len = save.len().min(SAVE_SIZE as usize);
+            flash.0[start..start + len].copy_from_slice(&save[..len]);
+        }
+        return FileFlash {
+            flash,
+            save_path: save_path.to_string(),
+        };
+    }
+
+    fn flush_save(&self) -> Result<(), WriteError> {
+        let start = SAVE_OFFSET as usize;
+        match fs::write(
+            &self.save_path,
+            &self.flash.0[start..start + SAVE_SIZE as usize],
+        ) {
+            Ok(_) => return Ok(()),
+            Err(_) => return Err(WriteError("couldn't write save file")),
+        }
+    }
+}
+
+impl Storage for FileFlash {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+        return self.flash.read(offset, buf);
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError> {
+        self.flash.erase_sector(offset)?;
+        return self.flush_save();
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError> {
+        self.flash.write(offset, data)?;
+        return self.flush_save();
+    }
+}
+
+// The real panel inverts on its way to the glass and leaves its memory
+// alone, so this does the same.
+fn shown(panel: &Panel, inverted: bool) -> SimulatorDisplay<BinaryColor> {
+    let mut shown = SimulatorDisplay::new(Size::new(WIDTH as u32, HEIGHT as u32));
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let color = match panel.pixel(x, y) != inverted {
+                true => BinaryColor::On,
+                false => BinaryColor::Off,
+            };
+            shown
+                .draw_pixel(Pixel(Point::new(x as i32, y as i32), color))
+                .ok();
+        }
+    }
+    return shown;
+}
+
+// The other end of a link cable. Whatever's connected gets read on a thread
+// of its own, so the main loop never has to wait for it.
+struct StreamLink {
+    incoming: Receiver<Vec<u8>>,
+    // Whatever's arrived that the last read didn't have room for.
+    pending: VecDeque<u8>,
+    outgoing: Arc<Mutex<Option<Box<dyn Write + Send>>>>,
+}
+
+impl StreamLink {
+    // Waits for the other simulator to connect, and again whenever it goes
+    // away.
+    fn listen(port: u16) -> StreamLink {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        return StreamLink::spawn(move || match listener.accept() {
+            Ok((stream, _)) => Some(stream),
+            Err(_) => None,
+        });
+    }
+
+    // Keeps trying until the other simulator's listening.
+    fn connect(address: String) -> StreamLink {
+        return StreamLink::spawn(move || match TcpStream::connect(&address) {
+            Ok(stream) => Some(stream),
+            Err(_) => {
+                thread::sleep(Duration::from_secs(1));
+                None
+            }
+        });
+    }
+
+    // Anything that behaves like a serial port, like one end of a pair of
+    // pseudo-terminals from socat.
+    fn serial(path: &str) -> StreamLink {
+        let reader = OpenOptions::new().read(true).open(path).unwrap();
+        let writer = OpenOptions::new().write(true).open(path).unwrap();
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || forward(reader, &sender));
+        return StreamLink {
+            incoming,
+            pending: VecDeque::new(),
+            outgoing: Arc::new(Mutex::new(Some(Box::new(writer)))),
+        };
+    }
+
+    fn spawn<F>(mut open: F) -> StreamLink
+    where
+        F: FnMut() -> Option<TcpStream> + Send + 'static,
+    {
+        let (sender, incoming) = mpsc::channel();
+        let outgoing: Arc<Mutex<Option<Box<dyn Write + Send>>>> = Arc::new(Mutex::new(None));
+        let shared = outgoing.clone();
+        thread::spawn(move || loop {
+            let stream = match open() {
+                Some(s) => s,
+                None => continue,
+            };
+            println!("link connected");
+            *shared.lock().unwrap() = Some(Box::new(stream.try_clone().unwrap()));
+            if !forward(stream, &sender) {
+                return;
+            }
+            *shared.lock().unwrap() = None;
+            println!("link disconnected");
+        });
+        return StreamLink {
+            incoming,
+            pending: VecDeque::new(),
+            outgoing,
+        };
+    }
+}
+
+// Passes everything read along until the stream ends. Returns false if
+// there's nobody left to pass it to.
+fn forward<R: Read>(mut reader: R, sender: &Sender<Vec<u8>>) -> bool {
+    let mut buf = [0u8; 256];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => return true,
+            Ok(read) => {
+                if sender.send(buf[..read].to_vec()).is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Link for StreamLink {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, LinkError> {
+        while self.pending.len() < buf.len() {
+            match self.incoming.try_recv() {
+                Ok(data) => self.pending.extend(data),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) if self.pending.is_empty() => {
+                    return Err(LinkError("LINK CLOSED"))
+                }
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        let len = self.pending.len().min(buf.len());
+        for (to, from) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *to = from;
+        }
+        return Ok(len);
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), LinkError> {
+        // Nothing plugged in is the same as nobody listening.
+        match self.outgoing.lock().unwrap().as_mut() {
+            Some(stream) => match stream.write_all(data) {
+                Ok(_) => return Ok(()),
+                Err(_) => return Err(LinkError("LINK CLOSED")),
+            },
+            None => return Ok(()),
+        }
+    }
+}
+
+fn button_for(keycode: Keycode) -> Option<Buttons> {
+    match keycode {
+        Keycode::Left => Some(Buttons::LEFT),
+        Keycode::Up => Some(Buttons::UP),
+        Keycode::Right => Some(Buttons::RIGHT),
+        Keycode::Down => Some(Buttons::DOWN),
+        Keycode::Z | Keycode::Return => Some(Buttons::A),
+        Keycode::X | Keycode::Backspace => Some(Buttons::B),
+        _ => None,
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 && args.len() != 5 {
+        println!(
+            "usage: {} <rom-file> <save-file> [--listen <port> | --connect <host:port> | --serial <path>]",
+            args[0]
+        );
+        println!("arrows move, z/enter is A, x/backspace is B");
+        return;
+    }
+
+    // Two simulators can trade by having one listen and the other connect.
+    let mut link: Box<dyn Link> = match args.get(3).map(|a| a.as_str()) {
+        Some("--listen") => Box::new(StreamLink::listen(args[4].parse().unwrap())),
+        Some("--connect") => Box::new(StreamLink::connect(args[4].clone())),
+        Some("--serial") => Box::new(StreamLink::serial(&args[4])),
+        Some(other) => {
+            println!("unknown option {}", other);
+            return;
+        }
+        None => Box::new(NoLink),
+    };
+
+    let mut flash = FileFlash::open(&args[1], &args[2]);
+    let mut save = Save::load(&mut flash);
+    let mut app = App::boot(&save);
+    let mut buttons = ButtonStates::new();
+    let mut leds = Leds::new();
+    let mut led = MockLed::new();
+    let mut speaker = Speaker::new();
+    let mut tone = None;
+    let mut battle = BattleState::new();
+
+    let mut display = FrameBuffer::new();
+    let mut panel = Panel::new();
+    // Shown instead of the panel while it's asleep, so the panel itself
+    // still matches what the frame buffer last sent it.
+    let blank: SimulatorDisplay<BinaryColor> =
+        SimulatorDisplay::new(Size::new(WIDTH as u32, HEIGHT as u32));
+    let mut showing_blank = false;
+    let output_settings = OutputSettingsBuilder::new()
+        .theme(BinaryColorTheme::OledBlue)
+        .scale(4)
+        .build();
+    let mut window = Window::new("pokemon", &output_settings);
+    window.update(&shown(&panel, false));
+    let mut inverted = false;
+
+    let mut clock = Clock::new();
+    let mut last = Instant::now();
+
+    loop {
+        for event in window.events() {
+            match event {
+                SimulatorEvent::Quit => return,
+                SimulatorEvent::KeyDown { keycode, .. } => {
+                    if let Some(button) = button_for(keycode) {
+                        buttons.update(button, true);
+                    }
+                }
+                SimulatorEvent::KeyUp { keycode, .. } => {
+                    if let Some(button) = button_for(keycode) {
+                        buttons.update(button, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut ctx = Context {
+            storage: &mut flash,
+            save: &mut save,
+            leds: &mut leds,
+            link: link.as_mut(),
+            scanner: &mut FakeNetworks,
+            speaker: &mut speaker,
+            battle: &mut battle,
+        };
+        // Only whole milliseconds come off, so the leftovers aren't lost.
+        let elapsed_ms = last.elapsed().as_millis() as u32;
+        last += Duration::from_millis(elapsed_ms as u64);
+        app.elapse(elapsed_ms, &mut ctx);
+        for _ in 0..clock.advance(elapsed_ms) {
+            app.tick(&mut buttons, &mut ctx);
+        }
+        let awake = !app.is_asleep();
+        if !awake && !showing_blank {
+            window.update(&blank);
+            showing_blank = true;
+        }
+        let mut changed = inverted != ctx.save.data.settings.inverted;
+        inverted = ctx.save.data.settings.inverted;
+        if awake && app.render(&mut display, &mut ctx) {
+            display.flush(&mut panel).ok();
+            changed = true;
+        }
+        if awake && (changed || showing_blank) {
+            window.update(&shown(&panel, inverted));
+            showing_blank = false;
+        }
+        // There's no neopixel on screen, so the colours go to the terminal.
+        let brightness = match awake {
+            true => ctx.save.data.settings.led_brightness,
+            false => 0,
+        };
+        ctx.leds.show(brightness, &mut led);
+        for colour in led.frames() {
+            println!("led #{:02x}{:02x}{:02x}", colour.r, colour.g, colour.b);
+        }
+        led.clear();
+        // No sound either, just whenever the pitch changes. cry-export writes
+        // them out as WAVs for listening to.
+        let playing = ctx.speaker.tone();
+        if playing != tone {
+            match playing {
+                Some(playing) => println!("tone {}hz", playing.hz),
+                None => println!("quiet"),
+            }
+            tone = playing;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
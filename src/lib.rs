@@ -1,9 +1,11 @@
 #![no_std]
 
 pub mod pokedex {
-    use minicbor::{bytes::ByteArray, Decode, Encode};
+    use minicbor::{Decode, Encode};
 
-    #[derive(Encode, Decode, Debug)]
+    pub const SPECIES_COUNT: u8 = 151;
+
+    #[derive(Encode, Decode, Debug, Clone)]
     pub struct Pokemon {
         #[n(0)]
         pub species_id: u8,
@@ -13,7 +15,7 @@ pub mod pokedex {
         #[n(2)]
         pub level: u8,
         #[n(3)]
-        pub xp: u16,
+        pub xp: u32,
         #[n(5)]
         pub current_hp: u16,
 
@@ -32,6 +34,35 @@ pub mod pokedex {
 
         #[n(12)]
         pub moves: [Option<u16>; 4],
+        #[n(13)]
+        pub status: Option<AilmentType>,
+        // Missing on anything saved or sent before there were shinies.
+        #[n(14)]
+        pub shiny: Option<bool>,
+    }
+
+    impl Pokemon {
+        pub fn is_shiny(&self) -> bool {
+            return self.shiny == Some(true);
+        }
+
+        // The nickname if it has one, otherwise whatever its species is called.
+        pub fn name<'a>(&'a self, species: &'a PokemonSpecies) -> &'a str {
+            match &self.nickname {
+                Some(nickname) => name_str(nickname),
+                None => name_str(&species.name),
+            }
+        }
+    }
+
+    // Names are stored in fixed size buffers, with padding after the last
+    // letter. This chops that padding off so the name can be drawn.
+    pub fn name_str(name: &[u8]) -> &str {
+        let end = name
+            .iter()
+            .position(|c| !c.is_ascii_graphic() && *c != b' ')
+            .unwrap_or(name.len());
+        return core::str::from_utf8(&name[..end]).unwrap_or("");
     }
 
     #[derive(Encode, Decode, Debug, Clone, Copy)]
@@ -60,7 +91,7 @@ pub mod pokedex {
         #[n(4)]
         pub capture_rate: u8,
         #[n(5)]
-        pub base_experience: u8,
+        pub base_experience: u16,
 
         #[n(7)]
         pub hp: SpeciesStatData,
@@ -75,11 +106,43 @@ pub mod pokedex {
         #[n(12)]
         pub speed: SpeciesStatData,
 
+        // Where its sprite is in the sprites section.
         #[n(13)]
-        pub sprite: ByteArray<578>,
+        pub sprite: u8,
+
+        #[n(15)]
+        pub evolutions: [Option<Evolution>; MAX_EVOLUTIONS],
+
+        // Where its back sprite is in the sprites section, if it has one.
+        // Anything without gets its front sprite flipped instead.
+        #[n(16)]
+        pub back_sprite: Option<u8>,
     }
 
-    #[derive(Encode, Decode, Debug)]
+    // Eevee is the only one with more than one, and it has three.
+    pub const MAX_EVOLUTIONS: usize = 3;
+
+    #[derive(Encode, Decode, Debug, Clone, Copy)]
+    pub struct Evolution {
+        #[n(0)]
+        pub species_id: u8,
+        #[n(1)]
+        pub trigger: EvolutionTrigger,
+    }
+
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+    pub enum EvolutionTrigger {
+        // Reaching at least this level.
+        #[n(0)]
+        Level(#[n(0)] u8),
+        // Using the item with this id on it, so far only stones.
+        #[n(1)]
+        Item(#[n(0)] u8),
+        #[n(2)]
+        Trade,
+    }
+
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
     #[cbor(index_only)]
     pub enum Stats {
         #[n(0)]
@@ -94,6 +157,10 @@ pub mod pokedex {
         SpecialDefense,
         #[n(5)]
         Speed,
+        #[n(6)]
+        Accuracy,
+        #[n(7)]
+        Evasion,
     }
 
     #[derive(Encode, Decode, Debug, Clone, Copy)]
@@ -121,21 +188,35 @@ pub mod pokedex {
     }
 
     impl GrowthRate {
-        fn xp_for_level(&self, level: u8) {
-            match self {
-                GrowthRate::ERRATIC => todo!(),
-                GrowthRate::FAST => todo!(),
-                GrowthRate::MEDIUM_FAST => todo!(),
-                GrowthRate::MEDIUM_SLOW => todo!(),
-                GrowthRate::SLOW => todo!(),
-                GrowthRate::FLUCTUATING => todo!(),
-            }
+        // Total xp needed to reach a level. These are the formulas from the
+        // games, done in i64 since medium slow dips below zero at level 1.
+        pub fn xp_for_level(&self, level: u8) -> u32 {
+            let n = level as i64;
+            let cube = n * n * n;
+            let xp = match self {
+                GrowthRate::ERRATIC => match level {
+                    0..=49 => cube * (100 - n) / 50,
+                    50..=67 => cube * (150 - n) / 100,
+                    68..=97 => cube * ((1911 - 10 * n) / 3) / 500,
+                    _ => cube * (160 - n) / 100,
+                },
+                GrowthRate::FAST => 4 * cube / 5,
+                GrowthRate::MEDIUM_FAST => cube,
+                GrowthRate::MEDIUM_SLOW => 6 * cube / 5 - 15 * n * n + 100 * n - 140,
+                GrowthRate::SLOW => 5 * cube / 4,
+                GrowthRate::FLUCTUATING => match level {
+                    0..=14 => cube * ((n + 1) / 3 + 24) / 50,
+                    15..=35 => cube * (n + 14) / 50,
+                    _ => cube * (n / 2 + 32) / 50,
+                },
+            };
+            return xp.max(0) as u32;
         }
     }
 
     // These could be created dynamically from the JSON data. This isn't so bad
     // though so /shruggie.
-    #[derive(Encode, Decode, Debug, Clone, Copy)]
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
     #[cbor(index_only)]
     pub enum Type {
         #[n(0)]
@@ -176,6 +257,27 @@ pub mod pokedex {
         Fairy,
     }
 
+    pub const ALL_TYPES: [Type; 18] = [
+        Type::Normal,
+        Type::Fighting,
+        Type::Flying,
+        Type::Poison,
+        Type::Ground,
+        Type::Rock,
+        Type::Bug,
+        Type::Ghost,
+        Type::Steel,
+        Type::Fire,
+        Type::Water,
+        Type::Grass,
+        Type::Electric,
+        Type::Psychic,
+        Type::Ice,
+        Type::Dragon,
+        Type::Dark,
+        Type::Fairy,
+    ];
+
     impl Type {
         pub fn name(&self) -> &'static str {
             match self {
@@ -199,6 +301,76 @@ pub mod pokedex {
                 Type::Fairy => "FAIRY",
             }
         }
+
+        // How well a move of this type hits a defender of the given type, as
+        // a percentage.
+        pub fn effectiveness(&self, defender: Type) -> u16 {
+            use Type::*;
+            match (self, defender) {
+                (Normal, Ghost)
+                | (Fighting, Ghost)
+                | (Poison, Steel)
+                | (Ground, Flying)
+                | (Ghost, Normal)
+                | (Electric, Ground)
+                | (Psychic, Dark)
+                | (Dragon, Fairy) => return 0,
+
+                (Fighting, Normal | Rock | Steel | Ice | Dark)
+                | (Flying, Fighting | Bug | Grass)
+                | (Poison, Grass | Fairy)
+                | (Ground, Poison | Rock | Steel | Fire | Electric)
+                | (Rock, Flying | Bug | Fire | Ice)
+                | (Bug, Grass | Psychic | Dark)
+                | (Ghost, Ghost | Psychic)
+                | (Steel, Rock | Ice | Fairy)
+                | (Fire, Bug | Steel | Grass | Ice)
+                | (Water, Ground | Rock | Fire)
+                | (Grass, Ground | Rock | Water)
+                | (Electric, Flying | Water)
+                | (Psychic, Fighting | Poison)
+                | (Ice, Flying | Ground | Grass | Dragon)
+                | (Dragon, Dragon)
+                | (Dark, Ghost | Psychic)
+                | (Fairy, Fighting | Dragon | Dark) => return 200,
+
+                (Normal, Rock | Steel)
+                | (Fighting, Flying | Poison | Bug | Psychic | Fairy)
+                | (Flying, Rock | Steel | Electric)
+                | (Poison, Poison | Ground | Rock | Ghost)
+                | (Ground, Bug | Grass)
+                | (Rock, Fighting | Ground | Steel)
+                | (Bug, Fighting | Flying | Poison | Ghost | Steel | Fire | Fairy)
+                | (Ghost, Dark)
+                | (Steel, Steel | Fire | Water | Electric)
+                | (Fire, Rock | Fire | Water | Dragon)
+                | (Water, Water | Grass | Dragon)
+                | (Grass, Flying | Poison | Bug | Steel | Fire | Grass | Dragon)
+                | (Electric, Grass | Electric | Dragon)
+                | (Psychic, Steel | Psychic)
+                | (Ice, Steel | Fire | Water | Ice)
+                | (Dragon, Steel)
+                | (Dark, Fighting | Dark | Fairy)
+                | (Fairy, Poison | Steel | Fire) => return 50,
+
+                _ => return 100,
+            }
+        }
+    }
+
+    impl PokemonSpecies {
+        // Effectiveness against both of this species' types, as a percentage.
+        pub fn effectiveness_against(&self, attack: Type) -> u16 {
+            let primary = attack.effectiveness(self.type_primary);
+            return match self.type_secondary {
+                Some(t) => primary * attack.effectiveness(t) / 100,
+                None => primary,
+            };
+        }
+
+        pub fn has_type(&self, t: Type) -> bool {
+            return self.type_primary == t || self.type_secondary == Some(t);
+        }
     }
 
     #[derive(Encode, Decode, Debug)]
@@ -239,6 +411,8 @@ pub mod pokedex {
         #[n(4)]
         pub target: Target,
 
+        // Zero accuracy means the move never misses, zero power means it
+        // doesn't do damage directly.
         #[n(5)]
         pub accuracy: u8,
         #[n(6)]
@@ -246,7 +420,7 @@ pub mod pokedex {
         #[n(7)]
         pub pp: u8,
         #[n(8)]
-        pub priority: u8,
+        pub priority: i8,
 
         #[n(9)]
         pub parameters: Parameters,
@@ -261,8 +435,9 @@ pub mod pokedex {
         pub ailment: Option<AilmentParameter>,
         #[n(1)]
         pub crit_rate: u8,
+        // A percentage of the damage done. Negative for recoil.
         #[n(2)]
-        pub drain: u8,
+        pub drain: i8,
         #[n(3)]
         pub flinch_chance: u8,
         #[n(4)]
@@ -283,7 +458,7 @@ pub mod pokedex {
         pub chance: u8,
     }
 
-    #[derive(Encode, Decode, Debug)]
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
     #[cbor(index_only)]
     pub enum AilmentType {
         #[n(0)]
@@ -349,31 +524,104 @@ pub mod pokedex {
     #[derive(Encode, Decode, Debug)]
     pub struct StatChange {
         #[n(0)]
-        pub amount: u8,
+        pub amount: i8,
         #[n(1)]
         pub stat: Stats,
     }
+
+    #[derive(Encode, Decode, Debug, Clone, Copy)]
+    pub struct Item {
+        #[n(0)]
+        pub id: u8,
+        #[n(1)]
+        pub name: [u8; 12],
+        #[n(2)]
+        pub effect: ItemEffect,
+    }
+
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+    pub enum ItemEffect {
+        // Restores this much hp. Zero means all of it.
+        #[n(0)]
+        Heal(#[n(0)] u16),
+        // Cures the given ailment, or any ailment if there isn't one.
+        #[n(1)]
+        CureStatus(#[n(0)] Option<AilmentType>),
+        // The multiplier is in tenths, so a regular poke ball is 10.
+        #[n(2)]
+        Ball(#[n(0)] u8),
+        // Brings back a fainted pokemon with this percentage of its hp.
+        #[n(3)]
+        Revive(#[n(0)] u8),
+        #[n(4)]
+        EvolutionStone,
+        #[n(5)]
+        TechnicalMachine(#[n(0)] u16),
+        #[n(6)]
+        RareCandy,
+    }
 }
 
 pub mod generation {
     use crate::pokedex::{
-        LearnCondition, LearnableMove, Pokemon, PokemonSpecies, SpeciesStatData, StatChange,
-        StatData,
+        LearnCondition, LearnableMove, Pokemon, PokemonSpecies, SpeciesStatData, StatData,
     };
 
-    trait Random {
-        fn random(&self) -> u8;
+    pub trait Random {
+        fn random(&mut self) -> u8;
 
-        fn generate_iv(&self) -> u8 {
+        fn generate_iv(&mut self) -> u8 {
             return self.random() & 0b00011111;
         }
+
+        // Not perfectly uniform, but close enough for picking things.
+        fn below(&mut self, max: u8) -> u8 {
+            if max == 0 {
+                return 0;
+            }
+            return self.random() % max;
+        }
+    }
+
+    // A small xorshift generator. Everything generated from the same seed
+    // comes out the same, which is the whole point: the same network should
+    // always give you the same thing.
+    pub struct SeededRandom {
+        state: u32,
+    }
+
+    impl SeededRandom {
+        pub fn new(seed: u32) -> SeededRandom {
+            // Seeds that are close together give very similar first few
+            // numbers, so give the bits a good stir first.
+            let mut state = seed;
+            state ^= state >> 16;
+            state = state.wrapping_mul(0x7feb352d);
+            state ^= state >> 15;
+            state = state.wrapping_mul(0x846ca68b);
+            state ^= state >> 16;
+            // Xorshift gets stuck on zero forever.
+            if state == 0 {
+                state = 0x9e3779b9;
+            }
+            return SeededRandom { state };
+        }
+    }
+
+    impl Random for SeededRandom {
+        fn random(&mut self) -> u8 {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 17;
+            self.state ^= self.state << 5;
+            return (self.state >> 24) as u8;
+        }
     }
 
     pub fn generate_pokemon(
         species: PokemonSpecies,
         move_list: &mut dyn Iterator<Item = &LearnableMove>,
         level: u8,
-        rng: &dyn Random,
+        rng: &mut dyn Random,
     ) -> Pokemon {
         let mut moves = [None; 4];
         let mut i = 0;
@@ -382,6 +630,10 @@ pub mod generation {
                 if lvl > level {
                     break;
                 }
+                // Some moves are listed once per game they appeared in.
+                if moves.contains(&Some(m.id)) {
+                    continue;
+                }
                 moves[i] = Some(m.id);
                 i = (i + 1) % 4;
             }
@@ -393,7 +645,7 @@ pub mod generation {
             effort_value: 0,
             individual_value: hp_iv,
         };
-        return Pokemon {
+        let mut pokemon = Pokemon {
             species_id: species.id,
             nickname: None,
             level,
@@ -406,10 +658,68 @@ pub mod generation {
             special_defense: new_stat(species.special_defense, level, rng),
             speed: new_stat(species.speed, level, rng),
             moves,
+            status: None,
+            shiny: None,
         };
+        pokemon.shiny = Some(shiny_ivs(&pokemon));
+        return pokemon;
+    }
+
+    // One in this many comes out shiny.
+    pub const SHINY_ODDS: u32 = 4096;
+
+    // Decided by the IVs rather than a roll of its own, so adding shinies
+    // didn't change anything else a seed generates. They get scrambled
+    // first, otherwise only the ones with awful stats would ever be shiny.
+    fn shiny_ivs(pokemon: &Pokemon) -> bool {
+        let stats = [
+            pokemon.hp,
+            pokemon.attack,
+            pokemon.defense,
+            pokemon.special_attack,
+            pokemon.special_defense,
+            pokemon.speed,
+        ];
+        let ivs = stats
+            .iter()
+            .fold(0u32, |bits, s| bits << 5 | s.individual_value as u32);
+        let mut mixed = ivs.wrapping_mul(0x9e3779b1);
+        mixed ^= mixed >> 16;
+        return mixed % SHINY_ODDS == 0;
+    }
+
+    // Works the stats out again from scratch, for after a level up. Whatever
+    // hp was gained goes on top of the current hp too.
+    pub fn recalculate_stats(pokemon: &mut Pokemon, species: &PokemonSpecies) {
+        let level = pokemon.level;
+        let old_hp = pokemon.hp.value;
+        pokemon.hp.value = calculate_hp_stat(
+            species.hp.base_value,
+            pokemon.hp.effort_value,
+            pokemon.hp.individual_value,
+            level,
+        );
+        for (stat, base) in [
+            (&mut pokemon.attack, species.attack),
+            (&mut pokemon.defense, species.defense),
+            (&mut pokemon.special_attack, species.special_attack),
+            (&mut pokemon.special_defense, species.special_defense),
+            (&mut pokemon.speed, species.speed),
+        ] {
+            stat.value = calculate_stat(
+                base.base_value,
+                stat.effort_value,
+                stat.individual_value,
+                level,
+            );
+        }
+        if pokemon.current_hp > 0 {
+            pokemon.current_hp += pokemon.hp.value.saturating_sub(old_hp);
+            pokemon.current_hp = pokemon.current_hp.min(pokemon.hp.value);
+        }
     }
 
-    fn new_stat(stat: SpeciesStatData, level: u8, rng: &dyn Random) -> StatData {
+    fn new_stat(stat: SpeciesStatData, level: u8, rng: &mut dyn Random) -> StatData {
         let iv = rng.generate_iv();
         return StatData {
             value: calculate_stat(stat.base_value, 0, iv, level),
@@ -425,4 +735,12310 @@ pub mod generation {
     fn calculate_hp_stat(base: u16, ev: u16, iv: u8, level: u8) -> u16 {
         return (((2 * base) + (iv as u16) + ev) * (level as u16) / 100) + (level as u16) + 10;
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::pokedex::Type;
+        use crate::rom::testing::species;
+
+        #[test]
+        fn about_one_in_shiny_odds_comes_out_shiny() {
+            let mut shinies = 0;
+            let mut first = None;
+            for seed in 0..SHINY_ODDS * 16 {
+                let mut rng = SeededRandom::new(seed);
+                let bulbasaur = species(1, Type::Grass, None);
+                let pokemon = generate_pokemon(bulbasaur, &mut [].iter(), 10, &mut rng);
+                if pokemon.is_shiny() {
+                    shinies += 1;
+                    first.get_or_insert(pokemon);
+                }
+            }
+            // Always the same count for the same seeds, but it ought to be
+            // somewhere near sixteen.
+            assert!((8..=32).contains(&shinies), "{} shinies", shinies);
+
+            // Only the IVs count, so training and levelling up leave it be.
+            let mut shiny = first.unwrap();
+            shiny.level = 50;
+            shiny.attack.effort_value = 200;
+            recalculate_stats(&mut shiny, &species(1, Type::Grass, None));
+            assert!(shiny_ivs(&shiny));
+            shiny.speed.individual_value ^= 1;
+            assert!(!shiny_ivs(&shiny));
+        }
+    }
+}
+
+pub mod trainer {
+    use crate::generation::{generate_pokemon, Random, SeededRandom};
+    use crate::pokedex::{Pokemon, PokemonSpecies, Type, ALL_TYPES, SPECIES_COUNT};
+    use crate::rom::{read_move_list, read_pokemon, ReadError, Storage};
+
+    pub const TEAM_SIZE: usize = 6;
+
+    // How hard we look for a species matching the trainer's theme before
+    // settling for whatever turns up. Some types barely exist in gen 1.
+    const THEME_ATTEMPTS: u8 = 24;
+
+    const TRAINER_NAMES: [&str; 32] = [
+        "ALEX", "BEN", "CARA", "DANA", "ELI", "FERN", "GUS", "HANA", "IVY", "JOE", "KIM", "LEO",
+        "MAY", "NED", "OLA", "PIP", "QUIN", "ROY", "SUE", "TED", "UMA", "VIC", "WES", "XIA",
+        "YUKI", "ZED", "ANA", "BO", "CY", "DEV", "EVE", "FAY",
+    ];
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum TrainerClass {
+        Youngster,
+        Lass,
+        BugCatcher,
+        Hiker,
+        Swimmer,
+        Fisherman,
+        BirdKeeper,
+        BlackBelt,
+        Psychic,
+        Engineer,
+        Burglar,
+        Channeler,
+        AceTrainer,
+        GymLeader,
+    }
+
+    const TRAINER_CLASSES: [TrainerClass; 13] = [
+        TrainerClass::Youngster,
+        TrainerClass::Lass,
+        TrainerClass::BugCatcher,
+        TrainerClass::Hiker,
+        TrainerClass::Swimmer,
+        TrainerClass::Fisherman,
+        TrainerClass::BirdKeeper,
+        TrainerClass::BlackBelt,
+        TrainerClass::Psychic,
+        TrainerClass::Engineer,
+        TrainerClass::Burglar,
+        TrainerClass::Channeler,
+        TrainerClass::AceTrainer,
+    ];
+
+    impl TrainerClass {
+        pub fn name(&self) -> &'static str {
+            match self {
+                TrainerClass::Youngster => "YOUNGSTER",
+                TrainerClass::Lass => "LASS",
+                TrainerClass::BugCatcher => "BUG CATCHER",
+                TrainerClass::Hiker => "HIKER",
+                TrainerClass::Swimmer => "SWIMMER",
+                TrainerClass::Fisherman => "FISHERMAN",
+                TrainerClass::BirdKeeper => "BIRD KEEPER",
+                TrainerClass::BlackBelt => "BLACK BELT",
+                TrainerClass::Psychic => "PSYCHIC",
+                TrainerClass::Engineer => "ENGINEER",
+                TrainerClass::Burglar => "BURGLAR",
+                TrainerClass::Channeler => "CHANNELER",
+                TrainerClass::AceTrainer => "ACE TRAINER",
+                TrainerClass::GymLeader => "LEADER",
+            }
+        }
+
+        // The type a class is known for, if it has one. Everyone else picks
+        // a favourite at random.
+        fn specialty(&self) -> Option<Type> {
+            match self {
+                TrainerClass::BugCatcher => Some(Type::Bug),
+                TrainerClass::Hiker => Some(Type::Rock),
+                TrainerClass::Swimmer | TrainerClass::Fisherman => Some(Type::Water),
+                TrainerClass::BirdKeeper => Some(Type::Flying),
+                TrainerClass::BlackBelt => Some(Type::Fighting),
+                TrainerClass::Psychic => Some(Type::Psychic),
+                TrainerClass::Engineer => Some(Type::Electric),
+                TrainerClass::Burglar => Some(Type::Fire),
+                TrainerClass::Channeler => Some(Type::Ghost),
+                _ => None,
+            }
+        }
+    }
+
+    pub struct Trainer {
+        // The fingerprint of the network this trainer came from, or something
+        // mixed from it for anyone in a gym. This is how we remember who's
+        // already been beaten.
+        pub id: u32,
+        pub name: &'static str,
+        pub class: TrainerClass,
+        pub theme: Type,
+        pub team: [Option<Pokemon>; TEAM_SIZE],
+    }
+
+    // Squashes a network's BSSID down into a seed. The BSSID doesn't change
+    // when the SSID gets renamed or the signal wobbles, so it's a nice stable
+    // thing to hang a trainer off.
+    pub fn fingerprint(bssid: &[u8; 6]) -> u32 {
+        let mut hash = 0x811c9dc5u32;
+        for byte in bssid {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+        return hash;
+    }
+
+    // Whatever can see the wifi networks around the board. Only the BSSIDs
+    // matter, so that's all it hands back.
+    pub trait Scanner {
+        // Fills in as many as fit and says how many that was.
+        fn scan(&mut self, found: &mut [[u8; 6]]) -> usize;
+    }
+
+    // Stands in for a radio until there's a driver for one. It's always the
+    // same neighbourhood, so the same trainers keep turning up and beating
+    // one of them sticks.
+    pub struct FakeNetworks;
+
+    const FAKE_BSSIDS: [[u8; 6]; 6] = [
+        [0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x01],
+        [0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x02],
+        [0x34, 0x97, 0xf6, 0x10, 0x22, 0x9e],
+        [0x60, 0x38, 0xe0, 0x5a, 0x7c, 0x13],
+        [0xa4, 0x2b, 0xb0, 0xc1, 0x08, 0x44],
+        [0xf0, 0x9f, 0xc2, 0x7e, 0x31, 0xd5],
+    ];
+
+    impl Scanner for FakeNetworks {
+        fn scan(&mut self, found: &mut [[u8; 6]]) -> usize {
+            let count = found.len().min(FAKE_BSSIDS.len());
+            found[..count].copy_from_slice(&FAKE_BSSIDS[..count]);
+            return count;
+        }
+    }
+
+    // Builds the trainer for a network. Everything comes out of an rng seeded
+    // with the fingerprint, so the same network always gives the same trainer.
+    // The level is roughly where the team should sit.
+    pub fn generate_trainer(
+        fingerprint: u32,
+        level: u8,
+        storage: &mut dyn Storage,
+    ) -> Result<Trainer, ReadError> {
+        return build_trainer(fingerprint, None, level, storage);
+    }
+
+    // Same as generate_trainer, but the trainer has to use the given type. Gyms
+    // use this so everyone inside sticks to the leader's type.
+    pub fn generate_themed_trainer(
+        fingerprint: u32,
+        theme: Type,
+        level: u8,
+        storage: &mut dyn Storage,
+    ) -> Result<Trainer, ReadError> {
+        return build_trainer(fingerprint, Some(theme), level, storage);
+    }
+
+    fn build_trainer(
+        fingerprint: u32,
+        theme: Option<Type>,
+        level: u8,
+        storage: &mut dyn Storage,
+    ) -> Result<Trainer, ReadError> {
+        let mut rng = SeededRandom::new(fingerprint);
+        let name = TRAINER_NAMES[rng.below(TRAINER_NAMES.len() as u8) as usize];
+        let (class, theme) = match theme {
+            Some(t) => (
+                TRAINER_CLASSES
+                    .iter()
+                    .find(|c| c.specialty() == Some(t))
+                    .copied()
+                    .unwrap_or(TrainerClass::AceTrainer),
+                t,
+            ),
+            None => {
+                let class = TRAINER_CLASSES[rng.below(TRAINER_CLASSES.len() as u8) as usize];
+                match class.specialty() {
+                    Some(t) => (class, t),
+                    None => (class, ALL_TYPES[rng.below(ALL_TYPES.len() as u8) as usize]),
+                }
+            }
+        };
+
+        let size = (1 + rng.below(3) + level / 25).min(TEAM_SIZE as u8) as usize;
+        let team = generate_team(theme, level, size, &mut rng, storage)?;
+        return Ok(Trainer {
+            id: fingerprint,
+            name,
+            class,
+            theme,
+            team,
+        });
+    }
+
+    // Fills out a team of pokemon sharing a type, at around the given level.
+    pub fn generate_team(
+        theme: Type,
+        level: u8,
+        size: usize,
+        rng: &mut dyn Random,
+        storage: &mut dyn Storage,
+    ) -> Result<[Option<Pokemon>; TEAM_SIZE], ReadError> {
+        let mut team: [Option<Pokemon>; TEAM_SIZE] = Default::default();
+        for slot in team.iter_mut().take(size) {
+            let species = pick_species(theme, rng, storage)?;
+            let moves = read_move_list(species.id, storage)?;
+            let member_level = (level.saturating_sub(2) + rng.below(3)).clamp(2, 100);
+            *slot = Some(generate_pokemon(
+                species,
+                &mut moves.iter(),
+                member_level,
+                rng,
+            ));
+        }
+        return Ok(team);
+    }
+
+    fn pick_species(
+        theme: Type,
+        rng: &mut dyn Random,
+        storage: &mut dyn Storage,
+    ) -> Result<PokemonSpecies, ReadError> {
+        let mut attempts = 0;
+        loop {
+            let id = 1 + rng.below(SPECIES_COUNT);
+            let species = read_pokemon(id, storage)?;
+            attempts += 1;
+            if species.type_primary == theme
+                || species.type_secondary == Some(theme)
+                || attempts >= THEME_ATTEMPTS
+            {
+                return Ok(species);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::rom::testing::{species, TestRom};
+
+        // Every species, with the types handed out in turn.
+        fn rom() -> TestRom {
+            let mut rom = TestRom::new();
+            for id in 1..=SPECIES_COUNT {
+                let type_ = ALL_TYPES[id as usize % ALL_TYPES.len()];
+                rom.add_species(&species(id, type_, None));
+            }
+            return rom;
+        }
+
+        fn summary(trainer: &Trainer) -> [Option<(u8, u8, u16)>; TEAM_SIZE] {
+            return trainer
+                .team
+                .clone()
+                .map(|p| p.map(|p| (p.species_id, p.level, p.hp.value)));
+        }
+
+        #[test]
+        fn the_same_network_gives_the_same_trainer() {
+            let mut rom = rom();
+            let mut names = [""; FAKE_BSSIDS.len()];
+            for (bssid, name) in FAKE_BSSIDS.iter().zip(names.iter_mut()) {
+                let id = fingerprint(bssid);
+                let first = generate_trainer(id, 30, &mut rom).unwrap();
+                let again = generate_trainer(id, 30, &mut rom).unwrap();
+                assert_eq!(first.id, id);
+                assert_eq!(
+                    (first.name, first.class, first.theme),
+                    (again.name, again.class, again.theme)
+                );
+                assert_eq!(summary(&first), summary(&again));
+                assert!(first.team[0].is_some());
+                *name = first.name;
+            }
+            // But the neighbours aren't all the same person.
+            assert!(names.iter().any(|name| *name != names[0]));
+        }
+    }
+}
+
+pub mod gym {
+    use crate::ai::Difficulty;
+    use crate::generation::{Random, SeededRandom};
+    use crate::pokedex::{Type, ALL_TYPES};
+    use crate::rom::{ReadError, Storage};
+    use crate::trainer::{generate_team, generate_themed_trainer, Trainer, TrainerClass};
+
+    pub const BADGE_COUNT: usize = 8;
+    const MAX_GYM_TRAINERS: u8 = 4;
+    const GYM_ODDS: u32 = 4;
+
+    const LEADER_NAMES: [&str; 16] = [
+        "BROCK", "MISTY", "SURGE", "ERIKA", "KOGA", "SABRINA", "BLAINE", "GIOVANNI", "FALKNER",
+        "BUGSY", "WHITNEY", "MORTY", "CHUCK", "JASMINE", "PRYCE", "CLAIR",
+    ];
+
+    // Everything about progression hangs off how many badges you've got.
+    // Pokemon won't grow past the level cap, wild pokemon don't get tougher
+    // than the wild level, and gyms get harder with every badge.
+    pub fn level_cap(badges: u8) -> u8 {
+        return (20 + 10 * badges as u32).min(100) as u8;
+    }
+
+    pub fn max_wild_level(badges: u8) -> u8 {
+        return (10 + 8 * badges as u32).min(100) as u8;
+    }
+
+    // How cleverly trainers battle.
+    pub fn trainer_difficulty(badges: u8) -> Difficulty {
+        return match badges {
+            0..=1 => Difficulty::Easy,
+            2..=5 => Difficulty::Normal,
+            _ => Difficulty::Hard,
+        };
+    }
+
+    fn gym_level(badges: u8) -> u8 {
+        return (12 + 8 * badges as u32).min(100) as u8;
+    }
+
+    pub fn badge_name(theme: Type) -> &'static str {
+        match theme {
+            Type::Normal => "PLAIN",
+            Type::Fighting => "FIST",
+            Type::Flying => "ZEPHYR",
+            Type::Poison => "SOUL",
+            Type::Ground => "EARTH",
+            Type::Rock => "BOULDER",
+            Type::Bug => "HIVE",
+            Type::Ghost => "FOG",
+            Type::Steel => "MINERAL",
+            Type::Fire => "VOLCANO",
+            Type::Water => "CASCADE",
+            Type::Grass => "RAINBOW",
+            Type::Electric => "THUNDER",
+            Type::Psychic => "MARSH",
+            Type::Ice => "GLACIER",
+            Type::Dragon => "RISING",
+            Type::Dark => "SHADOW",
+            Type::Fairy => "PIXIE",
+        }
+    }
+
+    // A gym only remembers what it needs to rebuild its trainers. Generating
+    // them all up front would eat a lot of RAM for no good reason.
+    pub struct Gym {
+        pub id: u32,
+        pub leader_name: &'static str,
+        pub theme: Type,
+        pub level: u8,
+        pub trainer_count: u8,
+    }
+
+    // Only some networks have a gym on them. The low bits of a fingerprint
+    // don't mix very well, so this looks a little further up.
+    pub fn is_gym(fingerprint: u32) -> bool {
+        return (fingerprint >> 8).is_multiple_of(GYM_ODDS);
+    }
+
+    // Builds the gym for a network. Who runs it and what type they use is
+    // fixed by the fingerprint, but how tough it is depends on how many badges
+    // the player has when they walk in.
+    pub fn generate_gym(fingerprint: u32, badges: u8) -> Gym {
+        let mut rng = SeededRandom::new(fingerprint ^ 0x6779_6d21);
+        let leader_name = LEADER_NAMES[rng.below(LEADER_NAMES.len() as u8) as usize];
+        let theme = ALL_TYPES[rng.below(ALL_TYPES.len() as u8) as usize];
+        let trainer_count = (1 + rng.below(MAX_GYM_TRAINERS)).min(1 + badges / 2);
+        return Gym {
+            id: fingerprint,
+            leader_name,
+            theme,
+            level: gym_level(badges),
+            trainer_count,
+        };
+    }
+
+    impl Gym {
+        // The trainers you have to get through before the leader. They're a
+        // little weaker than the leader, and each gets its own id so beating
+        // one is remembered separately.
+        pub fn trainer(&self, index: u8, storage: &mut dyn Storage) -> Result<Trainer, ReadError> {
+            return generate_themed_trainer(
+                self.trainer_id(index),
+                self.theme,
+                self.level.saturating_sub(4),
+                storage,
+            );
+        }
+
+        pub fn trainer_id(&self, index: u8) -> u32 {
+            return self
+                .id
+                .wrapping_mul(0x01000193)
+                .wrapping_add(index as u32 + 1);
+        }
+
+        // Mixed the same way as the trainers' ids, so it can't collide with
+        // them or with whoever the network's own trainer is.
+        pub fn leader_id(&self) -> u32 {
+            return self.id.wrapping_mul(0x01000193);
+        }
+
+        pub fn leader(&self, storage: &mut dyn Storage) -> Result<Trainer, ReadError> {
+            let id = self.leader_id();
+            let mut rng = SeededRandom::new(id);
+            let size = (2 + self.level / 20) as usize;
+            let team = generate_team(self.theme, self.level + 2, size, &mut rng, storage)?;
+            return Ok(Trainer {
+                id,
+                name: self.leader_name,
+                class: TrainerClass::GymLeader,
+                theme: self.theme,
+                team,
+            });
+        }
+    }
+}
+
+pub mod encounter {
+    use crate::generation::{generate_pokemon, Random, SeededRandom};
+    use crate::gym::max_wild_level;
+    use crate::pokedex::{Pokemon, PokemonSpecies, Type, SPECIES_COUNT};
+    use crate::rom::{read_move_list, read_pokemon, ReadError, Storage};
+    use crate::time::TimeOfDay;
+
+    // How many species get turned away for being too rare before we take
+    // whatever's next.
+    const RARITY_ATTEMPTS: u8 = 16;
+    // Anything this hard to catch is worth making a fuss about.
+    const RARE_CAPTURE_RATE: u8 = 30;
+
+    pub fn is_rare(species: &PokemonSpecies) -> bool {
+        return species.capture_rate <= RARE_CAPTURE_RATE;
+    }
+
+    // What's out and about at different times of day.
+    fn active_types(time: TimeOfDay) -> &'static [Type] {
+        return match time {
+            TimeOfDay::Morning => &[Type::Bug, Type::Grass, Type::Flying, Type::Normal],
+            TimeOfDay::Day => &[
+                Type::Fire,
+                Type::Water,
+                Type::Electric,
+                Type::Ground,
+                Type::Rock,
+                Type::Fighting,
+            ],
+            TimeOfDay::Night => &[Type::Ghost, Type::Dark, Type::Poison, Type::Psychic],
+        };
+    }
+
+    fn is_active(species: &PokemonSpecies, time: TimeOfDay) -> bool {
+        let types = active_types(time);
+        return types.contains(&species.type_primary)
+            || species.type_secondary.is_some_and(|t| types.contains(&t));
+    }
+
+    // Picks whatever wild pokemon turns up. Rare pokemon are the ones that
+    // are hard to catch, so the capture rate doubles as how often they appear.
+    // Anything that's active at this time of day is twice as likely to stay.
+    pub fn wild_pokemon(
+        seed: u32,
+        badges: u8,
+        time: TimeOfDay,
+        storage: &mut dyn Storage,
+    ) -> Result<Pokemon, ReadError> {
+        let mut rng = SeededRandom::new(seed);
+        let max = max_wild_level(badges);
+        let level = (max / 2 + rng.below(max / 2 + 1)).max(2);
+
+        let mut attempts = 0;
+        let species = loop {
+            let species = read_pokemon(1 + rng.below(SPECIES_COUNT), storage)?;
+            attempts += 1;
+            let chance = match is_active(&species, time) {
+                true => species.capture_rate.saturating_mul(2),
+                false => species.capture_rate,
+            };
+            if rng.random() <= chance || attempts >= RARITY_ATTEMPTS {
+                break species;
+            }
+        };
+        let moves = read_move_list(species.id, storage)?;
+        return Ok(generate_pokemon(
+            species,
+            &mut moves.iter(),
+            level,
+            &mut rng,
+        ));
+    }
+}
+
+pub mod evolution {
+    use crate::generation::recalculate_stats;
+    use crate::pokedex::{EvolutionTrigger, Pokemon, PokemonSpecies};
+    use crate::rom::{read_pokemon, ReadError, Storage};
+
+    // Works out what a pokemon would turn into, if anything. Level triggers
+    // fire for any level at or past the one in the ROM, so a pokemon that
+    // stopped evolving gets asked again next time it levels up.
+    pub fn find_evolution(
+        pokemon: &Pokemon,
+        species: &PokemonSpecies,
+        trigger: EvolutionTrigger,
+    ) -> Option<u8> {
+        for evolution in species.evolutions.iter().flatten() {
+            let matched = match (evolution.trigger, trigger) {
+                (EvolutionTrigger::Level(needed), EvolutionTrigger::Level(_)) => {
+                    pokemon.level >= needed
+                }
+                (wanted, given) => wanted == given,
+            };
+            if matched {
+                return Some(evolution.species_id);
+            }
+        }
+        return None;
+    }
+
+    // Call this after a pokemon gains a level.
+    pub fn check_level_up(
+        pokemon: &Pokemon,
+        storage: &mut dyn Storage,
+    ) -> Result<Option<u8>, ReadError> {
+        let species = read_pokemon(pokemon.species_id, storage)?;
+        return Ok(find_evolution(
+            pokemon,
+            &species,
+            EvolutionTrigger::Level(pokemon.level),
+        ));
+    }
+
+    // Call this once a pokemon has arrived in a trade.
+    pub fn check_trade(
+        pokemon: &Pokemon,
+        storage: &mut dyn Storage,
+    ) -> Result<Option<u8>, ReadError> {
+        let species = read_pokemon(pokemon.species_id, storage)?;
+        return Ok(find_evolution(pokemon, &species, EvolutionTrigger::Trade));
+    }
+
+    // Turns a pokemon into its new species. IVs, EVs, moves and the nickname
+    // all come along, only the stats get worked out again.
+    pub fn evolve(pokemon: &mut Pokemon, into: &PokemonSpecies) {
+        pokemon.species_id = into.id;
+        recalculate_stats(pokemon, into);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::pokedex::{Evolution, Type};
+        use crate::rom::testing::{pokemon, species, TestRom};
+
+        const CHARMANDER: u8 = 4;
+        const CHARMELEON: u8 = 5;
+        const FIRE_STONE: u8 = 32;
+
+        fn rom() -> TestRom {
+            let mut rom = TestRom::new();
+            let mut charmander = species(CHARMANDER, Type::Fire, None);
+            charmander.evolutions[0] = Some(Evolution {
+                species_id: CHARMELEON,
+                trigger: EvolutionTrigger::Level(16),
+            });
+            rom.add_species(&charmander);
+            rom.add_species(&species(CHARMELEON, Type::Fire, None));
+            return rom;
+        }
+
+        #[test]
+        fn level_evolutions_fire_at_or_past_their_level() {
+            let mut rom = rom();
+            let kind = || species(CHARMANDER, Type::Fire, None);
+            for (level, wanted) in [(15, None), (16, Some(CHARMELEON)), (30, Some(CHARMELEON))] {
+                let mon = pokemon(kind(), level, [None; 4]);
+                assert_eq!(
+                    check_level_up(&mon, &mut rom).unwrap(),
+                    wanted,
+                    "level {}",
+                    level
+                );
+            }
+            // Nothing else sets it off.
+            let mon = pokemon(kind(), 30, [None; 4]);
+            assert_eq!(check_trade(&mon, &mut rom).unwrap(), None);
+            let charmander = read_pokemon(CHARMANDER, &mut rom).unwrap();
+            let stone = EvolutionTrigger::Item(FIRE_STONE);
+            assert_eq!(find_evolution(&mon, &charmander, stone), None);
+        }
+    }
+}
+
+pub mod battle {
+    use crate::ai::{choose_action, Action, Battler, Difficulty};
+    use crate::generation::{recalculate_stats, Random, SeededRandom};
+    use crate::gym::level_cap;
+    use crate::items::{use_item, ItemError, ItemOutcome, UseContext};
+    use crate::pokedex::{
+        name_str, AilmentType, DamageClass, Move, Parameters, Pokemon, PokemonSpecies, Stats,
+        Target, Type,
+    };
+    use crate::rom::{read_item, read_move, read_pokemon, ReadError, Storage};
+    use crate::save::{checksum, SaveData, PARTY_SIZE};
+    use crate::trainer::{Trainer, TEAM_SIZE};
+
+    pub const MOVE_SLOTS: usize = 4;
+
+    // The usual damage formula, minus crits and the random spread. Good
+    // enough for the AI to guess with, and the battle adds the rest.
+    pub fn base_damage(
+        attacker: &Pokemon,
+        attacker_species: &PokemonSpecies,
+        defender: &Pokemon,
+        defender_species: &PokemonSpecies,
+        m: &Move,
+    ) -> u16 {
+        let (attack, defense) = match m.damage_class {
+            DamageClass::Physical => (attacker.attack.value, defender.defense.value),
+            DamageClass::Special => (
+                attacker.special_attack.value,
+                defender.special_defense.value,
+            ),
+            DamageClass::Status => return 0,
+        };
+        return damage_formula(
+            attacker.level,
+            attack,
+            defense,
+            m,
+            attacker_species,
+            defender_species,
+        );
+    }
+
+    fn damage_formula(
+        level: u8,
+        attack: u16,
+        defense: u16,
+        m: &Move,
+        attacker_species: &PokemonSpecies,
+        defender_species: &PokemonSpecies,
+    ) -> u16 {
+        if m.power == 0 {
+            return 0;
+        }
+        let level = level as u32;
+        let mut damage =
+            (2 * level / 5 + 2) * m.power as u32 * attack as u32 / (defense as u32).max(1) / 50 + 2;
+        if attacker_species.has_type(m.type_) {
+            damage = damage * 3 / 2;
+        }
+        damage = damage * defender_species.effectiveness_against(m.type_) as u32 / 100;
+        return damage.min(u16::MAX as u32) as u16;
+    }
+
+    pub const MESSAGE_LENGTH: usize = 19;
+    const MESSAGE_SLOTS: usize = 16;
+
+    // Lines of text waiting to be shown, oldest first. Each line fits across
+    // the battle text box in the large font.
+    pub struct Messages {
+        lines: [[u8; MESSAGE_LENGTH]; MESSAGE_SLOTS],
+        head: usize,
+        len: usize,
+    }
+
+    impl Default for Messages {
+        fn default() -> Messages {
+            return Messages::new();
+        }
+    }
+
+    impl Messages {
+        pub fn new() -> Messages {
+            return Messages {
+                lines: [[0u8; MESSAGE_LENGTH]; MESSAGE_SLOTS],
+                head: 0,
+                len: 0,
+            };
+        }
+
+        // Glues the parts together, wrapping onto another line at a space if
+        // it's too long. Lines that don't fit in the queue are dropped.
+        pub fn push(&mut self, parts: &[&str]) {
+            let mut text = [0u8; MESSAGE_LENGTH * 4];
+            let mut length = 0;
+            for part in parts {
+                for c in part.bytes() {
+                    if length < text.len() {
+                        text[length] = c;
+                        length += 1;
+                    }
+                }
+            }
+
+            let mut start = 0;
+            while start < length {
+                let mut end = length.min(start + MESSAGE_LENGTH);
+                if end < length {
+                    if let Some(space) = text[start..end].iter().rposition(|c| *c == b' ') {
+                        end = start + space;
+                    }
+                }
+                if self.len == MESSAGE_SLOTS {
+                    return;
+                }
+                let slot = (self.head + self.len) % MESSAGE_SLOTS;
+                self.lines[slot] = [0u8; MESSAGE_LENGTH];
+                self.lines[slot][..end - start].copy_from_slice(&text[start..end]);
+                self.len += 1;
+                start = end;
+                while start < length && text[start] == b' ' {
+                    start += 1;
+                }
+            }
+        }
+
+        // The nth line still waiting, counting from the oldest.
+        pub fn peek(&self, n: usize) -> Option<&str> {
+            if n >= self.len {
+                return None;
+            }
+            return Some(name_str(&self.lines[(self.head + n) % MESSAGE_SLOTS]));
+        }
+
+        pub fn advance(&mut self) {
+            if self.len > 0 {
+                self.head = (self.head + 1) % MESSAGE_SLOTS;
+                self.len -= 1;
+            }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            return self.len == 0;
+        }
+    }
+
+    // Numbers go into messages a lot. There's no allocator, so they get
+    // written into a buffer the caller owns.
+    fn number(mut n: u32, buf: &mut [u8; 10]) -> &str {
+        let mut i = buf.len();
+        loop {
+            i -= 1;
+            buf[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        return core::str::from_utf8(&buf[i..]).unwrap_or("");
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Choice {
+        Fight(usize),
+        Switch(usize),
+        Item(u8),
+        Run,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Outcome {
+        Won,
+        Lost,
+        Ran,
+        Caught,
+        // Only over a link, when both sides run out at once.
+        Draw,
+    }
+
+    pub enum Opponent {
+        Wild,
+        Trainer {
+            id: u32,
+            name: &'static str,
+            difficulty: Difficulty,
+        },
+        // Another player, picking moves on their own board.
+        Link {
+            name: [u8; 12],
+            host: bool,
+        },
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Side {
+        Player,
+        Opponent,
+    }
+
+    // Stages go from -6 to 6, one per stat, and reset when a pokemon leaves
+    // the field.
+    const STAGE_COUNT: usize = 8;
+    // Per side: who's out, then species, hp, status and pp for each slot,
+    // then the stages.
+    const HASH_SIZE: usize = 2 * (1 + TEAM_SIZE * 8 + STAGE_COUNT);
+
+    pub struct Battle {
+        opponent: Opponent,
+        pub team: [Option<Pokemon>; TEAM_SIZE],
+        pub opponent_active: usize,
+        pub player_active: usize,
+        // PP isn't part of the save yet, so everyone starts a battle full.
+        pub player_pp: [[u8; MOVE_SLOTS]; PARTY_SIZE],
+        opponent_pp: [[u8; MOVE_SLOTS]; TEAM_SIZE],
+        stages: [[i8; STAGE_COUNT]; 2],
+        rng: SeededRandom,
+        escape_attempts: u8,
+        pub outcome: Option<Outcome>,
+        // Who went up a level, so they can be checked for evolution after.
+        pub levelled_up: [bool; PARTY_SIZE],
+    }
+
+    impl Battle {
+        pub fn wild(
+            pokemon: Pokemon,
+            seed: u32,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<Battle, ReadError> {
+            let mut team: [Option<Pokemon>; TEAM_SIZE] = Default::default();
+            team[0] = Some(pokemon);
+            let mut battle = Battle::new(Opponent::Wild, team, seed, save, storage)?;
+            let species = battle.species(Side::Opponent, save, storage)?;
+            messages.push(&["WILD ", name_str(&species.name), " APPEARED!"]);
+            battle.send_out_first(save, storage, messages)?;
+            return Ok(battle);
+        }
+
+        pub fn trainer(
+            trainer: Trainer,
+            difficulty: Difficulty,
+            seed: u32,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<Battle, ReadError> {
+            let opponent = Opponent::Trainer {
+                id: trainer.id,
+                name: trainer.name,
+                difficulty,
+            };
+            let mut battle = Battle::new(opponent, trainer.team, seed, save, storage)?;
+            messages.push(&[trainer.class.name(), " ", trainer.name, " WANTS TO FIGHT!"]);
+            let species = battle.species(Side::Opponent, save, storage)?;
+            messages.push(&[trainer.name, " SENT OUT ", name_str(&species.name), "!"]);
+            battle.send_out_first(save, storage, messages)?;
+            return Ok(battle);
+        }
+
+        // Against another player's party. Both boards set up the same battle
+        // from their own side, and from then on every turn goes through
+        // play_link_turn.
+        pub fn link(
+            team: [Option<Pokemon>; TEAM_SIZE],
+            name: [u8; 12],
+            host: bool,
+            seed: u32,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<Battle, ReadError> {
+            let opponent = Opponent::Link { name, host };
+            let mut battle = Battle::new(opponent, team, seed, save, storage)?;
+            messages.push(&[name_str(&name), " WANTS TO FIGHT!"]);
+            // Their first pokemon might not be up to it.
+            match battle.team.iter().position(can_fight) {
+                Some(slot) => battle.opponent_active = slot,
+                None => {
+                    messages.push(&[name_str(&name), " HAS NO POKEMON THAT CAN FIGHT!"]);
+                    battle.outcome = Some(Outcome::Ran);
+                    return Ok(battle);
+                }
+            }
+            let species = battle.species(Side::Opponent, save, storage)?;
+            save.dex.mark_seen(species.id);
+            messages.push(&[name_str(&name), " SENT OUT ", name_str(&species.name), "!"]);
+            battle.send_out_first(save, storage, messages)?;
+            return Ok(battle);
+        }
+
+        fn new(
+            opponent: Opponent,
+            team: [Option<Pokemon>; TEAM_SIZE],
+            seed: u32,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+        ) -> Result<Battle, ReadError> {
+            let mut battle = Battle {
+                opponent,
+                team,
+                opponent_active: 0,
+                player_active: 0,
+                player_pp: [[0; MOVE_SLOTS]; PARTY_SIZE],
+                opponent_pp: [[0; MOVE_SLOTS]; TEAM_SIZE],
+                stages: [[0; STAGE_COUNT]; 2],
+                rng: SeededRandom::new(seed),
+                escape_attempts: 0,
+                outcome: None,
+                levelled_up: [false; PARTY_SIZE],
+            };
+            for (pp, pokemon) in battle.player_pp.iter_mut().zip(save.party.iter()) {
+                *pp = full_pp(pokemon, storage)?;
+            }
+            for (pp, pokemon) in battle.opponent_pp.iter_mut().zip(battle.team.iter()) {
+                *pp = full_pp(pokemon, storage)?;
+            }
+            if let Some(p) = &battle.team[0] {
+                save.dex.mark_seen(p.species_id);
+            }
+            return Ok(battle);
+        }
+
+        fn send_out_first(
+            &mut self,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            match save.party.iter().position(can_fight) {
+                Some(slot) => return self.send_out(slot, save, storage, messages),
+                None => {
+                    messages.push(&["YOU HAVE NO POKEMON THAT CAN FIGHT!"]);
+                    self.outcome = Some(Outcome::Ran);
+                    return Ok(());
+                }
+            }
+        }
+
+        pub fn is_wild(&self) -> bool {
+            return matches!(self.opponent, Opponent::Wild);
+        }
+
+        pub fn is_link(&self) -> bool {
+            return matches!(self.opponent, Opponent::Link { .. });
+        }
+
+        // The guest sees the battle the other way round from the host. Where
+        // the order matters to the outcome, it goes by the host's side so
+        // both boards agree.
+        fn is_guest(&self) -> bool {
+            return matches!(self.opponent, Opponent::Link { host: false, .. });
+        }
+
+        // Puts a pokemon from the party in, without using up a turn. For the
+        // start of the battle and after the last one fainted.
+        pub fn send_out(
+            &mut self,
+            slot: usize,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            self.player_active = slot;
+            self.stages[0] = [0; STAGE_COUNT];
+            let pokemon = match &save.party[slot] {
+                Some(p) => p,
+                None => return Ok(()),
+            };
+            let species = read_pokemon(pokemon.species_id, storage)?;
+            messages.push(&["GO! ", pokemon.name(&species), "!"]);
+            return Ok(());
+        }
+
+        // Whether the player has to pick someone new before carrying on.
+        pub fn needs_switch(&self, save: &SaveData) -> bool {
+            return self.outcome.is_none() && !can_fight(&save.party[self.player_active]);
+        }
+
+        // Hands over a caught pokemon once the battle's done.
+        pub fn take_caught(&mut self) -> Option<Pokemon> {
+            if self.outcome != Some(Outcome::Caught) {
+                return None;
+            }
+            return self.team[self.opponent_active].take();
+        }
+
+        // For when it stops from outside, like the link dropping.
+        pub fn call_off(&mut self) {
+            if self.outcome.is_none() {
+                self.outcome = Some(Outcome::Ran);
+            }
+        }
+
+        pub fn play_turn(
+            &mut self,
+            choice: Choice,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            return self.turn(choice, None, save, storage, messages);
+        }
+
+        // Both players' picks, with the seed they agreed on for the turn.
+        pub fn play_link_turn(
+            &mut self,
+            choice: Choice,
+            theirs: Choice,
+            seed: u32,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            self.rng = SeededRandom::new(seed);
+            // Running away is giving up, which hands the other side the win.
+            // Items are never offered and can't be sent, so a turn with one
+            // in is a bug, and both boards turn it down the same way.
+            match (choice, theirs) {
+                (Choice::Item(_), _) | (_, Choice::Item(_)) => {
+                    messages.push(&["NO ITEMS IN LINK BATTLES!"]);
+                }
+                (Choice::Run, Choice::Run) => {
+                    messages.push(&["NOBODY WANTS TO FIGHT!"]);
+                    self.outcome = Some(Outcome::Draw);
+                }
+                (Choice::Run, _) => {
+                    messages.push(&["YOU GAVE UP!"]);
+                    self.outcome = Some(Outcome::Lost);
+                }
+                (_, Choice::Run) => {
+                    messages.push(&[self.trainer_name(), " GAVE UP!"]);
+                    self.outcome = Some(Outcome::Won);
+                }
+                _ => return self.turn(choice, Some(theirs), save, storage, messages),
+            }
+            return Ok(());
+        }
+
+        fn turn(
+            &mut self,
+            choice: Choice,
+            theirs: Option<Choice>,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            // Everything other than fighting happens before anyone moves.
+            let mut player_move = None;
+            match choice {
+                Choice::Fight(slot) => {
+                    let pp = self.player_pp[self.player_active];
+                    player_move = match pp.iter().all(|p| *p == 0) {
+                        true => Some(None),
+                        false => Some(Some(slot)),
+                    };
+                }
+                Choice::Switch(slot) => {
+                    if let Some(p) = &save.party[self.player_active] {
+                        let species = read_pokemon(p.species_id, storage)?;
+                        messages.push(&["COME BACK, ", p.name(&species), "!"]);
+                    }
+                    self.send_out(slot, save, storage, messages)?;
+                }
+                Choice::Item(item_id) => {
+                    if !self.use_item(item_id, save, storage, messages)? {
+                        return Ok(());
+                    }
+                    if self.outcome.is_some() {
+                        return Ok(());
+                    }
+                }
+                Choice::Run => {
+                    if !self.is_wild() {
+                        messages.push(&["YOU CAN'T RUN FROM A TRAINER BATTLE!"]);
+                        return Ok(());
+                    }
+                    if self.try_escape(save) {
+                        messages.push(&["GOT AWAY SAFELY!"]);
+                        self.outcome = Some(Outcome::Ran);
+                        return Ok(());
+                    }
+                    messages.push(&["CAN'T ESCAPE!"]);
+                }
+            }
+
+            let action = match theirs {
+                Some(choice) => self.link_action(choice),
+                None => self.choose_opponent_action(save, storage)?,
+            };
+            let opponent_move = match action {
+                Action::Fight(slot) => Some(slot),
+                Action::Struggle => None,
+                Action::Switch(slot) => {
+                    self.opponent_switch(slot, save, storage, messages)?;
+                    return self.finish_turn(None, player_move, save, storage, messages);
+                }
+            };
+            return self.finish_turn(Some(opponent_move), player_move, save, storage, messages);
+        }
+
+        fn finish_turn(
+            &mut self,
+            opponent_move: Option<Option<usize>>,
+            player_move: Option<Option<usize>>,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            // Higher priority goes first, then whoever's faster. Ties are a
+            // coin toss.
+            let player_first = match (player_move, opponent_move) {
+                (Some(p), Some(o)) => {
+                    let player_priority = self.priority(Side::Player, p, save, storage)?;
+                    let opponent_priority = self.priority(Side::Opponent, o, save, storage)?;
+                    let player_speed = self.speed(Side::Player, save);
+                    let opponent_speed = self.speed(Side::Opponent, save);
+                    if player_priority != opponent_priority {
+                        player_priority > opponent_priority
+                    } else if player_speed != opponent_speed {
+                        player_speed > opponent_speed
+                    } else {
+                        // Heads is the host, so it's the same player on
+                        // both boards.
+                        (self.rng.below(2) == 0) != self.is_guest()
+                    }
+                }
+                _ => true,
+            };
+            let order = match player_first {
+                true => [(Side::Player, player_move), (Side::Opponent, opponent_move)],
+                false => [(Side::Opponent, opponent_move), (Side::Player, player_move)],
+            };
+            for (side, m) in order {
+                let slot = match m {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                if !self.both_standing(save) {
+                    break;
+                }
+                self.use_move(side, slot, save, storage, messages)?;
+            }
+
+            let sides = match self.is_guest() {
+                true => [Side::Opponent, Side::Player],
+                false => [Side::Player, Side::Opponent],
+            };
+            for side in sides {
+                if self.both_standing(save) {
+                    self.status_damage(side, save, storage, messages)?;
+                }
+            }
+            return self.check_fainted(save, storage, messages);
+        }
+
+        // Everything a turn can change, host's side first so both boards get
+        // the same number for the same battle.
+        pub fn link_hash(&self, save: &SaveData) -> u32 {
+            let player = (
+                self.player_active,
+                &save.party,
+                &self.player_pp,
+                &self.stages[0],
+            );
+            let opponent = (
+                self.opponent_active,
+                &self.team,
+                &self.opponent_pp,
+                &self.stages[1],
+            );
+            let sides = match self.is_guest() {
+                true => [opponent, player],
+                false => [player, opponent],
+            };
+            let mut buf = [0u8; HASH_SIZE];
+            let mut at = 0;
+            for (active, party, pp, stages) in sides {
+                buf[at] = active as u8;
+                at += 1;
+                for (pokemon, pp) in party.iter().zip(pp.iter()) {
+                    if let Some(p) = pokemon {
+                        buf[at] = p.species_id;
+                        buf[at + 1..at + 3].copy_from_slice(&p.current_hp.to_be_bytes());
+                        buf[at + 3] = p.status.map_or(0, |s| s as u8 + 1);
+                    }
+                    buf[at + 4..at + 8].copy_from_slice(pp);
+                    at += 8;
+                }
+                for stage in stages {
+                    buf[at] = *stage as u8;
+                    at += 1;
+                }
+            }
+            return checksum(&buf);
+        }
+
+        fn both_standing(&self, save: &SaveData) -> bool {
+            return can_fight(&save.party[self.player_active])
+                && can_fight(&self.team[self.opponent_active]);
+        }
+
+        // What the other player picked, put the way the AI would have.
+        fn link_action(&self, choice: Choice) -> Action {
+            let pp = self.opponent_pp[self.opponent_active];
+            match choice {
+                Choice::Switch(slot) => return Action::Switch(slot),
+                Choice::Fight(_) if pp.iter().all(|p| *p == 0) => return Action::Struggle,
+                Choice::Fight(slot) => return Action::Fight(slot),
+                // play_link_turn deals with these before anyone moves.
+                Choice::Item(_) | Choice::Run => unreachable!(),
+            }
+        }
+
+        fn choose_opponent_action(
+            &mut self,
+            save: &SaveData,
+            storage: &mut dyn Storage,
+        ) -> Result<Action, ReadError> {
+            let difficulty = match self.opponent {
+                Opponent::Trainer { difficulty, .. } => difficulty,
+                _ => Difficulty::Wild,
+            };
+            let (own, opponent) = match (
+                &self.team[self.opponent_active],
+                &save.party[self.player_active],
+            ) {
+                (Some(o), Some(p)) => (o, p),
+                _ => return Ok(Action::Struggle),
+            };
+            let own_species = read_pokemon(own.species_id, storage)?;
+            let opponent_species = read_pokemon(opponent.species_id, storage)?;
+            return choose_action(
+                difficulty,
+                &Battler {
+                    pokemon: own,
+                    species: &own_species,
+                    pp: self.opponent_pp[self.opponent_active],
+                },
+                &Battler {
+                    pokemon: opponent,
+                    species: &opponent_species,
+                    pp: self.player_pp[self.player_active],
+                },
+                &self.team,
+                self.opponent_active,
+                &mut self.rng,
+                storage,
+            );
+        }
+
+        fn opponent_switch(
+            &mut self,
+            slot: usize,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            let old = self.species(Side::Opponent, save, storage)?;
+            messages.push(&[self.trainer_name(), " WITHDREW ", name_str(&old.name), "!"]);
+            self.opponent_active = slot;
+            self.stages[1] = [0; STAGE_COUNT];
+            let new = self.species(Side::Opponent, save, storage)?;
+            save.dex.mark_seen(new.id);
+            messages.push(&[self.trainer_name(), " SENT OUT ", name_str(&new.name), "!"]);
+            return Ok(());
+        }
+
+        fn trainer_name(&self) -> &str {
+            match &self.opponent {
+                Opponent::Wild => "",
+                Opponent::Trainer { name, .. } => name,
+                Opponent::Link { name, .. } => name_str(name),
+            }
+        }
+
+        // Returns whether the item got used, and so whether the turn is gone.
+        fn use_item(
+            &mut self,
+            item_id: u8,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<bool, ReadError> {
+            let item = read_item(item_id, storage)?;
+            let cap = level_cap(save.badge_count());
+            if let crate::pokedex::ItemEffect::Ball(_) = item.effect {
+                if !save.has_room() {
+                    messages.push(&["YOUR PARTY AND BOX ARE FULL!"]);
+                    return Ok(false);
+                }
+            }
+            let pokemon = match &mut save.party[self.player_active] {
+                Some(p) => p,
+                None => return Ok(false),
+            };
+            let outcome = match use_item(&item, pokemon, UseContext::Battle, cap, storage) {
+                Ok(o) => o,
+                Err(ItemError(text)) => {
+                    messages.push(&[text]);
+                    return Ok(false);
+                }
+            };
+            save.remove_item(item_id);
+            messages.push(&["USED ", name_str(&item.name), "!"]);
+            match outcome {
+                ItemOutcome::ThrewBall(multiplier) => {
+                    self.throw_ball(multiplier, save, storage, messages)?
+                }
+                o => messages.push(&[o.message()]),
+            }
+            return Ok(true);
+        }
+
+        fn throw_ball(
+            &mut self,
+            multiplier: u8,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            if !self.is_wild() {
+                messages.push(&["THE TRAINER BLOCKED THE BALL!"]);
+                return Ok(());
+            }
+            let target = match &self.team[self.opponent_active] {
+                Some(p) => p,
+                None => return Ok(()),
+            };
+            let species = read_pokemon(target.species_id, storage)?;
+
+            // Roughly the gen 3 formula, squashed into a byte. Weaker and
+            // sleepier pokemon are easier to catch.
+            let max = target.hp.value.max(1) as u32;
+            let mut chance = (3 * max - 2 * target.current_hp as u32)
+                * species.capture_rate as u32
+                * multiplier as u32
+                / 10
+                / (3 * max);
+            chance = match target.status {
+                Some(AilmentType::Sleep) | Some(AilmentType::Freeze) => chance * 2,
+                Some(_) => chance * 3 / 2,
+                None => chance,
+            };
+            if multiplier == u8::MAX || self.rng.random() as u32 <= chance {
+                messages.push(&["GOTCHA! ", name_str(&species.name), " WAS CAUGHT!"]);
+                save.dex.mark_caught(species.id);
+                self.outcome = Some(Outcome::Caught);
+            } else {
+                messages.push(&["OH NO! IT BROKE FREE!"]);
+            }
+            return Ok(());
+        }
+
+        fn try_escape(&mut self, save: &SaveData) -> bool {
+            self.escape_attempts += 1;
+            let own = self.speed(Side::Player, save) as u32;
+            let wild = (self.speed(Side::Opponent, save) as u32 / 4) % 256;
+            // Same as the games: if that wraps round to nothing, there's no
+            // stopping you.
+            if wild == 0 {
+                return true;
+            }
+            let odds = own * 32 / wild + 30 * self.escape_attempts as u32;
+            return odds > 255 || (self.rng.random() as u32) < odds;
+        }
+
+        fn pokemon<'a>(&'a self, side: Side, save: &'a SaveData) -> Option<&'a Pokemon> {
+            match side {
+                Side::Player => save.party[self.player_active].as_ref(),
+                Side::Opponent => self.team[self.opponent_active].as_ref(),
+            }
+        }
+
+        fn species(
+            &self,
+            side: Side,
+            save: &SaveData,
+            storage: &mut dyn Storage,
+        ) -> Result<PokemonSpecies, ReadError> {
+            match self.pokemon(side, save) {
+                Some(p) => return read_pokemon(p.species_id, storage),
+                None => return Err(ReadError("nobody on the field")),
+            }
+        }
+
+        fn stage(&self, side: Side, stat: Stats) -> i8 {
+            return self.stages[side as usize][stat as usize];
+        }
+
+        fn speed(&self, side: Side, save: &SaveData) -> u16 {
+            let pokemon = match self.pokemon(side, save) {
+                Some(p) => p,
+                None => return 0,
+            };
+            let speed = apply_stage(pokemon.speed.value, self.stage(side, Stats::Speed));
+            return match pokemon.status {
+                Some(AilmentType::Paralysis) => speed / 4,
+                _ => speed,
+            };
+        }
+
+        fn priority(
+            &self,
+            side: Side,
+            slot: Option<usize>,
+            save: &SaveData,
+            storage: &mut dyn Storage,
+        ) -> Result<i8, ReadError> {
+            let move_id = match (self.pokemon(side, save), slot) {
+                (Some(p), Some(slot)) => p.moves[slot],
+                _ => None,
+            };
+            match move_id {
+                Some(id) => return Ok(read_move(id, storage)?.priority),
+                None => return Ok(0),
+            }
+        }
+
+        fn use_move(
+            &mut self,
+            side: Side,
+            slot: Option<usize>,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            let attacker_species = self.species(side, save, storage)?;
+            let defender_species = self.species(side.other(), save, storage)?;
+            let (attacker, defender) = match side {
+                Side::Player => (
+                    &mut save.party[self.player_active],
+                    &mut self.team[self.opponent_active],
+                ),
+                Side::Opponent => (
+                    &mut self.team[self.opponent_active],
+                    &mut save.party[self.player_active],
+                ),
+            };
+            let (attacker, defender) = match (attacker, defender) {
+                (Some(a), Some(d)) => (a, d),
+                _ => return Ok(()),
+            };
+            // Copied out so the pokemon can still be changed while their
+            // names are in use.
+            let attacker_name = attacker.nickname.unwrap_or(attacker_species.name);
+            let defender_name = defender.nickname.unwrap_or(defender_species.name);
+            let attacker_name = name_str(&attacker_name);
+            let defender_name = name_str(&defender_name);
+
+            match attacker.status {
+                Some(AilmentType::Sleep) => {
+                    if self.rng.below(3) != 0 {
+                        messages.push(&[attacker_name, " IS FAST ASLEEP!"]);
+                        return Ok(());
+                    }
+                    attacker.status = None;
+                    messages.push(&[attacker_name, " WOKE UP!"]);
+                }
+                Some(AilmentType::Freeze) => {
+                    if self.rng.below(5) != 0 {
+                        messages.push(&[attacker_name, " IS FROZEN SOLID!"]);
+                        return Ok(());
+                    }
+                    attacker.status = None;
+                    messages.push(&[attacker_name, " THAWED OUT!"]);
+                }
+                Some(AilmentType::Paralysis) if self.rng.below(4) == 0 => {
+                    messages.push(&[attacker_name, " IS FULLY PARALYZED!"]);
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            let m = match slot.and_then(|s| attacker.moves[s].map(|m| (s, m))) {
+                Some((s, move_id)) => {
+                    let pp = match side {
+                        Side::Player => &mut self.player_pp[self.player_active][s],
+                        Side::Opponent => &mut self.opponent_pp[self.opponent_active][s],
+                    };
+                    *pp = pp.saturating_sub(1);
+                    read_move(move_id, storage)?
+                }
+                None => struggle(),
+            };
+            messages.push(&[attacker_name, " USED ", name_str(&m.name), "!"]);
+
+            let (own, other) = match side {
+                Side::Player => (0, 1),
+                Side::Opponent => (1, 0),
+            };
+            if m.accuracy != 0 {
+                let stage = self.stages[own][Stats::Accuracy as usize]
+                    - self.stages[other][Stats::Evasion as usize];
+                let accuracy = apply_accuracy_stage(m.accuracy, stage);
+                if self.rng.below(100) >= accuracy {
+                    messages.push(&[attacker_name, "'S ATTACK MISSED!"]);
+                    return Ok(());
+                }
+            }
+            let targets_self = matches!(m.target, Target::User | Target::UserField);
+
+            if m.power > 0 {
+                let effectiveness = defender_species.effectiveness_against(m.type_);
+                if effectiveness == 0 {
+                    messages.push(&["IT DOESN'T AFFECT ", defender_name, "..."]);
+                    return Ok(());
+                }
+                let (attack, defense) = match m.damage_class {
+                    DamageClass::Special => (
+                        apply_stage(
+                            attacker.special_attack.value,
+                            self.stages[own][Stats::SpecialAttack as usize],
+                        ),
+                        apply_stage(
+                            defender.special_defense.value,
+                            self.stages[other][Stats::SpecialDefense as usize],
+                        ),
+                    ),
+                    _ => (
+                        apply_stage(
+                            attacker.attack.value,
+                            self.stages[own][Stats::Attack as usize],
+                        ),
+                        apply_stage(
+                            defender.defense.value,
+                            self.stages[other][Stats::Defense as usize],
+                        ),
+                    ),
+                };
+                let mut damage = damage_formula(
+                    attacker.level,
+                    attack,
+                    defense,
+                    &m,
+                    &attacker_species,
+                    &defender_species,
+                ) as u32;
+                if attacker.status == Some(AilmentType::Burn)
+                    && matches!(m.damage_class, DamageClass::Physical)
+                {
+                    damage /= 2;
+                }
+                let crit_odds = match m.parameters.crit_rate {
+                    0 => 16,
+                    _ => 8,
+                };
+                let critical = self.rng.below(crit_odds) == 0;
+                if critical {
+                    damage *= 2;
+                }
+                damage = (damage * (217 + self.rng.below(39) as u32) / 255).max(1);
+                let dealt = (damage as u16).min(defender.current_hp);
+                defender.current_hp -= dealt;
+
+                if critical {
+                    messages.push(&["A CRITICAL HIT!"]);
+                }
+                if effectiveness > 100 {
+                    messages.push(&["IT'S SUPER EFFECTIVE!"]);
+                } else if effectiveness < 100 {
+                    messages.push(&["IT'S NOT VERY EFFECTIVE..."]);
+                }
+
+                let drain = m.parameters.drain as i32;
+                let amount = ((dealt as i32 * drain.abs() / 100) as u16).max(1);
+                if drain > 0 {
+                    attacker.current_hp = (attacker.current_hp + amount).min(attacker.hp.value);
+                    messages.push(&[defender_name, " HAD ITS ENERGY DRAINED!"]);
+                } else if drain < 0 {
+                    attacker.current_hp = attacker.current_hp.saturating_sub(amount);
+                    messages.push(&[attacker_name, " IS HIT WITH RECOIL!"]);
+                }
+            }
+
+            if m.parameters.healing > 0 {
+                if attacker.current_hp == attacker.hp.value {
+                    messages.push(&["BUT IT FAILED!"]);
+                } else {
+                    let amount = (attacker.hp.value as u32 * m.parameters.healing as u32 / 100)
+                        .max(1) as u16;
+                    attacker.current_hp = (attacker.current_hp + amount).min(attacker.hp.value);
+                    messages.push(&[attacker_name, " REGAINED HEALTH!"]);
+                }
+            }
+
+            let mut did_something = m.power > 0 || m.parameters.healing > 0;
+            if let Some(ailment) = &m.parameters.ailment {
+                let chance = match ailment.chance {
+                    0 => 100,
+                    c => c,
+                };
+                if defender.current_hp > 0
+                    && defender.status.is_none()
+                    && lasting(ailment.ailment)
+                    && self.rng.below(100) < chance
+                {
+                    defender.status = Some(ailment.ailment);
+                    messages.push(&[defender_name, ailment_message(ailment.ailment)]);
+                    did_something = true;
+                }
+            }
+
+            let stat_roll = self.rng.below(100);
+            for change in m.stat_changes.iter().flatten() {
+                let chance = match m.parameters.stat_chance {
+                    0 => 100,
+                    c => c,
+                };
+                if stat_roll >= chance {
+                    break;
+                }
+                let (side_index, name) = match targets_self {
+                    true => (own, attacker_name),
+                    false => (other, defender_name),
+                };
+                if !targets_self && defender.current_hp == 0 {
+                    break;
+                }
+                let stage = &mut self.stages[side_index][change.stat as usize];
+                let before = *stage;
+                *stage = (*stage + change.amount).clamp(-6, 6);
+                did_something = true;
+                let text = match (*stage - before, change.amount > 0) {
+                    (0, true) => " WON'T GO HIGHER!",
+                    (0, false) => " WON'T GO LOWER!",
+                    (1, _) => " ROSE!",
+                    (-1, _) => " FELL!",
+                    (d, _) if d > 1 => " SHARPLY ROSE!",
+                    _ => " HARSHLY FELL!",
+                };
+                messages.push(&[name, "'S ", stat_name(change.stat), text]);
+            }
+
+            if !did_something {
+                messages.push(&["BUT NOTHING HAPPENED!"]);
+            }
+            return Ok(());
+        }
+
+        fn status_damage(
+            &mut self,
+            side: Side,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            let species = self.species(side, save, storage)?;
+            let pokemon = match side {
+                Side::Player => &mut save.party[self.player_active],
+                Side::Opponent => &mut self.team[self.opponent_active],
+            };
+            let pokemon = match pokemon {
+                Some(p) => p,
+                None => return Ok(()),
+            };
+            let text = match pokemon.status {
+                Some(AilmentType::Burn) => "'S HURT BY ITS BURN!",
+                Some(AilmentType::Poison) => "'S HURT BY POISON!",
+                _ => return Ok(()),
+            };
+            let damage = (pokemon.hp.value / 8).max(1);
+            pokemon.current_hp = pokemon.current_hp.saturating_sub(damage);
+            messages.push(&[pokemon.name(&species), text]);
+            return Ok(());
+        }
+
+        fn check_fainted(
+            &mut self,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            if !can_fight(&self.team[self.opponent_active]) {
+                let species = self.species(Side::Opponent, save, storage)?;
+                messages.push(&[name_str(&species.name), " FAINTED!"]);
+                // Nothing's learned beating a friend.
+                if !self.is_link() {
+                    self.award_xp(&species, save, storage, messages)?;
+                }
+
+                match self.team.iter().position(can_fight) {
+                    Some(slot) => {
+                        self.opponent_active = slot;
+                        self.stages[1] = [0; STAGE_COUNT];
+                        let next = self.species(Side::Opponent, save, storage)?;
+                        save.dex.mark_seen(next.id);
+                        messages.push(&[
+                            self.trainer_name(),
+                            " SENT OUT ",
+                            name_str(&next.name),
+                            "!",
+                        ]);
+                    }
+                    None => {
+                        if let Opponent::Trainer { id, .. } = self.opponent {
+                            save.defeated_trainers.record(id);
+                        }
+                        if !self.is_wild() {
+                            messages.push(&["YOU DEFEATED ", self.trainer_name(), "!"]);
+                        }
+                        self.outcome = Some(Outcome::Won);
+                    }
+                }
+            }
+
+            if !can_fight(&save.party[self.player_active]) {
+                if let Some(p) = &save.party[self.player_active] {
+                    let species = read_pokemon(p.species_id, storage)?;
+                    messages.push(&[p.name(&species), " FAINTED!"]);
+                }
+                let next = save.party.iter().position(can_fight);
+                if self.is_link() {
+                    // The other board can't wait on a pick here, so the
+                    // next one in line goes out, same as for trainers.
+                    match (next, self.outcome) {
+                        (Some(slot), None) => self.send_out(slot, save, storage, messages)?,
+                        (None, Some(_)) => {
+                            messages.push(&["IT'S A DRAW!"]);
+                            self.outcome = Some(Outcome::Draw);
+                        }
+                        (None, None) => {
+                            messages.push(&["YOU ARE OUT OF USABLE POKEMON!"]);
+                            self.outcome = Some(Outcome::Lost);
+                        }
+                        _ => {}
+                    }
+                } else if self.outcome.is_none() && next.is_none() {
+                    messages.push(&["YOU ARE OUT OF USABLE POKEMON!"]);
+                    messages.push(&["YOU BLACKED OUT!"]);
+                    // There's no pokemon center to limp back to, so the
+                    // party gets patched up here instead.
+                    for p in save.party.iter_mut().flatten() {
+                        p.current_hp = p.hp.value;
+                        p.status = None;
+                    }
+                    self.outcome = Some(Outcome::Lost);
+                }
+            }
+            return Ok(());
+        }
+
+        fn award_xp(
+            &mut self,
+            defeated_species: &PokemonSpecies,
+            save: &mut SaveData,
+            storage: &mut dyn Storage,
+            messages: &mut Messages,
+        ) -> Result<(), ReadError> {
+            let defeated_level = match &self.team[self.opponent_active] {
+                Some(p) => p.level as u32,
+                None => return Ok(()),
+            };
+            let mut xp = defeated_species.base_experience as u32 * defeated_level / 7;
+            if !self.is_wild() {
+                xp = xp * 3 / 2;
+            }
+            let cap = level_cap(save.badge_count());
+            let slot = self.player_active;
+            let pokemon = match &mut save.party[slot] {
+                Some(p) if p.current_hp > 0 => p,
+                _ => return Ok(()),
+            };
+            let species = read_pokemon(pokemon.species_id, storage)?;
+            let mut buf = [0u8; 10];
+            messages.push(&[
+                pokemon.name(&species),
+                " GAINED ",
+                number(xp, &mut buf),
+                " EXP!",
+            ]);
+            pokemon.xp += xp;
+            while pokemon.level < cap
+                && pokemon.xp >= species.growth_rate.xp_for_level(pokemon.level + 1)
+            {
+                pokemon.level += 1;
+                recalculate_stats(pokemon, &species);
+                self.levelled_up[slot] = true;
+                messages.push(&[
+                    pokemon.name(&species),
+                    " GREW TO LV. ",
+                    number(pokemon.level as u32, &mut buf),
+                    "!",
+                ]);
+            }
+            return Ok(());
+        }
+    }
+
+    impl Side {
+        fn other(&self) -> Side {
+            match self {
+                Side::Player => Side::Opponent,
+                Side::Opponent => Side::Player,
+            }
+        }
+    }
+
+    fn can_fight(pokemon: &Option<Pokemon>) -> bool {
+        return pokemon.as_ref().is_some_and(|p| p.current_hp > 0);
+    }
+
+    fn full_pp(
+        pokemon: &Option<Pokemon>,
+        storage: &mut dyn Storage,
+    ) -> Result<[u8; MOVE_SLOTS], ReadError> {
+        let mut pp = [0; MOVE_SLOTS];
+        if let Some(p) = pokemon {
+            for (slot, m) in p.moves.iter().enumerate() {
+                if let Some(id) = m {
+                    pp[slot] = read_move(*id, storage)?.pp;
+                }
+            }
+        }
+        return Ok(pp);
+    }
+
+    fn apply_stage(value: u16, stage: i8) -> u16 {
+        let stage = stage as i32;
+        let value = value as i32;
+        let staged = match stage >= 0 {
+            true => value * (2 + stage) / 2,
+            false => value * 2 / (2 - stage),
+        };
+        return staged.max(1) as u16;
+    }
+
+    fn apply_accuracy_stage(accuracy: u8, stage: i8) -> u8 {
+        let stage = stage.clamp(-6, 6) as i32;
+        let accuracy = accuracy as i32;
+        let staged = match stage >= 0 {
+            true => accuracy * (3 + stage) / 3,
+            false => accuracy * 3 / (3 - stage),
+        };
+        return staged.clamp(1, 255) as u8;
+    }
+
+    // Only the ailments that stick around after the battle are tracked on
+    // the pokemon. Confusion and friends need their own battle state first.
+    fn lasting(ailment: AilmentType) -> bool {
+        return matches!(
+            ailment,
+            AilmentType::Burn
+                | AilmentType::Freeze
+                | AilmentType::Paralysis
+                | AilmentType::Poison
+                | AilmentType::Sleep
+        );
+    }
+
+    fn ailment_message(ailment: AilmentType) -> &'static str {
+        match ailment {
+            AilmentType::Burn => " WAS BURNED!",
+            AilmentType::Freeze => " WAS FROZEN SOLID!",
+            AilmentType::Paralysis => " IS PARALYZED!",
+            AilmentType::Poison => " WAS POISONED!",
+            AilmentType::Sleep => " FELL ASLEEP!",
+            _ => " IS AFFECTED!",
+        }
+    }
+
+    fn stat_name(stat: Stats) -> &'static str {
+        match stat {
+            Stats::Hp => "HP",
+            Stats::Attack => "ATTACK",
+            Stats::Defense => "DEFENSE",
+            Stats::SpecialAttack => "SP. ATK",
+            Stats::SpecialDefense => "SP. DEF",
+            Stats::Speed => "SPEED",
+            Stats::Accuracy => "ACCURACY",
+            Stats::Evasion => "EVASION",
+        }
+    }
+
+    // What everyone falls back on once they're out of PP.
+    fn struggle() -> Move {
+        return Move {
+            id: 165,
+            name: *b"STRUGGLE\0\0\0\0",
+            type_: Type::Normal,
+            damage_class: DamageClass::Physical,
+            target: Target::SelectedPokemon,
+            accuracy: 0,
+            power: 50,
+            pp: 1,
+            priority: 0,
+            parameters: Parameters {
+                ailment: None,
+                crit_rate: 0,
+                drain: -25,
+                flinch_chance: 0,
+                healing: 0,
+                stat_chance: 0,
+                turn_range: None,
+                hit_range: None,
+            },
+            stat_changes: [None, None],
+        };
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::pokedex::AilmentParameter;
+        use crate::rom::testing::{attack, pokemon, species, TestRom};
+
+        const FIRE: u8 = 1;
+        const GRASS: u8 = 2;
+        const WATER: u8 = 3;
+
+        const TACKLE: u16 = 1;
+        const EMBER: u16 = 2;
+        const QUICK_ATTACK: u16 = 3;
+
+        fn rom() -> TestRom {
+            let mut rom = TestRom::new();
+            rom.add_species(&species(FIRE, Type::Fire, None));
+            rom.add_species(&species(GRASS, Type::Grass, None));
+            rom.add_species(&species(WATER, Type::Water, None));
+            rom.add_move(&attack(TACKLE, Type::Normal, 40, 35));
+            // Something for the rng to decide besides damage, and something
+            // to jump the queue.
+            let mut ember = attack(EMBER, Type::Fire, 40, 25);
+            ember.parameters.ailment = Some(AilmentParameter {
+                ailment: AilmentType::Burn,
+                chance: 30,
+            });
+            rom.add_move(&ember);
+            let mut quick_attack = attack(QUICK_ATTACK, Type::Normal, 40, 30);
+            quick_attack.priority = 1;
+            rom.add_move(&quick_attack);
+            return rom;
+        }
+
+        fn party(species_ids: [u8; 3]) -> SaveData {
+            let moves = [Some(TACKLE), Some(EMBER), Some(QUICK_ATTACK), None];
+            let mut save = SaveData::new();
+            for (slot, id) in species_ids.iter().enumerate() {
+                let types = [Type::Fire, Type::Grass, Type::Water];
+                save.party[slot] = Some(pokemon(
+                    species(*id, types[*id as usize - 1], None),
+                    20,
+                    moves,
+                ));
+            }
+            return save;
+        }
+
+        fn team(save: &SaveData) -> [Option<Pokemon>; TEAM_SIZE] {
+            return save.party.clone();
+        }
+
+        // Mostly attacks, with a switch now and then to whoever's still up.
+        fn pick(battle: &Battle, save: &SaveData, rng: &mut SeededRandom) -> Choice {
+            if rng.below(6) == 0 {
+                let mut others = (0..PARTY_SIZE)
+                    .filter(|slot| *slot != battle.player_active && can_fight(&save.party[*slot]));
+                if let Some(slot) = others.next_back() {
+                    return Choice::Switch(slot);
+                }
+            }
+            return Choice::Fight(rng.below(3) as usize);
+        }
+
+        #[test]
+        fn link_battles_stay_in_step() {
+            let mut rom = rom();
+            // Fire against fire ties on speed, so the coin toss gets used.
+            let mut host_save = party([FIRE, WATER, GRASS]);
+            let mut guest_save = party([FIRE, GRASS, WATER]);
+            let host_name = *b"RED\0\0\0\0\0\0\0\0\0";
+            let guest_name = *b"BLUE\0\0\0\0\0\0\0\0";
+            let mut messages = Messages::new();
+            let guest_team = team(&guest_save);
+            let mut host = Battle::link(
+                guest_team,
+                guest_name,
+                true,
+                7,
+                &mut host_save,
+                &mut rom,
+                &mut messages,
+            )
+            .unwrap();
+            let host_team = team(&host_save);
+            let mut guest = Battle::link(
+                host_team,
+                host_name,
+                false,
+                7,
+                &mut guest_save,
+                &mut rom,
+                &mut messages,
+            )
+            .unwrap();
+            assert_eq!(host.link_hash(&host_save), guest.link_hash(&guest_save));
+
+            let mut host_picks = SeededRandom::new(1);
+            let mut guest_picks = SeededRandom::new(2);
+            let mut turns = 0;
+            while host.outcome.is_none() && turns < 200 {
+                let mine = pick(&host, &host_save, &mut host_picks);
+                let theirs = pick(&guest, &guest_save, &mut guest_picks);
+                let seed = 1000 + turns;
+                let mut messages = Messages::new();
+                host.play_link_turn(mine, theirs, seed, &mut host_save, &mut rom, &mut messages)
+                    .unwrap();
+                let mut messages = Messages::new();
+                guest
+                    .play_link_turn(theirs, mine, seed, &mut guest_save, &mut rom, &mut messages)
+                    .unwrap();
+                assert_eq!(
+                    host.link_hash(&host_save),
+                    guest.link_hash(&guest_save),
+                    "turn {}",
+                    turns
+                );
+                turns += 1;
+            }
+
+            // Someone won, and both boards agree on who.
+            let expected = match host.outcome {
+                Some(Outcome::Won) => Some(Outcome::Lost),
+                Some(Outcome::Lost) => Some(Outcome::Won),
+                other => other,
+            };
+            assert!(host.outcome.is_some());
+            assert_eq!(guest.outcome, expected);
+            assert!(turns > 5);
+        }
+
+        #[test]
+        fn damage_follows_the_formula() {
+            let mut attacker = pokemon(species(FIRE, Type::Fire, None), 20, [None; 4]);
+            let mut defender = pokemon(species(GRASS, Type::Grass, None), 20, [None; 4]);
+            attacker.attack.value = 50;
+            defender.defense.value = 50;
+            let tackle = attack(TACKLE, Type::Normal, 40, 35);
+            let ember = attack(EMBER, Type::Fire, 40, 25);
+            let damage = |attacker: &Pokemon, defending: Type, m: &Move| {
+                return base_damage(
+                    attacker,
+                    &species(FIRE, Type::Fire, None),
+                    &defender,
+                    &species(GRASS, defending, None),
+                    m,
+                );
+            };
+
+            // (2 * 20 / 5 + 2) * 40 * 50 / 50 / 50 + 2
+            assert_eq!(damage(&attacker, Type::Normal, &tackle), 10);
+            // Half as much again for a fire type using a fire move, then
+            // doubled, halved or left alone by what it hits.
+            assert_eq!(damage(&attacker, Type::Grass, &ember), 30);
+            assert_eq!(damage(&attacker, Type::Water, &ember), 7);
+            assert_eq!(damage(&attacker, Type::Normal, &ember), 15);
+
+            attacker.attack.value = 100;
+            assert_eq!(damage(&attacker, Type::Normal, &tackle), 18);
+
+            let mut growl = attack(TACKLE, Type::Normal, 0, 40);
+            growl.damage_class = DamageClass::Status;
+            assert_eq!(damage(&attacker, Type::Normal, &growl), 0);
+        }
+
+        fn wild_battle(own_speed: u16, wild_speed: u16, seed: u32) -> (Battle, SaveData) {
+            let mut rom = rom();
+            let mut save = party([FIRE, GRASS, WATER]);
+            let wild = pokemon(
+                species(WATER, Type::Water, None),
+                20,
+                [Some(TACKLE), None, None, None],
+            );
+            let mut messages = Messages::new();
+            let mut battle = Battle::wild(wild, seed, &mut save, &mut rom, &mut messages).unwrap();
+            save.party[0].as_mut().unwrap().speed.value = own_speed;
+            battle.team[0].as_mut().unwrap().speed.value = wild_speed;
+            return (battle, save);
+        }
+
+        #[test]
+        fn escaping_gets_easier_with_speed_and_attempts() {
+            for seed in 1..100 {
+                // Outrunning it is a sure thing.
+                let (mut battle, save) = wild_battle(200, 100, seed);
+                assert!(battle.try_escape(&save));
+                // So is a speed that wraps round to nothing once it's quartered.
+                let (mut battle, save) = wild_battle(1, 1024, seed);
+                assert!(battle.try_escape(&save));
+                // However slow it is, the ninth go gets away.
+                let (mut battle, save) = wild_battle(1, 400, seed);
+                assert!((0..9).any(|_| battle.try_escape(&save)));
+            }
+
+            // Otherwise each go has a 30 in 256 chance to start with.
+            let escaped = (1..1000)
+                .filter(|seed| {
+                    let (mut battle, save) = wild_battle(1, 400, *seed);
+                    return battle.try_escape(&save);
+                })
+                .count();
+            assert!((60..180).contains(&escaped), "{}", escaped);
+        }
+
+        fn catches(current_hp: u16, status: Option<AilmentType>, multiplier: u8) -> usize {
+            let mut rom = rom();
+            return (1..500)
+                .filter(|seed| {
+                    let (mut battle, mut save) = wild_battle(100, 100, *seed);
+                    let wild = battle.team[0].as_mut().unwrap();
+                    wild.current_hp = current_hp;
+                    wild.status = status;
+                    let mut messages = Messages::new();
+                    battle
+                        .throw_ball(multiplier, &mut save, &mut rom, &mut messages)
+                        .unwrap();
+                    return battle.outcome == Some(Outcome::Caught);
+                })
+                .count();
+        }
+
+        #[test]
+        fn catching_gets_easier_as_it_weakens() {
+            let full_hp = pokemon(species(WATER, Type::Water, None), 20, [None; 4])
+                .hp
+                .value;
+            // A capture rate of 100 at full health is a 34 in 256 chance.
+            let healthy = catches(full_hp, None, 10);
+            assert!((30..110).contains(&healthy), "{}", healthy);
+            let weak = catches(1, None, 10);
+            let asleep = catches(1, Some(AilmentType::Sleep), 10);
+            let burnt = catches(1, Some(AilmentType::Burn), 10);
+            assert!(healthy < weak && weak < burnt && burnt < asleep);
+            assert_eq!(catches(full_hp, None, u8::MAX), 499);
+        }
+
+        // Anything that isn't wild counts, link battles included.
+        #[test]
+        fn trainers_block_the_ball() {
+            let mut rom = rom();
+            let mut save = party([FIRE, GRASS, WATER]);
+            let opponent = team(&save);
+            let mut messages = Messages::new();
+            let mut battle = Battle::link(
+                opponent,
+                *b"BLUE\0\0\0\0\0\0\0\0",
+                true,
+                7,
+                &mut save,
+                &mut rom,
+                &mut messages,
+            )
+            .unwrap();
+            battle
+                .throw_ball(u8::MAX, &mut save, &mut rom, &mut messages)
+                .unwrap();
+            assert_eq!(battle.outcome, None);
+        }
+    }
+}
+
+pub mod ai {
+    use crate::battle::{base_damage, MOVE_SLOTS};
+    use crate::generation::Random;
+    use crate::pokedex::{AilmentType, Move, Pokemon, PokemonSpecies, Target};
+    use crate::rom::{read_move, read_pokemon, ReadError, Storage};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Difficulty {
+        // Wild pokemon just flail about.
+        Wild,
+        Easy,
+        Normal,
+        Hard,
+    }
+
+    impl Difficulty {
+        // How much randomness gets mixed into each move's score. The lower
+        // it is, the more often the AI makes the best choice.
+        fn noise(&self) -> u8 {
+            match self {
+                Difficulty::Wild => 0,
+                Difficulty::Easy => 60,
+                Difficulty::Normal => 20,
+                Difficulty::Hard => 5,
+            }
+        }
+
+        // How bad a matchup has to be before switching out, or None to never
+        // switch.
+        fn switch_threshold(&self) -> Option<i16> {
+            match self {
+                Difficulty::Wild | Difficulty::Easy => None,
+                Difficulty::Normal => Some(-150),
+                Difficulty::Hard => Some(-100),
+            }
+        }
+    }
+
+    // One side of the battle as the AI sees it. PP belongs to the battle
+    // rather than the pokemon, so it gets passed in alongside.
+    pub struct Battler<'a> {
+        pub pokemon: &'a Pokemon,
+        pub species: &'a PokemonSpecies,
+        pub pp: [u8; MOVE_SLOTS],
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Action {
+        // The move slot to use.
+        Fight(usize),
+        // The team slot to bring in.
+        Switch(usize),
+        // Nothing left with any PP.
+        Struggle,
+    }
+
+    pub fn choose_action(
+        difficulty: Difficulty,
+        own: &Battler,
+        opponent: &Battler,
+        team: &[Option<Pokemon>],
+        active: usize,
+        rng: &mut dyn Random,
+        storage: &mut dyn Storage,
+    ) -> Result<Action, ReadError> {
+        let mut usable = [0usize; MOVE_SLOTS];
+        let mut usable_count = 0;
+        for slot in 0..MOVE_SLOTS {
+            if own.pokemon.moves[slot].is_some() && own.pp[slot] > 0 {
+                usable[usable_count] = slot;
+                usable_count += 1;
+            }
+        }
+        if usable_count == 0 {
+            return Ok(Action::Struggle);
+        }
+        if difficulty == Difficulty::Wild {
+            let pick = rng.below(usable_count as u8) as usize;
+            return Ok(Action::Fight(usable[pick]));
+        }
+
+        if let Some(threshold) = difficulty.switch_threshold() {
+            if let Some(slot) = pick_switch(threshold, own, opponent, team, active, storage)? {
+                return Ok(Action::Switch(slot));
+            }
+        }
+
+        // Every usable move gets a score and a bit of noise, in slot order so
+        // the same seed always gives the same choice.
+        let mut best = usable[0];
+        let mut best_score = i16::MIN;
+        for slot in usable[..usable_count].iter() {
+            let move_id = match own.pokemon.moves[*slot] {
+                Some(m) => m,
+                None => continue,
+            };
+            let m = read_move(move_id, storage)?;
+            let score =
+                score_move(&m, own.pp[*slot], own, opponent) + rng.below(difficulty.noise()) as i16;
+            if score > best_score {
+                best = *slot;
+                best_score = score;
+            }
+        }
+        return Ok(Action::Fight(best));
+    }
+
+    // Roughly how much a move is worth using right now. Damage counts as the
+    // share of the opponent's remaining hp it would take off.
+    fn score_move(m: &Move, pp: u8, own: &Battler, opponent: &Battler) -> i16 {
+        let targets_self = matches!(m.target, Target::User | Target::UserField);
+        if !targets_self && opponent.species.effectiveness_against(m.type_) == 0 {
+            return 0;
+        }
+        let accuracy = match m.accuracy {
+            0 => 100,
+            a => a as i16,
+        };
+
+        let mut score: i16 = 0;
+        if m.power > 0 {
+            let damage = base_damage(
+                own.pokemon,
+                own.species,
+                opponent.pokemon,
+                opponent.species,
+                m,
+            );
+            let hp = opponent.pokemon.current_hp.max(1);
+            score += (damage as u32 * 100 / hp as u32).min(100) as i16;
+            if damage >= hp {
+                score += 50;
+            }
+        }
+        if let Some(ailment) = &m.parameters.ailment {
+            if opponent.pokemon.status.is_none() {
+                // Status moves leave the chance at zero because they always
+                // land if they hit.
+                let chance = match ailment.chance {
+                    0 => 100,
+                    c => c as i16,
+                };
+                score += ailment_value(ailment.ailment) * chance / 100;
+            }
+        }
+        for change in m.stat_changes.iter().flatten() {
+            let amount = change.amount as i16;
+            if targets_self && amount > 0 {
+                score += 10 * amount;
+            } else if !targets_self && amount < 0 {
+                score -= 8 * amount;
+            }
+        }
+        if m.parameters.healing > 0 && own.pokemon.current_hp < own.pokemon.hp.value / 2 {
+            score += m.parameters.healing as i16;
+        }
+
+        score = score * accuracy / 100;
+        // Hang on to the last few uses of a move until there's nothing else.
+        if pp <= 2 {
+            score -= 10;
+        }
+        return score;
+    }
+
+    fn ailment_value(ailment: AilmentType) -> i16 {
+        match ailment {
+            AilmentType::Sleep | AilmentType::Freeze => 60,
+            AilmentType::Paralysis => 50,
+            AilmentType::Burn => 40,
+            AilmentType::Poison | AilmentType::LeechSeed => 35,
+            AilmentType::Confusion => 30,
+            AilmentType::Disable | AilmentType::Trap => 20,
+        }
+    }
+
+    // Positive when the first species has the upper hand on types alone.
+    fn matchup(own: &PokemonSpecies, opponent: &PokemonSpecies) -> i16 {
+        let offense = best_effectiveness(own, opponent);
+        let defense = best_effectiveness(opponent, own);
+        return offense as i16 - defense as i16;
+    }
+
+    fn best_effectiveness(attacker: &PokemonSpecies, defender: &PokemonSpecies) -> u16 {
+        let primary = defender.effectiveness_against(attacker.type_primary);
+        return match attacker.type_secondary {
+            Some(t) => primary.max(defender.effectiveness_against(t)),
+            None => primary,
+        };
+    }
+
+    // Looks for someone better suited to the current opponent, if the
+    // current matchup is bad enough to be worth giving up a turn for.
+    fn pick_switch(
+        threshold: i16,
+        own: &Battler,
+        opponent: &Battler,
+        team: &[Option<Pokemon>],
+        active: usize,
+        storage: &mut dyn Storage,
+    ) -> Result<Option<usize>, ReadError> {
+        let current = matchup(own.species, opponent.species);
+        if current > threshold {
+            return Ok(None);
+        }
+        let mut best = None;
+        let mut best_matchup = current;
+        for (slot, pokemon) in team.iter().enumerate() {
+            let pokemon = match pokemon {
+                Some(p) if slot != active && p.current_hp > 0 => p,
+                _ => continue,
+            };
+            let species = read_pokemon(pokemon.species_id, storage)?;
+            let candidate = matchup(&species, opponent.species);
+            if candidate > best_matchup {
+                best = Some(slot);
+                best_matchup = candidate;
+            }
+        }
+        return Ok(best);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::generation::SeededRandom;
+        use crate::pokedex::Type;
+        use crate::rom::testing::{attack, pokemon, species, TestRom};
+
+        const FIRE: u8 = 1;
+        const GRASS: u8 = 2;
+        const WATER: u8 = 3;
+
+        const EMBER: u16 = 1;
+        const TACKLE: u16 = 2;
+        const POUND: u16 = 3;
+
+        fn rom() -> TestRom {
+            let mut rom = TestRom::new();
+            rom.add_species(&species(FIRE, Type::Fire, None));
+            rom.add_species(&species(GRASS, Type::Grass, None));
+            rom.add_species(&species(WATER, Type::Water, None));
+            rom.add_move(&attack(EMBER, Type::Fire, 40, 25));
+            rom.add_move(&attack(TACKLE, Type::Normal, 40, 35));
+            rom.add_move(&attack(POUND, Type::Normal, 40, 35));
+            return rom;
+        }
+
+        // Picks for the first of the team against a lone opponent.
+        fn choose(
+            difficulty: Difficulty,
+            team: &[Option<Pokemon>],
+            active: usize,
+            pp: [u8; MOVE_SLOTS],
+            opponent: &Pokemon,
+            seed: u32,
+            rom: &mut TestRom,
+        ) -> Action {
+            let own = team[active].as_ref().unwrap();
+            let own_species = read_pokemon(own.species_id, rom).unwrap();
+            let opponent_species = read_pokemon(opponent.species_id, rom).unwrap();
+            let own = Battler {
+                pokemon: own,
+                species: &own_species,
+                pp,
+            };
+            let opponent = Battler {
+                pokemon: opponent,
+                species: &opponent_species,
+                pp: [10; MOVE_SLOTS],
+            };
+            let mut rng = SeededRandom::new(seed);
+            return choose_action(difficulty, &own, &opponent, team, active, &mut rng, rom)
+                .unwrap();
+        }
+
+        #[test]
+        fn prefers_super_effective_moves() {
+            let mut rom = rom();
+            let moves = [Some(TACKLE), Some(EMBER), None, None];
+            let team = [Some(pokemon(species(FIRE, Type::Fire, None), 20, moves))];
+            let opponent = pokemon(species(GRASS, Type::Grass, None), 20, [None; 4]);
+            for seed in 0..50 {
+                let action = choose(
+                    Difficulty::Hard,
+                    &team,
+                    0,
+                    [35, 25, 0, 0],
+                    &opponent,
+                    seed,
+                    &mut rom,
+                );
+                assert_eq!(action, Action::Fight(1));
+            }
+        }
+
+        #[test]
+        fn saves_the_last_few_pp() {
+            let mut rom = rom();
+            let moves = [Some(TACKLE), Some(POUND), None, None];
+            let team = [Some(pokemon(species(FIRE, Type::Fire, None), 20, moves))];
+            let opponent = pokemon(species(WATER, Type::Water, None), 20, [None; 4]);
+            for seed in 0..50 {
+                let action = choose(
+                    Difficulty::Hard,
+                    &team,
+                    0,
+                    [2, 35, 0, 0],
+                    &opponent,
+                    seed,
+                    &mut rom,
+                );
+                assert_eq!(action, Action::Fight(1));
+            }
+            // Unless it's all that's left.
+            let action = choose(
+                Difficulty::Hard,
+                &team,
+                0,
+                [2, 0, 0, 0],
+                &opponent,
+                0,
+                &mut rom,
+            );
+            assert_eq!(action, Action::Fight(0));
+            let action = choose(
+                Difficulty::Hard,
+                &team,
+                0,
+                [0, 0, 0, 0],
+                &opponent,
+                0,
+                &mut rom,
+            );
+            assert_eq!(action, Action::Struggle);
+        }
+
+        #[test]
+        fn switches_out_of_a_bad_matchup_and_stays_switched() {
+            let mut rom = rom();
+            let moves = [Some(TACKLE), None, None, None];
+            let team = [
+                Some(pokemon(species(GRASS, Type::Grass, None), 20, moves)),
+                Some(pokemon(species(WATER, Type::Water, None), 20, moves)),
+            ];
+            let opponent = pokemon(species(FIRE, Type::Fire, None), 20, [None; 4]);
+            let pp = [35, 0, 0, 0];
+            for difficulty in [Difficulty::Normal, Difficulty::Hard] {
+                let action = choose(difficulty, &team, 0, pp, &opponent, 0, &mut rom);
+                assert_eq!(action, Action::Switch(1));
+                // Once it's in, the one it came from isn't any better.
+                let action = choose(difficulty, &team, 1, pp, &opponent, 0, &mut rom);
+                assert_eq!(action, Action::Fight(0));
+            }
+            for difficulty in [Difficulty::Wild, Difficulty::Easy] {
+                let action = choose(difficulty, &team, 0, pp, &opponent, 0, &mut rom);
+                assert_eq!(action, Action::Fight(0));
+            }
+        }
+
+        #[test]
+        fn stays_in_without_anyone_better() {
+            let mut rom = rom();
+            let moves = [Some(TACKLE), None, None, None];
+            let mut fainted = pokemon(species(WATER, Type::Water, None), 20, moves);
+            fainted.current_hp = 0;
+            let team = [
+                Some(pokemon(species(GRASS, Type::Grass, None), 20, moves)),
+                Some(fainted),
+                Some(pokemon(species(GRASS, Type::Grass, None), 20, moves)),
+            ];
+            let opponent = pokemon(species(FIRE, Type::Fire, None), 20, [None; 4]);
+            let action = choose(
+                Difficulty::Hard,
+                &team,
+                0,
+                [35, 0, 0, 0],
+                &opponent,
+                0,
+                &mut rom,
+            );
+            assert_eq!(action, Action::Fight(0));
+        }
+
+        #[test]
+        fn same_seed_gives_the_same_choice() {
+            let mut rom = rom();
+            let moves = [Some(TACKLE), Some(POUND), Some(EMBER), None];
+            let team = [Some(pokemon(species(FIRE, Type::Fire, None), 20, moves))];
+            let opponent = pokemon(species(WATER, Type::Water, None), 20, [None; 4]);
+            let pp = [35, 35, 25, 0];
+            let mut picked = [false; MOVE_SLOTS];
+            for seed in 0..50 {
+                let first = choose(Difficulty::Easy, &team, 0, pp, &opponent, seed, &mut rom);
+                let again = choose(Difficulty::Easy, &team, 0, pp, &opponent, seed, &mut rom);
+                assert_eq!(first, again);
+                if let Action::Fight(slot) = first {
+                    picked[slot] = true;
+                }
+            }
+            // The noise really is doing something on easy.
+            assert!(picked.iter().filter(|p| **p).count() > 1);
+        }
+    }
+}
+
+pub mod items {
+    use crate::evolution::find_evolution;
+    use crate::generation::recalculate_stats;
+    use crate::pokedex::{EvolutionTrigger, Item, ItemEffect, LearnCondition, Pokemon};
+    use crate::rom::{read_move_list, read_pokemon, ReadError, Storage};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum UseContext {
+        Field,
+        Battle,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ItemOutcome {
+        Healed(u16),
+        Cured,
+        Revived,
+        LevelledUp(u8),
+        LearnedMove(u16),
+        // The battle takes it from here, it knows what's being caught.
+        ThrewBall(u8),
+        // Nothing has changed yet, the evolution screen does the rest.
+        Evolving(u8),
+        // Nothing has changed yet either. There's no room for the move, so
+        // the player has to pick one to forget first.
+        MovesFull(u16),
+    }
+
+    impl ItemOutcome {
+        pub fn message(&self) -> &'static str {
+            match self {
+                ItemOutcome::Healed(_) => "HP RESTORED",
+                ItemOutcome::Cured => "STATUS CURED",
+                ItemOutcome::Revived => "REVIVED",
+                ItemOutcome::LevelledUp(_) => "LEVELLED UP",
+                ItemOutcome::LearnedMove(_) => "LEARNED MOVE",
+                ItemOutcome::ThrewBall(_) => "THREW A BALL",
+                ItemOutcome::Evolving(_) => "WHAT? IT'S EVOLVING",
+                ItemOutcome::MovesFull(_) => "FORGET WHICH MOVE?",
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ItemError(pub &'static str);
+
+    impl From<ReadError> for ItemError {
+        fn from(e: ReadError) -> ItemError {
+            let ReadError(text) = e;
+            return ItemError(text);
+        }
+    }
+
+    const NO_EFFECT: ItemError = ItemError("IT WON'T HAVE ANY EFFECT");
+
+    impl ItemEffect {
+        pub fn usable_in(&self, context: UseContext) -> bool {
+            return !matches!(
+                (self, context),
+                (ItemEffect::Ball(_), UseContext::Field)
+                    | (ItemEffect::EvolutionStone, UseContext::Battle)
+                    | (ItemEffect::TechnicalMachine(_), UseContext::Battle)
+                    | (ItemEffect::RareCandy, UseContext::Battle)
+            );
+        }
+    }
+
+    // Puts a move from a TM in the given slot, forgetting whatever was there.
+    pub fn teach_move(pokemon: &mut Pokemon, slot: usize, move_id: u16) -> ItemOutcome {
+        pokemon.moves[slot] = Some(move_id);
+        return ItemOutcome::LearnedMove(move_id);
+    }
+
+    // Uses an item on a pokemon. If it doesn't do anything the pokemon is
+    // left alone and the item shouldn't be taken out of the bag.
+    pub fn use_item(
+        item: &Item,
+        pokemon: &mut Pokemon,
+        context: UseContext,
+        level_cap: u8,
+        storage: &mut dyn Storage,
+    ) -> Result<ItemOutcome, ItemError> {
+        if !item.effect.usable_in(context) {
+            return Err(ItemError("NOW IS NOT THE TIME"));
+        }
+        let fainted = pokemon.current_hp == 0;
+
+        match item.effect {
+            ItemEffect::Heal(amount) => {
+                if fainted || pokemon.current_hp >= pokemon.hp.value {
+                    return Err(NO_EFFECT);
+                }
+                let before = pokemon.current_hp;
+                pokemon.current_hp = match amount {
+                    0 => pokemon.hp.value,
+                    _ => pokemon
+                        .current_hp
+                        .saturating_add(amount)
+                        .min(pokemon.hp.value),
+                };
+                return Ok(ItemOutcome::Healed(pokemon.current_hp - before));
+            }
+            ItemEffect::CureStatus(ailment) => {
+                let cured = match (pokemon.status, ailment) {
+                    (None, _) => false,
+                    (Some(_), None) => true,
+                    (Some(current), Some(wanted)) => current == wanted,
+                };
+                if fainted || !cured {
+                    return Err(NO_EFFECT);
+                }
+                pokemon.status = None;
+                return Ok(ItemOutcome::Cured);
+            }
+            ItemEffect::Ball(multiplier) => return Ok(ItemOutcome::ThrewBall(multiplier)),
+            ItemEffect::Revive(percent) => {
+                if !fainted {
+                    return Err(NO_EFFECT);
+                }
+                let hp = (pokemon.hp.value as u32 * percent as u32 / 100).max(1);
+                pokemon.current_hp = hp as u16;
+                pokemon.status = None;
+                return Ok(ItemOutcome::Revived);
+            }
+            // Stones only do something to pokemon that can evolve with them.
+            ItemEffect::EvolutionStone => {
+                let species = read_pokemon(pokemon.species_id, storage)?;
+                match find_evolution(pokemon, &species, EvolutionTrigger::Item(item.id)) {
+                    Some(into) if !fainted => return Ok(ItemOutcome::Evolving(into)),
+                    _ => return Err(NO_EFFECT),
+                }
+            }
+            ItemEffect::TechnicalMachine(move_id) => {
+                if pokemon.moves.contains(&Some(move_id)) {
+                    return Err(NO_EFFECT);
+                }
+                let compatible = read_move_list(pokemon.species_id, storage)?
+                    .iter()
+                    .any(|m| m.id == move_id && matches!(m.condition, LearnCondition::Machine));
+                if !compatible {
+                    return Err(ItemError("IT CAN'T LEARN THAT"));
+                }
+                match pokemon.moves.iter().position(|m| m.is_none()) {
+                    Some(slot) => return Ok(teach_move(pokemon, slot, move_id)),
+                    None => return Ok(ItemOutcome::MovesFull(move_id)),
+                }
+            }
+            ItemEffect::RareCandy => {
+                if fainted || pokemon.level >= level_cap {
+                    return Err(NO_EFFECT);
+                }
+                let species = read_pokemon(pokemon.species_id, storage)?;
+                pokemon.level += 1;
+                pokemon.xp = species.growth_rate.xp_for_level(pokemon.level);
+                recalculate_stats(pokemon, &species);
+                return Ok(ItemOutcome::LevelledUp(pokemon.level));
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::pokedex::{AilmentType, LearnableMove, Type};
+        use crate::rom::testing::{pokemon, species, TestRom};
+
+        const FIRE: u8 = 1;
+        const TACKLE: u16 = 1;
+        const EMBER: u16 = 2;
+        const SURF: u16 = 3;
+
+        fn item(effect: ItemEffect) -> Item {
+            return Item {
+                id: 1,
+                name: *b"TESTITEM\0\0\0\0",
+                effect,
+            };
+        }
+
+        fn rom() -> TestRom {
+            let mut rom = TestRom::new();
+            let learns = |id| LearnableMove {
+                id,
+                condition: LearnCondition::Machine,
+            };
+            rom.add_species_learning(
+                &species(FIRE, Type::Fire, None),
+                &[learns(EMBER), learns(SURF)],
+            );
+            return rom;
+        }
+
+        fn fire(moves: [Option<u16>; 4]) -> Pokemon {
+            return pokemon(species(FIRE, Type::Fire, None), 10, moves);
+        }
+
+        fn using(effect: ItemEffect, pokemon: &mut Pokemon) -> Result<ItemOutcome, ItemError> {
+            return use_item(&item(effect), pokemon, UseContext::Field, 20, &mut rom());
+        }
+
+        #[test]
+        fn healing_stops_at_full() {
+            let mut hurt = fire([None; 4]);
+            let max = hurt.hp.value;
+            hurt.current_hp = max - 5;
+            assert_eq!(
+                using(ItemEffect::Heal(20), &mut hurt).unwrap(),
+                ItemOutcome::Healed(5)
+            );
+            assert_eq!(hurt.current_hp, max);
+            // Already full, so the potion stays in the bag.
+            assert!(using(ItemEffect::Heal(20), &mut hurt).is_err());
+
+            hurt.current_hp = 0;
+            assert!(using(ItemEffect::Heal(0), &mut hurt).is_err());
+            assert_eq!(
+                using(ItemEffect::Revive(50), &mut hurt).unwrap(),
+                ItemOutcome::Revived
+            );
+            assert_eq!(hurt.current_hp, max / 2);
+            assert!(using(ItemEffect::Revive(50), &mut hurt).is_err());
+        }
+
+        #[test]
+        fn cures_only_fix_what_they_say() {
+            let mut burnt = fire([None; 4]);
+            burnt.status = Some(AilmentType::Burn);
+            let antidote = ItemEffect::CureStatus(Some(AilmentType::Poison));
+            assert!(using(antidote, &mut burnt).is_err());
+            assert_eq!(burnt.status, Some(AilmentType::Burn));
+            assert_eq!(
+                using(ItemEffect::CureStatus(None), &mut burnt).unwrap(),
+                ItemOutcome::Cured
+            );
+            assert_eq!(burnt.status, None);
+            assert!(using(ItemEffect::CureStatus(None), &mut burnt).is_err());
+        }
+
+        #[test]
+        fn some_items_wait_for_the_right_moment() {
+            let mut fine = fire([None; 4]);
+            let mut rom = rom();
+            for effect in [ItemEffect::RareCandy, ItemEffect::TechnicalMachine(EMBER)] {
+                let outcome = use_item(&item(effect), &mut fine, UseContext::Battle, 20, &mut rom);
+                assert!(outcome.is_err());
+            }
+            assert!(using(ItemEffect::Ball(10), &mut fine).is_err());
+        }
+
+        #[test]
+        fn rare_candy_stops_at_the_level_cap() {
+            let mut mon = fire([None; 4]);
+            let before = mon.hp.value;
+            assert_eq!(
+                using(ItemEffect::RareCandy, &mut mon).unwrap(),
+                ItemOutcome::LevelledUp(11)
+            );
+            assert!(mon.hp.value >= before);
+            mon.level = 20;
+            assert!(using(ItemEffect::RareCandy, &mut mon).is_err());
+            assert_eq!(mon.level, 20);
+        }
+
+        #[test]
+        fn machines_teach_into_a_gap_or_ask_what_to_forget() {
+            let mut mon = fire([Some(TACKLE), None, None, None]);
+            assert_eq!(
+                using(ItemEffect::TechnicalMachine(EMBER), &mut mon).unwrap(),
+                ItemOutcome::LearnedMove(EMBER)
+            );
+            assert_eq!(mon.moves, [Some(TACKLE), Some(EMBER), None, None]);
+            // Already known, or not on its list.
+            assert!(using(ItemEffect::TechnicalMachine(EMBER), &mut mon).is_err());
+            assert!(using(ItemEffect::TechnicalMachine(99), &mut mon).is_err());
+
+            let mut full = fire([Some(TACKLE), Some(EMBER), Some(4), Some(5)]);
+            assert_eq!(
+                using(ItemEffect::TechnicalMachine(SURF), &mut full).unwrap(),
+                ItemOutcome::MovesFull(SURF)
+            );
+            // Nothing changes until the player picks one.
+            assert_eq!(full.moves, [Some(TACKLE), Some(EMBER), Some(4), Some(5)]);
+            teach_move(&mut full, 2, SURF);
+            assert_eq!(full.moves, [Some(TACKLE), Some(EMBER), Some(SURF), Some(5)]);
+        }
+    }
+}
+
+pub mod input {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Buttons {
+        LEFT,
+        UP,
+        RIGHT,
+        DOWN,
+        A,
+        B,
+    }
+
+    impl Buttons {
+        fn index(self) -> usize {
+            match self {
+                Buttons::LEFT => 0,
+                Buttons::UP => 1,
+                Buttons::RIGHT => 2,
+                Buttons::DOWN => 3,
+                Buttons::A => 4,
+                Buttons::B => 5,
+            }
+        }
+    }
+
+    pub struct ButtonStates {
+        pressed: [bool; 6],
+        was_consumed: [bool; 6],
+    }
+
+    impl Default for ButtonStates {
+        fn default() -> ButtonStates {
+            return ButtonStates::new();
+        }
+    }
+
+    impl ButtonStates {
+        pub fn new() -> ButtonStates {
+            return ButtonStates {
+                pressed: [false; 6],
+                was_consumed: [false; 6],
+            };
+        }
+        pub fn is_pressed(&self, button: Buttons) -> bool {
+            return self.pressed[button.index()] && !self.was_consumed[button.index()];
+        }
+        pub fn consume(&mut self, button: Buttons) -> bool {
+            if self.pressed[button.index()] && !self.was_consumed[button.index()] {
+                self.was_consumed[button.index()] = true;
+                return true;
+            }
+            return false;
+        }
+        // Whether anything's been pressed that nobody's dealt with yet.
+        pub fn any_pressed(&self) -> bool {
+            return (0..6).any(|i| self.pressed[i] && !self.was_consumed[i]);
+        }
+        pub fn consume_all(&mut self) {
+            self.was_consumed = self.pressed;
+        }
+        pub fn update(&mut self, button: Buttons, pressed: bool) {
+            if pressed && !self.pressed[button.index()] {
+                self.pressed[button.index()] = true;
+                self.was_consumed[button.index()] = false;
+            } else if !pressed && self.pressed[button.index()] {
+                self.pressed[button.index()] = false;
+            }
+        }
+    }
+}
+
+pub mod animation {
+    use embedded_graphics::{
+        pixelcolor::BinaryColor, prelude::*, primitives::Rectangle, style::PrimitiveStyleBuilder,
+    };
+
+    // Everything that moves is counted in ticks rather than frames, so it
+    // runs at the same speed whatever the display gets up to. The firmware
+    // and the simulator both feed the clock and tick the app this often.
+    pub const TICK_MS: u32 = 50;
+    // If something holds up the loop for ages, don't try to catch up on all of
+    // it at once. Animations can just run a little late.
+    const MAX_CATCH_UP: u32 = 4;
+
+    pub struct Clock {
+        leftover_ms: u32,
+    }
+
+    impl Default for Clock {
+        fn default() -> Clock {
+            return Clock::new();
+        }
+    }
+
+    impl Clock {
+        pub fn new() -> Clock {
+            return Clock { leftover_ms: 0 };
+        }
+
+        // Turns however long it's been into a number of ticks to run, keeping
+        // the remainder for next time.
+        pub fn advance(&mut self, elapsed_ms: u32) -> u32 {
+            let total = self.leftover_ms + elapsed_ms;
+            self.leftover_ms = total % TICK_MS;
+            return (total / TICK_MS).min(MAX_CATCH_UP);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Easing {
+        Linear,
+        // Quick to start, gentle at the end.
+        EaseOut,
+    }
+
+    // Moves a value from one number to another over a number of ticks.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Tween {
+        from: i32,
+        to: i32,
+        duration: u16,
+        elapsed: u16,
+        easing: Easing,
+    }
+
+    impl Tween {
+        pub fn new(from: i32, to: i32, duration: u16, easing: Easing) -> Tween {
+            return Tween {
+                from,
+                to,
+                duration: duration.max(1),
+                elapsed: 0,
+                easing,
+            };
+        }
+
+        // Already where it's going, for when there's nothing to animate yet.
+        pub fn at(value: i32) -> Tween {
+            return Tween::new(value, value, 1, Easing::Linear).finished();
+        }
+
+        fn finished(mut self) -> Tween {
+            self.elapsed = self.duration;
+            return self;
+        }
+
+        // Heads somewhere new from wherever it's got to so far.
+        pub fn retarget(&mut self, to: i32, duration: u16) {
+            *self = Tween::new(self.value(), to, duration, self.easing);
+        }
+
+        // Returns whether anything moved.
+        pub fn tick(&mut self) -> bool {
+            if self.is_done() {
+                return false;
+            }
+            self.elapsed += 1;
+            return true;
+        }
+
+        pub fn is_done(&self) -> bool {
+            return self.elapsed >= self.duration;
+        }
+
+        pub fn target(&self) -> i32 {
+            return self.to;
+        }
+
+        pub fn value(&self) -> i32 {
+            let t = self.elapsed as i32;
+            let d = self.duration as i32;
+            let (progress, scale) = match self.easing {
+                Easing::Linear => (t, d),
+                Easing::EaseOut => (d * d - (d - t) * (d - t), d * d),
+            };
+            return self.from + (self.to - self.from) * progress / scale;
+        }
+    }
+
+    // Goes on and off forever, like a cursor.
+    pub struct Blink {
+        period: u8,
+        counter: u8,
+    }
+
+    impl Blink {
+        pub fn new(period: u8) -> Blink {
+            return Blink {
+                period: period.max(1),
+                counter: 0,
+            };
+        }
+
+        // Returns whether it just changed, so the caller knows to redraw.
+        pub fn tick(&mut self) -> bool {
+            let was = self.visible();
+            self.counter = (self.counter + 1) % (self.period * 2);
+            return was != self.visible();
+        }
+
+        // Straight back to visible, so a cursor that's just moved can be seen.
+        pub fn reset(&mut self) {
+            self.counter = 0;
+        }
+
+        pub fn visible(&self) -> bool {
+            return self.counter < self.period;
+        }
+    }
+
+    // On and off for a while, then back to normal. For when something gets
+    // hit.
+    pub struct Flash {
+        remaining: u8,
+        period: u8,
+    }
+
+    impl Default for Flash {
+        fn default() -> Flash {
+            return Flash::new();
+        }
+    }
+
+    impl Flash {
+        pub fn new() -> Flash {
+            return Flash {
+                remaining: 0,
+                period: 1,
+            };
+        }
+
+        pub fn start(&mut self, ticks: u8, period: u8) {
+            self.remaining = ticks;
+            self.period = period.max(1);
+        }
+
+        pub fn tick(&mut self) -> bool {
+            if self.remaining == 0 {
+                return false;
+            }
+            self.remaining -= 1;
+            return true;
+        }
+
+        pub fn is_active(&self) -> bool {
+            return self.remaining > 0;
+        }
+
+        pub fn visible(&self) -> bool {
+            return (self.remaining / self.period).is_multiple_of(2);
+        }
+    }
+
+    // Wobbles side to side, settling down as it runs out.
+    pub struct Shake {
+        remaining: u8,
+        amplitude: u8,
+    }
+
+    impl Default for Shake {
+        fn default() -> Shake {
+            return Shake::new();
+        }
+    }
+
+    impl Shake {
+        pub fn new() -> Shake {
+            return Shake {
+                remaining: 0,
+                amplitude: 0,
+            };
+        }
+
+        pub fn start(&mut self, ticks: u8, amplitude: u8) {
+            self.remaining = ticks;
+            self.amplitude = amplitude;
+        }
+
+        pub fn tick(&mut self) -> bool {
+            if self.remaining == 0 {
+                return false;
+            }
+            self.remaining -= 1;
+            return true;
+        }
+
+        pub fn is_active(&self) -> bool {
+            return self.remaining > 0;
+        }
+
+        pub fn offset(&self) -> Point {
+            if self.remaining == 0 {
+                return Point::zero();
+            }
+            let size = (self.amplitude as i32 * self.remaining as i32 / 4).clamp(1, 4);
+            return match self.remaining % 2 {
+                0 => Point::new(size, 0),
+                _ => Point::new(-size, 0),
+            };
+        }
+    }
+
+    // Slides a point in from somewhere off to the side.
+    pub struct Slide {
+        x: Tween,
+    }
+
+    impl Default for Slide {
+        fn default() -> Slide {
+            return Slide::new();
+        }
+    }
+
+    impl Slide {
+        pub fn new() -> Slide {
+            return Slide { x: Tween::at(0) };
+        }
+
+        // Starts the given distance away, negative for the left.
+        pub fn start(&mut self, distance: i32, ticks: u16) {
+            self.x = Tween::new(distance, 0, ticks, Easing::EaseOut);
+        }
+
+        pub fn tick(&mut self) -> bool {
+            return self.x.tick();
+        }
+
+        pub fn is_active(&self) -> bool {
+            return !self.x.is_done();
+        }
+
+        pub fn offset(&self) -> Point {
+            return Point::new(self.x.value(), 0);
+        }
+    }
+
+    // Where the stars go, in sixths of the box they're drawn over.
+    const SPARKLE_STARS: [(i32, i32); 4] = [(1, 1), (5, 2), (2, 5), (4, 4)];
+    const SPARKLE_PERIOD: u8 = 8;
+
+    // A few stars twinkling over something, one after another.
+    pub struct Sparkle {
+        remaining: u8,
+    }
+
+    impl Default for Sparkle {
+        fn default() -> Sparkle {
+            return Sparkle::new();
+        }
+    }
+
+    impl Sparkle {
+        pub fn new() -> Sparkle {
+            return Sparkle { remaining: 0 };
+        }
+
+        pub fn start(&mut self, ticks: u8) {
+            self.remaining = ticks;
+        }
+
+        pub fn tick(&mut self) -> bool {
+            if self.remaining == 0 {
+                return false;
+            }
+            self.remaining -= 1;
+            return true;
+        }
+
+        pub fn is_active(&self) -> bool {
+            return self.remaining > 0;
+        }
+
+        pub fn draw<D: DrawTarget<BinaryColor>>(
+            &self,
+            display: &mut D,
+            top_left: Point,
+            size: Size,
+            colour: BinaryColor,
+        ) {
+            if self.remaining == 0 {
+                return;
+            }
+            for (i, (x, y)) in SPARKLE_STARS.iter().enumerate() {
+                // Each star grows from a dot to a cross and back, a couple of
+                // ticks behind the one before it.
+                let phase = (self.remaining as usize + i * 2) % SPARKLE_PERIOD as usize;
+                let arm = match phase {
+                    2 | 3 => 2,
+                    1 | 4 => 1,
+                    0 | 5 => 0,
+                    _ => continue,
+                };
+                let centre =
+                    top_left + Point::new(size.width as i32 * x / 6, size.height as i32 * y / 6);
+                let style = PrimitiveStyleBuilder::new().fill_color(colour).build();
+                Rectangle::new(centre - Point::new(arm, 0), centre + Point::new(arm, 0))
+                    .into_styled(style)
+                    .draw(display)
+                    .ok();
+                Rectangle::new(centre - Point::new(0, arm), centre + Point::new(0, arm))
+                    .into_styled(style)
+                    .draw(display)
+                    .ok();
+            }
+        }
+    }
+
+    const WIPE_BANDS: i32 = 8;
+
+    // Covers the screen in horizontal blinds that open up to show whatever's
+    // underneath.
+    pub struct Wipe {
+        progress: Tween,
+    }
+
+    impl Wipe {
+        pub fn new(ticks: u16) -> Wipe {
+            return Wipe {
+                progress: Tween::new(0, 64 / WIPE_BANDS, ticks, Easing::Linear),
+            };
+        }
+
+        pub fn tick(&mut self) -> bool {
+            return self.progress.tick();
+        }
+
+        pub fn is_done(&self) -> bool {
+            return self.progress.is_done();
+        }
+
+        pub fn draw<D: DrawTarget<BinaryColor>>(&self, display: &mut D) {
+            let band = 64 / WIPE_BANDS;
+            let covered = band - self.progress.value();
+            if covered <= 0 {
+                return;
+            }
+            let style = PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .build();
+            for i in 0..WIPE_BANDS {
+                let top = i * band;
+                Rectangle::new(Point::new(0, top), Point::new(127, top + covered - 1))
+                    .into_styled(style)
+                    .draw(display)
+                    .ok();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        extern crate std;
+
+        use super::*;
+        use std::vec;
+        use std::vec::Vec;
+
+        #[test]
+        fn the_clock_keeps_the_leftovers() {
+            let mut clock = Clock::new();
+            assert_eq!(clock.advance(30), 0);
+            assert_eq!(clock.advance(30), 1);
+            assert_eq!(clock.advance(130), 2);
+            // The 40ms left over from before makes this one whole.
+            assert_eq!(clock.advance(10), 1);
+            assert_eq!(clock.advance(0), 0);
+        }
+
+        #[test]
+        fn the_clock_only_catches_up_so_far() {
+            let mut clock = Clock::new();
+            assert_eq!(clock.advance(TICK_MS * 100 + 20), MAX_CATCH_UP);
+            // What got dropped stays dropped, but the odd bit still counts.
+            assert_eq!(clock.advance(30), 1);
+        }
+
+        fn values(mut tween: Tween) -> Vec<i32> {
+            let mut values = vec![tween.value()];
+            while tween.tick() {
+                values.push(tween.value());
+            }
+            return values;
+        }
+
+        #[test]
+        fn tweens_get_there_in_the_ticks_they_were_given() {
+            assert_eq!(
+                values(Tween::new(0, 100, 4, Easing::Linear)),
+                vec![0, 25, 50, 75, 100]
+            );
+            assert_eq!(
+                values(Tween::new(0, 100, 4, Easing::EaseOut)),
+                vec![0, 43, 75, 93, 100]
+            );
+            assert_eq!(
+                values(Tween::new(10, -10, 2, Easing::Linear)),
+                vec![10, 0, -10]
+            );
+            // No time at all still takes a tick.
+            assert_eq!(values(Tween::new(0, 8, 0, Easing::Linear)), vec![0, 8]);
+            assert_eq!(values(Tween::at(5)), vec![5]);
+        }
+
+        #[test]
+        fn retargeting_carries_on_from_where_it_got_to() {
+            let mut tween = Tween::new(0, 100, 4, Easing::Linear);
+            tween.tick();
+            tween.retarget(0, 5);
+            assert_eq!(tween.target(), 0);
+            assert_eq!(values(tween), vec![25, 20, 15, 10, 5, 0]);
+        }
+
+        #[test]
+        fn effects_run_out_and_settle() {
+            let mut flash = Flash::new();
+            flash.start(4, 2);
+            let mut visible = Vec::new();
+            while flash.tick() {
+                visible.push(flash.visible());
+            }
+            assert_eq!(visible, vec![false, false, true, true]);
+            assert!(!flash.is_active());
+
+            let mut shake = Shake::new();
+            shake.start(6, 2);
+            let mut offsets = Vec::new();
+            while shake.tick() {
+                offsets.push(shake.offset().x);
+            }
+            assert_eq!(offsets, vec![-2, 2, -1, 1, -1, 0]);
+
+            let mut slide = Slide::new();
+            slide.start(-40, 3);
+            assert_eq!(slide.offset(), Point::new(-40, 0));
+            while slide.tick() {}
+            assert!(!slide.is_active());
+            assert_eq!(slide.offset(), Point::zero());
+        }
+    }
+}
+
+pub mod led {
+    use crate::pokedex::Type;
+
+    // How long the slow effects take to go round once, in ticks.
+    const PULSE_TICKS: u32 = 40;
+    const BLINK_TICKS: u32 = 6;
+    const RAINBOW_TICKS: u32 = 60;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Colour {
+        pub r: u8,
+        pub g: u8,
+        pub b: u8,
+    }
+
+    impl Colour {
+        pub const OFF: Colour = Colour::new(0, 0, 0);
+        pub const RED: Colour = Colour::new(0xff, 0x00, 0x00);
+        pub const GOLD: Colour = Colour::new(0xff, 0xc0, 0x00);
+        pub const WHITE: Colour = Colour::new(0xff, 0xff, 0xff);
+
+        pub const fn new(r: u8, g: u8, b: u8) -> Colour {
+            return Colour { r, g, b };
+        }
+
+        // Out of 255, so full brightness leaves it alone.
+        pub fn scale(&self, level: u8) -> Colour {
+            let scale = |c: u8| (c as u32 * level as u32 / 255) as u8;
+            return Colour::new(scale(self.r), scale(self.g), scale(self.b));
+        }
+
+        // Round the colour wheel from red, through green and blue, and back.
+        pub fn wheel(position: u8) -> Colour {
+            let p = position as u32 * 3;
+            return match position {
+                0..=84 => Colour::new((255 - p) as u8, p as u8, 0),
+                85..=169 => Colour::new(0, (510 - p) as u8, (p - 255) as u8),
+                _ => Colour::new((p - 510) as u8, 0, (765 - p) as u8),
+            };
+        }
+    }
+
+    // Roughly what each type looks like, for glowing along with the pokedex.
+    pub fn type_colour(t: Type) -> Colour {
+        return match t {
+            Type::Normal => Colour::new(0xa0, 0xa0, 0x80),
+            Type::Fighting => Colour::new(0xc0, 0x30, 0x20),
+            Type::Flying => Colour::new(0xa0, 0x90, 0xf0),
+            Type::Poison => Colour::new(0xa0, 0x40, 0xa0),
+            Type::Ground => Colour::new(0xe0, 0xc0, 0x60),
+            Type::Rock => Colour::new(0xb0, 0xa0, 0x30),
+            Type::Bug => Colour::new(0xa0, 0xb0, 0x20),
+            Type::Ghost => Colour::new(0x70, 0x50, 0x90),
+            Type::Steel => Colour::new(0xb0, 0xb0, 0xd0),
+            Type::Fire => Colour::new(0xf0, 0x80, 0x30),
+            Type::Water => Colour::new(0x60, 0x90, 0xf0),
+            Type::Grass => Colour::new(0x70, 0xc0, 0x50),
+            Type::Electric => Colour::new(0xf8, 0xd0, 0x30),
+            Type::Psychic => Colour::new(0xf8, 0x58, 0x88),
+            Type::Ice => Colour::new(0x98, 0xd8, 0xd8),
+            Type::Dragon => Colour::new(0x70, 0x38, 0xf8),
+            Type::Dark => Colour::new(0x70, 0x58, 0x48),
+            Type::Fairy => Colour::new(0xee, 0x99, 0xac),
+        };
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Effect {
+        Off,
+        Solid(Colour),
+        // Fades up and down.
+        Pulse(Colour),
+        Blink(Colour),
+        Rainbow,
+    }
+
+    impl Effect {
+        pub fn colour(&self, ticks: u32) -> Colour {
+            return match self {
+                Effect::Off => Colour::OFF,
+                Effect::Solid(c) => *c,
+                Effect::Pulse(c) => {
+                    let half = PULSE_TICKS / 2;
+                    let t = ticks % PULSE_TICKS;
+                    let t = if t < half { t } else { PULSE_TICKS - t };
+                    c.scale((t * 255 / half) as u8)
+                }
+                Effect::Blink(c) => match (ticks / BLINK_TICKS) % 2 {
+                    0 => *c,
+                    _ => Colour::OFF,
+                },
+                Effect::Rainbow => {
+                    Colour::wheel((ticks % RAINBOW_TICKS * 256 / RAINBOW_TICKS) as u8)
+                }
+            };
+        }
+    }
+
+    pub const RARE_ALERT: Effect = Effect::Blink(Colour::GOLD);
+    pub const SHINY_ALERT: Effect = Effect::Rainbow;
+    pub const LOW_HP: Effect = Effect::Pulse(Colour::RED);
+    pub const ALERT_TICKS: u16 = 40;
+
+    // Anything that can show a colour. Writing one should be quick, since it
+    // happens in the middle of the main loop.
+    pub trait Led {
+        fn show(&mut self, colour: Colour);
+    }
+
+    // Whatever the current screen wants, with a short alert on top now and
+    // then. It's moved along by the app's tick like everything else, and only
+    // touches the LED when the colour actually changes.
+    pub struct Leds {
+        effect: Effect,
+        ticks: u32,
+        alert: Option<(Effect, u16)>,
+        alert_ticks: u32,
+        shown: Option<Colour>,
+    }
+
+    impl Default for Leds {
+        fn default() -> Leds {
+            return Leds::new();
+        }
+    }
+
+    impl Leds {
+        pub fn new() -> Leds {
+            return Leds {
+                effect: Effect::Off,
+                ticks: 0,
+                alert: None,
+                alert_ticks: 0,
+                shown: None,
+            };
+        }
+
+        // Carries on from where it was if it's the same effect, so asking
+        // again every frame doesn't restart it.
+        pub fn set(&mut self, effect: Effect) {
+            if self.effect != effect {
+                self.effect = effect;
+                self.ticks = 0;
+            }
+        }
+
+        pub fn alert(&mut self, effect: Effect, ticks: u16) {
+            self.alert = Some((effect, ticks));
+            self.alert_ticks = 0;
+        }
+
+        pub fn tick(&mut self) {
+            self.ticks = self.ticks.wrapping_add(1);
+            if let Some((effect, remaining)) = self.alert {
+                self.alert = match remaining {
+                    0 | 1 => None,
+                    _ => Some((effect, remaining - 1)),
+                };
+                self.alert_ticks += 1;
+            }
+        }
+
+        pub fn colour(&self) -> Colour {
+            return match self.alert {
+                Some((effect, _)) => effect.colour(self.alert_ticks),
+                None => self.effect.colour(self.ticks),
+            };
+        }
+
+        // Zero brightness is off, whatever's going on.
+        pub fn show<L: Led>(&mut self, brightness: u8, led: &mut L) {
+            let colour = self.colour().scale(brightness);
+            if self.shown != Some(colour) {
+                led.show(colour);
+                self.shown = Some(colour);
+            }
+        }
+    }
+
+    const LOG_SIZE: usize = 64;
+
+    // Stands in for the neopixel on the host. It keeps the most recent frames
+    // so whatever's driving it can check what would have been shown.
+    pub struct MockLed {
+        frames: [Colour; LOG_SIZE],
+        len: usize,
+    }
+
+    impl Default for MockLed {
+        fn default() -> MockLed {
+            return MockLed::new();
+        }
+    }
+
+    impl MockLed {
+        pub fn new() -> MockLed {
+            return MockLed {
+                frames: [Colour::OFF; LOG_SIZE],
+                len: 0,
+            };
+        }
+
+        // Oldest first. Once it's full the oldest frames drop off.
+        pub fn frames(&self) -> &[Colour] {
+            return &self.frames[..self.len];
+        }
+
+        pub fn last(&self) -> Option<Colour> {
+            return self.frames().last().copied();
+        }
+
+        pub fn clear(&mut self) {
+            self.len = 0;
+        }
+    }
+
+    impl Led for MockLed {
+        fn show(&mut self, colour: Colour) {
+            if self.len == LOG_SIZE {
+                self.frames.copy_within(1.., 0);
+                self.len -= 1;
+            }
+            self.frames[self.len] = colour;
+            self.len += 1;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // Shows what's there now, then ticks and shows again, like the main
+        // loop does.
+        fn run(leds: &mut Leds, ticks: u32, led: &mut MockLed) {
+            leds.show(255, led);
+            for _ in 0..ticks {
+                leds.tick();
+                leds.show(255, led);
+            }
+        }
+
+        #[test]
+        fn solid_is_only_written_once() {
+            let mut leds = Leds::new();
+            let mut led = MockLed::new();
+            leds.set(Effect::Solid(Colour::RED));
+            run(&mut leds, 10, &mut led);
+            assert_eq!(led.frames(), &[Colour::RED]);
+            // Asking again carries on rather than starting over.
+            leds.set(Effect::Solid(Colour::RED));
+            run(&mut leds, 10, &mut led);
+            assert_eq!(led.frames(), &[Colour::RED]);
+        }
+
+        #[test]
+        fn pulse_fades_up_and_back_down() {
+            let mut leds = Leds::new();
+            let mut led = MockLed::new();
+            leds.set(LOW_HP);
+            run(&mut leds, PULSE_TICKS, &mut led);
+            let frames = led.frames();
+            let half = (PULSE_TICKS / 2) as usize;
+            assert_eq!(frames.len(), PULSE_TICKS as usize + 1);
+            assert_eq!(frames[0], Colour::OFF);
+            assert_eq!(frames[half], Colour::RED);
+            assert_eq!(frames[PULSE_TICKS as usize], Colour::OFF);
+            for pair in frames[..=half].windows(2) {
+                assert!(pair[0].r < pair[1].r);
+            }
+            for pair in frames[half..].windows(2) {
+                assert!(pair[0].r > pair[1].r);
+            }
+            assert!(frames.iter().all(|c| c.g == 0 && c.b == 0));
+        }
+
+        #[test]
+        fn blink_switches_every_few_ticks() {
+            let mut leds = Leds::new();
+            let mut led = MockLed::new();
+            leds.set(RARE_ALERT);
+            run(&mut leds, BLINK_TICKS * 4, &mut led);
+            let gold = Colour::GOLD;
+            assert_eq!(led.frames(), &[gold, Colour::OFF, gold, Colour::OFF, gold]);
+        }
+
+        #[test]
+        fn rainbow_goes_round_the_wheel() {
+            let mut leds = Leds::new();
+            let mut led = MockLed::new();
+            leds.set(SHINY_ALERT);
+            run(&mut leds, RAINBOW_TICKS, &mut led);
+            let frames = led.frames();
+            let third = (RAINBOW_TICKS / 3) as usize;
+            // Every tick moves it on a bit.
+            assert_eq!(frames.len(), RAINBOW_TICKS as usize + 1);
+            assert_eq!(frames[0], Colour::RED);
+            assert_eq!(frames[third], Colour::new(0, 0xff, 0));
+            assert_eq!(frames[third * 2], Colour::new(0, 0, 0xff));
+            assert_eq!(frames[third * 3], Colour::RED);
+        }
+
+        #[test]
+        fn alert_goes_back_to_the_effect_when_it_runs_out() {
+            let mut leds = Leds::new();
+            let mut led = MockLed::new();
+            leds.set(Effect::Solid(Colour::WHITE));
+            leds.alert(Effect::Solid(Colour::GOLD), 3);
+            run(&mut leds, 2, &mut led);
+            assert_eq!(led.frames(), &[Colour::GOLD]);
+            run(&mut leds, 1, &mut led);
+            assert_eq!(led.frames(), &[Colour::GOLD, Colour::WHITE]);
+            run(&mut leds, ALERT_TICKS as u32, &mut led);
+            assert_eq!(led.frames(), &[Colour::GOLD, Colour::WHITE]);
+        }
+
+        #[test]
+        fn brightness_scales_every_channel() {
+            let mut leds = Leds::new();
+            let mut led = MockLed::new();
+            leds.set(Effect::Solid(Colour::new(200, 100, 50)));
+            leds.show(255, &mut led);
+            leds.show(128, &mut led);
+            leds.show(128, &mut led);
+            leds.show(0, &mut led);
+            let expected = [
+                Colour::new(200, 100, 50),
+                Colour::new(100, 50, 25),
+                Colour::OFF,
+            ];
+            assert_eq!(led.frames(), &expected);
+        }
+    }
+}
+
+pub mod cry {
+    use crate::animation::TICK_MS;
+    use crate::generation::{Random, SeededRandom};
+
+    // Plenty for a piezo, and low enough that a timer can keep up with it.
+    pub const SAMPLE_RATE: u32 = 8000;
+    pub const MAX_NOTES: usize = 5;
+    // Unsigned, so silence sits in the middle. Each sample doubles as the
+    // PWM duty out of 255.
+    pub const SILENCE: u8 = 0x80;
+    const LOUDEST: u8 = 0x60;
+    // Enough of a ramp at either end of a note to stop it clicking.
+    const RAMP_SAMPLES: u32 = 64;
+
+    // One swoop of a square wave. The duty is in eighths, like the old
+    // handhelds had, which is most of what makes two cries sound different.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Note {
+        pub start_hz: u16,
+        pub end_hz: u16,
+        pub ms: u16,
+        pub duty: u8,
+        pub volume: u8,
+    }
+
+    impl Note {
+        pub fn samples(&self) -> u32 {
+            return self.ms as u32 * SAMPLE_RATE / 1000;
+        }
+    }
+
+    // A square wave, high for `duty` eighths of each cycle.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Tone {
+        pub hz: u16,
+        pub duty: u8,
+    }
+
+    // A handful of notes, made up from the species id so every species always
+    // sounds the same without storing anything.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Cry {
+        notes: [Note; MAX_NOTES],
+        len: usize,
+    }
+
+    impl Cry {
+        pub fn new(species_id: u8) -> Cry {
+            let mut rng = SeededRandom::new(0xc4e5_0000 | species_id as u32);
+            // Lower ids are mostly the earlier, smaller things, so they get to
+            // be a bit squeakier on average.
+            let base = 300 + (255 - species_id as u32) * 3 + rng.below(200) as u32;
+            let len = 2 + rng.below(MAX_NOTES as u8 - 1) as usize;
+            let mut notes = [Note {
+                start_hz: 0,
+                end_hz: 0,
+                ms: 0,
+                duty: 4,
+                volume: 0,
+            }; MAX_NOTES];
+            let mut hz = base;
+            for note in notes.iter_mut().take(len) {
+                // Cries tend to rise then fall, so each note drifts a little
+                // and the last one drops away.
+                let start = hz;
+                let end = match rng.below(3) {
+                    0 => start * 3 / 4,
+                    1 => start * 5 / 4,
+                    _ => start,
+                };
+                *note = Note {
+                    start_hz: start as u16,
+                    end_hz: end as u16,
+                    ms: 60 + rng.below(120) as u16,
+                    duty: 1 + rng.below(4),
+                    volume: LOUDEST - rng.below(LOUDEST / 2),
+                };
+                hz = end.clamp(200, 2000);
+            }
+            if let Some(last) = notes[..len].last_mut() {
+                last.end_hz = last.start_hz / 2;
+            }
+            return Cry { notes, len };
+        }
+
+        pub fn notes(&self) -> &[Note] {
+            return &self.notes[..self.len];
+        }
+
+        // What to play `ms` into the cry, or None once it's over. The board
+        // can't keep up with PCM, so it gets the pitch a tick at a time.
+        pub fn tone_at(&self, ms: u32) -> Option<Tone> {
+            let mut start = 0;
+            for note in self.notes() {
+                let t = ms - start;
+                if t < note.ms as u32 {
+                    let hz = note.start_hz as i32
+                        + (note.end_hz as i32 - note.start_hz as i32) * t as i32 / note.ms as i32;
+                    return Some(Tone {
+                        hz: hz as u16,
+                        duty: note.duty,
+                    });
+                }
+                start += note.ms as u32;
+            }
+            return None;
+        }
+
+        pub fn samples(&self) -> CrySamples {
+            return CrySamples {
+                cry: *self,
+                note: 0,
+                position: 0,
+                phase: 0,
+            };
+        }
+    }
+
+    // The cry as PCM at SAMPLE_RATE, one sample at a time, so nothing has to
+    // hold the whole thing in memory.
+    pub struct CrySamples {
+        cry: Cry,
+        note: usize,
+        position: u32,
+        phase: u32,
+    }
+
+    impl Iterator for CrySamples {
+        type Item = u8;
+
+        fn next(&mut self) -> Option<u8> {
+            let note = loop {
+                let note = self.cry.notes().get(self.note)?;
+                if self.position < note.samples() {
+                    break note;
+                }
+                self.note += 1;
+                self.position = 0;
+            };
+            let length = note.samples();
+            let t = self.position;
+            self.position += 1;
+
+            // Slide the pitch across the note, and step a 16 bit phase along
+            // by however much of a cycle one sample is.
+            let hz = note.start_hz as i32
+                + (note.end_hz as i32 - note.start_hz as i32) * t as i32 / length as i32;
+            self.phase = (self.phase + hz as u32 * 0x10000 / SAMPLE_RATE) & 0xffff;
+
+            let ramp = t.min(length - 1 - t).min(RAMP_SAMPLES);
+            let level = (note.volume as u32 * ramp / RAMP_SAMPLES) as u8;
+            if (self.phase >> 13) < note.duty as u32 {
+                return Some(SILENCE + level);
+            }
+            return Some(SILENCE - level);
+        }
+    }
+
+    // Whatever cry is playing. Screens start one here, the app steps it along
+    // every tick and the firmware plays the current tone on the piezo, much
+    // like the LED gets driven.
+    pub struct Speaker {
+        playing: Option<Cry>,
+        elapsed_ms: u32,
+    }
+
+    impl Default for Speaker {
+        fn default() -> Speaker {
+            return Speaker::new();
+        }
+    }
+
+    impl Speaker {
+        pub fn new() -> Speaker {
+            return Speaker {
+                playing: None,
+                elapsed_ms: 0,
+            };
+        }
+
+        // Anything still playing gets cut off, there's only one piezo.
+        pub fn play(&mut self, species_id: u8) {
+            self.playing = Some(Cry::new(species_id));
+            self.elapsed_ms = 0;
+        }
+
+        // Called at the start of each tick, so a cry started during one
+        // gets heard from the beginning.
+        pub fn tick(&mut self) {
+            if let Some(cry) = &self.playing {
+                self.elapsed_ms += TICK_MS;
+                if cry.tone_at(self.elapsed_ms).is_none() {
+                    self.playing = None;
+                }
+            }
+        }
+
+        pub fn tone(&self) -> Option<Tone> {
+            return self.playing?.tone_at(self.elapsed_ms);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // Changing these changes what every species sounds like, so it
+        // should only happen on purpose.
+        #[test]
+        fn cries_stay_the_same() {
+            let pinned: [(u8, usize, usize, u32); 4] = [
+                (1, 2, 1368, 0xb27c19ba),
+                (25, 4, 3496, 0x4bb3cbfa),
+                (150, 5, 5128, 0x4bce2c72),
+                (151, 2, 1496, 0xec2094f8),
+            ];
+            for (species_id, notes, samples, hash) in pinned {
+                let cry = Cry::new(species_id);
+                assert_eq!(cry.notes().len(), notes);
+                assert_eq!(cry.samples().count(), samples);
+                let digest = cry
+                    .samples()
+                    .fold(0u32, |h, s| h.wrapping_mul(31).wrapping_add(s as u32));
+                assert_eq!(digest, hash, "species {}", species_id);
+            }
+        }
+
+        #[test]
+        fn notes_start_and_end_quiet() {
+            for species_id in 1..=151 {
+                let cry = Cry::new(species_id);
+                let mut samples = cry.samples();
+                for note in cry.notes() {
+                    let length = note.samples() as usize;
+                    let first = samples.next().unwrap();
+                    let middle = samples.by_ref().take(length - 2);
+                    let loudest = middle.map(|s| s.abs_diff(SILENCE)).max().unwrap();
+                    let last = samples.next().unwrap();
+                    assert_eq!(first, SILENCE);
+                    assert_eq!(last, SILENCE);
+                    assert!(loudest <= LOUDEST);
+                }
+                assert_eq!(samples.next(), None);
+            }
+        }
+
+        #[test]
+        fn speaker_steps_through_the_cry_a_tick_at_a_time() {
+            let mut speaker = Speaker::new();
+            assert_eq!(speaker.tone(), None);
+            speaker.play(1);
+            speaker.play(25);
+            let cry = Cry::new(25);
+            let total: u32 = cry.notes().iter().map(|note| note.ms as u32).sum();
+            let mut ticks = 0;
+            while let Some(tone) = speaker.tone() {
+                assert_eq!(Some(tone), cry.tone_at(ticks * TICK_MS));
+                speaker.tick();
+                ticks += 1;
+            }
+            assert_eq!(ticks, total.div_ceil(TICK_MS));
+            // Ticking with nothing playing stays quiet.
+            speaker.tick();
+            assert_eq!(speaker.tone(), None);
+        }
+    }
+}
+
+pub mod sprite {
+    use embedded_graphics::{
+        image::{ImageDimensions, IntoPixelIter},
+        pixelcolor::BinaryColor,
+        prelude::*,
+    };
+
+    // Sprites are packed by pack-resources as a width and height byte
+    // followed by the pixels, one bit each with the most significant bit
+    // leftmost, rows top to bottom, squashed with PackBits.
+    pub const MAX_PACKED_SIZE: usize = 520;
+
+    pub struct PackedSprite {
+        width: u8,
+        height: u8,
+        len: usize,
+        data: [u8; MAX_PACKED_SIZE],
+    }
+
+    impl PackedSprite {
+        pub fn from_slice(packed: &[u8]) -> Option<PackedSprite> {
+            if packed.len() < 2 || packed.len() - 2 > MAX_PACKED_SIZE {
+                return None;
+            }
+            let mut data = [0u8; MAX_PACKED_SIZE];
+            data[..packed.len() - 2].copy_from_slice(&packed[2..]);
+            return Some(PackedSprite {
+                width: packed[0],
+                height: packed[1],
+                len: packed.len() - 2,
+                data,
+            });
+        }
+
+        fn row_bytes(&self) -> usize {
+            return (self.width as usize).div_ceil(8);
+        }
+    }
+
+    impl ImageDimensions for PackedSprite {
+        fn width(&self) -> u32 {
+            return self.width as u32;
+        }
+
+        fn height(&self) -> u32 {
+            return self.height as u32;
+        }
+    }
+
+    impl<'a> IntoPixelIter<BinaryColor> for &'a PackedSprite {
+        type PixelIterator = SpritePixels<'a>;
+
+        fn pixel_iter(self) -> Self::PixelIterator {
+            return SpritePixels {
+                sprite: self,
+                input: 0,
+                run: Run::None,
+                row: [0u8; MAX_ROW_BYTES],
+                point: Point::new(0, -1),
+            };
+        }
+    }
+
+    const MAX_ROW_BYTES: usize = 32;
+
+    // Where the decoder is in the current PackBits run.
+    enum Run {
+        None,
+        Literal(u8),
+        Repeat(u8, u8),
+    }
+
+    // Unpacks a row at a time as the pixels are asked for, so the whole
+    // image never has to exist in memory at once.
+    pub struct SpritePixels<'a> {
+        sprite: &'a PackedSprite,
+        input: usize,
+        run: Run,
+        row: [u8; MAX_ROW_BYTES],
+        point: Point,
+    }
+
+    impl<'a> SpritePixels<'a> {
+        fn next_byte(&mut self) -> Option<u8> {
+            loop {
+                match self.run {
+                    Run::Literal(left) => {
+                        let byte = *self.sprite.data[..self.sprite.len].get(self.input)?;
+                        self.input += 1;
+                        self.run = match left {
+                            1 => Run::None,
+                            _ => Run::Literal(left - 1),
+                        };
+                        return Some(byte);
+                    }
+                    Run::Repeat(byte, left) => {
+                        self.run = match left {
+                            1 => Run::None,
+                            _ => Run::Repeat(byte, left - 1),
+                        };
+                        return Some(byte);
+                    }
+                    Run::None => {
+                        let header = *self.sprite.data[..self.sprite.len].get(self.input)?;
+                        self.input += 1;
+                        self.run = match header {
+                            0..=127 => Run::Literal(header + 1),
+                            // A no-op in PackBits.
+                            128 => Run::None,
+                            _ => {
+                                let byte = *self.sprite.data[..self.sprite.len].get(self.input)?;
+                                self.input += 1;
+                                Run::Repeat(byte, (257 - header as u16) as u8)
+                            }
+                        };
+                    }
+                }
+            }
+        }
+
+        fn next_row(&mut self) -> Option<()> {
+            for i in 0..self.sprite.row_bytes().min(MAX_ROW_BYTES) {
+                self.row[i] = self.next_byte()?;
+            }
+            return Some(());
+        }
+    }
+
+    impl<'a> Iterator for SpritePixels<'a> {
+        type Item = Pixel<BinaryColor>;
+
+        fn next(&mut self) -> Option<Pixel<BinaryColor>> {
+            if self.point.y < 0 || self.point.x + 1 >= self.sprite.width as i32 {
+                if self.point.y + 1 >= self.sprite.height as i32 {
+                    return None;
+                }
+                self.next_row()?;
+                self.point = Point::new(0, self.point.y + 1);
+            } else {
+                self.point.x += 1;
+            }
+            let x = self.point.x as usize;
+            let on = self.row[x / 8] & (0x80 >> (x % 8)) != 0;
+            return Some(Pixel(self.point, BinaryColor::from(on)));
+        }
+    }
+}
+
+pub mod font {
+    use embedded_graphics::fonts::Font;
+    use embedded_graphics::geometry::Size;
+
+    // Font sheets are drawn in pokedex/fonts and turned into the raw images
+    // under src/fonts by `pack-resources fonts`. Glyphs go 16 to a row:
+    // printable ASCII first, then EXTRA_GLYPHS in order.
+    pub const GLYPHS_PER_ROW: u32 = 16;
+    pub const SHEET_ROWS: u32 = 7;
+    const ASCII_GLYPHS: u32 = 95;
+
+    // PK and MN are a glyph each, like on the Game Boy. Unicode doesn't have
+    // them, so they live in the private use area.
+    pub const PK: char = '\u{e000}';
+    pub const MN: char = '\u{e001}';
+
+    pub const EXTRA_GLYPHS: [char; 12] = ['é', '♂', '♀', PK, MN, '←', '↑', '→', '↓', '▶', '▷', '▼'];
+
+    // Control characters are blank, like padding at the end of a name.
+    // Anything else the sheet doesn't have comes out as a question mark.
+    fn glyph_index(c: char) -> u32 {
+        if c < ' ' {
+            return 0;
+        }
+        if c <= '~' {
+            return c as u32 - ' ' as u32;
+        }
+        match EXTRA_GLYPHS.iter().position(|g| *g == c) {
+            Some(i) => return ASCII_GLYPHS + i as u32,
+            None => return '?' as u32 - ' ' as u32,
+        }
+    }
+
+    // Everyday text. Same size as the PICO-8 font it replaces, so layouts
+    // didn't have to move.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct SmallFont;
+
+    impl Font for SmallFont {
+        const FONT_IMAGE: &'static [u8] = include_bytes!("fonts/small.raw");
+        const FONT_IMAGE_WIDTH: u32 = GLYPHS_PER_ROW * 4;
+        const CHARACTER_SIZE: Size = Size::new(4, 6);
+
+        fn char_offset(c: char) -> u32 {
+            return glyph_index(c);
+        }
+    }
+
+    // For the odd bit of text that has room to be readable.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LargeFont;
+
+    impl Font for LargeFont {
+        const FONT_IMAGE: &'static [u8] = include_bytes!("fonts/large.raw");
+        const FONT_IMAGE_WIDTH: u32 = GLYPHS_PER_ROW * 6;
+        const CHARACTER_SIZE: Size = Size::new(6, 8);
+
+        fn char_offset(c: char) -> u32 {
+            return glyph_index(c);
+        }
+    }
+}
+
+pub mod framebuffer {
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use embedded_graphics::prelude::*;
+
+    pub const WIDTH: usize = 128;
+    pub const HEIGHT: usize = 64;
+    pub const PAGES: usize = HEIGHT / 8;
+
+    // Every I2C write costs an address byte and a control byte on top of its
+    // payload, and a page update is two writes: one to point the column and
+    // page at the right place and one for the data.
+    const PAGE_OVERHEAD: u32 = 2 + 3 + 2;
+    // Nine clocks a byte once the ack is counted.
+    const BITS_PER_BYTE: u32 = 9;
+
+    // Whatever's on the other end of the wire. It gets handed a run of
+    // columns from one page at a time, laid out the way the sh1106 wants
+    // them: one byte per column, least significant bit at the top.
+    pub trait PageWriter {
+        type Error;
+
+        fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), Self::Error>;
+    }
+
+    // What a flush actually sent, for working out how long it took.
+    #[derive(Clone, Copy, Default, Debug)]
+    pub struct FlushStats {
+        pub pages: u32,
+        pub columns: u32,
+    }
+
+    impl FlushStats {
+        pub fn full() -> FlushStats {
+            return FlushStats {
+                pages: PAGES as u32,
+                columns: (PAGES * WIDTH) as u32,
+            };
+        }
+
+        pub fn add(&mut self, other: FlushStats) {
+            self.pages += other.pages;
+            self.columns += other.columns;
+        }
+
+        pub fn bytes(&self) -> u32 {
+            return self.pages * PAGE_OVERHEAD + self.columns;
+        }
+
+        pub fn micros(&self, clock_khz: u32) -> u32 {
+            return self.bytes() * BITS_PER_BYTE * 1000 / clock_khz;
+        }
+    }
+
+    // Screens redraw everything every time, which is simple but means a
+    // straight flush sends the whole kilobyte over bit-banged I2C even when
+    // only a cursor moved. Drawing into this instead keeps a copy of what
+    // the panel is showing, so a flush only sends the columns that changed.
+    pub struct FrameBuffer {
+        pixels: [u8; WIDTH * PAGES],
+        shown: [u8; WIDTH * PAGES],
+        // Nothing's known about the panel until the first flush.
+        stale: bool,
+    }
+
+    impl Default for FrameBuffer {
+        fn default() -> FrameBuffer {
+            return FrameBuffer::new();
+        }
+    }
+
+    impl FrameBuffer {
+        pub fn new() -> FrameBuffer {
+            return FrameBuffer {
+                pixels: [0; WIDTH * PAGES],
+                shown: [0; WIDTH * PAGES],
+                stale: true,
+            };
+        }
+
+        // For when the panel might not match any more, e.g. after a reset.
+        pub fn invalidate(&mut self) {
+            self.stale = true;
+        }
+
+        pub fn pixel(&self, x: usize, y: usize) -> bool {
+            return self.pixels[y / 8 * WIDTH + x] & (1 << (y % 8)) != 0;
+        }
+
+        // Sends one run per page, from the first changed column to the last.
+        // Splitting it up further rarely pays for the extra overhead.
+        pub fn flush<W: PageWriter>(&mut self, writer: &mut W) -> Result<FlushStats, W::Error> {
+            let mut stats = FlushStats::default();
+            for page in 0..PAGES {
+                let start = page * WIDTH;
+                let row = &self.pixels[start..start + WIDTH];
+                let shown = &self.shown[start..start + WIDTH];
+                let (first, last) = if self.stale {
+                    (0, WIDTH - 1)
+                } else {
+                    let first = match (0..WIDTH).find(|&x| row[x] != shown[x]) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    let last = (first..WIDTH).rev().find(|&x| row[x] != shown[x]).unwrap();
+                    (first, last)
+                };
+                writer.write_page(page as u8, first as u8, &row[first..=last])?;
+                self.shown[start + first..=start + last].copy_from_slice(&row[first..=last]);
+                stats.pages += 1;
+                stats.columns += (last - first + 1) as u32;
+            }
+            self.stale = false;
+            return Ok(stats);
+        }
+    }
+
+    impl DrawTarget<BinaryColor> for FrameBuffer {
+        type Error = core::convert::Infallible;
+
+        fn draw_pixel(&mut self, pixel: Pixel<BinaryColor>) -> Result<(), Self::Error> {
+            let Pixel(point, color) = pixel;
+            if point.x < 0 || point.y < 0 || point.x >= WIDTH as i32 || point.y >= HEIGHT as i32 {
+                return Ok(());
+            }
+            let index = point.y as usize / 8 * WIDTH + point.x as usize;
+            let mask = 1 << (point.y % 8);
+            match color {
+                BinaryColor::On => self.pixels[index] |= mask,
+                BinaryColor::Off => self.pixels[index] &= !mask,
+            }
+            return Ok(());
+        }
+
+        fn clear(&mut self, color: BinaryColor) -> Result<(), Self::Error> {
+            let fill = match color {
+                BinaryColor::On => 0xff,
+                BinaryColor::Off => 0x00,
+            };
+            self.pixels.fill(fill);
+            return Ok(());
+        }
+
+        fn size(&self) -> Size {
+            return Size::new(WIDTH as u32, HEIGHT as u32);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::app::{App, Context};
+        use crate::cry::Speaker;
+        use crate::input::{ButtonStates, Buttons};
+        use crate::led::Leds;
+        use crate::link::NoLink;
+        use crate::rom::{ReadError, Storage, WriteError};
+        use crate::save::Save;
+        use crate::screens::BattleState;
+        use crate::time::Timestamp;
+        use crate::trainer::FakeNetworks;
+
+        extern crate std;
+
+        // The same as the board's bit-banged bus, for putting times on the
+        // numbers.
+        const I2C_KHZ: u32 = 100;
+
+        // Erased flash with no ROM on it, which the menu gets by without.
+        struct Blank;
+
+        impl Storage for Blank {
+            fn read(&mut self, _offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+                buf.fill(0xff);
+                return Ok(());
+            }
+
+            fn erase_sector(&mut self, _offset: u32) -> Result<(), WriteError> {
+                return Ok(());
+            }
+
+            fn write(&mut self, _offset: u32, _data: &[u8]) -> Result<(), WriteError> {
+                return Ok(());
+            }
+        }
+
+        // Builds up what the panel would be showing from what gets sent.
+        struct Recorder {
+            panel: [u8; WIDTH * PAGES],
+            sent: FlushStats,
+        }
+
+        impl PageWriter for Recorder {
+            type Error = core::convert::Infallible;
+
+            fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), Self::Error> {
+                let start = page as usize * WIDTH + column as usize;
+                self.panel[start..start + data.len()].copy_from_slice(data);
+                self.sent.pages += 1;
+                self.sent.columns += data.len() as u32;
+                return Ok(());
+            }
+        }
+
+        #[test]
+        fn partial_flushes_reproduce_the_frame() {
+            let mut storage = Blank;
+            let mut save = Save::load(&mut storage);
+            save.data.player_name = Some(*b"RED\0\0\0\0\0\0\0\0\0");
+            save.data.set_clock(Timestamp::new(0, 12, 0));
+            let mut app = App::boot(&save);
+            let mut buttons = ButtonStates::new();
+            let mut leds = Leds::new();
+            let mut speaker = Speaker::new();
+            let mut battle = BattleState::new();
+            let mut display = FrameBuffer::new();
+            // Starts out different to anything the app would draw, so the
+            // first flush has to cover all of it.
+            let mut recorder = Recorder {
+                panel: [0xaa; WIDTH * PAGES],
+                sent: FlushStats::default(),
+            };
+
+            let presses = [
+                ("boot", None),
+                ("menu down", Some(Buttons::DOWN)),
+                ("menu down", Some(Buttons::DOWN)),
+                ("menu up", Some(Buttons::UP)),
+            ];
+            let full = FlushStats::full();
+            let mut frames = 0;
+            let mut reported = FlushStats::default();
+            std::println!("{:<10} {:>5} {:>5} {:>8}", "step", "pages", "bytes", "ms");
+            for (label, button) in presses {
+                if let Some(button) = button {
+                    buttons.update(button, true);
+                }
+                let mut ctx = Context {
+                    storage: &mut storage,
+                    save: &mut save,
+                    leds: &mut leds,
+                    link: &mut NoLink,
+                    scanner: &mut FakeNetworks,
+                    speaker: &mut speaker,
+                    battle: &mut battle,
+                };
+                app.tick(&mut buttons, &mut ctx);
+                if let Some(button) = button {
+                    buttons.update(button, false);
+                }
+                assert!(app.render(&mut display, &mut ctx));
+                let stats = display.flush(&mut recorder).unwrap();
+                std::println!(
+                    "{:<10} {:>5} {:>5} {:>8.1}",
+                    label,
+                    stats.pages,
+                    stats.bytes(),
+                    stats.micros(I2C_KHZ) as f32 / 1000.0
+                );
+                reported.add(stats);
+                assert_eq!(recorder.panel, display.pixels);
+                if frames == 0 {
+                    assert_eq!(stats.bytes(), full.bytes());
+                } else {
+                    // Only the cursor moved, which should be a sliver of
+                    // the whole screen.
+                    assert!(stats.bytes() > 0);
+                    assert!(stats.bytes() * 20 < full.bytes());
+                }
+                frames += 1;
+            }
+            std::println!(
+                "{} bytes in {:.1}ms, {} bytes in {:.1}ms flushing everything",
+                reported.bytes(),
+                reported.micros(I2C_KHZ) as f32 / 1000.0,
+                frames * full.bytes(),
+                (frames * full.micros(I2C_KHZ)) as f32 / 1000.0,
+            );
+
+            assert_eq!(recorder.sent.bytes(), reported.bytes());
+            assert!(reported.bytes() < frames * full.bytes());
+            // Nothing's changed since, so there's nothing to send.
+            assert_eq!(display.flush(&mut recorder).unwrap().bytes(), 0);
+        }
+    }
+}
+
+pub mod rom {
+    use crate::pokedex::{
+        Item, LearnCondition, LearnableMove, Move, MoveListChunk, PokemonSpecies, SPECIES_COUNT,
+    };
+    use crate::sprite::{PackedSprite, MAX_PACKED_SIZE};
+
+    pub const DATA_OFFSET: u32 = 0x200000;
+    const DATA_BUFFER_SIZE: usize = 256;
+
+    // The lookup table has a row per species, and then a row for each of the
+    // other sections. Sections have their own lookup table at the start, with
+    // offsets relative to the start of the section.
+    const ITEMS_ROW: u8 = SPECIES_COUNT + 1;
+    const ITEM_BUFFER_SIZE: usize = 64;
+    const MOVES_ROW: u8 = SPECIES_COUNT + 2;
+    const MOVE_BUFFER_SIZE: usize = 128;
+    const SPRITES_ROW: u8 = SPECIES_COUNT + 3;
+    // Room for the packed sprite and the few bytes CBOR wraps it in.
+    const SPRITE_BUFFER_SIZE: usize = MAX_PACKED_SIZE + 8;
+
+    #[derive(Debug)]
+    pub struct ReadError(pub &'static str);
+
+    #[derive(Debug)]
+    pub struct WriteError(pub &'static str);
+
+    // Anything we can pull raw bytes out of. On the device this is the SPI
+    // flash, but keeping it behind a trait means screens don't need to know
+    // that.
+    pub trait Storage {
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError>;
+
+        // Flash can only flip bits from 1 to 0, so anything being written
+        // needs its sector erased first. Offsets here are sector aligned.
+        fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError>;
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError>;
+    }
+
+    pub fn read_pokemon(id: u8, storage: &mut dyn Storage) -> Result<PokemonSpecies, ReadError> {
+        let (data_offset, size) = read_row(id, storage)?;
+
+        // Prefer to return an error than to panic.
+        if size > DATA_BUFFER_SIZE {
+            return Err(ReadError("data larger than buffer"));
+        }
+
+        let mut buf = [0u8; DATA_BUFFER_SIZE];
+        if storage.read(data_offset, &mut buf[..size]).is_err() {
+            return Err(ReadError("read data failed"));
+        }
+
+        match minicbor::decode(&buf) {
+            Ok(data) => return Ok(data),
+            Err(_) => return Err(ReadError("failed to decode data")),
+        }
+    }
+
+    const MAX_LEARNABLE_MOVES: usize = 96;
+    const CHUNK_BUFFER_SIZE: usize = 256;
+
+    // Every move a species can learn, in the order pack-resources wrote them
+    // (level up moves first, lowest level first).
+    pub struct MoveList {
+        moves: [LearnableMove; MAX_LEARNABLE_MOVES],
+        count: usize,
+    }
+
+    impl MoveList {
+        pub fn iter(&self) -> core::slice::Iter<'_, LearnableMove> {
+            return self.moves[..self.count].iter();
+        }
+    }
+
+    pub fn read_move_list(id: u8, storage: &mut dyn Storage) -> Result<MoveList, ReadError> {
+        // The move list chunks sit straight after the species data, each one
+        // with a length prefix.
+        let (data_offset, size) = read_row(id, storage)?;
+        let mut offset = data_offset + size as u32;
+        let mut list = MoveList {
+            moves: [LearnableMove {
+                id: 0,
+                condition: LearnCondition::Machine,
+            }; MAX_LEARNABLE_MOVES],
+            count: 0,
+        };
+        loop {
+            let mut prefix = [0u8; 4];
+            if storage.read(offset, &mut prefix).is_err() {
+                return Err(ReadError("read chunk length failed"));
+            }
+            let length = u32::from_be_bytes(prefix) as usize;
+            if length > CHUNK_BUFFER_SIZE {
+                return Err(ReadError("chunk larger than buffer"));
+            }
+            let mut buf = [0u8; CHUNK_BUFFER_SIZE];
+            if storage.read(offset + 4, &mut buf[..length]).is_err() {
+                return Err(ReadError("read chunk failed"));
+            }
+            let chunk: MoveListChunk = match minicbor::decode(&buf[..length]) {
+                Ok(c) => c,
+                Err(_) => return Err(ReadError("failed to decode chunk")),
+            };
+            for m in chunk.moves.iter().flatten() {
+                if list.count == MAX_LEARNABLE_MOVES {
+                    break;
+                }
+                list.moves[list.count] = *m;
+                list.count += 1;
+            }
+            if chunk.is_final_chunk {
+                return Ok(list);
+            }
+            offset += 4 + length as u32;
+        }
+    }
+
+    pub fn read_item(id: u8, storage: &mut dyn Storage) -> Result<Item, ReadError> {
+        let (data_offset, size) = read_section_row(ITEMS_ROW, id, storage)?;
+        if size > ITEM_BUFFER_SIZE {
+            return Err(ReadError("item larger than buffer"));
+        }
+        let mut buf = [0u8; ITEM_BUFFER_SIZE];
+        if storage.read(data_offset, &mut buf[..size]).is_err() {
+            return Err(ReadError("read item failed"));
+        }
+        match minicbor::decode(&buf[..size]) {
+            Ok(item) => return Ok(item),
+            Err(_) => return Err(ReadError("failed to decode item")),
+        }
+    }
+
+    // Moves are stored by id, so there's a hole at 0 and wherever the data
+    // didn't have a move. Those fail to decode like any other bad data.
+    pub fn read_move(id: u16, storage: &mut dyn Storage) -> Result<Move, ReadError> {
+        let index = match u8::try_from(id) {
+            Ok(i) => i,
+            Err(_) => return Err(ReadError("move id out of bounds")),
+        };
+        let (data_offset, size) = read_section_row(MOVES_ROW, index, storage)?;
+        if size > MOVE_BUFFER_SIZE {
+            return Err(ReadError("move larger than buffer"));
+        }
+        let mut buf = [0u8; MOVE_BUFFER_SIZE];
+        if storage.read(data_offset, &mut buf[..size]).is_err() {
+            return Err(ReadError("read move failed"));
+        }
+        match minicbor::decode(&buf[..size]) {
+            Ok(m) => return Ok(m),
+            Err(_) => return Err(ReadError("failed to decode move")),
+        }
+    }
+
+    // Sprites live in their own section, still packed. They're unpacked a row
+    // at a time as they're drawn.
+    pub fn read_sprite(index: u8, storage: &mut dyn Storage) -> Result<PackedSprite, ReadError> {
+        let (data_offset, size) = read_section_row(SPRITES_ROW, index, storage)?;
+        if size > SPRITE_BUFFER_SIZE {
+            return Err(ReadError("sprite larger than buffer"));
+        }
+        let mut buf = [0u8; SPRITE_BUFFER_SIZE];
+        if storage.read(data_offset, &mut buf[..size]).is_err() {
+            return Err(ReadError("read sprite failed"));
+        }
+        let packed = match minicbor::Decoder::new(&buf[..size]).bytes() {
+            Ok(p) => p,
+            Err(_) => return Err(ReadError("failed to decode sprite")),
+        };
+        match PackedSprite::from_slice(packed) {
+            Some(sprite) => return Ok(sprite),
+            None => return Err(ReadError("sprite larger than buffer")),
+        }
+    }
+
+    // Looks up an entry in one of the sections after the species.
+    fn read_section_row(
+        section: u8,
+        index: u8,
+        storage: &mut dyn Storage,
+    ) -> Result<(u32, usize), ReadError> {
+        let (section_offset, section_size) = read_row(section, storage)?;
+        if (index as usize + 1) * 8 > section_size {
+            return Err(ReadError("section index out of bounds"));
+        }
+        let mut entry = [0u8; 8];
+        if storage
+            .read(section_offset + (index as u32) * 8, &mut entry)
+            .is_err()
+        {
+            return Err(ReadError("read section row failed"));
+        }
+        let offset = u32::from_be_bytes(entry[0..4].try_into().unwrap());
+        let size = u32::from_be_bytes(entry[4..8].try_into().unwrap()) as usize;
+        return Ok((section_offset + offset, size));
+    }
+
+    // Looks up where a row's data lives, and how big it is.
+    fn read_row(id: u8, storage: &mut dyn Storage) -> Result<(u32, usize), ReadError> {
+        // First read this id's row in the lookup table.
+        let mut table_entry = [0u8; 8];
+        let row_offset = match DATA_OFFSET.checked_add(u32::from(id) * 8) {
+            Some(i) => i,
+            None => return Err(ReadError("row offset out of bounds")),
+        };
+        if storage.read(row_offset, &mut table_entry).is_err() {
+            return Err(ReadError("read row failed"));
+        }
+
+        // Parse the row data.
+        let offset = u32::from_be_bytes(table_entry[0..4].try_into().unwrap());
+        let size: usize = match u32::from_be_bytes(table_entry[4..8].try_into().unwrap()).try_into()
+        {
+            Ok(i) => i,
+            Err(_) => return Err(ReadError("failed to convert size")),
+        };
+
+        match DATA_OFFSET.checked_add(offset) {
+            Some(i) => return Ok((i, size)),
+            None => return Err(ReadError("data offset overflowed")),
+        }
+    }
+
+    // A tiny ROM laid out the way pack-resources does it, holding only what a
+    // test puts in it. Everything else reads back as erased flash, and writes
+    // go nowhere.
+    #[cfg(test)]
+    pub mod testing {
+        use super::*;
+        use crate::generation::{generate_pokemon, SeededRandom};
+        use crate::pokedex::{
+            DamageClass, GrowthRate, Parameters, Pokemon, SpeciesStatData, Target, Type,
+        };
+        use crate::save::{SAVE_OFFSET, SAVE_SIZE, SECTOR_SIZE};
+
+        const SIZE: usize = 0xc000;
+        // Room for every species, for anything that picks them at random.
+        const SPECIES_START: usize = 0x800;
+        // Both sections have a row for every id that can go in them.
+        const SECTION_TABLE: usize = 256 * 8;
+        const MOVES_SECTION: usize = 0x8000;
+        const ITEMS_SECTION: usize = 0xa000;
+
+        // The packed resources, plus a save area that keeps whatever gets
+        // written to it so a save can be loaded back.
+        pub struct TestRom {
+            data: [u8; SIZE],
+            save: [u8; SAVE_SIZE as usize],
+            species_end: usize,
+            moves_end: usize,
+            items_end: usize,
+        }
+
+        impl Default for TestRom {
+            fn default() -> TestRom {
+                return TestRom::new();
+            }
+        }
+
+        impl TestRom {
+            pub fn new() -> TestRom {
+                let mut rom = TestRom {
+                    data: [0xff; SIZE],
+                    save: [0xff; SAVE_SIZE as usize],
+                    species_end: SPECIES_START,
+                    moves_end: MOVES_SECTION + SECTION_TABLE,
+                    items_end: ITEMS_SECTION + SECTION_TABLE,
+                };
+                for (row, section) in [(MOVES_ROW, MOVES_SECTION), (ITEMS_ROW, ITEMS_SECTION)] {
+                    rom.data[section..section + SECTION_TABLE].fill(0);
+                    rom.set_row(row as usize * 8, section, SECTION_TABLE);
+                }
+                return rom;
+            }
+
+            // With an empty move list after it, so nothing learns anything.
+            pub fn add_species(&mut self, species: &PokemonSpecies) {
+                self.add_species_learning(species, &[]);
+            }
+
+            // Up to 16 moves, which all fit in the one chunk.
+            pub fn add_species_learning(
+                &mut self,
+                species: &PokemonSpecies,
+                learnable: &[LearnableMove],
+            ) {
+                let start = self.species_end;
+                let len = self.encode(start, species);
+                self.set_row(species.id as usize * 8, start, len);
+                let mut moves = [None; 16];
+                for (slot, m) in moves.iter_mut().zip(learnable) {
+                    *slot = Some(*m);
+                }
+                let chunk = MoveListChunk {
+                    is_final_chunk: true,
+                    moves,
+                };
+                let chunk_len = self.encode(start + len + 4, &chunk);
+                self.data[start + len..start + len + 4]
+                    .copy_from_slice(&(chunk_len as u32).to_be_bytes());
+                self.species_end = start + len + 4 + chunk_len;
+            }
+
+            pub fn add_move(&mut self, m: &Move) {
+                let start = self.moves_end;
+                let len = self.encode(start, m);
+                self.set_row(
+                    MOVES_SECTION + m.id as usize * 8,
+                    start - MOVES_SECTION,
+                    len,
+                );
+                self.moves_end = start + len;
+            }
+
+            pub fn add_item(&mut self, item: &Item) {
+                let start = self.items_end;
+                let len = self.encode(start, item);
+                self.set_row(
+                    ITEMS_SECTION + item.id as usize * 8,
+                    start - ITEMS_SECTION,
+                    len,
+                );
+                self.items_end = start + len;
+            }
+
+            fn encode<T: minicbor::Encode>(&mut self, at: usize, value: &T) -> usize {
+                let mut writer: &mut [u8] = &mut self.data[at..];
+                let space = writer.len();
+                minicbor::encode(value, &mut writer).unwrap();
+                return space - writer.len();
+            }
+
+            fn set_row(&mut self, at: usize, offset: usize, size: usize) {
+                self.data[at..at + 4].copy_from_slice(&(offset as u32).to_be_bytes());
+                self.data[at + 4..at + 8].copy_from_slice(&(size as u32).to_be_bytes());
+            }
+        }
+
+        impl TestRom {
+            fn save_range(&mut self, offset: u32, len: usize) -> Result<&mut [u8], WriteError> {
+                let start = (offset as usize).wrapping_sub(SAVE_OFFSET as usize);
+                return self
+                    .save
+                    .get_mut(start..start + len)
+                    .ok_or(WriteError("OUTSIDE THE SAVE"));
+            }
+        }
+
+        impl Storage for TestRom {
+            fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+                if let Ok(save) = self.save_range(offset, buf.len()) {
+                    buf.copy_from_slice(save);
+                    return Ok(());
+                }
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    let at = (offset as usize + i).wrapping_sub(DATA_OFFSET as usize);
+                    *byte = self.data.get(at).copied().unwrap_or(0xff);
+                }
+                return Ok(());
+            }
+
+            fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError> {
+                self.save_range(offset, SECTOR_SIZE as usize)?.fill(0xff);
+                return Ok(());
+            }
+
+            fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError> {
+                self.save_range(offset, data.len())?.copy_from_slice(data);
+                return Ok(());
+            }
+        }
+
+        // Middling stats all round, so only the types tell them apart.
+        pub fn species(id: u8, type_primary: Type, type_secondary: Option<Type>) -> PokemonSpecies {
+            let stat = SpeciesStatData {
+                base_value: 60,
+                effort_value_yield: 0,
+            };
+            return PokemonSpecies {
+                id,
+                name: *b"TESTMON\0\0\0\0\0",
+                type_primary,
+                type_secondary,
+                growth_rate: GrowthRate::FAST,
+                capture_rate: 100,
+                base_experience: 60,
+                hp: stat,
+                attack: stat,
+                defense: stat,
+                special_attack: stat,
+                special_defense: stat,
+                speed: stat,
+                sprite: 0,
+                evolutions: [None; 3],
+                back_sprite: None,
+            };
+        }
+
+        // A plain attack that never misses and does nothing else.
+        pub fn attack(id: u16, type_: Type, power: u8, pp: u8) -> Move {
+            return Move {
+                id,
+                name: *b"TESTMOVE\0\0\0\0",
+                type_,
+                damage_class: DamageClass::Physical,
+                target: Target::SelectedPokemon,
+                accuracy: 0,
+                power,
+                pp,
+                priority: 0,
+                parameters: Parameters {
+                    ailment: None,
+                    crit_rate: 0,
+                    drain: 0,
+                    flinch_chance: 0,
+                    healing: 0,
+                    stat_chance: 0,
+                    turn_range: None,
+                    hit_range: None,
+                },
+                stat_changes: [None, None],
+            };
+        }
+
+        pub fn pokemon(species: PokemonSpecies, level: u8, moves: [Option<u16>; 4]) -> Pokemon {
+            let mut rng = SeededRandom::new(species.id as u32);
+            let mut pokemon = generate_pokemon(species, &mut [].iter(), level, &mut rng);
+            pokemon.moves = moves;
+            return pokemon;
+        }
+    }
+}
+
+// Stand-ins for the board's flash and panel, shared by the tools that run
+// the app on a computer. Nothing in here touches SDL, so anything using it
+// can run wherever the tests do.
+#[cfg(unix)]
+pub mod host {
+    extern crate std;
+
+    use crate::framebuffer::{PageWriter, PAGES, WIDTH};
+    use crate::rom::{ReadError, Storage, WriteError, DATA_OFFSET};
+    use crate::save::SECTOR_SIZE;
+    use std::vec;
+    use std::vec::Vec;
+
+    // Same size as the flash chip on the board.
+    pub const FLASH_SIZE: usize = 0x400000;
+
+    pub struct MemFlash(pub Vec<u8>);
+
+    impl MemFlash {
+        // Erased flash reads back as all ones, which is also a blank save.
+        pub fn with_rom(rom: &[u8]) -> MemFlash {
+            let mut data = vec![0xff; FLASH_SIZE];
+            let start = DATA_OFFSET as usize;
+            data[start..start + rom.len()].copy_from_slice(rom);
+            return MemFlash(data);
+        }
+    }
+
+    impl Storage for MemFlash {
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+            let start = offset as usize;
+            match self.0.get(start..start + buf.len()) {
+                Some(data) => buf.copy_from_slice(data),
+                None => return Err(ReadError("read past the end of flash")),
+            }
+            return Ok(());
+        }
+
+        fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError> {
+            let start = offset as usize / SECTOR_SIZE as usize * SECTOR_SIZE as usize;
+            match self.0.get_mut(start..start + SECTOR_SIZE as usize) {
+                Some(sector) => sector.fill(0xff),
+                None => return Err(WriteError("erase past the end of flash")),
+            }
+            return Ok(());
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError> {
+            let start = offset as usize;
+            match self.0.get_mut(start..start + data.len()) {
+                Some(target) => target.copy_from_slice(data),
+                None => return Err(WriteError("write past the end of flash")),
+            }
+            return Ok(());
+        }
+    }
+
+    // Plays the part of the sh1106's memory, so whatever's driving it goes
+    // through the same partial flushes as the board does.
+    pub struct Panel {
+        pages: [u8; WIDTH * PAGES],
+    }
+
+    impl Default for Panel {
+        fn default() -> Panel {
+            return Panel::new();
+        }
+    }
+
+    impl Panel {
+        pub fn new() -> Panel {
+            return Panel {
+                pages: [0; WIDTH * PAGES],
+            };
+        }
+
+        pub fn pixel(&self, x: usize, y: usize) -> bool {
+            return self.pages[y / 8 * WIDTH + x] & (1 << (y % 8)) != 0;
+        }
+    }
+
+    impl PageWriter for Panel {
+        type Error = core::convert::Infallible;
+
+        fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), Self::Error> {
+            let start = page as usize * WIDTH + column as usize;
+            self.pages[start..start + data.len()].copy_from_slice(data);
+            return Ok(());
+        }
+    }
+}
+
+pub mod time {
+    pub const SECONDS_PER_MINUTE: u32 = 60;
+    pub const SECONDS_PER_HOUR: u32 = 60 * SECONDS_PER_MINUTE;
+    pub const SECONDS_PER_DAY: u32 = 24 * SECONDS_PER_HOUR;
+
+    // Same split as the old games: morning from four, day from ten, and
+    // night from six in the evening.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TimeOfDay {
+        Morning,
+        Day,
+        Night,
+    }
+
+    impl TimeOfDay {
+        pub fn name(&self) -> &'static str {
+            match self {
+                TimeOfDay::Morning => "MORNING",
+                TimeOfDay::Day => "DAY",
+                TimeOfDay::Night => "NIGHT",
+            }
+        }
+    }
+
+    // Seconds since midnight on the day the player set the clock. There's no
+    // calendar, just days counted from then, which is all anything like a
+    // daily event needs.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Timestamp(pub u32);
+
+    impl Timestamp {
+        pub fn new(day: u32, hour: u8, minute: u8) -> Timestamp {
+            return Timestamp(
+                day * SECONDS_PER_DAY
+                    + hour as u32 * SECONDS_PER_HOUR
+                    + minute as u32 * SECONDS_PER_MINUTE,
+            );
+        }
+
+        pub fn day(&self) -> u32 {
+            return self.0 / SECONDS_PER_DAY;
+        }
+
+        pub fn hour(&self) -> u8 {
+            return (self.0 % SECONDS_PER_DAY / SECONDS_PER_HOUR) as u8;
+        }
+
+        pub fn minute(&self) -> u8 {
+            return (self.0 % SECONDS_PER_HOUR / SECONDS_PER_MINUTE) as u8;
+        }
+
+        pub fn time_of_day(&self) -> TimeOfDay {
+            return match self.hour() {
+                4..=9 => TimeOfDay::Morning,
+                10..=17 => TimeOfDay::Day,
+                _ => TimeOfDay::Night,
+            };
+        }
+
+        // Zero if the other one's actually later, e.g. after the clock's been
+        // set back.
+        pub fn seconds_since(&self, earlier: Timestamp) -> u32 {
+            return self.0.saturating_sub(earlier.0);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn the_time_of_day_turns_over_on_the_hour() {
+            for (hour, minute, time) in [
+                (0, 0, TimeOfDay::Night),
+                (3, 59, TimeOfDay::Night),
+                (4, 0, TimeOfDay::Morning),
+                (9, 59, TimeOfDay::Morning),
+                (10, 0, TimeOfDay::Day),
+                (17, 59, TimeOfDay::Day),
+                (18, 0, TimeOfDay::Night),
+                (23, 59, TimeOfDay::Night),
+            ] {
+                let now = Timestamp::new(3, hour, minute);
+                assert_eq!((now.hour(), now.minute()), (hour, minute));
+                assert_eq!(now.time_of_day(), time, "at {}:{}", hour, minute);
+            }
+        }
+
+        #[test]
+        fn midnight_starts_the_next_day() {
+            let late = Timestamp::new(0, 23, 59);
+            let next = Timestamp(late.0 + SECONDS_PER_MINUTE);
+            assert_eq!((next.day(), next.hour(), next.minute()), (1, 0, 0));
+            assert_eq!(next.seconds_since(late), SECONDS_PER_MINUTE);
+            assert_eq!(late.seconds_since(next), 0);
+        }
+    }
+}
+
+pub mod save {
+    use crate::gym::{Gym, BADGE_COUNT};
+    use crate::pokedex::{Pokemon, Type, SPECIES_COUNT};
+    use crate::rom::{Storage, WriteError};
+    use crate::time::Timestamp;
+    use minicbor::{bytes::ByteArray, Decode, Encode};
+
+    // The save lives well clear of the packed resources. There are two slots
+    // and we always write to the one we didn't load from, so losing power
+    // halfway through a write leaves the previous save intact.
+    pub const SAVE_OFFSET: u32 = 0x300000;
+    const SLOT_SIZE: u32 = 0x4000;
+    pub const SAVE_SIZE: u32 = 2 * SLOT_SIZE;
+    pub const SECTOR_SIZE: u32 = 0x1000;
+    const SAVE_MAGIC: u32 = 0x504b5356; // "PKSV"
+    const HEADER_SIZE: usize = 16;
+    const SAVE_BUFFER_SIZE: usize = 4096;
+
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+    #[cbor(index_only)]
+    pub enum TextSpeed {
+        #[n(0)]
+        Slow,
+        #[n(1)]
+        Medium,
+        #[n(2)]
+        Fast,
+    }
+
+    impl TextSpeed {
+        pub fn name(&self) -> &'static str {
+            match self {
+                TextSpeed::Slow => "SLOW",
+                TextSpeed::Medium => "MEDIUM",
+                TextSpeed::Fast => "FAST",
+            }
+        }
+
+        // How quickly messages get typed out.
+        pub fn chars_per_tick(&self) -> usize {
+            match self {
+                TextSpeed::Slow => 1,
+                TextSpeed::Medium => 2,
+                TextSpeed::Fast => 4,
+            }
+        }
+    }
+
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+    pub struct Settings {
+        #[n(0)]
+        pub text_speed: TextSpeed,
+        #[n(1)]
+        pub contrast: u8,
+        #[n(2)]
+        pub inverted: bool,
+        // Zero means the neopixel stays off.
+        #[n(3)]
+        pub led_brightness: u8,
+        #[n(4)]
+        pub battle_animations: bool,
+        // Zero means never sleep.
+        #[n(5)]
+        pub auto_sleep_minutes: u8,
+    }
+
+    impl Default for Settings {
+        fn default() -> Settings {
+            return Settings::new();
+        }
+    }
+
+    impl Settings {
+        pub fn new() -> Settings {
+            return Settings {
+                text_speed: TextSpeed::Medium,
+                contrast: 0x7f,
+                inverted: false,
+                led_brightness: 0x20,
+                battle_animations: true,
+                auto_sleep_minutes: 5,
+            };
+        }
+    }
+
+    pub const PARTY_SIZE: usize = 6;
+    const DEX_BYTES: usize = (SPECIES_COUNT as usize).div_ceil(8);
+
+    // One bit per species for each of seen and caught. Catching something
+    // means you've seen it too, so caught is always a subset of seen.
+    #[derive(Encode, Decode, Debug)]
+    pub struct DexProgress {
+        #[n(0)]
+        seen: ByteArray<DEX_BYTES>,
+        #[n(1)]
+        caught: ByteArray<DEX_BYTES>,
+    }
+
+    impl Default for DexProgress {
+        fn default() -> DexProgress {
+            return DexProgress::new();
+        }
+    }
+
+    impl DexProgress {
+        pub fn new() -> DexProgress {
+            return DexProgress {
+                seen: ByteArray::from([0u8; DEX_BYTES]),
+                caught: ByteArray::from([0u8; DEX_BYTES]),
+            };
+        }
+
+        pub fn mark_seen(&mut self, species_id: u8) {
+            set_bit(&mut self.seen, species_id);
+        }
+
+        pub fn mark_caught(&mut self, species_id: u8) {
+            set_bit(&mut self.seen, species_id);
+            set_bit(&mut self.caught, species_id);
+        }
+
+        pub fn has_seen(&self, species_id: u8) -> bool {
+            return get_bit(&self.seen, species_id);
+        }
+
+        pub fn has_caught(&self, species_id: u8) -> bool {
+            return get_bit(&self.caught, species_id);
+        }
+
+        pub fn seen_count(&self) -> u32 {
+            return self.seen.iter().map(|b| b.count_ones()).sum();
+        }
+
+        pub fn caught_count(&self) -> u32 {
+            return self.caught.iter().map(|b| b.count_ones()).sum();
+        }
+    }
+
+    const DEFEATED_TRAINER_SLOTS: usize = 32;
+    const DEFEATED_TRAINER_BYTES: usize = DEFEATED_TRAINER_SLOTS * 4;
+
+    // Trainers we've already beaten, by network fingerprint. There's only so
+    // much room, so once it fills up the oldest ones get forgotten and can be
+    // fought again.
+    #[derive(Encode, Decode, Debug)]
+    pub struct DefeatedTrainers {
+        #[n(0)]
+        ids: ByteArray<DEFEATED_TRAINER_BYTES>,
+        #[n(1)]
+        next: u8,
+    }
+
+    impl Default for DefeatedTrainers {
+        fn default() -> DefeatedTrainers {
+            return DefeatedTrainers::new();
+        }
+    }
+
+    impl DefeatedTrainers {
+        pub fn new() -> DefeatedTrainers {
+            return DefeatedTrainers {
+                ids: ByteArray::from([0u8; DEFEATED_TRAINER_BYTES]),
+                next: 0,
+            };
+        }
+
+        pub fn contains(&self, trainer_id: u32) -> bool {
+            return self
+                .ids
+                .chunks(4)
+                .any(|id| u32::from_be_bytes(id.try_into().unwrap()) == trainer_id);
+        }
+
+        pub fn record(&mut self, trainer_id: u32) {
+            if self.contains(trainer_id) {
+                return;
+            }
+            let i = self.next as usize % DEFEATED_TRAINER_SLOTS;
+            self.ids[i * 4..i * 4 + 4].copy_from_slice(&trainer_id.to_be_bytes());
+            self.next = ((i + 1) % DEFEATED_TRAINER_SLOTS) as u8;
+        }
+
+        // Oldest first, so recording them again in this order ends up
+        // forgetting the same ones.
+        pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+            let start = self.next as usize % DEFEATED_TRAINER_SLOTS;
+            return (0..DEFEATED_TRAINER_SLOTS)
+                .map(move |i| (start + i) % DEFEATED_TRAINER_SLOTS)
+                .map(move |i| u32::from_be_bytes(self.ids[i * 4..i * 4 + 4].try_into().unwrap()))
+                .filter(|id| *id != 0);
+        }
+    }
+
+    // A badge is named after the type of the gym it came from, and only one
+    // badge per type can be won.
+    #[derive(Encode, Decode, Debug, Clone, Copy)]
+    pub struct Badge {
+        #[n(0)]
+        pub theme: Type,
+        #[n(1)]
+        pub gym_id: u32,
+    }
+
+    pub const BAG_SIZE: usize = 16;
+
+    // A new game starts with some poke balls and potions, ids as in
+    // pokedex/items.json.
+    const STARTING_BAG: [Option<BagSlot>; BAG_SIZE] = {
+        let mut bag = [None; BAG_SIZE];
+        bag[0] = Some(BagSlot {
+            item_id: 0,
+            quantity: 5,
+        });
+        bag[1] = Some(BagSlot {
+            item_id: 4,
+            quantity: 3,
+        });
+        bag
+    };
+
+    #[derive(Encode, Decode, Debug, Clone, Copy)]
+    pub struct BagSlot {
+        #[n(0)]
+        pub item_id: u8,
+        #[n(1)]
+        pub quantity: u8,
+    }
+
+    // Species ids start at 1, so bit 0 is bulbasaur.
+    fn set_bit(bits: &mut [u8; DEX_BYTES], species_id: u8) {
+        if species_id == 0 || species_id > SPECIES_COUNT {
+            return;
+        }
+        let i = (species_id - 1) as usize;
+        bits[i / 8] |= 1 << (i % 8);
+    }
+
+    fn get_bit(bits: &[u8; DEX_BYTES], species_id: u8) -> bool {
+        if species_id == 0 || species_id > SPECIES_COUNT {
+            return false;
+        }
+        let i = (species_id - 1) as usize;
+        return bits[i / 8] & (1 << (i % 8)) != 0;
+    }
+
+    // A trade both sides have agreed to that hasn't been finished at this
+    // end yet. The link module explains how it gets settled.
+    #[derive(Encode, Decode, Debug)]
+    pub struct PendingTrade {
+        #[n(0)]
+        pub id: u32,
+        #[n(1)]
+        pub slot: u8,
+        #[n(2)]
+        pub incoming: Pokemon,
+        #[n(3)]
+        pub partner: [u8; 12],
+    }
+
+    // How many finished trades can be waiting on the other side to say it
+    // finished them too.
+    pub const FINISHED_TRADE_SLOTS: usize = 4;
+
+    #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+    pub struct FinishedTrade {
+        #[n(0)]
+        pub id: u32,
+        #[n(1)]
+        pub partner: [u8; 12],
+    }
+
+    // Trades this side has finished that the other side might not have. Each
+    // one stays until that player says they've finished it too, or links up
+    // again without it pending, since until then they might still ask.
+    #[derive(Encode, Decode, Debug)]
+    pub struct FinishedTrades {
+        #[n(0)]
+        trades: [Option<FinishedTrade>; FINISHED_TRADE_SLOTS],
+    }
+
+    impl Default for FinishedTrades {
+        fn default() -> FinishedTrades {
+            return FinishedTrades::new();
+        }
+    }
+
+    impl FinishedTrades {
+        pub fn new() -> FinishedTrades {
+            return FinishedTrades {
+                trades: [None; FINISHED_TRADE_SLOTS],
+            };
+        }
+
+        pub fn contains(&self, id: u32, partner: &[u8; 12]) -> bool {
+            return self
+                .trades
+                .iter()
+                .flatten()
+                .any(|t| t.id == id && t.partner == *partner);
+        }
+
+        // Returns false if there's no room left.
+        pub fn record(&mut self, id: u32, partner: &[u8; 12]) -> bool {
+            if self.contains(id, partner) {
+                return true;
+            }
+            match self.trades.iter().position(|t| t.is_none()) {
+                Some(i) => {
+                    self.trades[i] = Some(FinishedTrade {
+                        id,
+                        partner: *partner,
+                    });
+                    return true;
+                }
+                None => return false,
+            }
+        }
+
+        pub fn forget(&mut self, id: u32, partner: &[u8; 12]) {
+            for slot in self.trades.iter_mut() {
+                if slot.is_some_and(|t| t.id == id && t.partner == *partner) {
+                    *slot = None;
+                }
+            }
+        }
+
+        // Forgets everything with a partner apart from the one trade they've
+        // still got pending, if any. They've settled the rest already.
+        pub fn prune(&mut self, partner: &[u8; 12], keep: u32) {
+            for slot in self.trades.iter_mut() {
+                if slot.is_some_and(|t| t.partner == *partner && t.id != keep) {
+                    *slot = None;
+                }
+            }
+        }
+
+        pub fn is_full(&self) -> bool {
+            return self.trades.iter().all(|t| t.is_some());
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = &FinishedTrade> + '_ {
+            return self.trades.iter().flatten();
+        }
+    }
+
+    // Arrays only decode up to 16 long, which is also about as much as the
+    // save buffer has room for next to a full party.
+    pub const BOX_SIZE: usize = 16;
+
+    // Where caught pokemon go once the party's full. Like the party, it's
+    // kept packed from the front so list positions are slots.
+    #[derive(Encode, Decode, Debug)]
+    pub struct PokemonBox {
+        #[n(0)]
+        pub pokemon: [Option<Pokemon>; BOX_SIZE],
+    }
+
+    impl Default for PokemonBox {
+        fn default() -> PokemonBox {
+            return PokemonBox::new();
+        }
+    }
+
+    impl PokemonBox {
+        pub fn new() -> PokemonBox {
+            return PokemonBox {
+                pokemon: Default::default(),
+            };
+        }
+
+        pub fn count(&self) -> usize {
+            return self.pokemon.iter().filter(|p| p.is_some()).count();
+        }
+
+        pub fn is_full(&self) -> bool {
+            return self.pokemon.iter().all(|p| p.is_some());
+        }
+
+        // Hands it back if there's no room.
+        pub fn add(&mut self, pokemon: Pokemon) -> Result<usize, Pokemon> {
+            match self.pokemon.iter().position(|p| p.is_none()) {
+                Some(slot) => {
+                    self.pokemon[slot] = Some(pokemon);
+                    return Ok(slot);
+                }
+                None => return Err(pokemon),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct BoxError(pub &'static str);
+
+    // Takes one out and shuffles the rest up to close the gap.
+    fn take_packed(slots: &mut [Option<Pokemon>], slot: usize) -> Option<Pokemon> {
+        let pokemon = slots.get_mut(slot)?.take()?;
+        slots[slot..].rotate_left(1);
+        return Some(pokemon);
+    }
+
+    #[derive(Encode, Decode, Debug)]
+    pub struct SaveData {
+        #[n(0)]
+        pub settings: Settings,
+        #[n(1)]
+        pub player_name: Option<[u8; 12]>,
+        #[n(2)]
+        pub party: [Option<Pokemon>; PARTY_SIZE],
+        #[n(3)]
+        pub dex: DexProgress,
+        #[n(4)]
+        pub defeated_trainers: DefeatedTrainers,
+        #[n(5)]
+        pub badges: [Option<Badge>; BADGE_COUNT],
+        #[n(6)]
+        pub bag: [Option<BagSlot>; BAG_SIZE],
+        // Seconds on the clock, see `Timestamp`. Nothing keeps time while the
+        // board's off, so after a restart it carries on from the last save.
+        #[n(7)]
+        pub clock: Option<u32>,
+        #[n(8)]
+        pub pending_trade: Option<PendingTrade>,
+        // 9 was the id of the last finished trade, which forgot about it as
+        // soon as the player traded with someone else. Left unused so old
+        // saves still decode.
+        #[n(10)]
+        pub finished_trades: Option<FinishedTrades>,
+        // Missing on saves from before there was a box.
+        #[n(11)]
+        pub pokemon_box: Option<PokemonBox>,
+    }
+
+    impl Default for SaveData {
+        fn default() -> SaveData {
+            return SaveData::new();
+        }
+    }
+
+    impl SaveData {
+        pub fn new() -> SaveData {
+            return SaveData {
+                settings: Settings::new(),
+                player_name: None,
+                party: Default::default(),
+                dex: DexProgress::new(),
+                defeated_trainers: DefeatedTrainers::new(),
+                badges: [None; BADGE_COUNT],
+                bag: STARTING_BAG,
+                clock: None,
+                pending_trade: None,
+                finished_trades: None,
+                pokemon_box: None,
+            };
+        }
+
+        pub fn finished_trades(&mut self) -> &mut FinishedTrades {
+            return self.finished_trades.get_or_insert_with(FinishedTrades::new);
+        }
+
+        pub fn pokemon_box(&mut self) -> &mut PokemonBox {
+            return self.pokemon_box.get_or_insert_with(PokemonBox::new);
+        }
+
+        pub fn boxed(&self, slot: usize) -> Option<&Pokemon> {
+            return self.pokemon_box.as_ref()?.pokemon.get(slot)?.as_ref();
+        }
+
+        pub fn box_count(&self) -> usize {
+            return self.pokemon_box.as_ref().map_or(0, |b| b.count());
+        }
+
+        // Whether something caught now would have anywhere to go.
+        pub fn has_room(&self) -> bool {
+            return self.party.iter().any(|p| p.is_none())
+                || !self.pokemon_box.as_ref().is_some_and(|b| b.is_full());
+        }
+
+        // None until the player's set the clock.
+        pub fn now(&self) -> Option<Timestamp> {
+            return self.clock.map(Timestamp);
+        }
+
+        pub fn set_clock(&mut self, time: Timestamp) {
+            self.clock = Some(time.0);
+        }
+
+        pub fn advance_clock(&mut self, seconds: u32) {
+            if let Some(clock) = &mut self.clock {
+                *clock = clock.wrapping_add(seconds);
+            }
+        }
+
+        pub fn quantity(&self, item_id: u8) -> u8 {
+            return self
+                .bag
+                .iter()
+                .flatten()
+                .find(|s| s.item_id == item_id)
+                .map_or(0, |s| s.quantity);
+        }
+
+        // Returns false if there's no room for a new kind of item. Stacks top
+        // out at 99 like the real thing.
+        pub fn add_item(&mut self, item_id: u8, quantity: u8) -> bool {
+            for slot in self.bag.iter_mut().flatten() {
+                if slot.item_id == item_id {
+                    slot.quantity = slot.quantity.saturating_add(quantity).min(99);
+                    return true;
+                }
+            }
+            match self.bag.iter().position(|s| s.is_none()) {
+                Some(i) => {
+                    self.bag[i] = Some(BagSlot {
+                        item_id,
+                        quantity: quantity.min(99),
+                    });
+                    return true;
+                }
+                None => return false,
+            }
+        }
+
+        // Takes one of an item out of the bag. Returns false if there wasn't
+        // one to take.
+        pub fn remove_item(&mut self, item_id: u8) -> bool {
+            for slot in self.bag.iter_mut() {
+                if let Some(s) = slot {
+                    if s.item_id == item_id {
+                        s.quantity -= 1;
+                        if s.quantity == 0 {
+                            *slot = None;
+                        }
+                        return true;
+                    }
+                }
+            }
+            return false;
+        }
+
+        pub fn badge_count(&self) -> u8 {
+            return self.badges.iter().filter(|b| b.is_some()).count() as u8;
+        }
+
+        pub fn has_badge(&self, theme: Type) -> bool {
+            return self.badges.iter().flatten().any(|b| b.theme == theme);
+        }
+
+        // Hands over the badge for beating a gym's leader. Returns false if
+        // there was nothing to give, either because the player already has
+        // that type's badge or the case is full.
+        pub fn award_badge(&mut self, gym: &Gym) -> bool {
+            self.defeated_trainers.record(gym.leader_id());
+            if self.has_badge(gym.theme) {
+                return false;
+            }
+            match self.badges.iter().position(|b| b.is_none()) {
+                Some(slot) => {
+                    self.badges[slot] = Some(Badge {
+                        theme: gym.theme,
+                        gym_id: gym.id,
+                    });
+                    return true;
+                }
+                None => return false,
+            }
+        }
+
+        // Puts the pokemon in the first free party slot, handing it back if
+        // the party is already full.
+        pub fn add_to_party(&mut self, pokemon: Pokemon) -> Result<usize, Pokemon> {
+            match self.party.iter().position(|p| p.is_none()) {
+                Some(slot) => {
+                    self.party[slot] = Some(pokemon);
+                    return Ok(slot);
+                }
+                None => return Err(pokemon),
+            }
+        }
+
+        // Moves a party member into the box. The party has to keep at least
+        // one, and a trade that's still pending knows its pokemon by party
+        // slot, so nothing can shuffle up until it's settled.
+        pub fn deposit(&mut self, slot: usize) -> Result<usize, BoxError> {
+            if self.pending_trade.is_some() {
+                return Err(BoxError("FINISH YOUR TRADE FIRST"));
+            }
+            if self.party.iter().filter(|p| p.is_some()).count() <= 1 {
+                return Err(BoxError("YOU NEED ONE IN YOUR PARTY"));
+            }
+            if self.pokemon_box.as_ref().is_some_and(|b| b.is_full()) {
+                return Err(BoxError("THE BOX IS FULL"));
+            }
+            let pokemon = take_packed(&mut self.party, slot).ok_or(BoxError("NOTHING THERE"))?;
+            return Ok(self.pokemon_box().add(pokemon).unwrap_or(0));
+        }
+
+        // Moves one out of the box onto the end of the party.
+        pub fn withdraw(&mut self, slot: usize) -> Result<usize, BoxError> {
+            if self.party.iter().all(|p| p.is_some()) {
+                return Err(BoxError("YOUR PARTY IS FULL"));
+            }
+            let pokemon = take_packed(&mut self.pokemon_box().pokemon, slot)
+                .ok_or(BoxError("NOTHING THERE"))?;
+            return Ok(self.add_to_party(pokemon).unwrap_or(0));
+        }
+    }
+
+    pub struct Save {
+        pub data: SaveData,
+        // Which slot the current data came from, and its sequence number. The
+        // next commit goes to the other slot with the next number.
+        slot: u32,
+        sequence: u32,
+    }
+
+    struct SlotHeader {
+        sequence: u32,
+        length: usize,
+        checksum: u32,
+    }
+
+    impl Save {
+        // Loads the newest valid slot. If neither slot holds anything we can
+        // use we start fresh rather than refusing to boot.
+        pub fn load(storage: &mut dyn Storage) -> Save {
+            // Newest first. A slot that was only half written when the power
+            // went has a good header but bad data, so the other one gets a
+            // go before giving up.
+            let mut slots: [(u32, Option<u32>); 2] = [(0, None), (1, None)];
+            for (slot, sequence) in slots.iter_mut() {
+                *sequence = read_header(storage, *slot).map(|h| h.sequence);
+            }
+            if slots[1].1 > slots[0].1 {
+                slots.swap(0, 1);
+            }
+
+            let mut buf = [0u8; SAVE_BUFFER_SIZE];
+            for (slot, sequence) in slots {
+                let sequence = match sequence {
+                    Some(s) => s,
+                    None => continue,
+                };
+                if let Some(data) = read_slot(storage, slot, &mut buf) {
+                    return Save {
+                        data,
+                        slot,
+                        sequence,
+                    };
+                }
+            }
+            return Save {
+                data: SaveData::new(),
+                slot: 1,
+                sequence: 0,
+            };
+        }
+
+        // Which slot it came from. The next commit goes to the other one.
+        pub fn slot(&self) -> u32 {
+            return self.slot;
+        }
+
+        // Zero when neither slot held a save and this one's brand new.
+        pub fn sequence(&self) -> u32 {
+            return self.sequence;
+        }
+
+        pub fn commit(&mut self, storage: &mut dyn Storage) -> Result<(), WriteError> {
+            let mut buf = [0u8; SAVE_BUFFER_SIZE];
+            let length = {
+                let mut writer: &mut [u8] = &mut buf[HEADER_SIZE..];
+                if minicbor::encode(&self.data, &mut writer).is_err() {
+                    return Err(WriteError("save data larger than buffer"));
+                }
+                SAVE_BUFFER_SIZE - HEADER_SIZE - writer.len()
+            };
+
+            let slot = 1 - self.slot;
+            let sequence = self.sequence.wrapping_add(1);
+            let checksum = checksum(&buf[HEADER_SIZE..HEADER_SIZE + length]);
+            buf[0..4].copy_from_slice(&SAVE_MAGIC.to_be_bytes());
+            buf[4..8].copy_from_slice(&sequence.to_be_bytes());
+            buf[8..12].copy_from_slice(&(length as u32).to_be_bytes());
+            buf[12..16].copy_from_slice(&checksum.to_be_bytes());
+
+            let base = slot_offset(slot);
+            let total = HEADER_SIZE + length;
+            let mut erased = 0;
+            while erased < total as u32 {
+                storage.erase_sector(base + erased)?;
+                erased += SECTOR_SIZE;
+            }
+            storage.write(base, &buf[..total])?;
+
+            self.slot = slot;
+            self.sequence = sequence;
+            return Ok(());
+        }
+    }
+
+    fn slot_offset(slot: u32) -> u32 {
+        return SAVE_OFFSET + slot * SLOT_SIZE;
+    }
+
+    fn read_header(storage: &mut dyn Storage, slot: u32) -> Option<SlotHeader> {
+        let mut header = [0u8; HEADER_SIZE];
+        if storage.read(slot_offset(slot), &mut header).is_err() {
+            return None;
+        }
+        if u32::from_be_bytes(header[0..4].try_into().unwrap()) != SAVE_MAGIC {
+            return None;
+        }
+        let length = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+        if length > SAVE_BUFFER_SIZE - HEADER_SIZE {
+            return None;
+        }
+        return Some(SlotHeader {
+            sequence: u32::from_be_bytes(header[4..8].try_into().unwrap()),
+            length,
+            checksum: u32::from_be_bytes(header[12..16].try_into().unwrap()),
+        });
+    }
+
+    fn read_slot(storage: &mut dyn Storage, slot: u32, buf: &mut [u8]) -> Option<SaveData> {
+        let header = read_header(storage, slot)?;
+        let data = &mut buf[..header.length];
+        if storage
+            .read(slot_offset(slot) + HEADER_SIZE as u32, data)
+            .is_err()
+        {
+            return None;
+        }
+        if checksum(data) != header.checksum {
+            return None;
+        }
+        return minicbor::decode(data).ok();
+    }
+
+    // Plain CRC-32. Slow and table-free, but saves are small.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = 0xffffffffu32;
+        for byte in data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (!(crc & 1)).wrapping_add(1);
+                crc = (crc >> 1) ^ (0xedb88320 & mask);
+            }
+        }
+        return !crc;
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::pokedex::AilmentType;
+        use crate::rom::testing::{pokemon, species};
+        use crate::rom::ReadError;
+
+        // Just the save area, starting out erased.
+        struct SaveFlash([u8; SAVE_SIZE as usize]);
+
+        impl SaveFlash {
+            fn range(&mut self, offset: u32, len: usize) -> &mut [u8] {
+                let start = (offset - SAVE_OFFSET) as usize;
+                return &mut self.0[start..start + len];
+            }
+        }
+
+        impl Storage for SaveFlash {
+            fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+                buf.copy_from_slice(self.range(offset, buf.len()));
+                return Ok(());
+            }
+
+            fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError> {
+                self.range(offset, SECTOR_SIZE as usize).fill(0xff);
+                return Ok(());
+            }
+
+            fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError> {
+                self.range(offset, data.len()).copy_from_slice(data);
+                return Ok(());
+            }
+        }
+
+        fn named(name: &[u8]) -> Option<[u8; 12]> {
+            let mut buf = [0u8; 12];
+            buf[..name.len()].copy_from_slice(name);
+            return Some(buf);
+        }
+
+        #[test]
+        fn blank_flash_is_a_new_game() {
+            let mut flash = SaveFlash([0xff; SAVE_SIZE as usize]);
+            let save = Save::load(&mut flash);
+            assert_eq!(save.sequence(), 0);
+            assert_eq!(save.data.player_name, None);
+        }
+
+        #[test]
+        fn loads_the_newest_slot() {
+            let mut flash = SaveFlash([0xff; SAVE_SIZE as usize]);
+            let mut save = Save::load(&mut flash);
+            save.data.player_name = named(b"RED");
+            save.commit(&mut flash).unwrap();
+            save.data.player_name = named(b"BLUE");
+            save.commit(&mut flash).unwrap();
+
+            let loaded = Save::load(&mut flash);
+            assert_eq!(loaded.sequence(), 2);
+            assert_eq!(loaded.slot(), save.slot());
+            assert_eq!(loaded.data.player_name, named(b"BLUE"));
+        }
+
+        #[test]
+        fn falls_back_when_the_newest_slot_is_damaged() {
+            let mut flash = SaveFlash([0xff; SAVE_SIZE as usize]);
+            let mut save = Save::load(&mut flash);
+            save.data.player_name = named(b"RED");
+            save.commit(&mut flash).unwrap();
+            save.data.player_name = named(b"BLUE");
+            save.commit(&mut flash).unwrap();
+
+            // One flipped bit in the newest slot's data fails its checksum.
+            let newest = slot_offset(save.slot()) + HEADER_SIZE as u32;
+            flash.range(newest, 1)[0] ^= 0x01;
+            let mut loaded = Save::load(&mut flash);
+            assert_eq!(loaded.sequence(), 1);
+            assert_eq!(loaded.data.player_name, named(b"RED"));
+
+            // The next commit goes over the damaged slot, not the good one.
+            loaded.commit(&mut flash).unwrap();
+            assert_eq!(loaded.slot(), save.slot());
+            assert_eq!(Save::load(&mut flash).sequence(), 2);
+        }
+
+        #[test]
+        fn catching_counts_as_seeing() {
+            let mut dex = DexProgress::new();
+            dex.mark_seen(16);
+            dex.mark_caught(25);
+            dex.mark_caught(SPECIES_COUNT);
+            // Seeing something already caught doesn't undo it.
+            dex.mark_seen(25);
+            for id in [16, 25, SPECIES_COUNT] {
+                assert!(dex.has_seen(id));
+            }
+            assert!(!dex.has_caught(16));
+            assert!(dex.has_caught(25) && dex.has_caught(SPECIES_COUNT));
+            assert!(!dex.has_seen(1) && !dex.has_seen(17));
+            assert_eq!((dex.seen_count(), dex.caught_count()), (3, 2));
+        }
+
+        #[test]
+        fn species_out_of_range_are_ignored() {
+            let mut dex = DexProgress::new();
+            dex.mark_caught(0);
+            dex.mark_caught(SPECIES_COUNT + 1);
+            dex.mark_seen(255);
+            assert!(!dex.has_seen(0) && !dex.has_caught(SPECIES_COUNT + 1));
+            assert_eq!((dex.seen_count(), dex.caught_count()), (0, 0));
+        }
+
+        #[test]
+        fn the_dex_survives_a_reload() {
+            let mut flash = SaveFlash([0xff; SAVE_SIZE as usize]);
+            let mut save = Save::load(&mut flash);
+            save.data.dex.mark_seen(1);
+            save.data.dex.mark_caught(151);
+            save.commit(&mut flash).unwrap();
+
+            let loaded = Save::load(&mut flash);
+            assert!(loaded.data.dex.has_seen(1) && !loaded.data.dex.has_caught(1));
+            assert!(loaded.data.dex.has_caught(151));
+            assert_eq!(loaded.data.dex.seen_count(), 2);
+        }
+
+        fn boxable(species_id: u8) -> Pokemon {
+            return pokemon(species(species_id, Type::Normal, None), 5, [Some(1); 4]);
+        }
+
+        #[test]
+        fn a_full_party_and_box_still_fit() {
+            let mut flash = SaveFlash([0xff; SAVE_SIZE as usize]);
+            let mut save = Save::load(&mut flash);
+            // As big as each pokemon gets, with everything else full too.
+            let mut biggest = pokemon(species(151, Type::Psychic, None), 100, [Some(999); 4]);
+            biggest.nickname = named(b"ABCDEFGHIJKL");
+            biggest.status = Some(AilmentType::Paralysis);
+            biggest.shiny = Some(true);
+            biggest.xp = u32::MAX;
+            let data = &mut save.data;
+            data.player_name = named(b"ABCDEFGHIJKL");
+            for slot in data.party.iter_mut() {
+                *slot = Some(biggest.clone());
+            }
+            for _ in 0..BOX_SIZE {
+                assert!(data.pokemon_box().add(biggest.clone()).is_ok());
+            }
+            for id in 0..DEFEATED_TRAINER_SLOTS as u32 {
+                data.defeated_trainers.record(u32::MAX - id);
+            }
+            for (i, slot) in data.bag.iter_mut().enumerate() {
+                *slot = Some(BagSlot {
+                    item_id: 200 + i as u8,
+                    quantity: 99,
+                });
+            }
+            for id in 0..FINISHED_TRADE_SLOTS as u32 {
+                data.finished_trades()
+                    .record(u32::MAX - id, b"ABCDEFGHIJKL");
+            }
+            data.pending_trade = Some(PendingTrade {
+                id: u32::MAX,
+                slot: 5,
+                incoming: biggest.clone(),
+                partner: *b"ABCDEFGHIJKL",
+            });
+            data.clock = Some(u32::MAX);
+            save.commit(&mut flash).unwrap();
+
+            let loaded = Save::load(&mut flash);
+            assert_eq!(loaded.sequence(), 1);
+            assert_eq!(loaded.data.box_count(), BOX_SIZE);
+            assert!(!loaded.data.has_room());
+        }
+
+        #[test]
+        fn moving_in_and_out_of_the_box_keeps_both_packed() {
+            let mut data = SaveData::new();
+            for id in [1, 2, 3] {
+                data.add_to_party(boxable(id)).ok();
+            }
+            assert_eq!(data.deposit(0).unwrap(), 0);
+            assert_eq!(data.deposit(0).unwrap(), 1);
+            let party: [Option<u8>; PARTY_SIZE] =
+                data.party.clone().map(|p| p.map(|p| p.species_id));
+            assert_eq!(party, [Some(3), None, None, None, None, None]);
+            // Someone has to stay behind.
+            assert!(data.deposit(0).is_err());
+
+            assert_eq!(data.withdraw(0).unwrap(), 1);
+            assert_eq!(data.boxed(0).map(|p| p.species_id), Some(2));
+            assert_eq!(data.box_count(), 1);
+            assert_eq!(data.party[1].as_ref().map(|p| p.species_id), Some(1));
+            assert!(data.withdraw(1).is_err());
+        }
+
+        #[test]
+        fn a_pending_trade_holds_the_party_still() {
+            let mut data = SaveData::new();
+            for id in [1, 2] {
+                data.add_to_party(boxable(id)).ok();
+            }
+            data.pending_trade = Some(PendingTrade {
+                id: 1,
+                slot: 1,
+                incoming: boxable(3),
+                partner: [0; 12],
+            });
+            assert!(data.deposit(0).is_err());
+            assert_eq!(data.party[1].as_ref().map(|p| p.species_id), Some(2));
+        }
+    }
+}
+
+pub mod link {
+    use crate::app::Context;
+    use crate::battle::{Choice, MOVE_SLOTS};
+    use crate::generation::{Random, SeededRandom};
+    use crate::pokedex::{name_str, Pokemon};
+    use crate::save::{checksum, PendingTrade, PARTY_SIZE, SAVE_OFFSET, SAVE_SIZE};
+    use crate::trainer::TEAM_SIZE;
+
+    // Bumped whenever the messages change, so two boards running different
+    // firmware don't try to make sense of each other.
+    pub const PROTOCOL_VERSION: u8 = 4;
+    const SYNC: u8 = 0x7e;
+    // Comfortably more than an encoded pokemon. The UART only buffers 128
+    // bytes, so frames want to stay under that too.
+    pub const MAX_PAYLOAD: usize = 120;
+    // Sync, kind and length up front, a CRC-32 on the end.
+    const MAX_FRAME: usize = MAX_PAYLOAD + 7;
+
+    const HELLO: u8 = 1;
+    const OFFER: u8 = 2;
+    const ACCEPT: u8 = 3;
+    const READY: u8 = 4;
+    const CANCEL: u8 = 5;
+    const PING: u8 = 6;
+    const MEMBER: u8 = 7;
+    const TEAM: u8 = 8;
+    const TURN: u8 = 9;
+    // The save-tool on the host asks for a piece of the save region and gets
+    // back whatever's in flash there. Nothing else on the board uses these.
+    pub const READ_SAVE: u8 = 10;
+    pub const SAVE_DATA: u8 = 11;
+    pub const SAVE_CHUNK: usize = 64;
+    const SETTLED: u8 = 12;
+
+    // What each end is after. Someone trading can't do much with someone
+    // who wants a battle.
+    const TRADING: u8 = 0;
+    const BATTLING: u8 = 1;
+
+    // All in ticks.
+    const HELLO_TICKS: u16 = 20;
+    const PING_TICKS: u16 = 20;
+    const TIMEOUT_TICKS: u16 = 100;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LinkError(pub &'static str);
+
+    // Anything bytes can go down: the UART on the board, a socket or a
+    // terminal on the host. Reading never waits, it just hands back whatever
+    // has turned up since last time.
+    pub trait Link {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, LinkError>;
+        fn write(&mut self, data: &[u8]) -> Result<(), LinkError>;
+    }
+
+    // For when there's nothing plugged in. Nobody ever answers.
+    pub struct NoLink;
+
+    impl Link for NoLink {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize, LinkError> {
+            return Ok(0);
+        }
+
+        fn write(&mut self, _data: &[u8]) -> Result<(), LinkError> {
+            return Ok(());
+        }
+    }
+
+    pub fn write_frame(link: &mut dyn Link, kind: u8, payload: &[u8]) -> Result<(), LinkError> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(LinkError("FRAME TOO LONG"));
+        }
+        let mut buf = [0u8; MAX_FRAME];
+        let len = payload.len();
+        buf[0] = SYNC;
+        buf[1] = kind;
+        buf[2] = len as u8;
+        buf[3..3 + len].copy_from_slice(payload);
+        let crc = checksum(&buf[1..3 + len]);
+        buf[3 + len..7 + len].copy_from_slice(&crc.to_be_bytes());
+        return link.write(&buf[..7 + len]);
+    }
+
+    pub struct Frame {
+        pub kind: u8,
+        len: usize,
+        data: [u8; MAX_PAYLOAD],
+    }
+
+    impl Frame {
+        pub fn payload(&self) -> &[u8] {
+            return &self.data[..self.len];
+        }
+    }
+
+    // Picks frames out of the byte stream. Anything before a sync byte gets
+    // skipped, so it finds its feet again after a half-sent frame.
+    pub struct FrameReader {
+        buf: [u8; MAX_FRAME],
+        len: usize,
+    }
+
+    impl Default for FrameReader {
+        fn default() -> FrameReader {
+            return FrameReader::new();
+        }
+    }
+
+    impl FrameReader {
+        pub fn new() -> FrameReader {
+            return FrameReader {
+                buf: [0; MAX_FRAME],
+                len: 0,
+            };
+        }
+
+        pub fn push(&mut self, byte: u8) -> Result<Option<Frame>, LinkError> {
+            if self.len == 0 && byte != SYNC {
+                return Ok(None);
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+            if self.len < 3 {
+                return Ok(None);
+            }
+            let len = self.buf[2] as usize;
+            if len > MAX_PAYLOAD {
+                self.len = 0;
+                return Err(LinkError("FRAME TOO LONG"));
+            }
+            if self.len < len + 7 {
+                return Ok(None);
+            }
+            self.len = 0;
+            let crc = u32::from_be_bytes(self.buf[3 + len..7 + len].try_into().unwrap());
+            if crc != checksum(&self.buf[1..3 + len]) {
+                return Err(LinkError("BAD CHECKSUM"));
+            }
+            let mut data = [0; MAX_PAYLOAD];
+            data[..len].copy_from_slice(&self.buf[3..3 + len]);
+            return Ok(Some(Frame {
+                kind: self.buf[1],
+                len,
+                data,
+            }));
+        }
+    }
+
+    // Who's on the other end, and what they remember about trades. Sent by
+    // both sides when they start, and again in reply until each has heard
+    // from the other.
+    struct Hello {
+        version: u8,
+        mode: u8,
+        heard: bool,
+        nonce: u32,
+        // Zero for none.
+        pending: u32,
+        name: [u8; 12],
+    }
+
+    const HELLO_SIZE: usize = 23;
+
+    impl Hello {
+        fn encode(&self) -> [u8; HELLO_SIZE] {
+            let mut buf = [0u8; HELLO_SIZE];
+            buf[0] = self.version;
+            buf[1] = self.mode;
+            buf[2] = self.heard as u8;
+            buf[3..7].copy_from_slice(&self.nonce.to_be_bytes());
+            buf[7..11].copy_from_slice(&self.pending.to_be_bytes());
+            buf[11..23].copy_from_slice(&self.name);
+            return buf;
+        }
+
+        fn decode(data: &[u8]) -> Option<Hello> {
+            if data.len() != HELLO_SIZE {
+                return None;
+            }
+            let word = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+            return Some(Hello {
+                version: data[0],
+                mode: data[1],
+                heard: data[2] != 0,
+                nonce: word(3),
+                pending: word(7),
+                name: data[11..23].try_into().unwrap(),
+            });
+        }
+    }
+
+    fn word(data: &[u8]) -> Option<u32> {
+        return Some(u32::from_be_bytes(data.try_into().ok()?));
+    }
+
+    // The part every kind of link shares: saying hello, keeping the line
+    // alive and picking frames out of whatever turns up.
+    struct Session {
+        mode: u8,
+        reader: FrameReader,
+        buf: [u8; 32],
+        read: usize,
+        used: usize,
+        nonce: u32,
+        their_nonce: Option<u32>,
+        partner: [u8; 12],
+        since_sent: u16,
+        since_heard: u16,
+    }
+
+    impl Session {
+        fn new(mode: u8, nonce: u32) -> Session {
+            return Session {
+                mode,
+                reader: FrameReader::new(),
+                buf: [0; 32],
+                read: 0,
+                used: 0,
+                nonce,
+                their_nonce: None,
+                partner: [0; 12],
+                // Say hello straight away.
+                since_sent: HELLO_TICKS,
+                since_heard: 0,
+            };
+        }
+
+        fn partner(&self) -> Option<&str> {
+            return self.their_nonce.map(|_| name_str(&self.partner));
+        }
+
+        // Called every tick. Says hello until someone answers, then pings
+        // now and then so the other end knows we're still here.
+        fn tick(&mut self, ctx: &mut Context) -> Result<(), LinkError> {
+            self.since_sent = self.since_sent.saturating_add(1);
+            self.since_heard = self.since_heard.saturating_add(1);
+            if self.their_nonce.is_none() {
+                if self.since_sent >= HELLO_TICKS {
+                    return self.send_hello(ctx);
+                }
+                return Ok(());
+            }
+            if self.since_heard >= TIMEOUT_TICKS {
+                return Err(LinkError("LINK LOST"));
+            }
+            if self.since_sent >= PING_TICKS {
+                return self.send(ctx, PING, &[]);
+            }
+            return Ok(());
+        }
+
+        // The next frame that's turned up, if there is one. Pings only
+        // matter for keeping the link alive, so they never come out.
+        fn next(&mut self, ctx: &mut Context) -> Result<Option<Frame>, LinkError> {
+            loop {
+                if self.used == self.read {
+                    self.read = ctx.link.read(&mut self.buf)?;
+                    self.used = 0;
+                    if self.read == 0 {
+                        return Ok(None);
+                    }
+                }
+                let byte = self.buf[self.used];
+                self.used += 1;
+                match self.reader.push(byte) {
+                    Ok(Some(frame)) => {
+                        self.since_heard = 0;
+                        if frame.kind != PING {
+                            return Ok(Some(frame));
+                        }
+                    }
+                    Ok(None) => {}
+                    // Whatever was on the line before anyone said hello
+                    // doesn't count.
+                    Err(_) if self.their_nonce.is_none() => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        fn send(&mut self, ctx: &mut Context, kind: u8, payload: &[u8]) -> Result<(), LinkError> {
+            self.since_sent = 0;
+            return write_frame(ctx.link, kind, payload);
+        }
+
+        fn send_hello(&mut self, ctx: &mut Context) -> Result<(), LinkError> {
+            let data = &ctx.save.data;
+            let hello = Hello {
+                version: PROTOCOL_VERSION,
+                mode: self.mode,
+                heard: self.their_nonce.is_some(),
+                nonce: self.nonce,
+                pending: data.pending_trade.as_ref().map_or(0, |p| p.id),
+                name: data.player_name.unwrap_or([0; 12]),
+            };
+            return self.send(ctx, HELLO, &hello.encode());
+        }
+
+        // Answers a hello. Hands it back if it's from someone new, which
+        // includes the same someone after a restart.
+        fn greet(&mut self, payload: &[u8], ctx: &mut Context) -> Result<Option<Hello>, LinkError> {
+            let hello = Hello::decode(payload).ok_or(LinkError("BAD HELLO"))?;
+            if hello.version != PROTOCOL_VERSION {
+                return Err(LinkError("DIFFERENT VERSION"));
+            }
+            if hello.mode != self.mode {
+                return match hello.mode {
+                    TRADING => Err(LinkError("WANTS TO TRADE")),
+                    _ => Err(LinkError("WANTS TO BATTLE")),
+                };
+            }
+            let known = self.their_nonce == Some(hello.nonce);
+            self.their_nonce = Some(hello.nonce);
+            self.partner = hello.name;
+            if !hello.heard {
+                self.send_hello(ctx)?;
+            }
+            // The same hello again, from before they'd heard us.
+            if known {
+                return Ok(None);
+            }
+            return Ok(Some(hello));
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Outcome {
+        // Which party slot the new arrival is in.
+        Traded(usize),
+        Cancelled,
+        // The link went wrong. Anything half done gets settled next time the
+        // same two players link up.
+        Failed(&'static str),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Phase {
+        Connecting,
+        // Talking, and nothing offered from this end yet.
+        Choosing,
+        Offered,
+        // This side is happy with both offers and is waiting on the other.
+        Accepted,
+        // Written down in the save and waiting for the other side to say
+        // it's done the same.
+        Prepared,
+        Finished(Outcome),
+    }
+
+    // One trading session, from the handshake to both saves being written.
+    //
+    // The swap itself is a two step commit. Once both sides have accepted
+    // the same pair, each writes the trade to its save as pending and sends
+    // READY. Whoever gets READY back swaps the pokemon, remembers the trade
+    // as finished with that player and sends SETTLED. If the link drops in
+    // between, the pending trade stays in the save and the next handshake
+    // with the same player settles it: if they've got it pending too it goes
+    // ahead, otherwise this side waits for them to say with SETTLED whether
+    // they finished it. A finished trade is only forgotten once the other
+    // side says it's finished it too, or links up again without it pending,
+    // so trading with someone else in the meantime doesn't lose it. Either
+    // way nobody ends up with both pokemon or neither.
+    pub struct Trade {
+        phase: Phase,
+        session: Session,
+        slot: usize,
+        my_digest: u32,
+        their_offer: Option<Pokemon>,
+        their_digest: u32,
+        their_accept: Option<u32>,
+        id: u32,
+        // Set when the handshake settled a trade from last time.
+        notice: Option<&'static str>,
+        // The party slot the last pokemon to arrive went into, so it can
+        // evolve once the screen's done with.
+        arrived: Option<usize>,
+    }
+
+    impl Trade {
+        pub fn new(nonce: u32) -> Trade {
+            return Trade {
+                phase: Phase::Connecting,
+                session: Session::new(TRADING, nonce),
+                slot: 0,
+                my_digest: 0,
+                their_offer: None,
+                their_digest: 0,
+                their_accept: None,
+                id: 0,
+                notice: None,
+                arrived: None,
+            };
+        }
+
+        pub fn phase(&self) -> Phase {
+            return self.phase;
+        }
+
+        pub fn partner(&self) -> Option<&str> {
+            return self.session.partner();
+        }
+
+        pub fn slot(&self) -> usize {
+            return self.slot;
+        }
+
+        pub fn their_offer(&self) -> Option<&Pokemon> {
+            return self.their_offer.as_ref();
+        }
+
+        pub fn notice(&self) -> Option<&'static str> {
+            return self.notice;
+        }
+
+        pub fn arrived(&self) -> Option<usize> {
+            return self.arrived;
+        }
+
+        // Called every tick. Returns whether anything changed that's worth
+        // redrawing for.
+        pub fn poll(&mut self, ctx: &mut Context) -> bool {
+            if let Phase::Finished(outcome) = self.phase {
+                if let Outcome::Traded(_) = outcome {
+                    self.hear_settled(ctx);
+                }
+                return false;
+            }
+            if let Err(e) = self.session.tick(ctx) {
+                return self.fail(e, ctx);
+            }
+            let mut changed = false;
+            loop {
+                let frame = match self.session.next(ctx) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => return changed,
+                    Err(e) => return self.fail(e, ctx),
+                };
+                changed |= match self.handle(&frame, ctx) {
+                    Ok(changed) => changed,
+                    Err(e) => return self.fail(e, ctx),
+                };
+                if let Phase::Finished(_) = self.phase {
+                    return true;
+                }
+            }
+        }
+
+        // Puts one of the party up for trade.
+        pub fn offer(&mut self, slot: usize, ctx: &mut Context) -> bool {
+            if self.phase != Phase::Choosing {
+                return false;
+            }
+            let pokemon = match &ctx.save.data.party[slot] {
+                Some(p) => p,
+                None => return false,
+            };
+            let mut buf = [0u8; MAX_PAYLOAD];
+            let len = {
+                let mut writer: &mut [u8] = &mut buf;
+                if minicbor::encode(pokemon, &mut writer).is_err() {
+                    return self.fail(LinkError("POKéMON TOO BIG"), ctx);
+                }
+                MAX_PAYLOAD - writer.len()
+            };
+            self.slot = slot;
+            self.my_digest = checksum(&buf[..len]);
+            self.phase = Phase::Offered;
+            if let Err(e) = self.session.send(ctx, OFFER, &buf[..len]) {
+                return self.fail(e, ctx);
+            }
+            return true;
+        }
+
+        // Agrees to swap for whatever they've offered.
+        pub fn accept(&mut self, ctx: &mut Context) -> bool {
+            if self.phase != Phase::Offered || self.their_offer.is_none() {
+                return false;
+            }
+            self.phase = Phase::Accepted;
+            let digest = self.their_digest.to_be_bytes();
+            if let Err(e) = self.session.send(ctx, ACCEPT, &digest) {
+                return self.fail(e, ctx);
+            }
+            if let Err(e) = self.prepare_if_agreed(ctx) {
+                return self.fail(e, ctx);
+            }
+            return true;
+        }
+
+        // Too late once it's been written down. Returns whether it stopped.
+        pub fn cancel(&mut self, ctx: &mut Context) -> bool {
+            match self.phase {
+                Phase::Prepared => return false,
+                Phase::Finished(_) => return true,
+                _ => {}
+            }
+            self.session.send(ctx, CANCEL, &[]).ok();
+            self.phase = Phase::Finished(Outcome::Cancelled);
+            return true;
+        }
+
+        // Hangs about after a trade for the other side to say it's finished
+        // too, so this side can stop remembering it. Nothing else matters by
+        // now, and if it never turns up the next handshake sorts it out.
+        fn hear_settled(&mut self, ctx: &mut Context) {
+            while let Ok(Some(frame)) = self.session.next(ctx) {
+                if frame.kind == SETTLED {
+                    self.handle(&frame, ctx).ok();
+                }
+            }
+        }
+
+        // Tells the other end it's over, though it'll work that out for
+        // itself soon enough if this doesn't get through.
+        fn fail(&mut self, error: LinkError, ctx: &mut Context) -> bool {
+            if self.phase != Phase::Prepared {
+                self.session.send(ctx, CANCEL, &[]).ok();
+            }
+            self.phase = Phase::Finished(Outcome::Failed(error.0));
+            return true;
+        }
+
+        fn handle(&mut self, frame: &Frame, ctx: &mut Context) -> Result<bool, LinkError> {
+            let payload = frame.payload();
+            match frame.kind {
+                HELLO => {
+                    let hello = match self.session.greet(payload, ctx)? {
+                        Some(hello) => hello,
+                        None => return Ok(false),
+                    };
+                    // Either the start of the session or they've restarted,
+                    // and either way anything half done gets sorted out first.
+                    self.their_offer = None;
+                    self.their_accept = None;
+                    self.phase = Phase::Connecting;
+                    if self.settle(&hello, ctx)? {
+                        self.phase = Phase::Choosing;
+                    }
+                    return Ok(true);
+                }
+                OFFER => {
+                    let pokemon: Pokemon =
+                        minicbor::decode(payload).map_err(|_| LinkError("BAD OFFER"))?;
+                    self.their_offer = Some(pokemon);
+                    self.their_digest = checksum(payload);
+                    self.their_accept = None;
+                    // Whatever was agreed was for something else.
+                    if self.phase == Phase::Accepted {
+                        self.phase = Phase::Offered;
+                    }
+                    return Ok(true);
+                }
+                ACCEPT => {
+                    self.their_accept = Some(word(payload).ok_or(LinkError("BAD ACCEPT"))?);
+                    self.prepare_if_agreed(ctx)?;
+                    return Ok(true);
+                }
+                READY => {
+                    let id = word(payload).ok_or(LinkError("BAD READY"))?;
+                    if self.phase != Phase::Prepared || id != self.id {
+                        return Ok(false);
+                    }
+                    self.finish(false, ctx)?;
+                    self.phase = Phase::Finished(Outcome::Traded(self.slot));
+                    return Ok(true);
+                }
+                SETTLED => {
+                    if payload.len() != 5 {
+                        return Err(LinkError("BAD SETTLED"));
+                    }
+                    let id = word(&payload[..4]).unwrap_or(0);
+                    let done = payload[4] != 0;
+                    let partner = self.session.partner;
+                    let data = &mut ctx.save.data;
+                    if done && data.finished_trades().contains(id, &partner) {
+                        data.finished_trades().forget(id, &partner);
+                        ctx.persist().map_err(|_| LinkError("COULDN'T SAVE"))?;
+                    }
+                    let pending = match &ctx.save.data.pending_trade {
+                        Some(t) => t.id == id && t.partner == partner,
+                        None => false,
+                    };
+                    if !pending {
+                        return Ok(false);
+                    }
+                    match done {
+                        true => {
+                            // They've already finished, so there's nothing
+                            // for this side to remember.
+                            self.finish(true, ctx)?;
+                            self.notice = Some("OLD TRADE DONE");
+                        }
+                        false => {
+                            ctx.save.data.pending_trade = None;
+                            ctx.persist().map_err(|_| LinkError("COULDN'T SAVE"))?;
+                            self.notice = Some("OLD TRADE UNDONE");
+                        }
+                    }
+                    if self.phase == Phase::Connecting {
+                        if ctx.save.data.finished_trades().is_full() {
+                            return Err(LinkError("TOO MANY OLD TRADES"));
+                        }
+                        self.phase = Phase::Choosing;
+                    }
+                    return Ok(true);
+                }
+                CANCEL => {
+                    // They never got as far as writing it down, or they'd
+                    // have sent READY first.
+                    if self.phase == Phase::Prepared {
+                        ctx.save.data.pending_trade = None;
+                        ctx.persist().map_err(|_| LinkError("COULDN'T SAVE"))?;
+                    }
+                    self.phase = Phase::Finished(Outcome::Cancelled);
+                    return Ok(true);
+                }
+                _ => return Err(LinkError("UNKNOWN MESSAGE")),
+            }
+        }
+
+        // Writes the trade down once both sides have agreed to the same two
+        // pokemon.
+        fn prepare_if_agreed(&mut self, ctx: &mut Context) -> Result<(), LinkError> {
+            if self.phase != Phase::Accepted || self.their_accept != Some(self.my_digest) {
+                return Ok(());
+            }
+            let incoming = match self.their_offer.take() {
+                Some(p) => p,
+                None => return Ok(()),
+            };
+            let nonce = self.session.their_nonce.unwrap_or(0);
+            self.id = (self.session.nonce ^ nonce).max(1);
+            ctx.save.data.pending_trade = Some(PendingTrade {
+                id: self.id,
+                slot: self.slot as u8,
+                incoming,
+                partner: self.session.partner,
+            });
+            if ctx.persist().is_err() {
+                ctx.save.data.pending_trade = None;
+                return Err(LinkError("COULDN'T SAVE"));
+            }
+            self.phase = Phase::Prepared;
+            return self.session.send(ctx, READY, &self.id.to_be_bytes());
+        }
+
+        // Swaps in the pending trade for good and tells the other side.
+        // Unless they've said they've finished it already, it's remembered
+        // until they do.
+        fn finish(&mut self, confirmed: bool, ctx: &mut Context) -> Result<(), LinkError> {
+            let data = &mut ctx.save.data;
+            let trade = match data.pending_trade.take() {
+                Some(t) => t,
+                None => return Ok(()),
+            };
+            if !confirmed {
+                data.finished_trades().record(trade.id, &trade.partner);
+            }
+            let id = trade.id;
+            data.dex.mark_caught(trade.incoming.species_id);
+            data.party[trade.slot as usize] = Some(trade.incoming);
+            self.arrived = Some(trade.slot as usize);
+            ctx.persist().map_err(|_| LinkError("COULDN'T SAVE"))?;
+            return self.send_settled(id, true, ctx);
+        }
+
+        fn send_settled(
+            &mut self,
+            id: u32,
+            done: bool,
+            ctx: &mut Context,
+        ) -> Result<(), LinkError> {
+            let mut payload = [0u8; 5];
+            payload[..4].copy_from_slice(&id.to_be_bytes());
+            payload[4] = done as u8;
+            return self.session.send(ctx, SETTLED, &payload);
+        }
+
+        // Sorts out whatever either side left half done last time. Returns
+        // false if this side has to wait for them to say how its own pending
+        // trade ended.
+        fn settle(&mut self, hello: &Hello, ctx: &mut Context) -> Result<bool, LinkError> {
+            let data = &mut ctx.save.data;
+            // Anything they haven't got pending any more, they've settled.
+            let before = data.finished_trades().iter().count();
+            data.finished_trades().prune(&hello.name, hello.pending);
+            let pruned = data.finished_trades().iter().count() != before;
+            let (id, partner) = match &data.pending_trade {
+                Some(t) => (t.id, t.partner),
+                None => (0, [0; 12]),
+            };
+            if pruned {
+                ctx.persist().map_err(|_| LinkError("COULDN'T SAVE"))?;
+            }
+            // Their half done trade with this side, which only this side can
+            // say the ending of.
+            if hello.pending != 0 && hello.pending != id {
+                let done = ctx
+                    .save
+                    .data
+                    .finished_trades()
+                    .contains(hello.pending, &hello.name);
+                self.send_settled(hello.pending, done, ctx)?;
+            }
+            if id != 0 {
+                // Only the player it was with can say how it ended.
+                if partner != hello.name {
+                    return Err(LinkError("FINISH OLD TRADE"));
+                }
+                if hello.pending != id {
+                    return Ok(false);
+                }
+                self.finish(false, ctx)?;
+                self.notice = Some("OLD TRADE DONE");
+            }
+            // Every one of these is waiting on a different player, and there
+            // wouldn't be anywhere to remember another.
+            if ctx.save.data.finished_trades().is_full() {
+                return Err(LinkError("TOO MANY OLD TRADES"));
+            }
+            return Ok(true);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum BattlePhase {
+        Connecting,
+        // Sending each other's parties across.
+        Exchanging,
+        // Got all of theirs, so the battle can start.
+        Ready,
+        // Nothing picked from this end for the turn yet.
+        Choosing,
+        // Picked, and waiting on the other side.
+        Waiting,
+        // Stopped early, and why.
+        Ended(&'static str),
+    }
+
+    // What one side sends each turn. The seed is this side's half of the
+    // turn's randomness, and the hash is of the battle as it stood before
+    // the turn so the two ends can check they still agree.
+    #[derive(Clone, Copy)]
+    struct Pick {
+        choice: Choice,
+        seed: u32,
+        hash: u32,
+    }
+
+    const PICK_SIZE: usize = 12;
+
+    impl Pick {
+        // Items can't be used in a link battle, so they never get sent.
+        fn encode(&self, turn: u16) -> Option<[u8; PICK_SIZE]> {
+            let (kind, slot) = match self.choice {
+                Choice::Fight(slot) => (0, slot),
+                Choice::Switch(slot) => (1, slot),
+                Choice::Run => (2, 0),
+                Choice::Item(_) => return None,
+            };
+            let mut buf = [0u8; PICK_SIZE];
+            buf[0..2].copy_from_slice(&turn.to_be_bytes());
+            buf[2] = kind;
+            buf[3] = slot as u8;
+            buf[4..8].copy_from_slice(&self.seed.to_be_bytes());
+            buf[8..12].copy_from_slice(&self.hash.to_be_bytes());
+            return Some(buf);
+        }
+
+        fn decode(data: &[u8]) -> Option<(u16, Pick)> {
+            if data.len() != PICK_SIZE {
+                return None;
+            }
+            let slot = data[3] as usize;
+            let choice = match data[2] {
+                0 if slot < MOVE_SLOTS => Choice::Fight(slot),
+                1 if slot < TEAM_SIZE => Choice::Switch(slot),
+                2 => Choice::Run,
+                _ => return None,
+            };
+            let pick = Pick {
+                choice,
+                seed: word(&data[4..8])?,
+                hash: word(&data[8..12])?,
+            };
+            return Some((u16::from_be_bytes([data[0], data[1]]), pick));
+        }
+    }
+
+    // One battle against another player, in lockstep. Neither end is in
+    // charge: both have both parties, both pick a move each turn and send
+    // it over along with half a seed, and both play the same turn with the
+    // same seed. As long as the battle code does the same thing on both
+    // boards, they end up in the same place, and a hash of the battle goes
+    // along with every pick to make sure of it.
+    pub struct LinkBattle {
+        phase: BattlePhase,
+        session: Session,
+        seeds: SeededRandom,
+        team: [Option<Pokemon>; TEAM_SIZE],
+        // The next party slot to send while exchanging, one a tick so the
+        // other end's UART keeps up.
+        sending: Option<usize>,
+        turn: u16,
+        mine: Option<Pick>,
+        theirs: Option<Pick>,
+    }
+
+    impl LinkBattle {
+        pub fn new(nonce: u32) -> LinkBattle {
+            return LinkBattle {
+                phase: BattlePhase::Connecting,
+                session: Session::new(BATTLING, nonce),
+                seeds: SeededRandom::new(nonce),
+                team: Default::default(),
+                sending: None,
+                turn: 0,
+                mine: None,
+                theirs: None,
+            };
+        }
+
+        pub fn phase(&self) -> BattlePhase {
+            return self.phase;
+        }
+
+        pub fn partner(&self) -> Option<&str> {
+            return self.session.partner();
+        }
+
+        pub fn partner_name(&self) -> [u8; 12] {
+            return self.session.partner;
+        }
+
+        // Some things need to mean the same player on both ends, like who
+        // wins a speed tie. Whoever picked the bigger nonce is the host.
+        pub fn is_host(&self) -> bool {
+            return Some(self.session.nonce) > self.session.their_nonce;
+        }
+
+        // For setting the battle up, which comes out the same on both ends.
+        pub fn seed(&self) -> u32 {
+            return self.session.nonce ^ self.session.their_nonce.unwrap_or(0);
+        }
+
+        // Hands over their party once it's all here, and starts the first
+        // turn.
+        pub fn start(&mut self) -> Option<[Option<Pokemon>; TEAM_SIZE]> {
+            if self.phase != BattlePhase::Ready {
+                return None;
+            }
+            self.phase = BattlePhase::Choosing;
+            return Some(core::mem::take(&mut self.team));
+        }
+
+        // Called every tick. Returns whether anything changed that's worth
+        // redrawing for.
+        pub fn poll(&mut self, ctx: &mut Context) -> bool {
+            if let BattlePhase::Ended(_) = self.phase {
+                return false;
+            }
+            let result = self
+                .session
+                .tick(ctx)
+                .and_then(|_| self.send_next_member(ctx));
+            if let Err(e) = result {
+                return self.fail(e, ctx);
+            }
+            let mut changed = false;
+            loop {
+                let frame = match self.session.next(ctx) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => return changed,
+                    Err(e) => return self.fail(e, ctx),
+                };
+                changed |= match self.handle(&frame, ctx) {
+                    Ok(changed) => changed,
+                    Err(e) => return self.fail(e, ctx),
+                };
+                if let BattlePhase::Ended(_) = self.phase {
+                    return true;
+                }
+            }
+        }
+
+        // Sends this end's pick for the turn, along with a hash of the battle
+        // as it is now.
+        pub fn choose(&mut self, choice: Choice, hash: u32, ctx: &mut Context) -> bool {
+            if self.phase != BattlePhase::Choosing {
+                return false;
+            }
+            let seed = u32::from_be_bytes([
+                self.seeds.random(),
+                self.seeds.random(),
+                self.seeds.random(),
+                self.seeds.random(),
+            ]);
+            let pick = Pick { choice, seed, hash };
+            let data = match pick.encode(self.turn) {
+                Some(data) => data,
+                None => return false,
+            };
+            self.mine = Some(pick);
+            self.phase = BattlePhase::Waiting;
+            let result = self
+                .session
+                .send(ctx, TURN, &data)
+                .and_then(|_| self.compare());
+            if let Err(e) = result {
+                return self.fail(e, ctx);
+            }
+            return true;
+        }
+
+        // Once both picks are in: this end's, theirs, and the seed to play
+        // the turn with.
+        pub fn next_turn(&mut self) -> Option<(Choice, Choice, u32)> {
+            if self.phase != BattlePhase::Waiting {
+                return None;
+            }
+            let (mine, theirs) = match (self.mine, self.theirs) {
+                (Some(mine), Some(theirs)) => (mine, theirs),
+                _ => return None,
+            };
+            self.mine = None;
+            self.theirs = None;
+            self.turn = self.turn.wrapping_add(1);
+            self.phase = BattlePhase::Choosing;
+            return Some((mine.choice, theirs.choice, mine.seed ^ theirs.seed));
+        }
+
+        // Backs out before the battle's started.
+        pub fn cancel(&mut self, ctx: &mut Context) {
+            if let BattlePhase::Ended(_) = self.phase {
+                return;
+            }
+            self.session.send(ctx, CANCEL, &[]).ok();
+            self.phase = BattlePhase::Ended("CANCELLED");
+        }
+
+        fn fail(&mut self, error: LinkError, ctx: &mut Context) -> bool {
+            self.session.send(ctx, CANCEL, &[]).ok();
+            self.phase = BattlePhase::Ended(error.0);
+            return true;
+        }
+
+        // Only the same battle gives the same hash, so a difference means
+        // the two ends have drifted apart and there's no getting back.
+        fn compare(&self) -> Result<(), LinkError> {
+            match (self.mine, self.theirs) {
+                (Some(mine), Some(theirs)) if mine.hash != theirs.hash => {
+                    return Err(LinkError("OUT OF SYNC"))
+                }
+                _ => return Ok(()),
+            }
+        }
+
+        fn send_next_member(&mut self, ctx: &mut Context) -> Result<(), LinkError> {
+            let mut slot = match self.sending {
+                Some(slot) => slot,
+                None => return Ok(()),
+            };
+            while slot < PARTY_SIZE && ctx.save.data.party[slot].is_none() {
+                slot += 1;
+            }
+            if slot == PARTY_SIZE {
+                self.sending = None;
+                let count = ctx.save.data.party.iter().flatten().count() as u8;
+                return self.session.send(ctx, TEAM, &[count]);
+            }
+            self.sending = Some(slot + 1);
+            let mut buf = [0u8; MAX_PAYLOAD];
+            buf[0] = slot as u8;
+            let len = {
+                let mut writer: &mut [u8] = &mut buf[1..];
+                if let Some(pokemon) = &ctx.save.data.party[slot] {
+                    minicbor::encode(pokemon, &mut writer)
+                        .map_err(|_| LinkError("POKéMON TOO BIG"))?;
+                }
+                MAX_PAYLOAD - writer.len()
+            };
+            return self.session.send(ctx, MEMBER, &buf[..len]);
+        }
+
+        fn handle(&mut self, frame: &Frame, ctx: &mut Context) -> Result<bool, LinkError> {
+            let payload = frame.payload();
+            match frame.kind {
+                HELLO => {
+                    let hello = match self.session.greet(payload, ctx)? {
+                        Some(hello) => hello,
+                        None => return Ok(false),
+                    };
+                    // Nobody would be host, and both would think they'd
+                    // heard themselves.
+                    if hello.nonce == self.session.nonce {
+                        return Err(LinkError("TRY AGAIN"));
+                    }
+                    // A restart halfway through can't be picked up from.
+                    match self.phase {
+                        BattlePhase::Connecting | BattlePhase::Exchanging => {}
+                        _ => return Err(LinkError("LINK LOST")),
+                    }
+                    self.phase = BattlePhase::Exchanging;
+                    self.team = Default::default();
+                    self.sending = Some(0);
+                    return Ok(true);
+                }
+                MEMBER => {
+                    if self.phase != BattlePhase::Exchanging {
+                        return Ok(false);
+                    }
+                    let slot = match payload.first() {
+                        Some(slot) if (*slot as usize) < TEAM_SIZE => *slot as usize,
+                        _ => return Err(LinkError("BAD POKéMON")),
+                    };
+                    let pokemon: Pokemon =
+                        minicbor::decode(&payload[1..]).map_err(|_| LinkError("BAD POKéMON"))?;
+                    self.team[slot] = Some(pokemon);
+                    return Ok(false);
+                }
+                TEAM => {
+                    if self.phase != BattlePhase::Exchanging {
+                        return Ok(false);
+                    }
+                    // Frames come in order, so everything should be here by
+                    // now.
+                    let count = payload.first().ok_or(LinkError("BAD TEAM"))?;
+                    if self.team.iter().flatten().count() != *count as usize {
+                        return Err(LinkError("BAD TEAM"));
+                    }
+                    self.phase = BattlePhase::Ready;
+                    return Ok(true);
+                }
+                TURN => {
+                    let (turn, pick) = Pick::decode(payload).ok_or(LinkError("BAD TURN"))?;
+                    match self.phase {
+                        BattlePhase::Ready | BattlePhase::Choosing | BattlePhase::Waiting => {}
+                        _ => return Ok(false),
+                    }
+                    if turn != self.turn || self.theirs.is_some() {
+                        return Err(LinkError("OUT OF STEP"));
+                    }
+                    self.theirs = Some(pick);
+                    self.compare()?;
+                    return Ok(true);
+                }
+                CANCEL => {
+                    self.phase = BattlePhase::Ended("LINK CANCELLED");
+                    return Ok(true);
+                }
+                _ => return Err(LinkError("UNKNOWN MESSAGE")),
+            }
+        }
+    }
+
+    // Answers the save-tool while the board's sitting on the main menu, so
+    // a backup doesn't need the flasher. There's no hello, it only ever
+    // speaks when spoken to.
+    pub struct SaveServer {
+        reader: FrameReader,
+        buf: [u8; 32],
+    }
+
+    impl Default for SaveServer {
+        fn default() -> SaveServer {
+            return SaveServer::new();
+        }
+    }
+
+    impl SaveServer {
+        pub fn new() -> SaveServer {
+            return SaveServer {
+                reader: FrameReader::new(),
+                buf: [0; 32],
+            };
+        }
+
+        pub fn poll(&mut self, ctx: &mut Context) -> Result<(), LinkError> {
+            loop {
+                let read = ctx.link.read(&mut self.buf)?;
+                if read == 0 {
+                    return Ok(());
+                }
+                for i in 0..read {
+                    // Anything garbled just gets asked for again.
+                    if let Ok(Some(frame)) = self.reader.push(self.buf[i]) {
+                        if frame.kind == READ_SAVE {
+                            answer(frame.payload(), ctx)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // The request is an offset into the save region and a length. Asking
+    // for anything outside it gets nothing back rather than someone else's
+    // flash.
+    fn answer(request: &[u8], ctx: &mut Context) -> Result<(), LinkError> {
+        if request.len() != 5 {
+            return Ok(());
+        }
+        let offset = word(&request[..4]).unwrap_or(0);
+        let mut len = (request[4] as usize).min(SAVE_CHUNK);
+        if offset
+            .checked_add(len as u32)
+            .is_none_or(|end| end > SAVE_SIZE)
+        {
+            len = 0;
+        }
+        let mut reply = [0u8; 4 + SAVE_CHUNK];
+        reply[..4].copy_from_slice(&offset.to_be_bytes());
+        if ctx
+            .storage
+            .read(SAVE_OFFSET + offset, &mut reply[4..4 + len])
+            .is_err()
+        {
+            len = 0;
+        }
+        return write_frame(ctx.link, SAVE_DATA, &reply[..4 + len]);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::battle::{Battle, Messages};
+        use crate::cry::Speaker;
+        use crate::led::Leds;
+        use crate::pokedex::Type;
+        use crate::rom::testing::{attack, pokemon, species, TestRom};
+        use crate::save::Save;
+        use crate::screens::BattleState;
+        use crate::trainer::FakeNetworks;
+
+        const WIRE_SIZE: usize = 2048;
+
+        // Bytes on their way from one end to the other.
+        struct Wire {
+            bytes: [u8; WIRE_SIZE],
+            len: usize,
+        }
+
+        // A pair of wires, crossed over. Each end reads from its own and
+        // writes to the other one's. It can be set to get pulled out right
+        // after a given kind of frame goes down it.
+        struct Cable {
+            wires: [Wire; 2],
+            cut_after: Option<u8>,
+            cut: bool,
+        }
+
+        impl Cable {
+            fn new() -> Cable {
+                let wire = || Wire {
+                    bytes: [0; WIRE_SIZE],
+                    len: 0,
+                };
+                return Cable {
+                    wires: [wire(), wire()],
+                    cut_after: None,
+                    cut: false,
+                };
+            }
+        }
+
+        struct End<'a> {
+            cable: &'a mut Cable,
+            side: usize,
+        }
+
+        impl Link for End<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, LinkError> {
+                let wire = &mut self.cable.wires[self.side];
+                let n = wire.len.min(buf.len());
+                buf[..n].copy_from_slice(&wire.bytes[..n]);
+                wire.bytes.copy_within(n..wire.len, 0);
+                wire.len -= n;
+                return Ok(n);
+            }
+
+            // Frames go down in one write each, so the kind is always the
+            // second byte.
+            fn write(&mut self, data: &[u8]) -> Result<(), LinkError> {
+                if self.cable.cut {
+                    return Ok(());
+                }
+                let wire = &mut self.cable.wires[1 - self.side];
+                if wire.len + data.len() > WIRE_SIZE {
+                    return Err(LinkError("WIRE FULL"));
+                }
+                wire.bytes[wire.len..wire.len + data.len()].copy_from_slice(data);
+                wire.len += data.len();
+                self.cable.cut = self.cable.cut_after == data.get(1).copied();
+                return Ok(());
+            }
+        }
+
+        const FIRE: u8 = 1;
+        const WATER: u8 = 2;
+        const TACKLE: u16 = 1;
+
+        // Everything one player's board has to hand.
+        struct Board {
+            rom: TestRom,
+            save: Save,
+            leds: Leds,
+            scanner: FakeNetworks,
+            speaker: Speaker,
+            battle: BattleState,
+        }
+
+        impl Board {
+            fn new(name: &[u8], party: &[u8]) -> Board {
+                let mut rom = TestRom::new();
+                rom.add_species(&species(FIRE, Type::Fire, None));
+                rom.add_species(&species(WATER, Type::Water, None));
+                rom.add_move(&attack(TACKLE, Type::Normal, 40, 35));
+                let mut save = Save::load(&mut rom);
+                let mut player_name = [0u8; 12];
+                player_name[..name.len()].copy_from_slice(name);
+                save.data.player_name = Some(player_name);
+                for (slot, id) in party.iter().enumerate() {
+                    let types = [Type::Fire, Type::Water];
+                    let moves = [Some(TACKLE), None, None, None];
+                    let kind = species(*id, types[*id as usize - 1], None);
+                    save.data.party[slot] = Some(pokemon(kind, 10 + slot as u8, moves));
+                }
+                save.commit(&mut rom).unwrap();
+                return Board {
+                    rom,
+                    save,
+                    leds: Leds::new(),
+                    scanner: FakeNetworks,
+                    speaker: Speaker::new(),
+                    battle: BattleState::new(),
+                };
+            }
+
+            fn ctx<'a>(&'a mut self, link: &'a mut dyn Link) -> Context<'a> {
+                return Context {
+                    storage: &mut self.rom,
+                    save: &mut self.save,
+                    leds: &mut self.leds,
+                    link,
+                    scanner: &mut self.scanner,
+                    speaker: &mut self.speaker,
+                    battle: &mut self.battle,
+                };
+            }
+        }
+
+        fn poll(board: &mut Board, session: &mut LinkBattle, cable: &mut Cable, side: usize) {
+            let mut end = End { cable, side };
+            session.poll(&mut board.ctx(&mut end));
+        }
+
+        fn choose(
+            board: &mut Board,
+            session: &mut LinkBattle,
+            choice: Choice,
+            hash: u32,
+            cable: &mut Cable,
+            side: usize,
+        ) {
+            let mut end = End { cable, side };
+            assert!(session.choose(choice, hash, &mut board.ctx(&mut end)));
+        }
+
+        fn same_party(a: &[Option<Pokemon>], b: &[Option<Pokemon>]) -> bool {
+            let summary = |p: &Option<Pokemon>| p.as_ref().map(|p| (p.species_id, p.level, p.xp));
+            return a.iter().map(summary).eq(b.iter().map(summary));
+        }
+
+        #[test]
+        fn picks_survive_the_trip() {
+            for choice in [Choice::Fight(3), Choice::Switch(5), Choice::Run] {
+                let pick = Pick {
+                    choice,
+                    seed: 0xdeadbeef,
+                    hash: 0x01020304,
+                };
+                let (turn, back) = Pick::decode(&pick.encode(513).unwrap()).unwrap();
+                assert_eq!(turn, 513);
+                assert_eq!(back.choice, choice);
+                assert_eq!(back.seed, pick.seed);
+                assert_eq!(back.hash, pick.hash);
+            }
+            let item = Pick {
+                choice: Choice::Item(1),
+                seed: 0,
+                hash: 0,
+            };
+            assert!(item.encode(0).is_none());
+            // Out of range slots and unknown kinds are turned away.
+            let mut data = Pick {
+                choice: Choice::Fight(0),
+                seed: 0,
+                hash: 0,
+            }
+            .encode(0)
+            .unwrap();
+            data[3] = MOVE_SLOTS as u8;
+            assert!(Pick::decode(&data).is_none());
+            data[2] = 3;
+            data[3] = 0;
+            assert!(Pick::decode(&data).is_none());
+            assert!(Pick::decode(&data[..PICK_SIZE - 1]).is_none());
+        }
+
+        #[test]
+        fn frames_come_through_and_damage_is_caught() {
+            let mut cable = Cable::new();
+            write_frame(
+                &mut End {
+                    cable: &mut cable,
+                    side: 0,
+                },
+                TURN,
+                b"HELLO",
+            )
+            .unwrap();
+            // Noise on the line before it gets skipped.
+            let mut bytes = [0u8; 32];
+            bytes[0] = 0x55;
+            let len = End {
+                cable: &mut cable,
+                side: 1,
+            }
+            .read(&mut bytes[1..])
+            .unwrap()
+                + 1;
+            let mut reader = FrameReader::new();
+            let mut frames = bytes[..len].iter().filter_map(|b| reader.push(*b).unwrap());
+            let frame = frames.next().unwrap();
+            assert_eq!(frame.kind, TURN);
+            assert_eq!(frame.payload(), b"HELLO");
+
+            bytes[5] ^= 0x01;
+            let mut reader = FrameReader::new();
+            let mut result = Ok(None);
+            for byte in &bytes[1..len] {
+                result = reader.push(*byte);
+            }
+            assert_eq!(result.err(), Some(LinkError("BAD CHECKSUM")));
+        }
+
+        #[test]
+        fn link_battles_play_in_lockstep_until_the_hashes_differ() {
+            let mut cable = Cable::new();
+            let mut red = Board::new(b"RED", &[FIRE, WATER]);
+            let mut blue = Board::new(b"BLUE", &[WATER, FIRE, FIRE]);
+            let mut red_link = LinkBattle::new(1234);
+            let mut blue_link = LinkBattle::new(5678);
+
+            for _ in 0..100 {
+                poll(&mut red, &mut red_link, &mut cable, 0);
+                poll(&mut blue, &mut blue_link, &mut cable, 1);
+            }
+            assert_eq!(red_link.phase(), BattlePhase::Ready);
+            assert_eq!(blue_link.phase(), BattlePhase::Ready);
+            assert_ne!(red_link.is_host(), blue_link.is_host());
+            assert_eq!(red_link.seed(), blue_link.seed());
+
+            // Each end got the other's whole party.
+            let red_team = red_link.start().unwrap();
+            let blue_team = blue_link.start().unwrap();
+            assert!(same_party(&red_team, &blue.save.data.party));
+            assert!(same_party(&blue_team, &red.save.data.party));
+
+            let mut messages = Messages::new();
+            let mut red_battle = Battle::link(
+                red_team,
+                red_link.partner_name(),
+                red_link.is_host(),
+                red_link.seed(),
+                &mut red.save.data,
+                &mut red.rom,
+                &mut messages,
+            )
+            .unwrap();
+            let mut blue_battle = Battle::link(
+                blue_team,
+                blue_link.partner_name(),
+                blue_link.is_host(),
+                blue_link.seed(),
+                &mut blue.save.data,
+                &mut blue.rom,
+                &mut messages,
+            )
+            .unwrap();
+
+            for turn in 0..4 {
+                let red_pick = Choice::Fight(0);
+                let blue_pick = match turn {
+                    1 => Choice::Switch(2),
+                    _ => Choice::Fight(0),
+                };
+                let red_hash = red_battle.link_hash(&red.save.data);
+                let blue_hash = blue_battle.link_hash(&blue.save.data);
+                assert_eq!(red_hash, blue_hash, "turn {}", turn);
+                choose(&mut red, &mut red_link, red_pick, red_hash, &mut cable, 0);
+                assert_eq!(red_link.next_turn(), None);
+                choose(
+                    &mut blue,
+                    &mut blue_link,
+                    blue_pick,
+                    blue_hash,
+                    &mut cable,
+                    1,
+                );
+                poll(&mut red, &mut red_link, &mut cable, 0);
+                poll(&mut blue, &mut blue_link, &mut cable, 1);
+
+                // Both ends play the same turn from their own side.
+                let (mine, theirs, seed) = red_link.next_turn().unwrap();
+                assert_eq!((mine, theirs), (red_pick, blue_pick));
+                assert_eq!(blue_link.next_turn(), Some((blue_pick, red_pick, seed)));
+                red_battle
+                    .play_link_turn(
+                        mine,
+                        theirs,
+                        seed,
+                        &mut red.save.data,
+                        &mut red.rom,
+                        &mut messages,
+                    )
+                    .unwrap();
+                blue_battle
+                    .play_link_turn(
+                        theirs,
+                        mine,
+                        seed,
+                        &mut blue.save.data,
+                        &mut blue.rom,
+                        &mut messages,
+                    )
+                    .unwrap();
+            }
+
+            // One board's idea of the battle gets knocked out of shape.
+            let hash = red_battle.link_hash(&red.save.data);
+            choose(
+                &mut red,
+                &mut red_link,
+                Choice::Fight(0),
+                hash ^ 1,
+                &mut cable,
+                0,
+            );
+            choose(
+                &mut blue,
+                &mut blue_link,
+                Choice::Fight(0),
+                hash,
+                &mut cable,
+                1,
+            );
+            poll(&mut red, &mut red_link, &mut cable, 0);
+            poll(&mut blue, &mut blue_link, &mut cable, 1);
+            assert_eq!(red_link.phase(), BattlePhase::Ended("OUT OF SYNC"));
+            assert_eq!(blue_link.phase(), BattlePhase::Ended("OUT OF SYNC"));
+            assert_eq!(red_link.next_turn(), None);
+        }
+
+        fn poll_trade(board: &mut Board, trade: &mut Trade, cable: &mut Cable, side: usize) {
+            let mut end = End { cable, side };
+            trade.poll(&mut board.ctx(&mut end));
+        }
+
+        // Both ends take their turn a few times over.
+        fn run_trades(
+            red: (&mut Board, &mut Trade),
+            blue: (&mut Board, &mut Trade),
+            cable: &mut Cable,
+            ticks: usize,
+        ) {
+            for _ in 0..ticks {
+                poll_trade(red.0, red.1, cable, 0);
+                poll_trade(blue.0, blue.1, cable, 1);
+            }
+        }
+
+        // As if the board had been switched off and on again.
+        fn reload(board: &mut Board) {
+            board.save = Save::load(&mut board.rom);
+        }
+
+        // Which pokemon is which goes by species and level, which are all
+        // different to start with.
+        fn copies(boards: [&Board; 2], species_id: u8, level: u8) -> usize {
+            return boards
+                .iter()
+                .flat_map(|b| b.save.data.party.iter().flatten())
+                .filter(|p| p.species_id == species_id && p.level == level)
+                .count();
+        }
+
+        // Trades red's FIRE for blue's WATER, with the cable pulled out just
+        // after the first frame of the given kind. Returns whether the swap
+        // went through in the end.
+        fn trade_cut_after(kind: Option<u8>) -> bool {
+            let mut red = Board::new(b"RED", &[FIRE, WATER]);
+            let mut blue = Board::new(b"BLUE", &[WATER, FIRE]);
+            let mut cable = Cable::new();
+            let mut red_trade = Trade::new(1234);
+            let mut blue_trade = Trade::new(5678);
+            let red_side = (&mut red, &mut red_trade);
+            run_trades(red_side, (&mut blue, &mut blue_trade), &mut cable, 5);
+            assert_eq!(red_trade.phase(), Phase::Choosing);
+            assert_eq!(blue_trade.phase(), Phase::Choosing);
+
+            cable.cut_after = kind;
+            red_trade.offer(
+                0,
+                &mut red.ctx(&mut End {
+                    cable: &mut cable,
+                    side: 0,
+                }),
+            );
+            blue_trade.offer(
+                0,
+                &mut blue.ctx(&mut End {
+                    cable: &mut cable,
+                    side: 1,
+                }),
+            );
+            run_trades(
+                (&mut red, &mut red_trade),
+                (&mut blue, &mut blue_trade),
+                &mut cable,
+                2,
+            );
+            red_trade.accept(&mut red.ctx(&mut End {
+                cable: &mut cable,
+                side: 0,
+            }));
+            blue_trade.accept(&mut blue.ctx(&mut End {
+                cable: &mut cable,
+                side: 1,
+            }));
+            run_trades(
+                (&mut red, &mut red_trade),
+                (&mut blue, &mut blue_trade),
+                &mut cable,
+                TIMEOUT_TICKS as usize * 2,
+            );
+
+            // Both get switched off, then linked up again to sort it out.
+            reload(&mut red);
+            reload(&mut blue);
+            let mut cable = Cable::new();
+            let mut red_trade = Trade::new(4321);
+            let mut blue_trade = Trade::new(8765);
+            run_trades(
+                (&mut red, &mut red_trade),
+                (&mut blue, &mut blue_trade),
+                &mut cable,
+                5,
+            );
+            assert_eq!(red_trade.phase(), Phase::Choosing);
+            assert_eq!(blue_trade.phase(), Phase::Choosing);
+            reload(&mut red);
+            reload(&mut blue);
+
+            assert!(red.save.data.pending_trade.is_none());
+            assert!(blue.save.data.pending_trade.is_none());
+            for (species_id, level) in [(FIRE, 10), (WATER, 11), (WATER, 10), (FIRE, 11)] {
+                assert_eq!(copies([&red, &blue], species_id, level), 1, "{:?}", kind);
+            }
+            let traded = blue.save.data.party[0].as_ref().map(|p| p.species_id) == Some(FIRE);
+            let red_got = red.save.data.party[0].as_ref().map(|p| p.species_id) == Some(WATER);
+            assert_eq!(traded, red_got);
+            return traded;
+        }
+
+        #[test]
+        fn a_cut_link_never_loses_or_copies_a_pokemon() {
+            assert!(trade_cut_after(None));
+            assert!(!trade_cut_after(Some(OFFER)));
+            // Blue has it written down, but red never did, so it's undone.
+            assert!(!trade_cut_after(Some(ACCEPT)));
+            // Blue went ahead on red's READY, so red finishes it next time.
+            assert!(trade_cut_after(Some(READY)));
+            assert!(trade_cut_after(Some(SETTLED)));
+        }
+    }
+}
+
+pub mod app {
+    use crate::animation::{Wipe, TICK_MS};
+    use crate::cry::Speaker;
+    use crate::input::ButtonStates;
+    use crate::led::{Effect, Leds};
+    use crate::link::Link;
+    use crate::rom::{Storage, WriteError};
+    use crate::save::Save;
+    use crate::screens::{
+        BagScreen, BattleScreen, BattleState, BoxScreen, ClockScreen, EvolutionScreen, GymScreen,
+        KeyboardScreen, LinkBattleScreen, MenuScreen, NameTarget, PartyScreen, PokedexScreen,
+        SettingsScreen, SummaryScreen, TradeScreen, TrainerCardScreen,
+    };
+    use crate::trainer::Scanner;
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+    // Everything a screen is allowed to touch outside of itself. The firmware
+    // builds one of these each time around the main loop.
+    pub struct Context<'a> {
+        pub storage: &'a mut dyn Storage,
+        pub save: &'a mut Save,
+        pub leds: &'a mut Leds,
+        pub link: &'a mut dyn Link,
+        pub scanner: &'a mut dyn Scanner,
+        pub speaker: &'a mut Speaker,
+        pub battle: &'a mut BattleState,
+    }
+
+    impl<'a> Context<'a> {
+        pub fn persist(&mut self) -> Result<(), WriteError> {
+            return self.save.commit(self.storage);
+        }
+    }
+
+    // What a screen wants the app to do after it's had a look at the world.
+    pub enum Transition {
+        None,
+        Redraw,
+        Push(ScreenState),
+        Replace(ScreenState),
+        Pop,
+    }
+
+    pub trait Screen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition;
+
+        // Called once a tick, whether or not anything was pressed. Anything
+        // animated moves along here and asks for a redraw.
+        fn update(&mut self, _ctx: &mut Context) -> Transition {
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context);
+
+        // What the neopixel should be doing while this is on top. It's asked
+        // again whenever something changes.
+        fn led(&mut self, _ctx: &mut Context) -> Effect {
+            return Effect::Off;
+        }
+    }
+
+    // All the screens we know about. We've got no allocator, so rather than
+    // boxing up trait objects every screen gets a variant here and we
+    // dispatch by hand.
+    pub enum ScreenState {
+        Menu(MenuScreen),
+        Pokedex(PokedexScreen),
+        Settings(SettingsScreen),
+        Party(PartyScreen),
+        Summary(SummaryScreen),
+        Keyboard(KeyboardScreen),
+        Clock(ClockScreen),
+        TrainerCard(TrainerCardScreen),
+        Gym(GymScreen),
+        Bag(BagScreen),
+        Box(BoxScreen),
+        Evolution(EvolutionScreen),
+        Battle(BattleScreen),
+        Trade(TradeScreen),
+        LinkBattle(LinkBattleScreen),
+    }
+
+    impl Screen for ScreenState {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            match self {
+                ScreenState::Menu(s) => s.handle_input(buttons, ctx),
+                ScreenState::Pokedex(s) => s.handle_input(buttons, ctx),
+                ScreenState::Settings(s) => s.handle_input(buttons, ctx),
+                ScreenState::Party(s) => s.handle_input(buttons, ctx),
+                ScreenState::Summary(s) => s.handle_input(buttons, ctx),
+                ScreenState::Keyboard(s) => s.handle_input(buttons, ctx),
+                ScreenState::Clock(s) => s.handle_input(buttons, ctx),
+                ScreenState::TrainerCard(s) => s.handle_input(buttons, ctx),
+                ScreenState::Gym(s) => s.handle_input(buttons, ctx),
+                ScreenState::Bag(s) => s.handle_input(buttons, ctx),
+                ScreenState::Box(s) => s.handle_input(buttons, ctx),
+                ScreenState::Evolution(s) => s.handle_input(buttons, ctx),
+                ScreenState::Battle(s) => s.handle_input(buttons, ctx),
+                ScreenState::Trade(s) => s.handle_input(buttons, ctx),
+                ScreenState::LinkBattle(s) => s.handle_input(buttons, ctx),
+            }
+        }
+
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            match self {
+                ScreenState::Menu(s) => s.update(ctx),
+                ScreenState::Pokedex(s) => s.update(ctx),
+                ScreenState::Settings(s) => s.update(ctx),
+                ScreenState::Party(s) => s.update(ctx),
+                ScreenState::Summary(s) => s.update(ctx),
+                ScreenState::Keyboard(s) => s.update(ctx),
+                ScreenState::Clock(s) => s.update(ctx),
+                ScreenState::TrainerCard(s) => s.update(ctx),
+                ScreenState::Gym(s) => s.update(ctx),
+                ScreenState::Bag(s) => s.update(ctx),
+                ScreenState::Box(s) => s.update(ctx),
+                ScreenState::Evolution(s) => s.update(ctx),
+                ScreenState::Battle(s) => s.update(ctx),
+                ScreenState::Trade(s) => s.update(ctx),
+                ScreenState::LinkBattle(s) => s.update(ctx),
+            }
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            match self {
+                ScreenState::Menu(s) => s.render(display, ctx),
+                ScreenState::Pokedex(s) => s.render(display, ctx),
+                ScreenState::Settings(s) => s.render(display, ctx),
+                ScreenState::Party(s) => s.render(display, ctx),
+                ScreenState::Summary(s) => s.render(display, ctx),
+                ScreenState::Keyboard(s) => s.render(display, ctx),
+                ScreenState::Clock(s) => s.render(display, ctx),
+                ScreenState::TrainerCard(s) => s.render(display, ctx),
+                ScreenState::Gym(s) => s.render(display, ctx),
+                ScreenState::Bag(s) => s.render(display, ctx),
+                ScreenState::Box(s) => s.render(display, ctx),
+                ScreenState::Evolution(s) => s.render(display, ctx),
+                ScreenState::Battle(s) => s.render(display, ctx),
+                ScreenState::Trade(s) => s.render(display, ctx),
+                ScreenState::LinkBattle(s) => s.render(display, ctx),
+            }
+        }
+
+        fn led(&mut self, ctx: &mut Context) -> Effect {
+            match self {
+                ScreenState::Menu(s) => s.led(ctx),
+                ScreenState::Pokedex(s) => s.led(ctx),
+                ScreenState::Settings(s) => s.led(ctx),
+                ScreenState::Party(s) => s.led(ctx),
+                ScreenState::Summary(s) => s.led(ctx),
+                ScreenState::Keyboard(s) => s.led(ctx),
+                ScreenState::Clock(s) => s.led(ctx),
+                ScreenState::TrainerCard(s) => s.led(ctx),
+                ScreenState::Gym(s) => s.led(ctx),
+                ScreenState::Bag(s) => s.led(ctx),
+                ScreenState::Box(s) => s.led(ctx),
+                ScreenState::Evolution(s) => s.led(ctx),
+                ScreenState::Battle(s) => s.led(ctx),
+                ScreenState::Trade(s) => s.led(ctx),
+                ScreenState::LinkBattle(s) => s.led(ctx),
+            }
+        }
+    }
+
+    impl ScreenState {
+        // Big moments get a wipe on the way in.
+        fn wipes_in(&self) -> bool {
+            return matches!(self, ScreenState::Battle(_) | ScreenState::Evolution(_));
+        }
+    }
+
+    const MAX_DEPTH: usize = 8;
+    // Every level of the stack is as big as the biggest screen, so one screen
+    // growing grows all eight. Anything large that's only needed once goes in
+    // the context instead, the way battles keep their text in BattleState.
+    const MAX_SCREEN_SIZE: usize = 1024;
+    const _: () = assert!(core::mem::size_of::<ScreenState>() <= MAX_SCREEN_SIZE);
+    const WIPE_TICKS: u16 = 8;
+    const EMPTY: Option<ScreenState> = None;
+
+    // The app is a stack of screens. Only the top one gets input and gets
+    // drawn; popping it reveals whatever was underneath exactly as it was left.
+    pub struct App {
+        stack: [Option<ScreenState>; MAX_DEPTH],
+        depth: usize,
+        dirty: bool,
+        wipe: Option<Wipe>,
+        // The part of a second the clock hasn't counted yet.
+        clock_ms: u32,
+        // Copied from the settings every tick, for the transitions that
+        // happen without a context to hand.
+        animations: bool,
+        // How long since anything was pressed, and whether that was long
+        // enough for the screen to have gone off.
+        idle_ticks: u32,
+        asleep: bool,
+    }
+
+    impl App {
+        pub fn new(root: ScreenState) -> App {
+            let mut stack = [EMPTY; MAX_DEPTH];
+            stack[0] = Some(root);
+            return App {
+                stack,
+                depth: 1,
+                dirty: true,
+                wipe: None,
+                clock_ms: 0,
+                animations: true,
+                idle_ticks: 0,
+                asleep: false,
+            };
+        }
+
+        // Where the firmware starts. A brand new save gets asked for the
+        // player's name before anything else, and then the time.
+        pub fn boot(save: &Save) -> App {
+            let mut app = App::new(ScreenState::Menu(MenuScreen::new()));
+            app.animations = save.data.settings.battle_animations;
+            if save.data.clock.is_none() {
+                app.apply(Transition::Push(ScreenState::Clock(ClockScreen::new())));
+            }
+            if save.data.player_name.is_none() {
+                app.apply(Transition::Push(ScreenState::Keyboard(
+                    KeyboardScreen::new(NameTarget::Player, save),
+                )));
+            }
+            return app;
+        }
+
+        // Moves the clock on by however long really went by. This is kept
+        // apart from ticks, since those get dropped when the loop falls
+        // behind and the time of day shouldn't slip because of it.
+        pub fn elapse(&mut self, elapsed_ms: u32, ctx: &mut Context) {
+            self.clock_ms += elapsed_ms;
+            ctx.save.data.advance_clock(self.clock_ms / 1000);
+            self.clock_ms %= 1000;
+        }
+
+        // Runs one tick of the clock. Input waits while a wipe is going.
+        pub fn tick(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) {
+            ctx.leds.tick();
+            ctx.speaker.tick();
+            self.animations = ctx.save.data.settings.battle_animations;
+            if buttons.any_pressed() {
+                self.idle_ticks = 0;
+                // Whatever woke it up shouldn't also do something.
+                if self.asleep {
+                    self.asleep = false;
+                    self.dirty = true;
+                    buttons.consume_all();
+                }
+            } else {
+                self.idle_ticks = self.idle_ticks.saturating_add(1);
+                let minutes = ctx.save.data.settings.auto_sleep_minutes as u32;
+                if minutes > 0 && self.idle_ticks >= minutes * 60 * 1000 / TICK_MS {
+                    self.asleep = true;
+                }
+            }
+            if let Some(wipe) = &mut self.wipe {
+                wipe.tick();
+                if wipe.is_done() {
+                    self.wipe = None;
+                }
+                self.dirty = true;
+                return;
+            }
+            let transition = self.top().handle_input(buttons, ctx);
+            self.apply(transition);
+            let transition = self.top().update(ctx);
+            self.apply(transition);
+            // Nothing the LED depends on can change without a redraw.
+            if self.dirty {
+                let effect = self.top().led(ctx);
+                ctx.leds.set(effect);
+            }
+        }
+
+        // Returns whether anything was drawn, so the caller knows if it's
+        // worth flushing the display.
+        pub fn render<D: DrawTarget<BinaryColor>>(
+            &mut self,
+            display: &mut D,
+            ctx: &mut Context,
+        ) -> bool {
+            if !self.dirty {
+                return false;
+            }
+            self.dirty = false;
+            display.clear(BinaryColor::Off).ok();
+            self.top().render(display, ctx);
+            if let Some(wipe) = &self.wipe {
+                wipe.draw(display);
+            }
+            return true;
+        }
+
+        // The screen itself is left alone, so links and battles carry on
+        // underneath. It's up to whoever owns the panel to turn it off.
+        pub fn is_asleep(&self) -> bool {
+            return self.asleep;
+        }
+
+        fn wipe_if_wanted(&mut self) {
+            if self.animations && self.top().wipes_in() {
+                self.wipe = Some(Wipe::new(WIPE_TICKS));
+            }
+        }
+
+        fn top(&mut self) -> &mut ScreenState {
+            return self.stack[self.depth - 1].as_mut().unwrap();
+        }
+
+        fn apply(&mut self, transition: Transition) {
+            match transition {
+                Transition::None => return,
+                Transition::Redraw => {}
+                Transition::Push(screen) => {
+                    // Running out of room is a bug, but it's better to stay on
+                    // the current screen than to fall over.
+                    if self.depth == MAX_DEPTH {
+                        return;
+                    }
+                    self.stack[self.depth] = Some(screen);
+                    self.depth += 1;
+                    self.wipe_if_wanted();
+                }
+                Transition::Replace(screen) => {
+                    self.stack[self.depth - 1] = Some(screen);
+                    self.wipe_if_wanted();
+                }
+                Transition::Pop => {
+                    // The root screen stays put.
+                    if self.depth == 1 {
+                        return;
+                    }
+                    self.depth -= 1;
+                    self.stack[self.depth] = None;
+                }
+            }
+            self.dirty = true;
+        }
+    }
+}
+
+pub mod widgets {
+    use crate::font::SmallFont;
+    use crate::input::{ButtonStates, Buttons};
+    use core::ops::Range;
+    use embedded_graphics::fonts::{Font, Text};
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::primitives::Rectangle;
+    use embedded_graphics::style::{PrimitiveStyle, TextStyle};
+    use embedded_layout::align::{horizontal, vertical, Align};
+    use embedded_text::{style::TextBoxStyleBuilder, TextBox};
+
+    fn white_text() -> TextStyle<BinaryColor, SmallFont> {
+        return TextStyle::new(SmallFont, BinaryColor::On);
+    }
+
+    // Big enough for any u32.
+    pub const MAX_DIGITS: usize = 10;
+
+    // A number written out into its own buffer, since there's no allocator.
+    // Padding only ever adds characters, so a number that's too wide for the
+    // space it was given comes out whole rather than chopped.
+    pub struct Number {
+        buf: [u8; MAX_DIGITS],
+        start: usize,
+    }
+
+    impl Number {
+        pub fn new(n: u32) -> Number {
+            return Number::format(n, 0, b' ', true);
+        }
+
+        // Pushed up against the right edge of `width` characters.
+        pub fn right(n: u32, width: usize) -> Number {
+            return Number::format(n, width, b' ', true);
+        }
+
+        // Takes up `width` characters whatever its length, so whatever comes
+        // after it stays put.
+        pub fn left(n: u32, width: usize) -> Number {
+            return Number::format(n, width, b' ', false);
+        }
+
+        // Like the 007 in the pokedex.
+        pub fn zeros(n: u32, width: usize) -> Number {
+            return Number::format(n, width, b'0', true);
+        }
+
+        fn format(mut n: u32, width: usize, fill: u8, right: bool) -> Number {
+            let width = width.min(MAX_DIGITS);
+            let mut buf = [fill; MAX_DIGITS];
+            let mut start = MAX_DIGITS;
+            loop {
+                start -= 1;
+                buf[start] = b'0' + (n % 10) as u8;
+                n /= 10;
+                if n == 0 {
+                    break;
+                }
+            }
+            if right {
+                start = start.min(MAX_DIGITS - width);
+                return Number { buf, start };
+            }
+            // Left aligned means shuffling the digits down to make room for
+            // spaces on the end.
+            let digits = MAX_DIGITS - start;
+            let padded = digits.max(width);
+            buf.copy_within(start.., MAX_DIGITS - padded);
+            for c in buf[MAX_DIGITS - padded + digits..].iter_mut() {
+                *c = b' ';
+            }
+            return Number {
+                buf,
+                start: MAX_DIGITS - padded,
+            };
+        }
+
+        pub fn as_str(&self) -> &str {
+            return core::str::from_utf8(&self.buf[self.start..]).unwrap_or("");
+        }
+    }
+
+    // An outlined bar filled in from the left, for hp and xp.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Bar {
+        bounds: Rectangle,
+        value: u32,
+        max: u32,
+        // Whether anything above zero always shows at least a pixel.
+        sliver: bool,
+    }
+
+    impl Bar {
+        // `width` is the inside of the bar, so the outline adds two.
+        pub fn hp(top_left: Point, width: u32, current: u16, max: u16) -> Bar {
+            return Bar {
+                bounds: Rectangle::new(top_left, top_left + Point::new(width as i32 + 1, 4)),
+                value: current.into(),
+                max: max.into(),
+                sliver: true,
+            };
+        }
+
+        // How far through the current level it is, from the xp totals for
+        // this level and the next.
+        pub fn xp(top_left: Point, width: u32, xp: u32, this_level: u32, next_level: u32) -> Bar {
+            return Bar {
+                bounds: Rectangle::new(top_left, top_left + Point::new(width as i32 + 1, 3)),
+                value: xp.saturating_sub(this_level),
+                max: next_level.saturating_sub(this_level),
+                sliver: false,
+            };
+        }
+
+        fn filled(&self) -> u32 {
+            let width = (self.bounds.size().width - 2) as u64;
+            let mut filled = (width * self.value as u64 / (self.max as u64).max(1)) as u32;
+            if self.sliver && self.value > 0 {
+                filled = filled.max(1);
+            }
+            return filled.min(width as u32);
+        }
+    }
+
+    impl Dimensions for Bar {
+        fn top_left(&self) -> Point {
+            return self.bounds.top_left;
+        }
+
+        fn bottom_right(&self) -> Point {
+            return self.bounds.bottom_right;
+        }
+
+        fn size(&self) -> Size {
+            return self.bounds.size();
+        }
+    }
+
+    impl Transform for Bar {
+        fn translate(&self, by: Point) -> Bar {
+            return Bar {
+                bounds: self.bounds.translate(by),
+                ..*self
+            };
+        }
+
+        fn translate_mut(&mut self, by: Point) -> &mut Bar {
+            self.bounds.translate_mut(by);
+            return self;
+        }
+    }
+
+    impl Drawable<BinaryColor> for Bar {
+        fn draw<D: DrawTarget<BinaryColor>>(self, display: &mut D) -> Result<(), D::Error> {
+            self.bounds
+                .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                .draw(display)?;
+            let filled = self.filled();
+            if filled == 0 {
+                return Ok(());
+            }
+            let inner = self.bounds.top_left + Point::new(1, 1);
+            let bottom = self.bounds.bottom_right.y - 1;
+            return Rectangle::new(inner, Point::new(inner.x + filled as i32 - 1, bottom))
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                .draw(display);
+        }
+    }
+
+    pub const ROW_HEIGHT: i32 = 7;
+
+    // The cursor and which rows are on screen for a list that's longer than
+    // the space it's got. It scrolls a row at a time once the cursor reaches
+    // the top or bottom, and the length is passed in since it's usually
+    // counted from the save on the fly.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct ScrollList {
+        cursor: usize,
+        first: usize,
+        rows: usize,
+    }
+
+    impl ScrollList {
+        pub fn new(rows: usize) -> ScrollList {
+            return ScrollList {
+                cursor: 0,
+                first: 0,
+                rows: rows.max(1),
+            };
+        }
+
+        pub fn cursor(&self) -> usize {
+            return self.cursor;
+        }
+
+        // Things can disappear from under the cursor, like the last of an
+        // item getting used up.
+        pub fn clamp(&mut self, len: usize) {
+            self.cursor = self.cursor.min(len.saturating_sub(1));
+            self.scroll_to_cursor();
+        }
+
+        // Up and down wrap around. Returns whether the cursor moved.
+        pub fn handle_input(&mut self, buttons: &mut ButtonStates, len: usize) -> bool {
+            if len == 0 {
+                return false;
+            }
+            self.clamp(len);
+            let before = self.cursor;
+            if buttons.consume(Buttons::UP) {
+                self.cursor = (self.cursor + len - 1) % len;
+            } else if buttons.consume(Buttons::DOWN) {
+                self.cursor = (self.cursor + 1) % len;
+            }
+            self.scroll_to_cursor();
+            return self.cursor != before;
+        }
+
+        fn scroll_to_cursor(&mut self) {
+            if self.cursor < self.first {
+                self.first = self.cursor;
+            } else if self.cursor >= self.first + self.rows {
+                self.first = self.cursor + 1 - self.rows;
+            }
+        }
+
+        pub fn visible(&self, len: usize) -> Range<usize> {
+            let first = self.first.min(len);
+            return first..(first + self.rows).min(len);
+        }
+
+        // Draws the cursor, arrows on the right when there's more above or
+        // below, and hands each visible row to `draw_row` with where its
+        // text goes.
+        pub fn draw<D, F>(
+            &self,
+            display: &mut D,
+            top_left: Point,
+            width: i32,
+            len: usize,
+            mut draw_row: F,
+        ) where
+            D: DrawTarget<BinaryColor>,
+            F: FnMut(&mut D, usize, Point),
+        {
+            let visible = self.visible(len);
+            for (row, i) in visible.clone().enumerate() {
+                let y = top_left.y + row as i32 * ROW_HEIGHT;
+                if i == self.cursor {
+                    Text::new("▶", Point::new(top_left.x, y))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                }
+                draw_row(display, i, Point::new(top_left.x + 6, y));
+            }
+            let arrow_x = top_left.x + width - SmallFont::CHARACTER_SIZE.width as i32;
+            if visible.start > 0 {
+                Text::new("↑", Point::new(arrow_x, top_left.y))
+                    .into_styled(white_text())
+                    .draw(display)
+                    .ok();
+            }
+            if visible.end < len {
+                let y = top_left.y + (self.rows as i32 - 1) * ROW_HEIGHT;
+                Text::new("↓", Point::new(arrow_x, y))
+                    .into_styled(white_text())
+                    .draw(display)
+                    .ok();
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum MenuEvent {
+        None,
+        Moved,
+        Selected(usize),
+        Cancelled,
+    }
+
+    // A scrolling list of fixed choices.
+    pub struct Menu<T: 'static> {
+        items: &'static [T],
+        label: fn(&T) -> &'static str,
+        list: ScrollList,
+    }
+
+    impl<T> Menu<T> {
+        pub fn new(items: &'static [T], label: fn(&T) -> &'static str, rows: usize) -> Menu<T> {
+            return Menu {
+                items,
+                label,
+                list: ScrollList::new(rows),
+            };
+        }
+
+        pub fn selected(&self) -> &T {
+            return &self.items[self.list.cursor()];
+        }
+
+        pub fn handle_input(&mut self, buttons: &mut ButtonStates) -> MenuEvent {
+            if self.list.handle_input(buttons, self.items.len()) {
+                return MenuEvent::Moved;
+            }
+            if buttons.consume(Buttons::A) {
+                return MenuEvent::Selected(self.list.cursor());
+            }
+            if buttons.consume(Buttons::B) {
+                return MenuEvent::Cancelled;
+            }
+            return MenuEvent::None;
+        }
+
+        pub fn draw<D: DrawTarget<BinaryColor>>(
+            &self,
+            display: &mut D,
+            top_left: Point,
+            width: i32,
+        ) {
+            let (items, label) = (self.items, self.label);
+            self.list.draw(
+                display,
+                top_left,
+                width,
+                items.len(),
+                |display, i, position| {
+                    Text::new(label(&items[i]), position)
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                },
+            );
+        }
+    }
+
+    // Only lets through pixels inside the area, so text that's scrolled off
+    // the top of a box doesn't end up drawn above it.
+    struct Clipped<'a, D> {
+        display: &'a mut D,
+        area: Rectangle,
+    }
+
+    impl<'a, D: DrawTarget<BinaryColor>> DrawTarget<BinaryColor> for Clipped<'a, D> {
+        type Error = D::Error;
+
+        fn draw_pixel(&mut self, pixel: Pixel<BinaryColor>) -> Result<(), D::Error> {
+            let Pixel(point, _) = pixel;
+            if point.x < self.area.top_left.x
+                || point.y < self.area.top_left.y
+                || point.x > self.area.bottom_right.x
+                || point.y > self.area.bottom_right.y
+            {
+                return Ok(());
+            }
+            return self.display.draw_pixel(pixel);
+        }
+
+        fn size(&self) -> Size {
+            return self.display.size();
+        }
+    }
+
+    // Word wrapped text shown a box at a time. The page lives with whoever's
+    // showing it, since the text usually does too.
+    pub struct PagedText<'a, F: Font + Copy> {
+        text: &'a str,
+        bounds: Rectangle,
+        style: TextStyle<BinaryColor, F>,
+        page: u32,
+    }
+
+    impl<'a, F: Font + Copy> PagedText<'a, F> {
+        pub fn new(
+            text: &'a str,
+            bounds: Rectangle,
+            style: TextStyle<BinaryColor, F>,
+        ) -> PagedText<'a, F> {
+            return PagedText {
+                text,
+                bounds,
+                style,
+                page: 0,
+            };
+        }
+
+        pub fn with_page(self, page: u32) -> PagedText<'a, F> {
+            return PagedText {
+                page: page.min(self.pages() - 1),
+                ..self
+            };
+        }
+
+        fn lines_per_page(&self) -> u32 {
+            return (self.bounds.size().height / F::CHARACTER_SIZE.height).max(1);
+        }
+
+        pub fn pages(&self) -> u32 {
+            let height = TextBoxStyleBuilder::from_text_style(self.style)
+                .build()
+                .measure_text_height(self.text, self.bounds.size().width);
+            let lines = (height / F::CHARACTER_SIZE.height).max(1);
+            return lines.div_ceil(self.lines_per_page());
+        }
+
+        pub fn is_last_page(&self) -> bool {
+            return self.page + 1 >= self.pages();
+        }
+
+        // Earlier pages are laid out above the box and clipped off, so the
+        // wrapping is always worked out from the start of the text.
+        pub fn draw<D: DrawTarget<BinaryColor>>(&self, display: &mut D) {
+            let scrolled = (self.page * self.lines_per_page() * F::CHARACTER_SIZE.height) as i32;
+            let top_left = self.bounds.top_left - Point::new(0, scrolled);
+            let mut clipped = Clipped {
+                display,
+                area: self.bounds,
+            };
+            TextBox::new(
+                self.text,
+                Rectangle::new(top_left, self.bounds.bottom_right),
+            )
+            .into_styled(TextBoxStyleBuilder::from_text_style(self.style).build())
+            .draw(&mut clipped)
+            .ok();
+            if !self.is_last_page() {
+                let marker = self.bounds.bottom_right
+                    - Point::new(
+                        SmallFont::CHARACTER_SIZE.width as i32 - 1,
+                        SmallFont::CHARACTER_SIZE.height as i32 - 1,
+                    );
+                Text::new("▼", marker)
+                    .into_styled(white_text())
+                    .draw(clipped.display)
+                    .ok();
+            }
+        }
+    }
+
+    // Labels down the left of a column with their values lined up against
+    // its right edge.
+    pub struct StatTable {
+        top_left: Point,
+        width: u32,
+        row_height: i32,
+    }
+
+    impl StatTable {
+        pub fn new(top_left: Point, width: u32, row_height: i32) -> StatTable {
+            return StatTable {
+                top_left,
+                width,
+                row_height,
+            };
+        }
+
+        pub fn draw<D: DrawTarget<BinaryColor>>(&self, display: &mut D, rows: &[(&str, &str)]) {
+            for (i, (label, value)) in rows.iter().enumerate() {
+                let top_left = self.top_left + Point::new(0, i as i32 * self.row_height);
+                let row = Rectangle::new(
+                    top_left,
+                    top_left
+                        + Point::new(
+                            self.width as i32 - 1,
+                            SmallFont::CHARACTER_SIZE.height as i32 - 1,
+                        ),
+                );
+                Text::new(label, top_left)
+                    .into_styled(white_text())
+                    .draw(display)
+                    .ok();
+                Text::new(value, top_left)
+                    .into_styled(white_text())
+                    .align_to(&row, horizontal::Right, vertical::Top)
+                    .draw(display)
+                    .ok();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn zero_still_gets_a_digit() {
+            assert_eq!(Number::new(0).as_str(), "0");
+            assert_eq!(Number::right(0, 3).as_str(), "  0");
+            assert_eq!(Number::left(0, 3).as_str(), "0  ");
+            assert_eq!(Number::zeros(0, 3).as_str(), "000");
+        }
+
+        #[test]
+        fn long_numbers_come_out_whole() {
+            assert_eq!(Number::new(1234).as_str(), "1234");
+            assert_eq!(Number::right(65535, 3).as_str(), "65535");
+            assert_eq!(Number::left(1234, 3).as_str(), "1234");
+            assert_eq!(Number::zeros(1234, 3).as_str(), "1234");
+            assert_eq!(Number::zeros(7, 3).as_str(), "007");
+            assert_eq!(Number::new(u32::MAX).as_str(), "4294967295");
+            // Asking for more room than there is gets as much as there is.
+            assert_eq!(Number::right(5, 20).as_str(), "         5");
+            assert_eq!(Number::left(5, 20).as_str(), "5         ");
+        }
+    }
+}
+
+pub mod screens {
+    use crate::ai::Difficulty;
+    use crate::animation::{Blink, Flash, Shake, Slide, Sparkle, Tween};
+    use crate::app::{Context, Screen, ScreenState, Transition};
+    use crate::battle::{Battle, Choice, Messages, MOVE_SLOTS};
+    use crate::encounter::{is_rare, wild_pokemon};
+    use crate::evolution::{check_level_up, check_trade, evolve};
+    use crate::font::{LargeFont, SmallFont};
+    use crate::gym::{
+        badge_name, generate_gym, is_gym, level_cap, max_wild_level, trainer_difficulty, Gym,
+    };
+    use crate::input::{ButtonStates, Buttons};
+    use crate::items::{teach_move, use_item, ItemError, ItemOutcome, UseContext};
+    use crate::led::{type_colour, Effect, ALERT_TICKS, LOW_HP, RARE_ALERT, SHINY_ALERT};
+    use crate::link::{BattlePhase, LinkBattle, Outcome, Phase, SaveServer, Trade};
+    use crate::pokedex::{name_str, Pokemon, SPECIES_COUNT};
+    use crate::rom::{read_item, read_move, read_pokemon, read_sprite, ReadError};
+    use crate::save::{BagSlot, BoxError, Save, Settings, TextSpeed, BAG_SIZE, PARTY_SIZE};
+    use crate::sprite::PackedSprite;
+    use crate::time::{TimeOfDay, Timestamp};
+    use crate::trainer::{fingerprint, generate_trainer, Trainer};
+    use crate::widgets::{Bar, Menu, MenuEvent, Number, ScrollList, StatTable, ROW_HEIGHT};
+    use embedded_graphics::fonts::Text;
+    use embedded_graphics::image::{Image, IntoPixelIter};
+    use embedded_graphics::primitives::Rectangle;
+    use embedded_graphics::style::{
+        PrimitiveStyle, PrimitiveStyleBuilder, TextStyle, TextStyleBuilder,
+    };
+    use embedded_graphics::{egline, egrectangle, egtext};
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+    fn white_solid() -> PrimitiveStyle<BinaryColor> {
+        return PrimitiveStyleBuilder::new()
+            .fill_color(BinaryColor::On)
+            .build();
+    }
+
+    fn white_outline() -> PrimitiveStyle<BinaryColor> {
+        return PrimitiveStyleBuilder::new()
+            .stroke_color(BinaryColor::On)
+            .stroke_width(1)
+            .build();
+    }
+
+    fn white_text() -> TextStyle<BinaryColor, SmallFont> {
+        return TextStyleBuilder::new(SmallFont)
+            .text_color(BinaryColor::On)
+            .build();
+    }
+
+    fn black_text() -> TextStyle<BinaryColor, SmallFont> {
+        return TextStyleBuilder::new(SmallFont)
+            .text_color(BinaryColor::Off)
+            .build();
+    }
+
+    fn white_large_text() -> TextStyle<BinaryColor, LargeFont> {
+        return TextStyleBuilder::new(LargeFont)
+            .text_color(BinaryColor::On)
+            .build();
+    }
+
+    // "HH:MM", in a buffer the caller holds on to.
+    fn format_time(hour: u8, minute: u8, buf: &mut [u8; 5]) -> &str {
+        *buf = [
+            b'0' + hour / 10,
+            b'0' + hour % 10,
+            b':',
+            b'0' + minute / 10,
+            b'0' + minute % 10,
+        ];
+        return core::str::from_utf8(buf).unwrap_or("");
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum MenuItem {
+        Scan,
+        Pokedex,
+        Party,
+        Boxes,
+        Bag,
+        Trade,
+        LinkBattle,
+        TrainerCard,
+        Settings,
+    }
+
+    impl MenuItem {
+        pub fn name(&self) -> &'static str {
+            match self {
+                MenuItem::Scan => "SCAN",
+                MenuItem::Pokedex => "POKéDEX",
+                MenuItem::Party => "PARTY",
+                MenuItem::Boxes => "BOXES",
+                MenuItem::Bag => "BAG",
+                MenuItem::Trade => "TRADE",
+                MenuItem::LinkBattle => "LINK BATTLE",
+                MenuItem::TrainerCard => "TRAINER CARD",
+                MenuItem::Settings => "SETTINGS",
+            }
+        }
+    }
+
+    // Trainer card sits above the link options so the scroll arrow never
+    // lands on its long name.
+    const MENU_ITEMS: [MenuItem; 9] = [
+        MenuItem::Scan,
+        MenuItem::Pokedex,
+        MenuItem::Party,
+        MenuItem::Boxes,
+        MenuItem::Bag,
+        MenuItem::TrainerCard,
+        MenuItem::Trade,
+        MenuItem::LinkBattle,
+        MenuItem::Settings,
+    ];
+
+    // As many as fit between the header and the bottom of the screen.
+    const MENU_ROWS: usize = 8;
+    // How many networks a scan looks at, and one scan in this many finds a
+    // trainer or a gym rather than a wild pokemon.
+    const MAX_NETWORKS: usize = 8;
+    const TRAINER_ODDS: u32 = 4;
+
+    pub struct MenuScreen {
+        menu: Menu<MenuItem>,
+        // Counts up every tick. How long the player takes to press A is the
+        // only randomness we've got.
+        ticks: u32,
+        // Which minute the clock in the corner is showing.
+        shown_minute: Option<u32>,
+        save_server: SaveServer,
+    }
+
+    impl Default for MenuScreen {
+        fn default() -> MenuScreen {
+            return MenuScreen::new();
+        }
+    }
+
+    impl MenuScreen {
+        pub fn new() -> MenuScreen {
+            return MenuScreen {
+                menu: Menu::new(&MENU_ITEMS, MenuItem::name, MENU_ROWS),
+                ticks: 0,
+                shown_minute: None,
+                save_server: SaveServer::new(),
+            };
+        }
+
+        // Every so often one of the networks around has a trainer or a gym on
+        // it, as long as they haven't been beaten already. Otherwise it's
+        // something wild.
+        fn scan(&self, ctx: &mut Context) -> Transition {
+            let badges = ctx.save.data.badge_count();
+            let mut found = [[0u8; 6]; MAX_NETWORKS];
+            let count = ctx.scanner.scan(&mut found);
+            if count > 0 && self.ticks.is_multiple_of(TRAINER_ODDS) {
+                let id = fingerprint(&found[(self.ticks / TRAINER_ODDS) as usize % count]);
+                if is_gym(id) {
+                    let gym = generate_gym(id, badges);
+                    if !ctx.save.data.defeated_trainers.contains(gym.leader_id()) {
+                        return Transition::Push(ScreenState::Gym(GymScreen::new(
+                            gym,
+                            !self.ticks,
+                        )));
+                    }
+                } else if !ctx.save.data.defeated_trainers.contains(id) {
+                    let battle = generate_trainer(id, max_wild_level(badges), ctx.storage)
+                        .and_then(|trainer| {
+                            BattleScreen::trainer(
+                                trainer,
+                                trainer_difficulty(badges),
+                                !self.ticks,
+                                ctx,
+                            )
+                        });
+                    match battle {
+                        Ok(b) => return Transition::Push(ScreenState::Battle(b)),
+                        Err(_) => return Transition::None,
+                    }
+                }
+            }
+            let time = ctx
+                .save
+                .data
+                .now()
+                .map_or(TimeOfDay::Day, |t| t.time_of_day());
+            let battle = wild_pokemon(self.ticks, badges, time, ctx.storage)
+                .and_then(|pokemon| BattleScreen::wild(pokemon, !self.ticks, ctx));
+            match battle {
+                Ok(b) => return Transition::Push(ScreenState::Battle(b)),
+                Err(_) => return Transition::None,
+            }
+        }
+
+        fn select(&self, ctx: &mut Context) -> Transition {
+            match self.menu.selected() {
+                MenuItem::Scan => return self.scan(ctx),
+                MenuItem::Pokedex => {
+                    return Transition::Push(ScreenState::Pokedex(PokedexScreen::new(1)))
+                }
+                MenuItem::Party => return Transition::Push(ScreenState::Party(PartyScreen::new())),
+                MenuItem::Boxes => return Transition::Push(ScreenState::Box(BoxScreen::new())),
+                MenuItem::Bag => return Transition::Push(ScreenState::Bag(BagScreen::new())),
+                MenuItem::Trade => {
+                    return Transition::Push(ScreenState::Trade(TradeScreen::new(self.ticks)))
+                }
+                MenuItem::LinkBattle => {
+                    return Transition::Push(ScreenState::LinkBattle(LinkBattleScreen::new(
+                        self.ticks,
+                    )))
+                }
+                MenuItem::TrainerCard => {
+                    return Transition::Push(ScreenState::TrainerCard(TrainerCardScreen {}))
+                }
+                MenuItem::Settings => {
+                    return Transition::Push(ScreenState::Settings(SettingsScreen::new()))
+                }
+            }
+        }
+    }
+
+    impl Screen for MenuScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            match self.menu.handle_input(buttons) {
+                MenuEvent::Moved => return Transition::Redraw,
+                MenuEvent::Selected(_) => return self.select(ctx),
+                // It's the bottom of the stack, so there's nowhere to go back to.
+                MenuEvent::Cancelled | MenuEvent::None => return Transition::None,
+            }
+        }
+
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            self.ticks = self.ticks.wrapping_add(1);
+            self.save_server.poll(ctx).ok();
+            let minute = ctx.save.data.now().map(|t| t.0 / 60);
+            if minute != self.shown_minute {
+                return Transition::Redraw;
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "MENU", top_left = (1, 1), style = black_text())
+                .draw(display)
+                .ok();
+            let now = ctx.save.data.now();
+            self.shown_minute = now.map(|t| t.0 / 60);
+            if let Some(now) = now {
+                let name = now.time_of_day().name();
+                let mut buf = [0; 5];
+                egtext!(
+                    text = name,
+                    top_left = (103 - name.len() as i32 * 4, 1),
+                    style = black_text()
+                )
+                .draw(display)
+                .ok();
+                egtext!(
+                    text = format_time(now.hour(), now.minute(), &mut buf),
+                    top_left = (107, 1),
+                    style = black_text()
+                )
+                .draw(display)
+                .ok();
+            }
+
+            self.menu.draw(display, Point::new(2, 8), 54);
+
+            egrectangle!(
+                top_left = (92, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "A:SELECT", top_left = (94, 58), style = black_text())
+                .draw(display)
+                .ok();
+        }
+    }
+
+    const SETTINGS_ROWS: usize = 6;
+    const SLEEP_OPTIONS: [u8; 5] = [0, 1, 2, 5, 10];
+
+    pub struct SettingsScreen {
+        cursor: usize,
+    }
+
+    impl Default for SettingsScreen {
+        fn default() -> SettingsScreen {
+            return SettingsScreen::new();
+        }
+    }
+
+    impl SettingsScreen {
+        pub fn new() -> SettingsScreen {
+            return SettingsScreen { cursor: 0 };
+        }
+
+        // Nudges the setting under the cursor one step in either direction.
+        // Returns whether anything actually changed.
+        fn adjust(&self, settings: &mut Settings, forward: bool) -> bool {
+            let before = *settings;
+            match self.cursor {
+                0 => {
+                    settings.text_speed = match (settings.text_speed, forward) {
+                        (TextSpeed::Slow, true) => TextSpeed::Medium,
+                        (TextSpeed::Medium, true) => TextSpeed::Fast,
+                        (TextSpeed::Medium, false) => TextSpeed::Slow,
+                        (TextSpeed::Fast, false) => TextSpeed::Medium,
+                        (speed, _) => speed,
+                    }
+                }
+                1 => {
+                    settings.contrast = if forward {
+                        settings.contrast.saturating_add(0x10) | 0x0f
+                    } else {
+                        settings.contrast.saturating_sub(0x10).max(0x0f)
+                    }
+                }
+                2 => settings.inverted = !settings.inverted,
+                3 => {
+                    settings.led_brightness = if forward {
+                        settings.led_brightness.saturating_add(0x20)
+                    } else {
+                        settings.led_brightness.saturating_sub(0x20)
+                    }
+                }
+                4 => settings.battle_animations = !settings.battle_animations,
+                _ => {
+                    let current = SLEEP_OPTIONS
+                        .iter()
+                        .position(|m| *m == settings.auto_sleep_minutes)
+                        .unwrap_or(0);
+                    let next = if forward {
+                        (current + 1).min(SLEEP_OPTIONS.len() - 1)
+                    } else {
+                        current.saturating_sub(1)
+                    };
+                    settings.auto_sleep_minutes = SLEEP_OPTIONS[next];
+                }
+            }
+            return *settings != before;
+        }
+    }
+
+    impl Screen for SettingsScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::UP) {
+                self.cursor = (self.cursor + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::DOWN) {
+                self.cursor = (self.cursor + 1) % SETTINGS_ROWS;
+                return Transition::Redraw;
+            }
+            let forward = if buttons.consume(Buttons::RIGHT) || buttons.consume(Buttons::A) {
+                true
+            } else if buttons.consume(Buttons::LEFT) {
+                false
+            } else if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            } else {
+                return Transition::None;
+            };
+
+            if !self.adjust(&mut ctx.save.data.settings, forward) {
+                return Transition::None;
+            }
+            // Settings are saved as soon as they change, there's no "apply".
+            ctx.persist().ok();
+            return Transition::Redraw;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            let settings = ctx.save.data.settings;
+
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "SETTINGS", top_left = (1, 1), style = black_text())
+                .draw(display)
+                .ok();
+
+            let contrast = Number::new(settings.contrast.into());
+            let brightness = Number::new(settings.led_brightness.into());
+            let sleep = Number::new(settings.auto_sleep_minutes.into());
+            let rows: [(&str, &str, &str); SETTINGS_ROWS] = [
+                ("TEXT SPEED", settings.text_speed.name(), ""),
+                ("CONTRAST", contrast.as_str(), ""),
+                ("INVERT", on_off(settings.inverted), ""),
+                match settings.led_brightness {
+                    0 => ("LED", "OFF", ""),
+                    _ => ("LED", brightness.as_str(), ""),
+                },
+                ("ANIMATIONS", on_off(settings.battle_animations), ""),
+                match settings.auto_sleep_minutes {
+                    0 => ("AUTO SLEEP", "NEVER", ""),
+                    _ => ("AUTO SLEEP", sleep.as_str(), "MIN"),
+                },
+            ];
+
+            for (i, (label, value, unit)) in rows.iter().enumerate() {
+                let y = 9 + (i as i32) * 7;
+                if i == self.cursor {
+                    egtext!(text = "▶", top_left = (2, y), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                egtext!(text = label, top_left = (8, y), style = white_text())
+                    .draw(display)
+                    .ok();
+                egtext!(text = value, top_left = (80, y), style = white_text())
+                    .draw(display)
+                    .ok();
+                egtext!(text = unit, top_left = (96, y), style = white_text())
+                    .draw(display)
+                    .ok();
+            }
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "←→:CHANGE B:BACK",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    fn on_off(value: bool) -> &'static str {
+        return if value { "ON" } else { "OFF" };
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum PartyMode {
+        View,
+        // Picking who to use an item from the bag on.
+        UseItem(u8),
+        // Their moves are full, so picking which one makes way for the TM.
+        Forget { item_id: u8, move_id: u16 },
+    }
+
+    pub struct PartyScreen {
+        cursor: usize,
+        move_cursor: usize,
+        mode: PartyMode,
+        message: Option<&'static str>,
+    }
+
+    impl Default for PartyScreen {
+        fn default() -> PartyScreen {
+            return PartyScreen::new();
+        }
+    }
+
+    impl PartyScreen {
+        pub fn new() -> PartyScreen {
+            return PartyScreen {
+                cursor: 0,
+                move_cursor: 0,
+                mode: PartyMode::View,
+                message: None,
+            };
+        }
+
+        pub fn use_item(item_id: u8) -> PartyScreen {
+            return PartyScreen {
+                cursor: 0,
+                move_cursor: 0,
+                mode: PartyMode::UseItem(item_id),
+                message: None,
+            };
+        }
+
+        fn apply_item(&mut self, item_id: u8, ctx: &mut Context) -> Transition {
+            let item = match read_item(item_id, ctx.storage) {
+                Ok(i) => i,
+                Err(ReadError(text)) => {
+                    self.message = Some(text);
+                    return Transition::Redraw;
+                }
+            };
+            let cap = level_cap(ctx.save.data.badge_count());
+            let pokemon = match &mut ctx.save.data.party[self.cursor] {
+                Some(p) => p,
+                None => return Transition::None,
+            };
+            let outcome = match use_item(&item, pokemon, UseContext::Field, cap, ctx.storage) {
+                Ok(outcome) => outcome,
+                Err(ItemError(text)) => {
+                    self.message = Some(text);
+                    return Transition::Redraw;
+                }
+            };
+            // Stones can't be given back, so they're used up even if the
+            // evolution doesn't go through. Same as the real thing.
+            let evolution = match outcome {
+                ItemOutcome::Evolving(into) => Some((into, false)),
+                ItemOutcome::LevelledUp(_) => check_level_up(pokemon, ctx.storage)
+                    .ok()
+                    .flatten()
+                    .map(|into| (into, true)),
+                ItemOutcome::MovesFull(move_id) => {
+                    self.mode = PartyMode::Forget { item_id, move_id };
+                    self.move_cursor = 0;
+                    self.message = None;
+                    return Transition::Redraw;
+                }
+                _ => None,
+            };
+            self.used_up(item_id, outcome, ctx);
+            match evolution {
+                Some((into, cancellable)) => {
+                    return Transition::Push(ScreenState::Evolution(EvolutionScreen::new(
+                        self.cursor,
+                        ctx.save,
+                        into,
+                        cancellable,
+                    )))
+                }
+                None => return Transition::Redraw,
+            }
+        }
+
+        fn used_up(&mut self, item_id: u8, outcome: ItemOutcome, ctx: &mut Context) {
+            ctx.save.data.remove_item(item_id);
+            ctx.persist().ok();
+            self.message = Some(outcome.message());
+
+            // Nothing left to use, so there's no point picking anyone else.
+            self.mode = match ctx.save.data.quantity(item_id) {
+                0 => PartyMode::View,
+                _ => PartyMode::UseItem(item_id),
+            };
+        }
+
+        // Their moves, with the one the TM would teach underneath.
+        fn render_forget<D: DrawTarget<BinaryColor>>(
+            &self,
+            move_id: u16,
+            display: &mut D,
+            ctx: &mut Context,
+        ) {
+            let moves = match &ctx.save.data.party[self.cursor] {
+                Some(p) => p.moves,
+                None => return,
+            };
+            for (i, id) in moves.iter().enumerate() {
+                let y = 9 + (i as i32) * 8;
+                if i == self.move_cursor {
+                    egtext!(text = "▶", top_left = (2, y), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                if let Some(Ok(m)) = id.map(|id| read_move(id, ctx.storage)) {
+                    egtext!(
+                        text = name_str(&m.name),
+                        top_left = (8, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+            }
+            egtext!(text = "NEW", top_left = (2, 45), style = white_text())
+                .draw(display)
+                .ok();
+            if let Ok(m) = read_move(move_id, ctx.storage) {
+                egtext!(
+                    text = name_str(&m.name),
+                    top_left = (20, 45),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+            }
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "A:FORGET   B:KEEP",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+
+        fn forget_input(
+            &mut self,
+            item_id: u8,
+            move_id: u16,
+            buttons: &mut ButtonStates,
+            ctx: &mut Context,
+        ) -> Transition {
+            // Backing out keeps the TM, and the moves they've already got.
+            if buttons.consume(Buttons::B) {
+                self.mode = PartyMode::UseItem(item_id);
+                self.message = Some("DIDN'T LEARN IT");
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::UP) {
+                self.move_cursor = (self.move_cursor + MOVE_SLOTS - 1) % MOVE_SLOTS;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::DOWN) {
+                self.move_cursor = (self.move_cursor + 1) % MOVE_SLOTS;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::A) {
+                let pokemon = match &mut ctx.save.data.party[self.cursor] {
+                    Some(p) => p,
+                    None => return Transition::None,
+                };
+                let outcome = teach_move(pokemon, self.move_cursor, move_id);
+                self.used_up(item_id, outcome, ctx);
+                return Transition::Redraw;
+            }
+            return Transition::None;
+        }
+    }
+
+    fn party_count(save: &Save) -> usize {
+        return save.data.party.iter().filter(|p| p.is_some()).count();
+    }
+
+    impl Screen for PartyScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if let PartyMode::Forget { item_id, move_id } = self.mode {
+                return self.forget_input(item_id, move_id, buttons, ctx);
+            }
+            let count = party_count(ctx.save);
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            if count == 0 {
+                return Transition::None;
+            }
+            if buttons.consume(Buttons::UP) {
+                self.cursor = (self.cursor + count - 1) % count;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::DOWN) {
+                self.cursor = (self.cursor + 1) % count;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::A) {
+                match self.mode {
+                    PartyMode::View => {
+                        return Transition::Push(ScreenState::Summary(SummaryScreen::new(
+                            self.cursor,
+                        )))
+                    }
+                    PartyMode::UseItem(item_id) => return self.apply_item(item_id, ctx),
+                    PartyMode::Forget { .. } => return Transition::None,
+                }
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            let title = match (self.message, self.mode) {
+                (Some(message), _) => message,
+                (None, PartyMode::UseItem(_)) => "USE ON WHICH \u{e000}\u{e001}?",
+                (None, PartyMode::View) => "PARTY",
+                (None, PartyMode::Forget { .. }) => "FORGET WHICH MOVE?",
+            };
+            egtext!(text = title, top_left = (1, 1), style = black_text())
+                .draw(display)
+                .ok();
+
+            if let PartyMode::Forget { move_id, .. } = self.mode {
+                self.render_forget(move_id, display, ctx);
+                return;
+            }
+
+            if party_count(ctx.save) == 0 {
+                egtext!(text = "NO POKéMON", top_left = (8, 9), style = white_text())
+                    .draw(display)
+                    .ok();
+            }
+            for i in 0..PARTY_SIZE {
+                let pokemon = match &ctx.save.data.party[i] {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let y = 9 + (i as i32) * 8;
+                if i == self.cursor {
+                    egtext!(text = "▶", top_left = (2, y), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                if let Ok(species) = read_pokemon(pokemon.species_id, ctx.storage) {
+                    egtext!(
+                        text = pokemon.name(&species),
+                        top_left = (8, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                egtext!(text = "LV", top_left = (64, y), style = white_text())
+                    .draw(display)
+                    .ok();
+                egtext!(
+                    text = Number::new(pokemon.level.into()).as_str(),
+                    top_left = (74, y),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+                if let PartyMode::UseItem(_) = self.mode {
+                    egtext!(
+                        text = Number::new(pokemon.current_hp.into()).as_str(),
+                        top_left = (92, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                    egtext!(
+                        text = Number::new(pokemon.hp.value.into()).as_str(),
+                        top_left = (110, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+            }
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = match self.mode {
+                    PartyMode::View => "A:SUMMARY  B:BACK",
+                    PartyMode::UseItem(_) | PartyMode::Forget { .. } => "A:USE      B:BACK",
+                },
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    // Seven rows fit between the header and the footer.
+    const BAG_ROWS: usize = 7;
+
+    pub struct BagScreen {
+        list: ScrollList,
+        message: Option<&'static str>,
+    }
+
+    impl Default for BagScreen {
+        fn default() -> BagScreen {
+            return BagScreen::new();
+        }
+    }
+
+    impl BagScreen {
+        pub fn new() -> BagScreen {
+            return BagScreen {
+                list: ScrollList::new(BAG_ROWS),
+                message: None,
+            };
+        }
+    }
+
+    fn bag_count(save: &Save) -> usize {
+        return save.data.bag.iter().filter(|s| s.is_some()).count();
+    }
+
+    impl Screen for BagScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            let slots: [Option<BagSlot>; BAG_SIZE] = ctx.save.data.bag;
+            let mut items = slots.iter().flatten();
+            let count = bag_count(ctx.save);
+            if count == 0 {
+                return Transition::None;
+            }
+            // Coming back from the party might leave us pointing off the end.
+            self.list.clamp(count);
+            if self.list.handle_input(buttons, count) {
+                self.message = None;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::A) {
+                let slot = match items.nth(self.list.cursor()) {
+                    Some(s) => s,
+                    None => return Transition::None,
+                };
+                let item = match read_item(slot.item_id, ctx.storage) {
+                    Ok(i) => i,
+                    Err(ReadError(text)) => {
+                        self.message = Some(text);
+                        return Transition::Redraw;
+                    }
+                };
+                if !item.effect.usable_in(UseContext::Field) {
+                    self.message = Some("NOW IS NOT THE TIME");
+                    return Transition::Redraw;
+                }
+                self.message = None;
+                return Transition::Push(ScreenState::Party(PartyScreen::use_item(item.id)));
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = self.message.unwrap_or("BAG"),
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+
+            let count = bag_count(ctx.save);
+            if count == 0 {
+                egtext!(text = "NO ITEMS", top_left = (8, 9), style = white_text())
+                    .draw(display)
+                    .ok();
+            }
+            let slots: [Option<BagSlot>; BAG_SIZE] = ctx.save.data.bag;
+            let storage = &mut *ctx.storage;
+            self.list.draw(
+                display,
+                Point::new(2, 9),
+                124,
+                count,
+                |display, i, position| {
+                    let slot = match slots.iter().flatten().nth(i) {
+                        Some(s) => s,
+                        None => return,
+                    };
+                    if let Ok(item) = read_item(slot.item_id, storage) {
+                        Text::new(name_str(&item.name), position)
+                            .into_styled(white_text())
+                            .draw(display)
+                            .ok();
+                    }
+                    Text::new("x", position + Point::new(88, 0))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                    Text::new(
+                        Number::new(slot.quantity.into()).as_str(),
+                        position + Point::new(94, 0),
+                    )
+                    .into_styled(white_text())
+                    .draw(display)
+                    .ok();
+                },
+            );
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "A:USE      B:BACK",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    // The box only has room for this many rows on screen at once.
+    const BOX_ROWS: usize = 7;
+
+    // The party down the left and the box down the right. Left and right
+    // pick a side, and A moves whoever's under the cursor across.
+    pub struct BoxScreen {
+        in_box: bool,
+        party: ScrollList,
+        boxed: ScrollList,
+        message: Option<&'static str>,
+    }
+
+    impl Default for BoxScreen {
+        fn default() -> BoxScreen {
+            return BoxScreen::new();
+        }
+    }
+
+    impl BoxScreen {
+        pub fn new() -> BoxScreen {
+            return BoxScreen {
+                in_box: false,
+                party: ScrollList::new(PARTY_SIZE),
+                boxed: ScrollList::new(BOX_ROWS),
+                message: None,
+            };
+        }
+
+        fn move_across(&mut self, ctx: &mut Context) -> Transition {
+            let data = &mut ctx.save.data;
+            let moved = match self.in_box {
+                true => data.withdraw(self.boxed.cursor()),
+                false => data.deposit(self.party.cursor()),
+            };
+            match moved {
+                Ok(_) => {
+                    ctx.persist().ok();
+                    self.message = None;
+                }
+                Err(BoxError(text)) => self.message = Some(text),
+            }
+            return Transition::Redraw;
+        }
+
+        // Whichever side isn't picked still shows its rows, just without a
+        // cursor.
+        fn render_side<D: DrawTarget<BinaryColor>>(
+            &self,
+            in_box: bool,
+            display: &mut D,
+            ctx: &mut Context,
+        ) {
+            let (list, count, x) = match in_box {
+                true => (&self.boxed, ctx.save.data.box_count(), 66),
+                false => (&self.party, party_count(ctx.save), 2),
+            };
+            let data = &ctx.save.data;
+            let storage = &mut *ctx.storage;
+            let mut draw_row = |display: &mut D, i: usize, position: Point| {
+                let pokemon = match in_box {
+                    true => data.boxed(i),
+                    false => data.party[i].as_ref(),
+                };
+                let pokemon = match pokemon {
+                    Some(p) => p,
+                    None => return,
+                };
+                if let Ok(species) = read_pokemon(pokemon.species_id, storage) {
+                    Text::new(pokemon.name(&species), position)
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                }
+            };
+            if count == 0 {
+                Text::new("EMPTY", Point::new(x + 6, 9))
+                    .into_styled(white_text())
+                    .draw(display)
+                    .ok();
+            } else if in_box == self.in_box {
+                list.draw(display, Point::new(x, 9), 60, count, &mut draw_row);
+            } else {
+                for (row, i) in list.visible(count).enumerate() {
+                    draw_row(display, i, Point::new(x + 6, 9 + row as i32 * ROW_HEIGHT));
+                }
+            }
+        }
+    }
+
+    impl Screen for BoxScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            if buttons.consume(Buttons::LEFT) || buttons.consume(Buttons::RIGHT) {
+                self.in_box = !self.in_box;
+                self.message = None;
+                return Transition::Redraw;
+            }
+            let (list, count) = match self.in_box {
+                true => (&mut self.boxed, ctx.save.data.box_count()),
+                false => (&mut self.party, party_count(ctx.save)),
+            };
+            if count == 0 {
+                return Transition::None;
+            }
+            // Whatever was last in a list moving out leaves nothing there.
+            list.clamp(count);
+            if list.handle_input(buttons, count) {
+                self.message = None;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::A) {
+                return self.move_across(ctx);
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            match self.message {
+                Some(message) => {
+                    egtext!(text = message, top_left = (1, 1), style = black_text())
+                        .draw(display)
+                        .ok();
+                }
+                None => {
+                    egtext!(text = "PARTY", top_left = (1, 1), style = black_text())
+                        .draw(display)
+                        .ok();
+                    egtext!(text = "BOX", top_left = (65, 1), style = black_text())
+                        .draw(display)
+                        .ok();
+                }
+            }
+            egline!(start = (63, 8), end = (63, 55), style = white_solid())
+                .draw(display)
+                .ok();
+
+            self.render_side(false, display, ctx);
+            self.render_side(true, display, ctx);
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "A:MOVE     B:BACK",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum BattleMode {
+        // Working through whatever the last turn had to say.
+        Messages,
+        Menu,
+        Fight,
+        Bag,
+        // Forced is when the last one fainted and someone has to go out.
+        Party { forced: bool },
+        // Picked, and waiting on the other player.
+        Waiting,
+    }
+
+    const BATTLE_MENU: [&str; 4] = ["FIGHT", "BAG", "PARTY", "RUN"];
+
+    const HP_DRAIN_TICKS: u16 = 12;
+    const SLIDE_TICKS: u16 = 10;
+    const HIT_TICKS: u8 = 8;
+    const SPARKLE_TICKS: u8 = 24;
+    const CURSOR_BLINK_TICKS: u8 = 8;
+    const PLAYER: usize = 0;
+    const OPPONENT: usize = 1;
+
+    pub struct BattleScreen {
+        battle: Battle,
+        mode: BattleMode,
+        cursor: usize,
+        // What's on screen lags behind the battle itself, so the bars can
+        // drain and new arrivals can slide in. Indexed by PLAYER and OPPONENT.
+        shown_active: [Option<usize>; 2],
+        hp: [Tween; 2],
+        slide: [Slide; 2],
+        sparkle: [Sparkle; 2],
+        flash: Flash,
+        shake: Shake,
+        blink: Blink,
+        // How many characters of the two lines in the text box have been
+        // typed out so far.
+        revealed: usize,
+    }
+
+    // The parts of a battle too big to keep on the screen stack, which has
+    // to leave room for its biggest screen at every level. There's only ever
+    // one battle going, so the firmware keeps these once for whichever battle
+    // screen is up.
+    pub struct BattleState {
+        messages: Messages,
+        link: Option<Linked>,
+    }
+
+    impl Default for BattleState {
+        fn default() -> BattleState {
+            return BattleState::new();
+        }
+    }
+
+    impl BattleState {
+        pub fn new() -> BattleState {
+            return BattleState {
+                messages: Messages::new(),
+                link: None,
+            };
+        }
+    }
+
+    // A link battle is fought with the real party, so it gets put back the
+    // way it was afterwards.
+    struct Linked {
+        session: LinkBattle,
+        party: [Option<Pokemon>; PARTY_SIZE],
+    }
+
+    impl BattleScreen {
+        pub fn wild(
+            pokemon: Pokemon,
+            seed: u32,
+            ctx: &mut Context,
+        ) -> Result<BattleScreen, ReadError> {
+            if pokemon.is_shiny() {
+                ctx.leds.alert(SHINY_ALERT, ALERT_TICKS);
+            } else if is_rare(&read_pokemon(pokemon.species_id, ctx.storage)?) {
+                ctx.leds.alert(RARE_ALERT, ALERT_TICKS);
+            }
+            ctx.speaker.play(pokemon.species_id);
+            *ctx.battle = BattleState::new();
+            let battle = Battle::wild(
+                pokemon,
+                seed,
+                &mut ctx.save.data,
+                ctx.storage,
+                &mut ctx.battle.messages,
+            )?;
+            return Ok(BattleScreen::new(battle));
+        }
+
+        pub fn trainer(
+            trainer: Trainer,
+            difficulty: Difficulty,
+            seed: u32,
+            ctx: &mut Context,
+        ) -> Result<BattleScreen, ReadError> {
+            *ctx.battle = BattleState::new();
+            let battle = Battle::trainer(
+                trainer,
+                difficulty,
+                seed,
+                &mut ctx.save.data,
+                ctx.storage,
+                &mut ctx.battle.messages,
+            )?;
+            return Ok(BattleScreen::new(battle));
+        }
+
+        // Takes over the link once both parties have been swapped.
+        pub fn link(mut session: LinkBattle, ctx: &mut Context) -> Result<BattleScreen, ReadError> {
+            let team = session.start().unwrap_or_default();
+            let party = ctx.save.data.party.clone();
+            *ctx.battle = BattleState::new();
+            let battle = Battle::link(
+                team,
+                session.partner_name(),
+                session.is_host(),
+                session.seed(),
+                &mut ctx.save.data,
+                ctx.storage,
+                &mut ctx.battle.messages,
+            )?;
+            ctx.battle.link = Some(Linked { session, party });
+            return Ok(BattleScreen::new(battle));
+        }
+
+        fn new(battle: Battle) -> BattleScreen {
+            return BattleScreen {
+                battle,
+                mode: BattleMode::Messages,
+                cursor: 0,
+                shown_active: [None, None],
+                hp: [Tween::at(0), Tween::at(0)],
+                slide: [Slide::new(), Slide::new()],
+                sparkle: [Sparkle::new(), Sparkle::new()],
+                flash: Flash::new(),
+                shake: Shake::new(),
+                blink: Blink::new(CURSOR_BLINK_TICKS),
+                revealed: 0,
+            };
+        }
+
+        fn play(&mut self, choice: Choice, ctx: &mut Context) -> Transition {
+            self.cursor = 0;
+            // The turn gets played once the other side has picked too. The
+            // session needs the whole context to talk over, so it's lent out.
+            if let Some(mut link) = ctx.battle.link.take() {
+                let hash = self.battle.link_hash(&ctx.save.data);
+                link.session.choose(choice, hash, ctx);
+                ctx.battle.link = Some(link);
+                self.mode = BattleMode::Waiting;
+                return Transition::Redraw;
+            }
+            let messages = &mut ctx.battle.messages;
+            if let Err(ReadError(text)) =
+                self.battle
+                    .play_turn(choice, &mut ctx.save.data, ctx.storage, messages)
+            {
+                messages.push(&[text]);
+            }
+            self.mode = BattleMode::Messages;
+            return Transition::Redraw;
+        }
+
+        // Keeps the link going, and plays the turn once both picks are in.
+        // Returns whether there's anything new to show.
+        fn poll_link(&mut self, ctx: &mut Context) -> bool {
+            if self.battle.outcome.is_some() {
+                return false;
+            }
+            let mut link = match ctx.battle.link.take() {
+                Some(link) => link,
+                None => return false,
+            };
+            let changed = link.session.poll(ctx);
+            let phase = link.session.phase();
+            let turn = link.session.next_turn();
+            ctx.battle.link = Some(link);
+            let messages = &mut ctx.battle.messages;
+            if let BattlePhase::Ended(text) = phase {
+                messages.push(&[text]);
+                self.battle.call_off();
+                self.mode = BattleMode::Messages;
+                return true;
+            }
+            let (mine, theirs, seed) = match turn {
+                Some(turn) => turn,
+                None => return changed,
+            };
+            if let Err(ReadError(text)) = self.battle.play_link_turn(
+                mine,
+                theirs,
+                seed,
+                &mut ctx.save.data,
+                ctx.storage,
+                messages,
+            ) {
+                messages.push(&[text]);
+            }
+            self.mode = BattleMode::Messages;
+            return true;
+        }
+
+        // Once the messages have run out, works out what comes next.
+        fn next_mode(&mut self, ctx: &mut Context) -> Transition {
+            if self.battle.outcome.is_some() {
+                return self.finish(ctx);
+            }
+            self.cursor = 0;
+            self.mode = match self.battle.needs_switch(&ctx.save.data) {
+                true => BattleMode::Party { forced: true },
+                false => BattleMode::Menu,
+            };
+            return Transition::Redraw;
+        }
+
+        fn finish(&mut self, ctx: &mut Context) -> Transition {
+            if let Some(link) = ctx.battle.link.take() {
+                ctx.save.data.party = link.party;
+                ctx.persist().ok();
+                return Transition::Pop;
+            }
+            if let Some(pokemon) = self.battle.take_caught() {
+                // Naming it takes this screen's place, there's no battle to
+                // go back to.
+                match caught(ctx, pokemon) {
+                    Transition::Push(screen) => return Transition::Replace(screen),
+                    _ => return Transition::Pop,
+                }
+            }
+            ctx.persist().ok();
+            for (slot, levelled) in self.battle.levelled_up.iter().enumerate() {
+                if !levelled {
+                    continue;
+                }
+                let evolution = match &ctx.save.data.party[slot] {
+                    Some(p) => check_level_up(p, ctx.storage).ok().flatten(),
+                    None => None,
+                };
+                if let Some(into) = evolution {
+                    return Transition::Replace(ScreenState::Evolution(EvolutionScreen::new(
+                        slot, ctx.save, into, true,
+                    )));
+                }
+            }
+            return Transition::Pop;
+        }
+
+        fn select_menu(&mut self, ctx: &mut Context) -> Transition {
+            self.mode = match self.cursor {
+                0 => BattleMode::Fight,
+                1 if ctx.battle.link.is_some() => {
+                    ctx.battle.messages.push(&["NO ITEMS IN LINK BATTLES!"]);
+                    BattleMode::Messages
+                }
+                1 => BattleMode::Bag,
+                2 => BattleMode::Party { forced: false },
+                _ => return self.play(Choice::Run, ctx),
+            };
+            self.cursor = 0;
+            return Transition::Redraw;
+        }
+
+        fn select_move(&mut self, ctx: &mut Context) -> Transition {
+            let active = self.battle.player_active;
+            let pp = self.battle.player_pp[active];
+            // With nothing left at all it struggles, whichever move was picked.
+            if pp[self.cursor] == 0 && pp.iter().any(|p| *p > 0) {
+                ctx.battle.messages.push(&["NO PP LEFT FOR THIS MOVE!"]);
+                self.mode = BattleMode::Messages;
+                return Transition::Redraw;
+            }
+            return self.play(Choice::Fight(self.cursor), ctx);
+        }
+
+        fn select_item(&mut self, ctx: &mut Context) -> Transition {
+            let slots: [Option<BagSlot>; BAG_SIZE] = ctx.save.data.bag;
+            let item_id = match slots.iter().flatten().nth(self.cursor) {
+                Some(slot) => slot.item_id,
+                None => return Transition::None,
+            };
+            return self.play(Choice::Item(item_id), ctx);
+        }
+
+        fn select_pokemon(&mut self, forced: bool, ctx: &mut Context) -> Transition {
+            let slot = self.cursor;
+            let pokemon = match &ctx.save.data.party[slot] {
+                Some(p) => p,
+                None => return Transition::None,
+            };
+            if pokemon.current_hp == 0 {
+                ctx.battle.messages.push(&["THERE'S NO WILL TO FIGHT!"]);
+            } else if slot == self.battle.player_active && !forced {
+                ctx.battle.messages.push(&["IT'S ALREADY OUT!"]);
+            } else if forced {
+                let messages = &mut ctx.battle.messages;
+                if let Err(ReadError(text)) =
+                    self.battle
+                        .send_out(slot, &mut ctx.save.data, ctx.storage, messages)
+                {
+                    messages.push(&[text]);
+                }
+            } else {
+                return self.play(Choice::Switch(slot), ctx);
+            }
+            self.mode = BattleMode::Messages;
+            return Transition::Redraw;
+        }
+
+        // Catches what's on screen up with the battle. Returns whether
+        // anything moved.
+        fn animate(&mut self, ctx: &mut Context) -> bool {
+            let mut moved = false;
+            let animations = ctx.save.data.settings.battle_animations;
+            let sides = [
+                (
+                    self.battle.player_active,
+                    &ctx.save.data.party[self.battle.player_active],
+                ),
+                (
+                    self.battle.opponent_active,
+                    &self.battle.team[self.battle.opponent_active],
+                ),
+            ];
+            for (side, (active, pokemon)) in sides.iter().enumerate() {
+                let hp = pokemon.as_ref().map_or(0, |p| p.current_hp) as i32;
+                if self.shown_active[side] != Some(*active) {
+                    // Someone new, so they come in from the edge with their
+                    // bar already full.
+                    self.shown_active[side] = Some(*active);
+                    self.hp[side] = Tween::at(hp);
+                    let distance = match side {
+                        PLAYER => 96,
+                        _ => -96,
+                    };
+                    if animations {
+                        self.slide[side].start(distance, SLIDE_TICKS);
+                        if pokemon.as_ref().is_some_and(|p| p.is_shiny()) {
+                            self.sparkle[side].start(SPARKLE_TICKS);
+                        }
+                    }
+                    moved = true;
+                } else if !animations && self.hp[side].target() != hp {
+                    // Straight to the new value, with nothing shaking.
+                    self.hp[side] = Tween::at(hp);
+                    moved = true;
+                } else if self.hp[side].target() != hp {
+                    if hp < self.hp[side].target() {
+                        match side {
+                            PLAYER => self.shake.start(HIT_TICKS, 2),
+                            _ => self.flash.start(HIT_TICKS, 2),
+                        }
+                    }
+                    self.hp[side].retarget(hp, HP_DRAIN_TICKS);
+                }
+                moved |= self.hp[side].tick();
+                moved |= self.slide[side].tick();
+                // Shinies sparkle once they've landed.
+                if !self.slide[side].is_active() {
+                    moved |= self.sparkle[side].tick();
+                }
+            }
+            moved |= self.flash.tick();
+            moved |= self.shake.tick();
+            return moved;
+        }
+
+        fn animating(&self) -> bool {
+            return self.slide.iter().any(|s| s.is_active())
+                || self.sparkle.iter().any(|s| s.is_active())
+                || self.hp.iter().any(|t| !t.is_done())
+                || self.flash.is_active()
+                || self.shake.is_active();
+        }
+
+        // Whether the text box is still typing out what's in it.
+        fn typing(&self, ctx: &Context) -> bool {
+            let total: usize = (0..2)
+                .filter_map(|i| ctx.battle.messages.peek(i))
+                .map(|l| l.chars().count())
+                .sum();
+            return self.revealed < total;
+        }
+
+        fn move_count(&self, ctx: &Context) -> usize {
+            return ctx.save.data.party[self.battle.player_active]
+                .as_ref()
+                .map_or(0, |p| p.moves.iter().filter(|m| m.is_some()).count());
+        }
+
+        // Over the top of a small sprite. The sprite's been inverted, so its
+        // own background colour is the one that stands out.
+        fn draw_sparkle<D: DrawTarget<BinaryColor>>(
+            &self,
+            display: &mut D,
+            side: usize,
+            sprite: &PackedSprite,
+            at: Point,
+        ) {
+            if self.slide[side].is_active() {
+                return;
+            }
+            if let Some(Pixel(_, background)) = sprite.pixel_iter().next() {
+                self.sparkle[side].draw(
+                    display,
+                    at,
+                    Size::new(sprite.width(), sprite.height()) / 2,
+                    background,
+                );
+            }
+        }
+
+        fn render_field<D: DrawTarget<BinaryColor>>(&self, display: &mut D, ctx: &mut Context) {
+            if let Some(opponent) = &self.battle.team[self.battle.opponent_active] {
+                if let Ok(species) = read_pokemon(opponent.species_id, ctx.storage) {
+                    if self.flash.visible() {
+                        let at = Point::new(94, 0) + self.slide[OPPONENT].offset();
+                        if let Ok(sprite) = read_sprite(species.sprite, ctx.storage) {
+                            draw_small_sprite(display, &sprite, at, false, opponent.is_shiny());
+                            self.draw_sparkle(display, OPPONENT, &sprite, at);
+                        }
+                    }
+                    egtext!(
+                        text = name_str(&species.name),
+                        top_left = (1, 1),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                draw_level(display, opponent.level, Point::new(60, 1));
+                Bar::hp(
+                    Point::new(1, 8),
+                    HP_BAR_WIDTH,
+                    self.hp[OPPONENT].value() as u16,
+                    opponent.hp.value,
+                )
+                .draw(display)
+                .ok();
+            }
+
+            if let Some(own) = &ctx.save.data.party[self.battle.player_active] {
+                if let Ok(species) = read_pokemon(own.species_id, ctx.storage) {
+                    let at = Point::new(2, 12) + self.slide[PLAYER].offset() + self.shake.offset();
+                    let (index, mirrored) = match species.back_sprite {
+                        Some(back) => (back, false),
+                        None => (species.sprite, true),
+                    };
+                    if let Ok(sprite) = read_sprite(index, ctx.storage) {
+                        draw_small_sprite(display, &sprite, at, mirrored, own.is_shiny());
+                        self.draw_sparkle(display, PLAYER, &sprite, at);
+                    }
+                    egtext!(
+                        text = own.name(&species),
+                        top_left = (60, 22),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                draw_level(display, own.level, Point::new(108, 22));
+                let shown_hp = self.hp[PLAYER].value() as u16;
+                Bar::hp(Point::new(60, 29), HP_BAR_WIDTH, shown_hp, own.hp.value)
+                    .draw(display)
+                    .ok();
+                // Padded so the slash doesn't wander about as the hp drains.
+                egtext!(
+                    text = Number::right(shown_hp.into(), 3).as_str(),
+                    top_left = (92, 35),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+                egtext!(text = "/", top_left = (104, 35), style = white_text())
+                    .draw(display)
+                    .ok();
+                egtext!(
+                    text = Number::new(own.hp.value.into()).as_str(),
+                    top_left = (110, 35),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+            }
+        }
+
+        fn render_text_box<D: DrawTarget<BinaryColor>>(&self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 44),
+                bottom_right = (127, 63),
+                style = white_outline()
+            )
+            .draw(display)
+            .ok();
+            if self.mode == BattleMode::Messages {
+                let mut remaining = self.revealed;
+                for i in 0..2 {
+                    if let Some(line) = ctx.battle.messages.peek(i) {
+                        let shown = match line.char_indices().nth(remaining) {
+                            Some((end, _)) => &line[..end],
+                            None => line,
+                        };
+                        remaining = remaining.saturating_sub(line.chars().count());
+                        egtext!(
+                            text = shown,
+                            top_left = (3, 47 + (i as i32) * 8),
+                            style = white_large_text()
+                        )
+                        .draw(display)
+                        .ok();
+                    }
+                }
+                // Waiting on a press to carry on.
+                if !self.animating() && !self.typing(ctx) && self.blink.visible() {
+                    egtext!(text = "▼", top_left = (121, 57), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                return;
+            }
+            if self.mode == BattleMode::Waiting {
+                let partner = ctx.battle.link.as_ref().and_then(|l| l.session.partner());
+                egtext!(
+                    text = "WAITING FOR",
+                    top_left = (3, 47),
+                    style = white_large_text()
+                )
+                .draw(display)
+                .ok();
+                egtext!(
+                    text = partner.unwrap_or(""),
+                    top_left = (3, 55),
+                    style = white_large_text()
+                )
+                .draw(display)
+                .ok();
+                return;
+            }
+
+            egtext!(text = "WHAT WILL", top_left = (3, 47), style = white_text())
+                .draw(display)
+                .ok();
+            if let Some(own) = &ctx.save.data.party[self.battle.player_active] {
+                if let Ok(species) = read_pokemon(own.species_id, ctx.storage) {
+                    let name = own.name(&species);
+                    egtext!(text = name, top_left = (3, 55), style = white_text())
+                        .draw(display)
+                        .ok();
+                    Text::new("DO?", Point::new(7 + name.len() as i32 * 4, 55))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                }
+            }
+            egline!(start = (68, 44), end = (68, 63), style = white_outline())
+                .draw(display)
+                .ok();
+            for (i, name) in BATTLE_MENU.iter().enumerate() {
+                let x = 78 + (i as i32 % 2) * 26;
+                let y = 47 + (i as i32 / 2) * 8;
+                if i == self.cursor && self.blink.visible() {
+                    egtext!(text = "▶", top_left = (x - 6, y), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                egtext!(text = name, top_left = (x, y), style = white_text())
+                    .draw(display)
+                    .ok();
+            }
+        }
+
+        fn render_moves<D: DrawTarget<BinaryColor>>(&self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 36),
+                bottom_right = (127, 63),
+                style = PrimitiveStyleBuilder::new()
+                    .stroke_color(BinaryColor::On)
+                    .fill_color(BinaryColor::Off)
+                    .stroke_width(1)
+                    .build()
+            )
+            .draw(display)
+            .ok();
+            egline!(start = (72, 36), end = (72, 63), style = white_outline())
+                .draw(display)
+                .ok();
+            let own = match &ctx.save.data.party[self.battle.player_active] {
+                Some(p) => p,
+                None => return,
+            };
+            let pp = self.battle.player_pp[self.battle.player_active];
+            for (i, move_id) in own.moves.iter().enumerate() {
+                let m = match move_id.map(|id| read_move(id, ctx.storage)) {
+                    Some(Ok(m)) => m,
+                    _ => continue,
+                };
+                let y = 39 + (i as i32) * 6;
+                if i == self.cursor {
+                    if self.blink.visible() {
+                        egtext!(text = "▶", top_left = (2, y), style = white_text())
+                            .draw(display)
+                            .ok();
+                    }
+                    egtext!(
+                        text = m.type_.name(),
+                        top_left = (76, 41),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                    egtext!(text = "PP", top_left = (76, 51), style = white_text())
+                        .draw(display)
+                        .ok();
+                    egtext!(
+                        text = Number::new(pp[i].into()).as_str(),
+                        top_left = (88, 51),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                    egtext!(text = "/", top_left = (100, 51), style = white_text())
+                        .draw(display)
+                        .ok();
+                    egtext!(
+                        text = Number::new(m.pp.into()).as_str(),
+                        top_left = (106, 51),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                egtext!(
+                    text = name_str(&m.name),
+                    top_left = (8, y),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+            }
+        }
+
+        fn render_bag<D: DrawTarget<BinaryColor>>(&self, display: &mut D, ctx: &mut Context) {
+            draw_header(display, "USE WHICH ITEM?");
+            let first = (self.cursor / 7) * 7;
+            let slots: [Option<BagSlot>; BAG_SIZE] = ctx.save.data.bag;
+            for (i, slot) in slots.iter().flatten().enumerate().skip(first).take(7) {
+                let y = 9 + ((i - first) as i32) * 7;
+                if i == self.cursor && self.blink.visible() {
+                    egtext!(text = "▶", top_left = (2, y), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                if let Ok(item) = read_item(slot.item_id, ctx.storage) {
+                    egtext!(
+                        text = name_str(&item.name),
+                        top_left = (8, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                egtext!(text = "x", top_left = (96, y), style = white_text())
+                    .draw(display)
+                    .ok();
+                egtext!(
+                    text = Number::new(slot.quantity.into()).as_str(),
+                    top_left = (102, y),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+            }
+            draw_footer(display, "A:USE      B:BACK");
+        }
+
+        fn render_party<D: DrawTarget<BinaryColor>>(
+            &self,
+            display: &mut D,
+            forced: bool,
+            ctx: &mut Context,
+        ) {
+            draw_header(display, "BRING OUT WHICH \u{e000}\u{e001}?");
+            let party = &ctx.save.data.party;
+            for (i, pokemon) in party.iter().enumerate() {
+                let pokemon = match pokemon {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let y = 9 + (i as i32) * 7;
+                if i == self.cursor && self.blink.visible() {
+                    egtext!(text = "▶", top_left = (2, y), style = white_text())
+                        .draw(display)
+                        .ok();
+                }
+                if let Ok(species) = read_pokemon(pokemon.species_id, ctx.storage) {
+                    egtext!(
+                        text = pokemon.name(&species),
+                        top_left = (8, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                draw_level(display, pokemon.level, Point::new(62, y));
+                egtext!(
+                    text = Number::new(pokemon.current_hp.into()).as_str(),
+                    top_left = (92, y),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+                egtext!(
+                    text = Number::new(pokemon.hp.value.into()).as_str(),
+                    top_left = (110, y),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+            }
+            draw_footer(
+                display,
+                match forced {
+                    true => "A:SEND OUT",
+                    false => "A:SEND OUT B:BACK",
+                },
+            );
+        }
+    }
+
+    impl BattleScreen {
+        fn handle_buttons(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            match self.mode {
+                BattleMode::Messages => {
+                    // Let the bars finish draining before moving on.
+                    if self.animating() {
+                        return Transition::None;
+                    }
+                    if buttons.consume(Buttons::A) || buttons.consume(Buttons::B) {
+                        // The first press finishes off the typing.
+                        if self.typing(ctx) {
+                            self.revealed = usize::MAX;
+                            return Transition::Redraw;
+                        }
+                        let messages = &mut ctx.battle.messages;
+                        messages.advance();
+                        // What was the second line is already all there.
+                        self.revealed = messages.peek(0).map_or(0, |l| l.chars().count());
+                        if messages.is_empty() {
+                            return self.next_mode(ctx);
+                        }
+                        return Transition::Redraw;
+                    }
+                }
+                BattleMode::Menu => {
+                    // Two by two, so up and down flip the row and left and
+                    // right flip the column.
+                    if buttons.consume(Buttons::UP) || buttons.consume(Buttons::DOWN) {
+                        self.cursor ^= 2;
+                        return Transition::Redraw;
+                    }
+                    if buttons.consume(Buttons::LEFT) || buttons.consume(Buttons::RIGHT) {
+                        self.cursor ^= 1;
+                        return Transition::Redraw;
+                    }
+                    if buttons.consume(Buttons::A) {
+                        return self.select_menu(ctx);
+                    }
+                }
+                // Nothing to do but wait.
+                BattleMode::Waiting => {}
+                BattleMode::Fight | BattleMode::Bag | BattleMode::Party { .. } => {
+                    let count = match self.mode {
+                        BattleMode::Fight => self.move_count(ctx),
+                        BattleMode::Bag => bag_count(ctx.save),
+                        _ => party_count(ctx.save),
+                    };
+                    if buttons.consume(Buttons::B)
+                        && self.mode != (BattleMode::Party { forced: true })
+                    {
+                        self.cursor = 0;
+                        self.mode = BattleMode::Menu;
+                        return Transition::Redraw;
+                    }
+                    if count == 0 {
+                        return Transition::None;
+                    }
+                    if buttons.consume(Buttons::UP) {
+                        self.cursor = (self.cursor + count - 1) % count;
+                        return Transition::Redraw;
+                    }
+                    if buttons.consume(Buttons::DOWN) {
+                        self.cursor = (self.cursor + 1) % count;
+                        return Transition::Redraw;
+                    }
+                    if buttons.consume(Buttons::A) {
+                        match self.mode {
+                            BattleMode::Fight => return self.select_move(ctx),
+                            BattleMode::Bag => return self.select_item(ctx),
+                            BattleMode::Party { forced } => {
+                                return self.select_pokemon(forced, ctx)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            return Transition::None;
+        }
+    }
+
+    impl Screen for BattleScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            let transition = self.handle_buttons(buttons, ctx);
+            // Show the cursor straight away wherever it's ended up.
+            if !matches!(transition, Transition::None) {
+                self.blink.reset();
+            }
+            return transition;
+        }
+
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            let mut redraw = self.poll_link(ctx);
+            redraw |= self.animate(ctx);
+            redraw |= self.blink.tick();
+            // Text waits for things to stop moving, so it's never typing out
+            // about a hit that hasn't landed yet.
+            if self.mode == BattleMode::Messages && !self.animating() && self.typing(ctx) {
+                self.revealed += ctx.save.data.settings.text_speed.chars_per_tick();
+                redraw = true;
+            }
+            // Nothing to say, for example when the player can't fight at all.
+            if self.mode == BattleMode::Messages && ctx.battle.messages.is_empty() {
+                return self.next_mode(ctx);
+            }
+            return match redraw {
+                true => Transition::Redraw,
+                false => Transition::None,
+            };
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            match self.mode {
+                BattleMode::Bag => return self.render_bag(display, ctx),
+                BattleMode::Party { forced } => return self.render_party(display, forced, ctx),
+                _ => {}
+            }
+            self.render_field(display, ctx);
+            match self.mode {
+                BattleMode::Fight => self.render_moves(display, ctx),
+                _ => self.render_text_box(display, ctx),
+            }
+        }
+
+        // Goes by the bar rather than the battle itself, so the warning comes
+        // on as the bar drains into the last quarter.
+        fn led(&mut self, ctx: &mut Context) -> Effect {
+            let max = match &ctx.save.data.party[self.battle.player_active] {
+                Some(p) => p.hp.value as i32,
+                None => return Effect::Off,
+            };
+            let shown = self.hp[PLAYER].value();
+            if shown > 0 && shown * 4 <= max {
+                return LOW_HP;
+            }
+            return Effect::Off;
+        }
+    }
+
+    fn draw_header<D: DrawTarget<BinaryColor>>(display: &mut D, text: &str) {
+        egrectangle!(
+            top_left = (0, 0),
+            bottom_right = (128, 6),
+            style = white_solid()
+        )
+        .draw(display)
+        .ok();
+        egtext!(text = text, top_left = (1, 1), style = black_text())
+            .draw(display)
+            .ok();
+    }
+
+    fn draw_footer<D: DrawTarget<BinaryColor>>(display: &mut D, text: &str) {
+        egrectangle!(
+            top_left = (56, 57),
+            bottom_right = (128, 64),
+            style = white_solid()
+        )
+        .draw(display)
+        .ok();
+        egtext!(text = text, top_left = (58, 58), style = black_text())
+            .draw(display)
+            .ok();
+    }
+
+    fn draw_level<D: DrawTarget<BinaryColor>>(display: &mut D, level: u8, top_left: Point) {
+        Text::new("L", top_left)
+            .into_styled(white_text())
+            .draw(display)
+            .ok();
+        Text::new(
+            Number::new(level.into()).as_str(),
+            top_left + Point::new(5, 0),
+        )
+        .into_styled(white_text())
+        .draw(display)
+        .ok();
+    }
+
+    const HP_BAR_WIDTH: u32 = 48;
+
+    const EVOLUTION_FRAMES: u8 = 144;
+
+    pub struct EvolutionScreen {
+        slot: usize,
+        from: u8,
+        into: u8,
+        cancellable: bool,
+        frame: u8,
+        // Whether it went through, once it's over.
+        finished: Option<bool>,
+    }
+
+    impl EvolutionScreen {
+        // Level up evolutions can be stopped with B, stones and trades can't.
+        pub fn new(slot: usize, save: &Save, into: u8, cancellable: bool) -> EvolutionScreen {
+            return EvolutionScreen {
+                slot,
+                from: save.data.party[slot].as_ref().map_or(0, |p| p.species_id),
+                into,
+                cancellable,
+                frame: 0,
+                finished: None,
+            };
+        }
+
+        // Flickers between the two silhouettes, faster and faster.
+        fn showing_new(&self) -> bool {
+            let period = match self.frame {
+                0..=47 => 24,
+                48..=95 => 12,
+                _ => 4,
+            };
+            return (self.frame / period) % 2 == 1;
+        }
+
+        fn complete(&mut self, ctx: &mut Context) {
+            let species = match read_pokemon(self.into, ctx.storage) {
+                Ok(s) => s,
+                Err(_) => {
+                    self.finished = Some(false);
+                    return;
+                }
+            };
+            if let Some(pokemon) = &mut ctx.save.data.party[self.slot] {
+                evolve(pokemon, &species);
+            }
+            ctx.save.data.dex.mark_caught(self.into);
+            ctx.persist().ok();
+            self.finished = Some(true);
+        }
+    }
+
+    impl Screen for EvolutionScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, _ctx: &mut Context) -> Transition {
+            if self.finished.is_some() {
+                if buttons.consume(Buttons::A) || buttons.consume(Buttons::B) {
+                    return Transition::Pop;
+                }
+                return Transition::None;
+            }
+            if buttons.consume(Buttons::B) && self.cancellable {
+                self.finished = Some(false);
+                return Transition::Redraw;
+            }
+            return Transition::None;
+        }
+
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            if self.finished.is_some() {
+                return Transition::None;
+            }
+            self.frame += 1;
+            if self.frame >= EVOLUTION_FRAMES {
+                self.complete(ctx);
+            }
+            return Transition::Redraw;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            let pokemon = match &ctx.save.data.party[self.slot] {
+                Some(p) => p,
+                None => return,
+            };
+            let (shown, style) = match self.finished {
+                Some(true) => (self.into, SpriteStyle::of(pokemon)),
+                Some(false) => (self.from, SpriteStyle::of(pokemon)),
+                None if self.frame == 0 => (self.from, SpriteStyle::of(pokemon)),
+                None if self.showing_new() => (self.into, SpriteStyle::Silhouette),
+                None => (self.from, SpriteStyle::Silhouette),
+            };
+            let species = match read_pokemon(shown, ctx.storage) {
+                Ok(s) => s,
+                Err(ReadError(text)) => {
+                    Text::new(text, Point::new(0, 0))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                    return;
+                }
+            };
+
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            // The nickname sticks around, so this is the right name whichever
+            // species it currently is.
+            egtext!(
+                text = pokemon.name(&species),
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+            if let Ok(sprite) = read_sprite(species.sprite, ctx.storage) {
+                draw_sprite(display, &sprite, style);
+            }
+
+            let lines = match self.finished {
+                Some(true) => [
+                    "CONGRATULATIONS!",
+                    "IT EVOLVED INTO",
+                    name_str(&species.name),
+                ],
+                Some(false) => ["HUH?", "IT STOPPED", "EVOLVING"],
+                None => ["WHAT?", "IT'S EVOLVING!", ""],
+            };
+            for (i, line) in lines.iter().enumerate() {
+                egtext!(
+                    text = line,
+                    top_left = (68, 16 + (i as i32) * 8),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+            }
+
+            let footer = match (self.finished, self.cancellable) {
+                (Some(_), _) => "A:CONTINUE",
+                (None, true) => "B:STOP",
+                (None, false) => "",
+            };
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = footer, top_left = (58, 58), style = black_text())
+                .draw(display)
+                .ok();
+        }
+    }
+
+    pub struct SummaryScreen {
+        slot: usize,
+    }
+
+    impl SummaryScreen {
+        pub fn new(slot: usize) -> SummaryScreen {
+            return SummaryScreen { slot };
+        }
+    }
+
+    impl Screen for SummaryScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::A) {
+                return Transition::Push(ScreenState::Keyboard(KeyboardScreen::new(
+                    NameTarget::Party(self.slot),
+                    ctx.save,
+                )));
+            }
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            return Transition::None;
+        }
+
+        fn led(&mut self, ctx: &mut Context) -> Effect {
+            let species_id = match &ctx.save.data.party[self.slot] {
+                Some(p) => p.species_id,
+                None => return Effect::Off,
+            };
+            return match read_pokemon(species_id, ctx.storage) {
+                Ok(species) => Effect::Pulse(type_colour(species.type_primary)),
+                Err(_) => Effect::Off,
+            };
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            let pokemon = match &ctx.save.data.party[self.slot] {
+                Some(p) => p,
+                None => return,
+            };
+            let species = match read_pokemon(pokemon.species_id, ctx.storage) {
+                Ok(s) => s,
+                Err(ReadError(text)) => {
+                    Text::new(text, Point::new(0, 0))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                    return;
+                }
+            };
+
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = pokemon.name(&species),
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+            egline!(start = (57, 7), end = (57, 64), style = white_solid())
+                .draw(display)
+                .ok();
+
+            egtext!(
+                text = name_str(&species.name),
+                top_left = (60, 9),
+                style = white_large_text()
+            )
+            .draw(display)
+            .ok();
+            let level = Number::new(pokemon.level.into());
+            StatTable::new(Point::new(60, 18), 64, 8).draw(display, &[("LV", level.as_str())]);
+            egtext!(text = "HP", top_left = (60, 26), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::right(pokemon.current_hp.into(), 3).as_str(),
+                top_left = (96, 26),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "/", top_left = (108, 26), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::right(pokemon.hp.value.into(), 3).as_str(),
+                top_left = (112, 26),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            Bar::hp(Point::new(60, 33), 62, pokemon.current_hp, pokemon.hp.value)
+                .draw(display)
+                .ok();
+
+            let xp = Number::new(pokemon.xp);
+            StatTable::new(Point::new(60, 40), 64, 8).draw(display, &[("EXP", xp.as_str())]);
+            let growth = species.growth_rate;
+            Bar::xp(
+                Point::new(60, 47),
+                62,
+                pokemon.xp,
+                growth.xp_for_level(pokemon.level),
+                growth.xp_for_level(pokemon.level.saturating_add(1)),
+            )
+            .draw(display)
+            .ok();
+
+            if let Ok(sprite) = read_sprite(species.sprite, ctx.storage) {
+                draw_sprite(display, &sprite, SpriteStyle::of(pokemon));
+            }
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "A:NICKNAME B:BACK",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    pub struct TrainerCardScreen {}
+
+    impl Screen for TrainerCardScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, _ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            let data = &ctx.save.data;
+
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "TRAINER CARD",
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+
+            egtext!(text = "NAME", top_left = (2, 10), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = data.player_name.as_ref().map_or("", |n| name_str(n)),
+                top_left = (40, 10),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "POKEDEX", top_left = (2, 18), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::new(data.dex.caught_count()).as_str(),
+                top_left = (40, 18),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "LV CAP", top_left = (64, 18), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::new(level_cap(data.badge_count()).into()).as_str(),
+                top_left = (92, 18),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+
+            // The badge case. Empty slots are just an outline, earned ones are
+            // filled in with the first letter of the badge.
+            egtext!(text = "BADGES", top_left = (2, 28), style = white_text())
+                .draw(display)
+                .ok();
+            for (i, badge) in data.badges.iter().enumerate() {
+                let x = 2 + (i as i32) * 15;
+                let outline = Rectangle::new(Point::new(x, 36), Point::new(x + 12, 48));
+                match badge {
+                    Some(b) => {
+                        outline.into_styled(white_solid()).draw(display).ok();
+                        let initial = [badge_name(b.theme).as_bytes()[0]];
+                        Text::new(
+                            core::str::from_utf8(&initial).unwrap(),
+                            Point::new(x + 5, 40),
+                        )
+                        .into_styled(black_text())
+                        .draw(display)
+                        .ok();
+                    }
+                    None => {
+                        outline
+                            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                            .draw(display)
+                            .ok();
+                    }
+                }
+            }
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "B:BACK", top_left = (58, 58), style = black_text())
+                .draw(display)
+                .ok();
+        }
+    }
+
+    // Inside a gym. Its trainers have to be beaten in order before the leader
+    // will battle, and beating the leader hands over the badge. Who's been
+    // beaten lives in the save, so walking out and coming back picks up where
+    // the player left off.
+    pub struct GymScreen {
+        gym: Gym,
+        seed: u32,
+        // Whether the leader's win has been dealt with, and if a badge came
+        // of it.
+        settled: bool,
+        awarded: bool,
+    }
+
+    impl GymScreen {
+        pub fn new(gym: Gym, seed: u32) -> GymScreen {
+            return GymScreen {
+                gym,
+                seed,
+                settled: false,
+                awarded: false,
+            };
+        }
+
+        // How many of the trainers are out of the way.
+        fn beaten(&self, ctx: &Context) -> u8 {
+            let defeated = &ctx.save.data.defeated_trainers;
+            return (0..self.gym.trainer_count)
+                .take_while(|i| defeated.contains(self.gym.trainer_id(*i)))
+                .count() as u8;
+        }
+
+        fn leader_beaten(&self, ctx: &Context) -> bool {
+            return ctx
+                .save
+                .data
+                .defeated_trainers
+                .contains(self.gym.leader_id());
+        }
+
+        fn challenge(&self, ctx: &mut Context) -> Transition {
+            let badges = ctx.save.data.badge_count();
+            let beaten = self.beaten(ctx);
+            // Leaders battle like they've got a couple more badges than you.
+            let (trainer, difficulty) = if beaten < self.gym.trainer_count {
+                (
+                    self.gym.trainer(beaten, ctx.storage),
+                    trainer_difficulty(badges),
+                )
+            } else {
+                (self.gym.leader(ctx.storage), trainer_difficulty(badges + 2))
+            };
+            let seed = self.seed.wrapping_add(beaten as u32);
+            match trainer.and_then(|t| BattleScreen::trainer(t, difficulty, seed, ctx)) {
+                Ok(b) => return Transition::Push(ScreenState::Battle(b)),
+                Err(_) => return Transition::None,
+            }
+        }
+    }
+
+    impl Screen for GymScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            if buttons.consume(Buttons::A) && !self.leader_beaten(ctx) {
+                return self.challenge(ctx);
+            }
+            return Transition::None;
+        }
+
+        // Battles happen on top of this screen, so coming back to it is the
+        // first chance to notice the leader went down.
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            if self.settled || !self.leader_beaten(ctx) {
+                return Transition::None;
+            }
+            self.settled = true;
+            self.awarded = ctx.save.data.award_badge(&self.gym);
+            ctx.persist().ok();
+            return Transition::Redraw;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "GYM", top_left = (1, 1), style = black_text())
+                .draw(display)
+                .ok();
+
+            egtext!(text = "LEADER", top_left = (2, 10), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = self.gym.leader_name,
+                top_left = (40, 10),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "TYPE", top_left = (2, 18), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = self.gym.theme.name(),
+                top_left = (40, 18),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "TRAINERS", top_left = (2, 26), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::new(self.beaten(ctx).into()).as_str(),
+                top_left = (40, 26),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "OF", top_left = (52, 26), style = white_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::new(self.gym.trainer_count.into()).as_str(),
+                top_left = (64, 26),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+
+            let done = self.leader_beaten(ctx);
+            if done {
+                egtext!(
+                    text = badge_name(self.gym.theme),
+                    top_left = (2, 38),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+                let status = if self.awarded {
+                    "BADGE GET!"
+                } else if ctx.save.data.has_badge(self.gym.theme) {
+                    "BADGE"
+                } else {
+                    "BADGE CASE FULL"
+                };
+                egtext!(text = status, top_left = (40, 38), style = white_text())
+                    .draw(display)
+                    .ok();
+            }
+
+            let footer = if done { "B:LEAVE" } else { "A:FIGHT B:LEAVE" };
+            let x = 128 - 4 * footer.len() as i32 - 4;
+            egrectangle!(
+                top_left = (x, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = footer, top_left = (x + 2, 58), style = black_text())
+                .draw(display)
+                .ok();
+        }
+    }
+
+    // Call this once a wild pokemon has been caught. It goes in the party, or
+    // the box if the party's full, and the player gets the chance to give it
+    // a nickname straight away.
+    pub fn caught(ctx: &mut Context, pokemon: Pokemon) -> Transition {
+        ctx.save.data.dex.mark_caught(pokemon.species_id);
+        let target = match ctx.save.data.add_to_party(pokemon) {
+            Ok(slot) => NameTarget::Party(slot),
+            Err(pokemon) => match ctx.save.data.pokemon_box().add(pokemon) {
+                Ok(slot) => NameTarget::Box(slot),
+                // Balls can't be thrown when there's nowhere for it to go.
+                Err(_) => return Transition::None,
+            },
+        };
+        ctx.persist().ok();
+        return Transition::Push(ScreenState::Keyboard(KeyboardScreen::new(target, ctx.save)));
+    }
+
+    pub const NAME_LENGTH: usize = 12;
+
+    const KEYBOARD_ROWS: usize = 5;
+    const KEYBOARD_COLUMNS: usize = 10;
+    const DELETE_KEY: u8 = 0x7f;
+    const END_KEY: u8 = b'\n';
+    const KEYBOARD_LAYOUT: [[u8; KEYBOARD_COLUMNS]; KEYBOARD_ROWS] = [
+        *b"ABCDEFGHIJ",
+        *b"KLMNOPQRST",
+        *b"UVWXYZ.,'-",
+        *b"0123456789",
+        [
+            b'!', b'?', b'/', b':', b'&', b'(', b')', b' ', DELETE_KEY, END_KEY,
+        ],
+    ];
+
+    pub enum KeyboardEvent {
+        None,
+        Changed,
+        Confirmed,
+        Cancelled,
+    }
+
+    // A grid of keys for typing with nothing but a d-pad. A types the key
+    // under the cursor and B rubs out the last letter, or gives up if there's
+    // nothing left to rub out. It doesn't care what the text is for, so
+    // anything that needs typing can drop one of these in.
+    pub struct Keyboard {
+        text: [u8; NAME_LENGTH],
+        length: usize,
+        row: usize,
+        column: usize,
+    }
+
+    impl Keyboard {
+        pub fn new(initial: &str) -> Keyboard {
+            let mut text = [0u8; NAME_LENGTH];
+            let length = initial.len().min(NAME_LENGTH);
+            text[..length].copy_from_slice(&initial.as_bytes()[..length]);
+            return Keyboard {
+                text,
+                length,
+                row: 0,
+                column: 0,
+            };
+        }
+
+        pub fn text(&self) -> &str {
+            return name_str(&self.text[..self.length]);
+        }
+
+        // The text padded out for storing in a name field.
+        pub fn to_name(&self) -> [u8; NAME_LENGTH] {
+            return self.text;
+        }
+
+        pub fn handle_input(&mut self, buttons: &mut ButtonStates) -> KeyboardEvent {
+            if buttons.consume(Buttons::UP) {
+                self.row = (self.row + KEYBOARD_ROWS - 1) % KEYBOARD_ROWS;
+                return KeyboardEvent::Changed;
+            }
+            if buttons.consume(Buttons::DOWN) {
+                self.row = (self.row + 1) % KEYBOARD_ROWS;
+                return KeyboardEvent::Changed;
+            }
+            if buttons.consume(Buttons::LEFT) {
+                self.column = (self.column + KEYBOARD_COLUMNS - 1) % KEYBOARD_COLUMNS;
+                return KeyboardEvent::Changed;
+            }
+            if buttons.consume(Buttons::RIGHT) {
+                self.column = (self.column + 1) % KEYBOARD_COLUMNS;
+                return KeyboardEvent::Changed;
+            }
+            if buttons.consume(Buttons::B) {
+                if self.length == 0 {
+                    return KeyboardEvent::Cancelled;
+                }
+                self.delete();
+                return KeyboardEvent::Changed;
+            }
+            if buttons.consume(Buttons::A) {
+                match KEYBOARD_LAYOUT[self.row][self.column] {
+                    END_KEY => return KeyboardEvent::Confirmed,
+                    DELETE_KEY => self.delete(),
+                    key => {
+                        if self.length == NAME_LENGTH {
+                            return KeyboardEvent::None;
+                        }
+                        self.text[self.length] = key;
+                        self.length += 1;
+                    }
+                }
+                return KeyboardEvent::Changed;
+            }
+            return KeyboardEvent::None;
+        }
+
+        fn delete(&mut self) {
+            if self.length > 0 {
+                self.length -= 1;
+                self.text[self.length] = 0;
+            }
+        }
+
+        pub fn render<D: DrawTarget<BinaryColor>>(&self, display: &mut D, top_left: Point) {
+            // What's been typed so far, with blanks for the rest.
+            let mut line = [b'_'; NAME_LENGTH];
+            line[..self.length].copy_from_slice(&self.text[..self.length]);
+            Text::new(core::str::from_utf8(&line).unwrap(), top_left)
+                .into_styled(white_text())
+                .draw(display)
+                .ok();
+
+            for (r, row) in KEYBOARD_LAYOUT.iter().enumerate() {
+                for (c, key) in row.iter().enumerate() {
+                    let position = top_left + Point::new((c as i32) * 12, 9 + (r as i32) * 7);
+                    let single = [*key];
+                    let label = match *key {
+                        DELETE_KEY => "DEL",
+                        END_KEY => "END",
+                        b' ' => "SPC",
+                        _ => core::str::from_utf8(&single).unwrap(),
+                    };
+                    if r == self.row && c == self.column {
+                        Rectangle::new(
+                            position - Point::new(1, 1),
+                            position + Point::new(label.len() as i32 * 4, 5),
+                        )
+                        .into_styled(white_solid())
+                        .draw(display)
+                        .ok();
+                        Text::new(label, position)
+                            .into_styled(black_text())
+                            .draw(display)
+                            .ok();
+                    } else {
+                        Text::new(label, position)
+                            .into_styled(white_text())
+                            .draw(display)
+                            .ok();
+                    }
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum NameTarget {
+        Player,
+        Party(usize),
+        Box(usize),
+    }
+
+    pub struct KeyboardScreen {
+        target: NameTarget,
+        keyboard: Keyboard,
+    }
+
+    impl KeyboardScreen {
+        pub fn new(target: NameTarget, save: &Save) -> KeyboardScreen {
+            let initial = match target {
+                NameTarget::Player => save.data.player_name.as_ref().map(|n| name_str(n)),
+                NameTarget::Party(slot) => save.data.party[slot]
+                    .as_ref()
+                    .and_then(|p| p.nickname.as_ref())
+                    .map(|n| name_str(n)),
+                NameTarget::Box(slot) => save
+                    .data
+                    .boxed(slot)
+                    .and_then(|p| p.nickname.as_ref())
+                    .map(|n| name_str(n)),
+            };
+            return KeyboardScreen {
+                target,
+                keyboard: Keyboard::new(initial.unwrap_or("")),
+            };
+        }
+
+        fn confirm(&self, ctx: &mut Context) -> Transition {
+            let name = match self.keyboard.text().len() {
+                0 => None,
+                _ => Some(self.keyboard.to_name()),
+            };
+            match self.target {
+                NameTarget::Player => {
+                    // Everyone needs a name.
+                    if name.is_none() {
+                        return Transition::None;
+                    }
+                    ctx.save.data.player_name = name;
+                }
+                NameTarget::Party(slot) => match &mut ctx.save.data.party[slot] {
+                    Some(pokemon) => pokemon.nickname = name,
+                    None => return Transition::Pop,
+                },
+                NameTarget::Box(slot) => match &mut ctx.save.data.pokemon_box().pokemon[slot] {
+                    Some(pokemon) => pokemon.nickname = name,
+                    None => return Transition::Pop,
+                },
+            }
+            ctx.persist().ok();
+            return Transition::Pop;
+        }
+    }
+
+    impl Screen for KeyboardScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            match self.keyboard.handle_input(buttons) {
+                KeyboardEvent::None => return Transition::None,
+                KeyboardEvent::Changed => return Transition::Redraw,
+                KeyboardEvent::Confirmed => return self.confirm(ctx),
+                KeyboardEvent::Cancelled => match self.target {
+                    NameTarget::Player => return Transition::None,
+                    NameTarget::Party(_) | NameTarget::Box(_) => return Transition::Pop,
+                },
+            }
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, _ctx: &mut Context) {
+            let title = match self.target {
+                NameTarget::Player => "YOUR NAME?",
+                NameTarget::Party(_) => "NICKNAME?",
+                NameTarget::Box(_) => "SENT TO THE BOX. NICKNAME?",
+            };
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = title, top_left = (1, 1), style = black_text())
+                .draw(display)
+                .ok();
+
+            self.keyboard.render(display, Point::new(4, 9));
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "A:TYPE     B:DEL",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    // Setting the clock. A new save asks for it once, since there's nothing
+    // on the board to keep time while it's switched off.
+    pub struct ClockScreen {
+        hour: u8,
+        minute: u8,
+        // Hours first, then minutes.
+        field: usize,
+    }
+
+    impl Default for ClockScreen {
+        fn default() -> ClockScreen {
+            return ClockScreen::new();
+        }
+    }
+
+    impl ClockScreen {
+        pub fn new() -> ClockScreen {
+            return ClockScreen {
+                hour: 12,
+                minute: 0,
+                field: 0,
+            };
+        }
+
+        fn step(&mut self, up: bool) {
+            let (value, limit) = match self.field {
+                0 => (&mut self.hour, 24),
+                _ => (&mut self.minute, 60),
+            };
+            *value = match up {
+                true => (*value + 1) % limit,
+                false => (*value + limit - 1) % limit,
+            };
+        }
+    }
+
+    impl Screen for ClockScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::UP) {
+                self.step(true);
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::DOWN) {
+                self.step(false);
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::LEFT) || buttons.consume(Buttons::RIGHT) {
+                self.field = 1 - self.field;
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::A) {
+                ctx.save
+                    .data
+                    .set_clock(Timestamp::new(0, self.hour, self.minute));
+                ctx.persist().ok();
+                return Transition::Pop;
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, _ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "WHAT TIME IS IT?",
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+
+            let mut buf = [0; 5];
+            egtext!(
+                text = format_time(self.hour, self.minute, &mut buf),
+                top_left = (49, 22),
+                style = white_large_text()
+            )
+            .draw(display)
+            .ok();
+            // Underline whichever half is being changed.
+            let x = 49 + self.field as i32 * 18;
+            egline!(start = (x, 31), end = (x + 10, 31), style = white_outline())
+                .draw(display)
+                .ok();
+            let name = Timestamp::new(0, self.hour, self.minute)
+                .time_of_day()
+                .name();
+            egtext!(
+                text = name,
+                top_left = (64 - name.len() as i32 * 2, 36),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "↑↓←→:SET  A:DONE",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    // Swapping a pokemon with whoever's on the other end of the link.
+    pub struct TradeScreen {
+        trade: Trade,
+        cursor: usize,
+    }
+
+    impl TradeScreen {
+        pub fn new(nonce: u32) -> TradeScreen {
+            return TradeScreen {
+                trade: Trade::new(nonce),
+                cursor: 0,
+            };
+        }
+
+        // Anything that evolves by being traded does it on the way out.
+        fn leave(&self, ctx: &mut Context) -> Transition {
+            let slot = match self.trade.arrived() {
+                Some(slot) => slot,
+                None => return Transition::Pop,
+            };
+            let evolution = match &ctx.save.data.party[slot] {
+                Some(p) => check_trade(p, ctx.storage).ok().flatten(),
+                None => None,
+            };
+            match evolution {
+                Some(into) => {
+                    return Transition::Replace(ScreenState::Evolution(EvolutionScreen::new(
+                        slot, ctx.save, into, false,
+                    )))
+                }
+                None => return Transition::Pop,
+            }
+        }
+
+        fn status(&self) -> &'static str {
+            match self.trade.phase() {
+                Phase::Connecting => "LINKING...",
+                Phase::Choosing => "PICK TO OFFER",
+                Phase::Offered if self.trade.their_offer().is_some() => "TRADE?",
+                Phase::Offered | Phase::Accepted => "WAITING...",
+                Phase::Prepared => "TRADING...",
+                Phase::Finished(Outcome::Traded(_)) => "TRADE DONE!",
+                Phase::Finished(Outcome::Cancelled) => "CANCELLED",
+                Phase::Finished(Outcome::Failed(text)) => text,
+            }
+        }
+
+        fn footer(&self) -> &'static str {
+            match self.trade.phase() {
+                Phase::Choosing => "A:OFFER    B:QUIT",
+                Phase::Offered if self.trade.their_offer().is_some() => "A:TRADE    B:QUIT",
+                Phase::Prepared => "",
+                Phase::Finished(_) => "A:OK",
+                _ => "B:QUIT",
+            }
+        }
+    }
+
+    impl Screen for TradeScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            if let Phase::Finished(_) = self.trade.phase() {
+                if buttons.consume(Buttons::A) || buttons.consume(Buttons::B) {
+                    return self.leave(ctx);
+                }
+                return Transition::None;
+            }
+            if buttons.consume(Buttons::B) {
+                return match self.trade.cancel(ctx) {
+                    true => self.leave(ctx),
+                    false => Transition::None,
+                };
+            }
+            let count = party_count(ctx.save);
+            match self.trade.phase() {
+                Phase::Choosing if count > 0 => {
+                    if buttons.consume(Buttons::UP) {
+                        self.cursor = (self.cursor + count - 1) % count;
+                        return Transition::Redraw;
+                    }
+                    if buttons.consume(Buttons::DOWN) {
+                        self.cursor = (self.cursor + 1) % count;
+                        return Transition::Redraw;
+                    }
+                    if buttons.consume(Buttons::A) && self.trade.offer(self.cursor, ctx) {
+                        return Transition::Redraw;
+                    }
+                }
+                Phase::Offered if buttons.consume(Buttons::A) && self.trade.accept(ctx) => {
+                    return Transition::Redraw;
+                }
+                _ => {}
+            }
+            return Transition::None;
+        }
+
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            if self.trade.poll(ctx) {
+                return Transition::Redraw;
+            }
+            return Transition::None;
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "TRADE", top_left = (1, 1), style = black_text())
+                .draw(display)
+                .ok();
+            if let Some(partner) = self.trade.partner() {
+                egtext!(text = "WITH", top_left = (60, 1), style = black_text())
+                    .draw(display)
+                    .ok();
+                egtext!(text = partner, top_left = (80, 1), style = black_text())
+                    .draw(display)
+                    .ok();
+            }
+            egline!(start = (57, 7), end = (57, 64), style = white_solid())
+                .draw(display)
+                .ok();
+
+            // Once something's been offered, the cursor stays on it.
+            let locked = self.trade.phase() != Phase::Choosing;
+            let cursor = match locked {
+                true => self.trade.slot(),
+                false => self.cursor,
+            };
+            for (i, pokemon) in ctx.save.data.party.iter().enumerate() {
+                let pokemon = match pokemon {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let y = 9 + (i as i32) * 8;
+                if i == cursor && self.trade.phase() != Phase::Connecting {
+                    egtext!(
+                        text = if locked { "▷" } else { "▶" },
+                        top_left = (2, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                if let Ok(species) = read_pokemon(pokemon.species_id, ctx.storage) {
+                    egtext!(
+                        text = pokemon.name(&species),
+                        top_left = (8, y),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+            }
+
+            if let Some(offer) = self.trade.their_offer() {
+                egtext!(
+                    text = "THEY OFFER",
+                    top_left = (60, 9),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+                if let Ok(species) = read_pokemon(offer.species_id, ctx.storage) {
+                    egtext!(
+                        text = offer.name(&species),
+                        top_left = (60, 17),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                draw_level(display, offer.level, Point::new(60, 25));
+            }
+            egtext!(
+                text = self.status(),
+                top_left = (60, 36),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            if let Some(notice) = self.trade.notice() {
+                egtext!(text = notice, top_left = (60, 44), style = white_text())
+                    .draw(display)
+                    .ok();
+            }
+
+            let footer = self.footer();
+            if !footer.is_empty() {
+                egrectangle!(
+                    top_left = (56, 57),
+                    bottom_right = (128, 64),
+                    style = white_solid()
+                )
+                .draw(display)
+                .ok();
+                egtext!(text = footer, top_left = (58, 58), style = black_text())
+                    .draw(display)
+                    .ok();
+            }
+        }
+    }
+
+    // Finds someone to battle and swaps parties with them, then hands the
+    // link over to the battle itself.
+    pub struct LinkBattleScreen {
+        link: Option<LinkBattle>,
+    }
+
+    impl LinkBattleScreen {
+        pub fn new(nonce: u32) -> LinkBattleScreen {
+            return LinkBattleScreen {
+                link: Some(LinkBattle::new(nonce)),
+            };
+        }
+    }
+
+    impl Screen for LinkBattleScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, ctx: &mut Context) -> Transition {
+            let link = match &mut self.link {
+                Some(link) => link,
+                None => return Transition::None,
+            };
+            if let BattlePhase::Ended(_) = link.phase() {
+                if buttons.consume(Buttons::A) || buttons.consume(Buttons::B) {
+                    return Transition::Pop;
+                }
+                return Transition::None;
+            }
+            if buttons.consume(Buttons::B) {
+                link.cancel(ctx);
+                return Transition::Pop;
+            }
+            return Transition::None;
+        }
+
+        fn update(&mut self, ctx: &mut Context) -> Transition {
+            let link = match &mut self.link {
+                Some(link) => link,
+                None => return Transition::None,
+            };
+            let changed = link.poll(ctx);
+            if link.phase() != BattlePhase::Ready {
+                return match changed {
+                    true => Transition::Redraw,
+                    false => Transition::None,
+                };
+            }
+            let link = match self.link.take() {
+                Some(link) => link,
+                None => return Transition::None,
+            };
+            match BattleScreen::link(link, ctx) {
+                Ok(battle) => return Transition::Replace(ScreenState::Battle(battle)),
+                Err(_) => return Transition::Pop,
+            }
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            let link = match &self.link {
+                Some(link) => link,
+                None => return,
+            };
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "LINK BATTLE",
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+            if let Some(partner) = link.partner() {
+                egtext!(text = "VS", top_left = (60, 1), style = black_text())
+                    .draw(display)
+                    .ok();
+                egtext!(text = partner, top_left = (72, 1), style = black_text())
+                    .draw(display)
+                    .ok();
+            }
+            egline!(start = (57, 7), end = (57, 64), style = white_solid())
+                .draw(display)
+                .ok();
+
+            for (i, pokemon) in ctx.save.data.party.iter().enumerate() {
+                let pokemon = match pokemon {
+                    Some(p) => p,
+                    None => continue,
+                };
+                if let Ok(species) = read_pokemon(pokemon.species_id, ctx.storage) {
+                    egtext!(
+                        text = pokemon.name(&species),
+                        top_left = (8, 9 + (i as i32) * 8),
+                        style = white_text()
+                    )
+                    .draw(display)
+                    .ok();
+                }
+            }
+
+            let (status, footer) = match link.phase() {
+                BattlePhase::Connecting => ("LINKING...", "B:QUIT"),
+                BattlePhase::Ended(text) => (text, "A:OK"),
+                _ => ("SWAPPING TEAMS...", "B:QUIT"),
+            };
+            egtext!(text = status, top_left = (60, 36), style = white_text())
+                .draw(display)
+                .ok();
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = footer, top_left = (58, 58), style = black_text())
+                .draw(display)
+                .ok();
+        }
+    }
+
+    pub struct PokedexScreen {
+        pokemon_id: u8,
+    }
+
+    impl PokedexScreen {
+        pub fn new(pokemon_id: u8) -> PokedexScreen {
+            return PokedexScreen { pokemon_id };
+        }
+    }
+
+    impl Screen for PokedexScreen {
+        fn handle_input(&mut self, buttons: &mut ButtonStates, _ctx: &mut Context) -> Transition {
+            if buttons.consume(Buttons::UP) {
+                self.pokemon_id = if self.pokemon_id >= SPECIES_COUNT {
+                    1
+                } else {
+                    self.pokemon_id + 1
+                };
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::DOWN) {
+                self.pokemon_id = if self.pokemon_id <= 1 {
+                    SPECIES_COUNT
+                } else {
+                    self.pokemon_id - 1
+                };
+                return Transition::Redraw;
+            }
+            if buttons.consume(Buttons::B) {
+                return Transition::Pop;
+            }
+            return Transition::None;
+        }
+
+        // Only things you've caught show their type, so only they glow.
+        fn led(&mut self, ctx: &mut Context) -> Effect {
+            if !ctx.save.data.dex.has_caught(self.pokemon_id) {
+                return Effect::Off;
+            }
+            return match read_pokemon(self.pokemon_id, ctx.storage) {
+                Ok(species) => Effect::Pulse(type_colour(species.type_primary)),
+                Err(_) => Effect::Off,
+            };
+        }
+
+        fn render<D: DrawTarget<BinaryColor>>(&mut self, display: &mut D, ctx: &mut Context) {
+            let pokemon = match read_pokemon(self.pokemon_id, ctx.storage) {
+                Ok(p) => p,
+                Err(ReadError(text)) => {
+                    Text::new("ERROR", Point::new(0, 0))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                    Text::new(text, Point::new(0, 6))
+                        .into_styled(white_text())
+                        .draw(display)
+                        .ok();
+                    return;
+                }
+            };
+
+            egrectangle!(
+                top_left = (0, 0),
+                bottom_right = (128, 6),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            let dex = &ctx.save.data.dex;
+            let seen = dex.has_seen(self.pokemon_id);
+            let caught = dex.has_caught(self.pokemon_id);
+            egtext!(
+                text = if seen { name_str(&pokemon.name) } else { "???" },
+                top_left = (1, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "SEEN", top_left = (64, 1), style = black_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::new(dex.seen_count()).as_str(),
+                top_left = (82, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+            egtext!(text = "OWN", top_left = (98, 1), style = black_text())
+                .draw(display)
+                .ok();
+            egtext!(
+                text = Number::new(dex.caught_count()).as_str(),
+                top_left = (114, 1),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+            egline!(start = (57, 7), end = (57, 64), style = white_solid())
+                .draw(display)
+                .ok();
+
+            // Only things you've caught get the full entry. Things you've only
+            // seen get a picture, and things you haven't even seen get a shadow.
+            if let Ok(sprite) = read_sprite(pokemon.sprite, ctx.storage) {
+                let style = match seen {
+                    true => SpriteStyle::Normal,
+                    false => SpriteStyle::Silhouette,
+                };
+                draw_sprite(display, &sprite, style);
+            }
+            if !caught {
+                egtext!(
+                    text = if seen { "NOT CAUGHT" } else { "NOT SEEN" },
+                    top_left = (60, 28),
+                    style = white_text()
+                )
+                .draw(display)
+                .ok();
+                return;
+            }
+
+            egtext!(
+                text = pokemon.type_primary.name(),
+                top_left = (58, 10),
+                style = white_text()
+            )
+            .draw(display)
+            .ok();
+            pokemon.type_secondary.and_then(|t| {
+                egtext!(text = t.name(), top_left = (58, 16), style = white_text())
+                    .draw(display)
+                    .ok()
+            });
+
+            let hp = Number::new(pokemon.hp.base_value.into());
+            let attack = Number::new(pokemon.attack.base_value.into());
+            let defense = Number::new(pokemon.defense.base_value.into());
+            let speed = Number::new(pokemon.speed.base_value.into());
+            let special_attack = Number::new(pokemon.special_attack.base_value.into());
+            let special_defense = Number::new(pokemon.special_defense.base_value.into());
+            StatTable::new(Point::new(58, 28), 30, 8).draw(
+                display,
+                &[
+                    ("HP", hp.as_str()),
+                    ("ATK", attack.as_str()),
+                    ("DEF", defense.as_str()),
+                ],
+            );
+            StatTable::new(Point::new(93, 28), 32, 8).draw(
+                display,
+                &[
+                    ("SPD", speed.as_str()),
+                    ("SATK", special_attack.as_str()),
+                    ("SDEF", special_defense.as_str()),
+                ],
+            );
+
+            egrectangle!(
+                top_left = (56, 57),
+                bottom_right = (128, 64),
+                style = white_solid()
+            )
+            .draw(display)
+            .ok();
+            egtext!(
+                text = "A:MOVES    B:BACK",
+                top_left = (58, 58),
+                style = black_text()
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum SpriteStyle {
+        Normal,
+        // Everything that isn't background filled in solid.
+        Silhouette,
+        // Shiny ones. With one bit a pixel there's no other colour to use,
+        // so they come out as a negative.
+        Inverted,
+    }
+
+    impl SpriteStyle {
+        fn of(pokemon: &Pokemon) -> SpriteStyle {
+            return match pokemon.is_shiny() {
+                true => SpriteStyle::Inverted,
+                false => SpriteStyle::Normal,
+            };
+        }
+    }
+
+    // Draws a species sprite in the usual spot on the left.
+    fn draw_sprite<D: DrawTarget<BinaryColor>>(
+        display: &mut D,
+        sprite: &PackedSprite,
+        style: SpriteStyle,
+    ) {
+        let offset = Point::new(0, 7);
+        if style == SpriteStyle::Normal {
+            let mut real_image = Image::new(sprite, Point::zero());
+            real_image.translate_mut(offset);
+            real_image.draw(display).ok();
+            return;
+        }
+
+        // The corners of a sprite are always background.
+        let background = match sprite.pixel_iter().next() {
+            Some(Pixel(_, color)) => color,
+            None => return,
+        };
+        display
+            .draw_iter(sprite.pixel_iter().map(|Pixel(point, color)| {
+                let color = match style {
+                    SpriteStyle::Silhouette if color == background => BinaryColor::Off,
+                    SpriteStyle::Silhouette => BinaryColor::On,
+                    _ => color.invert(),
+                };
+                Pixel(point + offset, color)
+            }))
+            .ok();
+    }
+
+    // Half size, for when two pokemon need to share the screen. Only every
+    // other pixel in each direction is kept. Mirrored turns it to face the
+    // other way.
+    fn draw_small_sprite<D: DrawTarget<BinaryColor>>(
+        display: &mut D,
+        sprite: &PackedSprite,
+        top_left: Point,
+        mirrored: bool,
+        inverted: bool,
+    ) {
+        display
+            .draw_iter(
+                sprite
+                    .pixel_iter()
+                    .filter(|Pixel(point, _)| point.x % 2 == 0 && point.y % 2 == 0)
+                    .map(|Pixel(point, color)| {
+                        let x = match mirrored {
+                            true => sprite.width() as i32 - 2 - point.x,
+                            false => point.x,
+                        };
+                        let color = match inverted {
+                            true => color.invert(),
+                            false => color,
+                        };
+                        Pixel(top_left + Point::new(x, point.y) / 2, color)
+                    }),
+            )
+            .ok();
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::cry::Speaker;
+        use crate::led::Leds;
+        use crate::link::NoLink;
+        use crate::pokedex::{Item, ItemEffect, LearnCondition, LearnableMove, Type};
+        use crate::rom::testing::{pokemon, species, TestRom};
+        use crate::trainer::FakeNetworks;
+
+        // Everything a screen gets handed, with the test ROM as the flash.
+        struct Board {
+            rom: TestRom,
+            save: Save,
+            leds: Leds,
+            link: NoLink,
+            scanner: FakeNetworks,
+            speaker: Speaker,
+            battle: BattleState,
+        }
+
+        impl Board {
+            fn new(mut rom: TestRom) -> Board {
+                let save = Save::load(&mut rom);
+                return Board {
+                    rom,
+                    save,
+                    leds: Leds::new(),
+                    link: NoLink,
+                    scanner: FakeNetworks,
+                    speaker: Speaker::new(),
+                    battle: BattleState::new(),
+                };
+            }
+
+            fn ctx(&mut self) -> Context<'_> {
+                return Context {
+                    storage: &mut self.rom,
+                    save: &mut self.save,
+                    leds: &mut self.leds,
+                    link: &mut self.link,
+                    scanner: &mut self.scanner,
+                    speaker: &mut self.speaker,
+                    battle: &mut self.battle,
+                };
+            }
+
+            fn press<S: Screen>(&mut self, screen: &mut S, button: Buttons) -> Transition {
+                let mut buttons = ButtonStates::new();
+                buttons.update(button, true);
+                return screen.handle_input(&mut buttons, &mut self.ctx());
+            }
+
+            // Runs the screen's animation for a number of ticks.
+            fn wait<S: Screen>(&mut self, screen: &mut S, ticks: u32) {
+                for _ in 0..ticks {
+                    screen.update(&mut self.ctx());
+                }
+            }
+        }
+
+        const FIRE: u8 = 1;
+        const SURF: u16 = 9;
+        const TM: u8 = 7;
+
+        #[test]
+        fn a_machine_waits_for_a_move_to_be_forgotten() {
+            let mut rom = TestRom::new();
+            let surf = LearnableMove {
+                id: SURF,
+                condition: LearnCondition::Machine,
+            };
+            rom.add_species_learning(&species(FIRE, Type::Fire, None), &[surf]);
+            rom.add_item(&Item {
+                id: TM,
+                name: *b"TM SURF\0\0\0\0\0",
+                effect: ItemEffect::TechnicalMachine(SURF),
+            });
+            let mut board = Board::new(rom);
+            let moves = [Some(1), Some(2), Some(3), Some(4)];
+            board.save.data.party[0] = Some(pokemon(species(FIRE, Type::Fire, None), 10, moves));
+            board.save.data.add_item(TM, 2);
+            let mut screen = PartyScreen::use_item(TM);
+            let forgetting = PartyMode::Forget {
+                item_id: TM,
+                move_id: SURF,
+            };
+            let moves_now = |board: &Board| board.save.data.party[0].as_ref().unwrap().moves;
+
+            board.press(&mut screen, Buttons::A);
+            assert_eq!(screen.mode, forgetting);
+            // Keeping them all leaves the machine in the bag.
+            board.press(&mut screen, Buttons::B);
+            assert_eq!(screen.mode, PartyMode::UseItem(TM));
+            assert_eq!(moves_now(&board), moves);
+            assert_eq!(board.save.data.quantity(TM), 2);
+
+            board.press(&mut screen, Buttons::A);
+            assert_eq!(screen.mode, forgetting);
+            board.press(&mut screen, Buttons::DOWN);
+            board.press(&mut screen, Buttons::DOWN);
+            board.press(&mut screen, Buttons::A);
+            assert_eq!(moves_now(&board), [Some(1), Some(2), Some(SURF), Some(4)]);
+            assert_eq!(board.save.data.quantity(TM), 1);
+            assert_eq!(screen.mode, PartyMode::UseItem(TM));
+        }
+
+        #[test]
+        fn a_catch_with_a_full_party_goes_to_the_box() {
+            let mut board = Board::new(TestRom::new());
+            let caught_one = |level| pokemon(species(FIRE, Type::Fire, None), level, [Some(1); 4]);
+            for _ in 0..PARTY_SIZE {
+                board.save.data.add_to_party(caught_one(5)).ok();
+            }
+            match caught(&mut board.ctx(), caught_one(7)) {
+                Transition::Push(ScreenState::Keyboard(k)) => {
+                    assert_eq!(k.target, NameTarget::Box(0))
+                }
+                _ => panic!("should have asked for a nickname"),
+            }
+            assert_eq!(board.save.data.boxed(0).map(|p| p.level), Some(7));
+
+            // Swap it for one of the party.
+            let mut screen = BoxScreen::new();
+            board.press(&mut screen, Buttons::A);
+            assert_eq!(board.save.data.box_count(), 2);
+            board.press(&mut screen, Buttons::RIGHT);
+            board.press(&mut screen, Buttons::A);
+            assert_eq!(board.save.data.party[5].as_ref().map(|p| p.level), Some(7));
+            board.press(&mut screen, Buttons::A);
+            assert_eq!(screen.message, Some("YOUR PARTY IS FULL"));
+            assert_eq!(board.save.data.box_count(), 1);
+        }
+
+        const CHARMANDER: u8 = 4;
+        const CHARMELEON: u8 = 5;
+
+        // A charmander at the level it evolves, with a nickname and some
+        // training behind it.
+        fn evolving() -> Board {
+            let mut rom = TestRom::new();
+            rom.add_species(&species(CHARMANDER, Type::Fire, None));
+            let mut charmeleon = species(CHARMELEON, Type::Fire, None);
+            charmeleon.attack.base_value = 80;
+            charmeleon.speed.base_value = 80;
+            rom.add_species(&charmeleon);
+            let mut board = Board::new(rom);
+            let mut mon = pokemon(species(CHARMANDER, Type::Fire, None), 16, [Some(1); 4]);
+            mon.nickname = Some(*b"CHAR\0\0\0\0\0\0\0\0");
+            mon.attack.effort_value = 500;
+            mon.attack.individual_value = 13;
+            board.save.data.party[0] = Some(mon);
+            return board;
+        }
+
+        #[test]
+        fn evolving_keeps_what_makes_it_theirs() {
+            let mut board = evolving();
+            let before = board.save.data.party[0].clone().unwrap();
+            let mut screen = EvolutionScreen::new(0, &board.save, CHARMELEON, true);
+            board.wait(&mut screen, EVOLUTION_FRAMES as u32 - 1);
+            assert_eq!(
+                board.save.data.party[0].as_ref().unwrap().species_id,
+                CHARMANDER
+            );
+            board.wait(&mut screen, 1);
+            assert_eq!(screen.finished, Some(true));
+
+            let after = board.save.data.party[0].as_ref().unwrap();
+            assert_eq!(after.species_id, CHARMELEON);
+            assert!(board.save.data.dex.has_caught(CHARMELEON));
+            assert_eq!(after.nickname, before.nickname);
+            assert_eq!(after.moves, before.moves);
+            assert_eq!((after.level, after.xp), (before.level, before.xp));
+            for (old, new) in [
+                (before.hp, after.hp),
+                (before.attack, after.attack),
+                (before.speed, after.speed),
+            ] {
+                assert_eq!(old.individual_value, new.individual_value);
+                assert_eq!(old.effort_value, new.effort_value);
+            }
+            // Only the stats the new species is better at go up.
+            assert!(after.attack.value > before.attack.value);
+            assert_eq!(after.defense.value, before.defense.value);
+        }
+
+        #[test]
+        fn b_stops_a_level_up_evolution_but_not_a_stone() {
+            let mut board = evolving();
+            let mut screen = EvolutionScreen::new(0, &board.save, CHARMELEON, true);
+            board.wait(&mut screen, 10);
+            board.press(&mut screen, Buttons::B);
+            board.wait(&mut screen, EVOLUTION_FRAMES as u32);
+            assert_eq!(screen.finished, Some(false));
+            assert_eq!(
+                board.save.data.party[0].as_ref().unwrap().species_id,
+                CHARMANDER
+            );
+            assert!(!board.save.data.dex.has_caught(CHARMELEON));
+
+            let mut screen = EvolutionScreen::new(0, &board.save, CHARMELEON, false);
+            board.wait(&mut screen, 10);
+            board.press(&mut screen, Buttons::B);
+            board.wait(&mut screen, EVOLUTION_FRAMES as u32);
+            assert_eq!(screen.finished, Some(true));
+            assert_eq!(
+                board.save.data.party[0].as_ref().unwrap().species_id,
+                CHARMELEON
+            );
+        }
+    }
 }
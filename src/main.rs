@@ -4,78 +4,241 @@
 extern crate nb;
 
 use bitbang_hal;
-use embedded_graphics::fonts::Text;
-use embedded_graphics::image::Image;
-use embedded_graphics::primitives::Rectangle;
-use embedded_graphics::style::{PrimitiveStyleBuilder, TextStyleBuilder};
-use embedded_graphics::{egline, egrectangle, egtext};
-use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
-use embedded_picofont::FontPico;
+use esp8266_hal::ehal::blocking::i2c::Write;
 use esp8266_hal::ehal::digital::v2::InputPin;
+use embedded_graphics::fonts::Text;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::style::TextStyle;
+use esp8266_hal::ehal::serial;
 use esp8266_hal::flash::ESPFlash;
 use esp8266_hal::gpio::{
-    Gpio0, Gpio12, Gpio13, Gpio14, Gpio15, Gpio16, Gpio2, Input, Output, PullDown, PullUp, PushPull,
+    Gpio0, Gpio10, Gpio12, Gpio13, Gpio14, Gpio15, Gpio16, Gpio2, Input, Output, PullDown, PullUp,
+    PushPull,
 };
 use esp8266_hal::prelude::*;
 use esp8266_hal::target::Peripherals;
 use esp8266_hal::time::{KiloHertz, Nanoseconds};
 use esp8266_hal::timer::Timer1;
-use minicbor::decode;
 use panic_halt as _;
-use pokemon::pokedex::PokemonSpecies;
-use sh1106::{prelude::*, Builder};
-use tinybmp::Bmp;
-
-enum Buttons {
-    LEFT,
-    UP,
-    RIGHT,
-    DOWN,
-    A,
-    B,
+use pokemon::animation::Clock;
+use pokemon::app::{App, Context};
+use pokemon::cry::{Speaker, Tone};
+use pokemon::font::SmallFont;
+use pokemon::framebuffer::{FrameBuffer, PageWriter, WIDTH};
+use pokemon::input::{ButtonStates, Buttons};
+use pokemon::led::{Colour, Led, Leds};
+use pokemon::link::{Link, LinkError};
+use pokemon::rom::{ReadError, Storage, WriteError};
+use pokemon::save::Save;
+use pokemon::screens::BattleState;
+use pokemon::trainer::FakeNetworks;
+use xtensa_lx::timer::get_cycle_count;
+
+// The CPU's cycle counter never stops and runs at the clock speed, so it's
+// what the main loop gets timed with. It wraps about every 53 seconds, which
+// is fine as long as we look at it more often than that.
+const CYCLES_PER_MS: u32 = 80_000;
+const CYCLES_PER_SECOND: u32 = CYCLES_PER_MS * 1000;
+
+// Byte 2 of the image header is the flash mode the bootloader sets up. Only
+// the two-wire modes leave GPIO9 and GPIO10 alone, so the runner asks for DIO.
+const FLASH_MODE_OFFSET: u32 = 2;
+const FLASH_MODE_DIO: u8 = 2;
+const FLASH_MODE_DOUT: u8 = 3;
+
+// The flash driver lives in the HAL, so it needs wrapping before we can hand
+// it to the app as storage.
+struct Flash(ESPFlash);
+
+impl Storage for Flash {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ReadError> {
+        match self.0.read(offset, buf) {
+            Ok(_) => return Ok(()),
+            Err(_) => return Err(ReadError("flash read failed")),
+        }
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), WriteError> {
+        match self.0.erase_sector(offset / 0x1000) {
+            Ok(_) => return Ok(()),
+            Err(_) => return Err(WriteError("flash erase failed")),
+        }
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), WriteError> {
+        match self.0.write(offset, data) {
+            Ok(_) => return Ok(()),
+            Err(_) => return Err(WriteError("flash write failed")),
+        }
+    }
 }
 
-impl Buttons {
-    fn to_index(&self) -> usize {
-        match self {
-            Buttons::LEFT => 0,
-            Buttons::UP => 1,
-            Buttons::RIGHT => 2,
-            Buttons::DOWN => 3,
-            Buttons::A => 4,
-            Buttons::B => 5,
+// The link cable is the UART, on the same pins the board gets flashed over.
+struct UartLink<S>(S);
+
+impl<S: serial::Read<u8> + serial::Write<u8>> Link for UartLink<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, LinkError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.0.read() {
+                Ok(byte) => buf[read] = byte,
+                Err(nb::Error::WouldBlock) => break,
+                Err(_) => return Err(LinkError("UART ERROR")),
+            }
+            read += 1;
+        }
+        return Ok(read);
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), LinkError> {
+        for byte in data {
+            if block!(self.0.write(*byte)).is_err() {
+                return Err(LinkError("UART ERROR"));
+            }
         }
+        return Ok(());
     }
 }
 
-struct ButtonStates {
-    pressed: [bool; 6],
-    was_consumed: [bool; 6],
+// Just enough of the sh1106 to start it up and write runs of columns. The
+// sh1106 crate's graphics mode keeps its own kilobyte copy of the screen and
+// its flush always sends all of it. There's no way to point it at a column,
+// so it couldn't do partial flushes, and its buffer would sit next to ours.
+// Contrast, inversion and sleep are all one-byte commands anyway.
+struct Oled<I2C> {
+    i2c: I2C,
 }
 
-impl ButtonStates {
-    fn new() -> ButtonStates {
-        return ButtonStates {
-            pressed: [false; 6],
-            was_consumed: [false; 6],
+const OLED_ADDRESS: u8 = 0x3c;
+// The controller has 132 columns of memory and the panel sits in the middle.
+const COLUMN_OFFSET: u8 = 2;
+// Same start-up sequence the driver crate uses for a 128x64 panel.
+const OLED_INIT: [u8; 21] = [
+    0xae, // display off
+    0xd5, 0x80, // clock divider
+    0xa8, 0x3f, // multiplex, 64 rows
+    0xd3, 0x00, // no display offset
+    0x40, // start line 0
+    0xad, 0x8b, // charge pump on
+    0xa1, // segment remap
+    0xc8, // scan com backwards
+    0xda, 0x12, // alternative com pins
+    0x81, 0x80, // contrast
+    0xd9, 0x1f, // precharge
+    0xdb, 0x40, // vcomh deselect
+];
+const OLED_ON: [u8; 3] = [
+    0xa4, // follow ram
+    0xa6, // not inverted until the settings say so
+    0xaf, // display on
+];
+
+impl<I2C: Write> Oled<I2C> {
+    fn init(&mut self) -> Result<(), I2C::Error> {
+        self.commands(&OLED_INIT)?;
+        return self.commands(&OLED_ON);
+    }
+
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), I2C::Error> {
+        return self.commands(&[0x81, contrast]);
+    }
+
+    // The panel flips every pixel itself, so nothing needs redrawing.
+    fn set_inverted(&mut self, inverted: bool) -> Result<(), I2C::Error> {
+        return match inverted {
+            true => self.commands(&[0xa7]),
+            false => self.commands(&[0xa6]),
         };
     }
-    fn is_pressed(&self, button: Buttons) -> bool {
-        return self.pressed[button.to_index()] && !self.was_consumed[button.to_index()];
+
+    // Off keeps whatever's in the panel's memory, so waking up is instant.
+    fn set_on(&mut self, on: bool) -> Result<(), I2C::Error> {
+        return match on {
+            true => self.commands(&[0xaf]),
+            false => self.commands(&[0xae]),
+        };
+    }
+
+    fn commands(&mut self, commands: &[u8]) -> Result<(), I2C::Error> {
+        // A leading zero marks the rest of the write as commands.
+        let mut buf = [0u8; OLED_INIT.len() + 1];
+        buf[1..commands.len() + 1].copy_from_slice(commands);
+        return self.i2c.write(OLED_ADDRESS, &buf[..commands.len() + 1]);
+    }
+}
+
+impl<I2C: Write> PageWriter for Oled<I2C> {
+    type Error = I2C::Error;
+
+    fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), Self::Error> {
+        let column = column + COLUMN_OFFSET;
+        self.commands(&[0xb0 | page, column & 0x0f, 0x10 | (column >> 4)])?;
+        // And 0x40 marks it as display data.
+        let mut buf = [0x40u8; WIDTH + 1];
+        buf[1..data.len() + 1].copy_from_slice(data);
+        return self.i2c.write(OLED_ADDRESS, &buf[..data.len() + 1]);
+    }
+}
+
+// Borrows the timer for as long as it takes to send one colour, since the
+// main loop needs it back for its delay.
+struct Neopixel<'a> {
+    timer: &'a mut Timer1,
+    pin: &'a mut Gpio15<Output<PushPull>>,
+}
+
+impl<'a> Led for Neopixel<'a> {
+    fn show(&mut self, colour: Colour) {
+        show_colour(self.timer, self.pin, colour.r, colour.g, colour.b);
     }
-    fn consume(&mut self, button: Buttons) -> bool {
-        if self.pressed[button.to_index()] && !self.was_consumed[button.to_index()] {
-            self.was_consumed[button.to_index()] = true;
-            return true;
+}
+
+// Square waves off the cycle counter, with an edge flipped whenever the main
+// loop comes round and one's due. Both timers are spoken for. A slow pass of
+// the loop stretches a cycle, which a piezo gets away with, and the edges
+// it missed are skipped rather than played late.
+struct Piezo {
+    pin: Gpio10<Output<PushPull>>,
+    tone: Option<Tone>,
+    high: bool,
+    next_edge: u32,
+}
+
+impl Piezo {
+    fn set_tone(&mut self, tone: Option<Tone>) {
+        if tone == self.tone {
+            return;
         }
-        return false;
+        self.tone = tone;
+        self.high = false;
+        self.pin.set_low().ok();
+        self.next_edge = get_cycle_count();
     }
-    fn update(&mut self, button: Buttons, pressed: bool) {
-        if pressed && !self.pressed[button.to_index()] {
-            self.pressed[button.to_index()] = true;
-            self.was_consumed[button.to_index()] = false;
-        } else if !pressed && self.pressed[button.to_index()] {
-            self.pressed[button.to_index()] = false;
+
+    fn poll(&mut self) {
+        let tone = match self.tone {
+            Some(tone) => tone,
+            None => return,
+        };
+        let now = get_cycle_count();
+        if (now.wrapping_sub(self.next_edge) as i32) < 0 {
+            return;
+        }
+        let period = CYCLES_PER_SECOND / tone.hz.max(1) as u32;
+        let high_for = period * tone.duty as u32 / 8;
+        self.high = !self.high;
+        let wait = match self.high {
+            true => high_for,
+            false => period - high_for,
+        };
+        match self.high {
+            true => self.pin.set_high().ok(),
+            false => self.pin.set_low().ok(),
+        };
+        self.next_edge = self.next_edge.wrapping_add(wait);
+        if (now.wrapping_sub(self.next_edge) as i32) >= 0 {
+            self.next_edge = now.wrapping_add(wait);
         }
     }
 }
@@ -143,267 +306,119 @@ fn main() -> ! {
                                                    // are 100KHz, and 400 KHz.
     timer2.start(KiloHertz(200));
     let i2c = bitbang_hal::i2c::I2cBB::new(scl, sda, timer2);
-    let mut display: GraphicsMode<_> = Builder::new().connect_i2c(i2c).into();
-
-    display.init().unwrap();
-    display.flush().ok();
-
-    let black_solid = PrimitiveStyleBuilder::new()
-        .fill_color(BinaryColor::Off)
-        .build();
-    let white_solid = PrimitiveStyleBuilder::new()
-        .fill_color(BinaryColor::On)
-        .build();
-    let white_text = TextStyleBuilder::new(FontPico)
-        .text_color(BinaryColor::On)
-        .build();
-    let black_text = TextStyleBuilder::new(FontPico)
-        .text_color(BinaryColor::Off)
-        .build();
-
-    display.flush().ok();
-
-    let mut pokemon_id = 1;
-    let mut pokemon: PokemonSpecies;
-    let mut has_drawn = false;
+    let mut oled = Oled { i2c };
+    let mut display = FrameBuffer::new();
+
+    oled.init().ok();
+    display.flush(&mut oled).ok();
+
+    // Link cable configuration!
+    let tx = pins.gpio1.into_uart();
+    let rx = pins.gpio3.into_uart();
+    let mut link = UartLink(dp.UART0.serial(tx, rx));
+
+    // There's no wifi driver yet, so scanning makes do with pretend networks.
+    let mut scanner = FakeNetworks;
+
+    // Piezo configuration! Every other pin's taken, but GPIO10 is free as
+    // long as the flash is in a two-wire mode. Driving it otherwise would
+    // take the flash down with it, so anything else stops here instead.
+    let mut flash_mode = [0u8; 1];
+    storage.read(FLASH_MODE_OFFSET, &mut flash_mode).unwrap();
+    if flash_mode[0] != FLASH_MODE_DIO && flash_mode[0] != FLASH_MODE_DOUT {
+        Text::new("FLASH MUST BE DIO", Point::new(0, 0))
+            .into_styled(TextStyle::new(SmallFont, BinaryColor::On))
+            .draw(&mut display)
+            .ok();
+        display.flush(&mut oled).ok();
+        loop {}
+    }
+    let mut piezo = Piezo {
+        pin: pins.gpio10.into_push_pull_output(),
+        tone: None,
+        high: false,
+        next_edge: 0,
+    };
+    piezo.pin.set_low().ok();
+    let mut speaker = Speaker::new();
+    let mut battle = BattleState::new();
+
+    let mut flash = Flash(storage);
+    let mut save = Save::load(&mut flash);
+    let mut contrast = None;
+    let mut inverted = None;
+    let mut panel_on = true;
+    let mut leds = Leds::new();
+    let mut app = App::boot(&save);
+    let mut clock = Clock::new();
+    let mut last = get_cycle_count();
     loop {
         btn_left = update_buttons(
             &mut btns, btn_left, &btn_up, &btn_right, &btn_down, &btn_a, &btn_b,
         );
 
-        if btns.consume(Buttons::UP) {
-            pokemon_id += 1;
-            has_drawn = false
-        }
-        if btns.consume(Buttons::DOWN) {
-            pokemon_id -= 1;
-            has_drawn = false
-        }
-        if pokemon_id <= 0 {
-            pokemon_id += 151;
-        } else if pokemon_id > 151 {
-            pokemon_id -= 151;
+        let mut ctx = Context {
+            storage: &mut flash,
+            save: &mut save,
+            leds: &mut leds,
+            link: &mut link,
+            scanner: &mut scanner,
+            speaker: &mut speaker,
+            battle: &mut battle,
+        };
+        // However long the last pass took, flushes and saves included, gets
+        // made up for with extra ticks. Only whole milliseconds are taken off
+        // the counter, so nothing is lost to rounding.
+        let elapsed_ms = get_cycle_count().wrapping_sub(last) / CYCLES_PER_MS;
+        last = last.wrapping_add(elapsed_ms * CYCLES_PER_MS);
+        app.elapse(elapsed_ms, &mut ctx);
+        for _ in 0..clock.advance(elapsed_ms) {
+            app.tick(&mut btns, &mut ctx);
         }
 
-        if !has_drawn {
-            has_drawn = true;
-            egrectangle!(
-                top_left = (0, 0),
-                bottom_right = (128, 64),
-                style = black_solid
-            )
-            .draw(&mut display)
-            .ok();
-
-            let res = read_pokemon(pokemon_id, &mut storage);
-            if res.is_err() {
-                Text::new("ERROR", Point::new(0, 0))
-                    .into_styled(white_text)
-                    .draw(&mut display)
-                    .ok();
-                let ReadError(text) = res.err().unwrap();
-                Text::new(text, Point::new(0, 6))
-                    .into_styled(white_text)
-                    .draw(&mut display)
-                    .ok();
-                display.flush().ok();
-                continue;
-            }
-            pokemon = res.unwrap();
-
-            egrectangle!(
-                top_left = (0, 0),
-                bottom_right = (128, 6),
-                style = white_solid
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text = core::str::from_utf8(&pokemon.name).unwrap(),
-                top_left = (1, 1),
-                style = black_text
-            )
-            .draw(&mut display)
-            .ok();
-            egline!(start = (57, 7), end = (57, 64), style = white_solid)
-                .draw(&mut display)
-                .ok();
-
-            egtext!(
-                text = pokemon.type_primary.name(),
-                top_left = (58, 10),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-            pokemon.type_secondary.and_then(|t| {
-                egtext!(text = t.name(), top_left = (58, 16), style = white_text)
-                    .draw(&mut display)
-                    .ok()
-            });
-
-            egtext!(text = "HP", top_left = (58, 28), style = white_text)
-                .draw(&mut display)
-                .ok();
-            egtext!(text = "ATK", top_left = (58, 36), style = white_text)
-                .draw(&mut display)
-                .ok();
-            egtext!(text = "DEF", top_left = (58, 44), style = white_text)
-                .draw(&mut display)
-                .ok();
-            egtext!(text = "SPD", top_left = (93, 28), style = white_text)
-                .draw(&mut display)
-                .ok();
-            egtext!(text = "SATK", top_left = (93, 36), style = white_text)
-                .draw(&mut display)
-                .ok();
-            egtext!(text = "SDEF", top_left = (93, 44), style = white_text)
-                .draw(&mut display)
-                .ok();
-
-            egtext!(
-                text = core::str::from_utf8(&num_to_str(pokemon.hp.base_value.into())).unwrap(),
-                top_left = (75, 28),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text = core::str::from_utf8(&num_to_str(pokemon.attack.base_value.into())).unwrap(),
-                top_left = (75, 36),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text =
-                    core::str::from_utf8(&num_to_str(pokemon.defense.base_value.into())).unwrap(),
-                top_left = (75, 44),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text = core::str::from_utf8(&num_to_str(pokemon.speed.base_value.into())).unwrap(),
-                top_left = (113, 28),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text = core::str::from_utf8(&num_to_str(pokemon.special_attack.base_value.into()))
-                    .unwrap(),
-                top_left = (113, 36),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text = core::str::from_utf8(&num_to_str(pokemon.special_defense.base_value.into()))
-                    .unwrap(),
-                top_left = (113, 44),
-                style = white_text
-            )
-            .draw(&mut display)
-            .ok();
-
-            egrectangle!(
-                top_left = (56, 57),
-                bottom_right = (128, 64),
-                style = white_solid
-            )
-            .draw(&mut display)
-            .ok();
-            egtext!(
-                text = "A:MOVES    B:BACK",
-                top_left = (58, 58),
-                style = black_text
-            )
-            .draw(&mut display)
-            .ok();
-
-            let image = Bmp::from_slice(pokemon.sprite.as_ref()).unwrap();
-            let mut real_image = Image::new(&image, Point::zero());
-            real_image.translate_mut(Point::new(0, 7));
-            real_image.draw(&mut display).ok();
-            display.flush().ok();
-            timer1.delay_ms(200);
+        // Contrast and inversion both belong to the panel itself, so they
+        // only get sent when the settings change.
+        let wanted_contrast = ctx.save.data.settings.contrast;
+        if contrast != Some(wanted_contrast) {
+            oled.set_contrast(wanted_contrast).ok();
+            contrast = Some(wanted_contrast);
         }
-    }
-
-    /*     loop {
-        configuring = button.into_push_pull_output();
-        configuring.set_high().ok();
-        button = configuring.into_floating_input();
-
-        if button.is_high().unwrap() {
-            show_colour(&mut timer1, &mut neopixel, 0x0f, 0x00, 0x00);
-        } else {
-            show_colour(&mut timer1, &mut neopixel, 0x00, 0x00, 0x0f);
+        let wanted_inverted = ctx.save.data.settings.inverted;
+        if inverted != Some(wanted_inverted) {
+            oled.set_inverted(wanted_inverted).ok();
+            inverted = Some(wanted_inverted);
         }
-    } */
-}
-
-fn num_to_str(mut num: u32) -> [u8; 3] {
-    let mut buf = [0u8; 3];
-    let mut base: u32 = 100;
-    let mut index_modifier = 0;
-    let mut has_non_zero = false;
-
-    for i in 0..3 {
-        let digit: u8 = (num / base).try_into().unwrap();
-        if digit == 0 && !has_non_zero {
-            index_modifier += 1;
-        } else {
-            has_non_zero = true;
-            buf[i - index_modifier] = digit + 48u8; // trust me babe :)
+        // Asleep, there's no point drawing anything. Waking up redraws.
+        let awake = !app.is_asleep();
+        if awake != panel_on {
+            oled.set_on(awake).ok();
+            panel_on = awake;
         }
-        num %= base;
-        base /= 10;
-    }
-    return buf;
-}
-
-const DATA_OFFSET: u32 = 0x200000;
-const DATA_BUFFER_SIZE: usize = 2048;
-
-#[derive(Debug)]
-struct ReadError(&'static str);
-
-fn read_pokemon(id: u8, flash: &mut ESPFlash) -> Result<PokemonSpecies, ReadError> {
-    // First read this id's row in the lookup table.
-    let mut table_entry = [0u8; 8];
-    let row_offset = match DATA_OFFSET.checked_add(u32::from(id) * 8) {
-        Some(i) => i,
-        None => return Err(ReadError("row offset out of bounds")),
-    };
-    if flash.read(row_offset, &mut table_entry).is_err() {
-        return Err(ReadError("read row failed"));
-    }
-
-    // Parse the row data.
-    let offset = u32::from_be_bytes(table_entry[0..4].try_into().unwrap());
-    let size: usize = match u32::from_be_bytes(table_entry[4..8].try_into().unwrap()).try_into() {
-        Ok(i) => i,
-        Err(_) => return Err(ReadError("failed to convert size")),
-    };
-
-    // Prefer to return an error than to panic.
-    if size > DATA_BUFFER_SIZE {
-        return Err(ReadError("data larger than buffer"));
-    }
-
-    // Now we can read the data that the row was pointing to.
-    let mut buf = [0u8; DATA_BUFFER_SIZE];
-    let data_offset = match DATA_OFFSET.checked_add(offset) {
-        Some(i) => i,
-        None => return Err(ReadError("data offset overflowed")),
-    };
-    flash.read(data_offset, &mut buf[..size]);
-
-    match minicbor::decode(&buf) {
-        Ok(data) => return Ok(data),
-        Err(_) => return Err(ReadError("failed to decode data")),
+        // Only the columns that changed go over the wire.
+        if awake && app.render(&mut display, &mut ctx) {
+            display.flush(&mut oled).ok();
+        }
+        // A colour only takes a few microseconds to send, and nothing gets
+        // sent unless it's changed.
+        let brightness = match awake {
+            true => ctx.save.data.settings.led_brightness,
+            false => 0,
+        };
+        ctx.leds.show(
+            brightness,
+            &mut Neopixel {
+                timer: &mut timer1,
+                pin: &mut neopixel,
+            },
+        );
+        // The tone only changes on a tick, but its edges get flipped on every
+        // pass.
+        let tone = match awake {
+            true => ctx.speaker.tone(),
+            false => None,
+        };
+        piezo.set_tone(tone);
+        piezo.poll();
     }
 }
 